@@ -1,170 +1,1264 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
+use clap::ValueEnum;
 use colored::Colorize;
+use rayon::prelude::*;
 
+use crate::hasher::{self, Digest};
+use crate::interrupt;
 use crate::output::DuplicateGroup;
+use crate::protection;
+use crate::scanner::FileEntry;
+use crate::util;
 
 /// Result of a hardlink operation
 #[derive(Debug, Default)]
 pub struct ActionResult {
     /// Number of files replaced with hardlinks
     pub files_linked: usize,
+    /// Number of files replaced with stub files
+    pub files_stubbed: usize,
     /// Number of bytes saved
     pub bytes_saved: u64,
     /// Errors encountered (path, error message)
     pub errors: Vec<(PathBuf, String)>,
+    /// Files skipped because their size or modification time changed after hashing
+    pub changed: Vec<PathBuf>,
+    /// Number of groups whose changes were undone after a failure
+    pub groups_rolled_back: usize,
+    /// Number of groups not acted on because they would reclaim less than the minimum saving
+    pub groups_below_min_saving: usize,
+    /// Number of groups of empty files not acted on
+    pub empty_groups_skipped: usize,
+    /// Number of times a duplicate was kept as an additional original because the
+    /// current one reached the filesystem's hardlink limit
+    pub link_limit_splits: usize,
+    /// Number of groups left untouched because their kept copy is protected
+    pub protected_keeper_groups: usize,
+    /// Number of groups not acted on because the run was interrupted
+    pub interrupted_groups: usize,
+    /// Archive members left untouched, they are only reported
+    pub archive_members: usize,
+    /// Files skipped because of their filesystem, by device ID
+    pub mount_skips: BTreeMap<u64, MountSkip>,
 }
 
-/// Select which file to keep as the "original" in a duplicate group.
-fn select_original(files: &[PathBuf]) -> &PathBuf {
+/// Files skipped on one filesystem
+#[derive(Debug, Clone)]
+pub struct MountSkip {
+    /// Why files on the filesystem were skipped
+    pub reason: String,
+    /// Number of files skipped
+    pub files: usize,
+    /// One of the skipped files, to identify the filesystem
+    pub example: PathBuf,
+}
+
+impl ActionResult {
+    /// Combine the results of two independently processed sets of groups
+    fn merge(mut self, other: ActionResult) -> ActionResult {
+        self.files_linked += other.files_linked;
+        self.files_stubbed += other.files_stubbed;
+        self.bytes_saved += other.bytes_saved;
+        self.errors.extend(other.errors);
+        self.changed.extend(other.changed);
+        self.groups_rolled_back += other.groups_rolled_back;
+        self.groups_below_min_saving += other.groups_below_min_saving;
+        self.empty_groups_skipped += other.empty_groups_skipped;
+        self.link_limit_splits += other.link_limit_splits;
+        self.protected_keeper_groups += other.protected_keeper_groups;
+        self.interrupted_groups += other.interrupted_groups;
+        self.archive_members += other.archive_members;
+        for (dev, skip) in other.mount_skips {
+            self.mount_skips
+                .entry(dev)
+                .and_modify(|s| s.files += skip.files)
+                .or_insert(skip);
+        }
+        self
+    }
+
+    /// Record a file skipped because of the filesystem it is on
+    fn skip_on_mount(&mut self, dev: u64, path: &Path, reason: String) {
+        self.mount_skips
+            .entry(dev)
+            .or_insert_with(|| MountSkip {
+                reason,
+                files: 0,
+                example: path.to_path_buf(),
+            })
+            .files += 1;
+    }
+}
+
+/// Filesystems an action can't be applied on, learned from the first failure on each so
+/// the remaining files are skipped instead of attempted one by one.
+#[derive(Debug, Default)]
+struct MountCapabilities {
+    unsupported: Mutex<HashMap<u64, String>>,
+}
+
+impl MountCapabilities {
+    /// Why the action can't be applied on the filesystem, if it is known not to work
+    fn unsupported(&self, dev: u64) -> Option<String> {
+        self.unsupported.lock().unwrap().get(&dev).cloned()
+    }
+
+    /// Remember the filesystem if the error applies to all of its files (e.g. it is
+    /// mounted read-only) and return the reason.
+    fn learn(&self, dev: u64, error: &io::Error) -> Option<String> {
+        if !matches!(
+            error.kind(),
+            io::ErrorKind::ReadOnlyFilesystem | io::ErrorKind::Unsupported
+        ) {
+            return None;
+        }
+        let reason = error.to_string();
+        self.unsupported
+            .lock()
+            .unwrap()
+            .entry(dev)
+            .or_insert_with(|| reason.clone());
+        Some(reason)
+    }
+}
+
+/// Options controlling how actions are applied
+#[derive(Debug, Default, Clone)]
+pub struct ActionOptions {
+    /// Only report what would happen without modifying files
+    pub dry_run: bool,
+    /// Print a line for every file that is acted on or skipped
+    pub verbose: bool,
+    /// Undo already applied changes of a group if any file in it fails
+    pub per_group_atomic: bool,
+    /// Only act on groups that reclaim at least this many bytes
+    pub min_saving: Option<u64>,
+    /// Temporarily lift write protection from the files that are replaced
+    pub force: bool,
+    /// Also act on groups of empty files, which reclaims no space
+    pub include_empty: bool,
+    /// What to do when the preferred kept copy of a group can't be linked to
+    pub on_protected_keeper: ProtectedKeeper,
+}
+
+/// What to do with a group whose preferred kept copy is protected, i.e. has the immutable
+/// attribute which prevents hardlinks to it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ProtectedKeeper {
+    /// Keep the file with the next shortest path that isn't protected
+    #[default]
+    NextBest,
+    /// Leave the group untouched
+    Skip,
+    /// Leave the group untouched and report it as an error
+    Fail,
+}
+
+/// Changes applied to a single group, kept so they can be undone if the group fails.
+///
+/// Each backup is a replaced path together with a hardlink to the inode it had before.
+#[derive(Debug, Default)]
+struct GroupJournal {
+    backups: Vec<(PathBuf, PathBuf)>,
+    /// Files created next to the replaced paths (e.g. stubs)
+    created: Vec<PathBuf>,
+    /// Lift write protection while undoing or committing changes
+    force: bool,
+}
+
+impl GroupJournal {
+    /// Remove created files and restore every replaced path to its previous inode,
+    /// in reverse order.
+    fn rollback(self) -> Vec<(PathBuf, String)> {
+        let mut errors = Vec::new();
+        for path in self.created.into_iter().rev() {
+            if let Err(e) = with_write_access(&path, self.force, || fs::remove_file(&path)) {
+                errors.push((path, format!("rollback failed: {}", e)));
+            }
+        }
+        for (path, backup) in self.backups.into_iter().rev() {
+            if let Err(e) = with_write_access(&path, self.force, || fs::rename(&backup, &path)) {
+                errors.push((path, format!("rollback failed: {}", e)));
+            }
+        }
+        errors
+    }
+
+    /// Drop the backups once the whole group succeeded.
+    fn commit(self) -> Vec<(PathBuf, String)> {
+        let mut errors = Vec::new();
+        for (_, backup) in self.backups {
+            if let Err(e) = with_write_access(&backup, self.force, || fs::remove_file(&backup)) {
+                errors.push((backup, e.to_string()));
+            }
+        }
+        errors
+    }
+}
+
+/// Select which file to keep as the "original" in a duplicate group, a reference file
+/// (`--compare-to`) if there is one.
+pub(crate) fn select_original(files: &[FileEntry]) -> &FileEntry {
     files
         .iter()
-        .min_by_key(|p| p.as_os_str().len())
+        .min_by_key(|f| (!f.reference, f.path.as_os_str().len()))
         .expect("group must have at least one file")
 }
 
+/// Select the file to link the duplicates of a group to, following `on_protected_keeper`
+/// if the preferred one is protected. A protected reference file is never replaced by
+/// keeping another copy, its group is skipped instead.
+///
+/// Returns `None` if the group must be left untouched.
+fn select_keeper<'a>(
+    group: &'a DuplicateGroup,
+    options: &ActionOptions,
+    result: &mut ActionResult,
+) -> Option<&'a FileEntry> {
+    choose_keeper(&group.files, options, result, protection::is_immutable)
+}
+
+fn choose_keeper<'a>(
+    files: &'a [FileEntry],
+    options: &ActionOptions,
+    result: &mut ActionResult,
+    is_protected: impl Fn(&Path) -> bool,
+) -> Option<&'a FileEntry> {
+    let preferred = select_original(files);
+    if !is_protected(&preferred.path) {
+        return Some(preferred);
+    }
+
+    let next_best = match options.on_protected_keeper {
+        ProtectedKeeper::Fail => {
+            result.errors.push((
+                preferred.path.clone(),
+                "kept copy is immutable, hardlinks to it can't be created".to_string(),
+            ));
+            return None;
+        }
+        policy => replacement_keeper(files, preferred, policy, is_protected),
+    };
+
+    if options.verbose {
+        match next_best {
+            Some(keeper) => println!(
+                "{} {} is immutable, keeping {} instead",
+                "[protected]".blue(),
+                preferred.path.display(),
+                keeper.path.display()
+            ),
+            None => println!(
+                "{} {} is immutable, skipping its group",
+                "[protected]".blue(),
+                preferred.path.display()
+            ),
+        }
+    }
+    if next_best.is_none() {
+        result.protected_keeper_groups += 1;
+    }
+    next_best
+}
+
+/// The file kept instead of a protected `preferred` one under `policy`, `None` if the group
+/// is left untouched
+fn replacement_keeper<'a>(
+    files: &'a [FileEntry],
+    preferred: &FileEntry,
+    policy: ProtectedKeeper,
+    is_protected: impl Fn(&Path) -> bool,
+) -> Option<&'a FileEntry> {
+    match policy {
+        ProtectedKeeper::NextBest if !preferred.reference => files
+            .iter()
+            .filter(|f| f.path != preferred.path && !is_protected(&f.path))
+            .min_by_key(|f| f.path.as_os_str().len()),
+        ProtectedKeeper::NextBest | ProtectedKeeper::Skip | ProtectedKeeper::Fail => None,
+    }
+}
+
+/// Select the file the actions keep of a group, following `on_protected_keeper` like
+/// [`hardlink_duplicates`] does. If they would leave the group untouched, that is the
+/// preferred file.
+pub(crate) fn kept_file(files: &[FileEntry], on_protected_keeper: ProtectedKeeper) -> &FileEntry {
+    choose_kept_file(files, on_protected_keeper, protection::is_immutable)
+}
+
+fn choose_kept_file(
+    files: &[FileEntry],
+    on_protected_keeper: ProtectedKeeper,
+    is_protected: impl Fn(&Path) -> bool,
+) -> &FileEntry {
+    let preferred = select_original(files);
+    if !is_protected(&preferred.path) {
+        return preferred;
+    }
+    replacement_keeper(files, preferred, on_protected_keeper, is_protected).unwrap_or(preferred)
+}
+
+/// Check if an error means the target inode already has the maximum number of hardlinks
+/// the filesystem allows (EMLINK), e.g. 65000 on ext4.
+fn is_link_limit_error(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::TooManyLinks
+}
+
+/// Check that a file still has the size and modification time captured when it was hashed.
+pub(crate) fn is_unchanged(file: &FileEntry, metadata: &fs::Metadata) -> bool {
+    metadata.len() == file.size && metadata.modified().ok() == file.modified
+}
+
 /// Replace duplicate files with hardlinks to the original.
 ///
 /// For each group:
 /// 1. Select one file as the "original". (shortest path)
 /// 2. For each duplicate: remove it and create a hardlink to original
 ///
+/// Files whose size or modification time changed since they were hashed are skipped,
+/// if the original changed the whole group is skipped.
+///
+/// If the original reaches the filesystem's hardlink limit, the next duplicate is kept as
+/// a new original and the remaining duplicates are linked to it instead.
+///
+/// If the original is immutable, `on_protected_keeper` decides whether another file is
+/// kept instead or the group is left untouched.
+///
+/// With `per_group_atomic`, a failure on any file of a group undoes the links already
+/// created for that group so each group is either fully processed or left untouched.
+///
+/// Groups of empty files (unless `include_empty` is set) and groups reclaiming less than
+/// `min_saving` bytes are left untouched.
+/// Groups are processed in parallel, files within a group are processed in order.
+///
 /// If `dry_run` is true, only prints what would happen without modifying files.
-pub fn hardlink_duplicates(
+pub fn hardlink_duplicates(groups: &[DuplicateGroup], options: &ActionOptions) -> ActionResult {
+    apply_to_groups(groups, options, |group, mounts, result| {
+        if let Some(original) = select_keeper(group, options, result) {
+            link_group_to(group, original, options, mounts, result);
+        }
+    })
+}
+
+/// Move one copy of each duplicate group into a content-addressed store and replace every
+/// file of the group with a hardlink to it.
+///
+/// Store objects are named by the group's content hash (`<store>/<first 2 hex chars>/<hash>`).
+/// The kept copy is linked into the store rather than moved, so its path is never missing.
+/// If the object already exists from an earlier run, all files are linked to it.
+///
+/// The store must be on the same filesystem as the files.
+pub fn consolidate_into_store(
     groups: &[DuplicateGroup],
-    dry_run: bool,
-    print_verbose_logs: bool,
+    store: &Path,
+    options: &ActionOptions,
 ) -> ActionResult {
-    let mut result = ActionResult::default();
-
-    for group in groups {
-        if group.files.len() < 2 {
-            continue;
-        }
+    apply_to_groups(groups, options, |group, mounts, result| {
+        store_group(group, store, options, mounts, result);
+    })
+}
 
+/// Replace duplicate files with small stub files pointing to the kept copy.
+///
+/// For each group the original (shortest path) is kept. Every other file is removed and a
+/// stub named `<file>.<extension>` is written in its place, see [`stub_contents`].
+///
+/// Changed files are skipped as when hardlinking, and with `per_group_atomic` a failure
+/// restores the group's files and removes its stubs.
+pub fn replace_with_stubs(
+    groups: &[DuplicateGroup],
+    extension: &str,
+    options: &ActionOptions,
+) -> ActionResult {
+    let extension = extension.trim_start_matches('.');
+    apply_to_groups(groups, options, |group, mounts, result| {
         let original = select_original(&group.files);
+        stub_group(group, original, extension, options, mounts, result);
+    })
+}
 
-        for path in &group.files {
-            if path == original {
-                continue;
+/// Run an action on every group with at least two files.
+///
+/// Groups of empty files are left untouched unless `include_empty` is set, as are groups
+/// reclaiming less than `min_saving` bytes.
+///
+/// Groups are processed in parallel, files within a group are processed in order.
+/// Filesystems found not to support the action are shared between all groups.
+///
+/// Once the run is interrupted, groups already being changed are finished and the
+/// remaining ones are left untouched.
+///
+/// Archive members (`--scan-archives`) can't be replaced, the action only applies to the
+/// loose files of their groups.
+fn apply_to_groups<F>(groups: &[DuplicateGroup], options: &ActionOptions, action: F) -> ActionResult
+where
+    F: Fn(&DuplicateGroup, &MountCapabilities, &mut ActionResult) + Sync,
+{
+    let mounts = MountCapabilities::default();
+    groups
+        .par_iter()
+        .map(|group| {
+            let mut result = ActionResult::default();
+            let loose = without_archive_members(group);
+            result.archive_members += group.files.len() - loose.files.len();
+            let group = loose.as_ref();
+
+            if group.files.len() < 2 {
+                return result;
+            }
+            if interrupt::requested() {
+                result.interrupted_groups += 1;
+            } else if group.size == 0 && !options.include_empty {
+                result.empty_groups_skipped += 1;
+            } else if options
+                .min_saving
+                .is_some_and(|min| group.wasted_bytes() < min)
+            {
+                result.groups_below_min_saving += 1;
+            } else {
+                action(group, &mounts, &mut result);
             }
+            result
+        })
+        .reduce(ActionResult::default, ActionResult::merge)
+}
 
-            use std::os::unix::fs::MetadataExt;
-            let meta_path = match fs::metadata(path) {
-                Ok(m) => m,
+/// The group without its archive members (`--scan-archives`), which are never changed
+pub(crate) fn without_archive_members(group: &DuplicateGroup) -> Cow<'_, DuplicateGroup> {
+    if group.files.iter().all(|f| f.archived.is_none()) {
+        return Cow::Borrowed(group);
+    }
+    let loose = group
+        .files
+        .iter()
+        .filter(|f| f.archived.is_none())
+        .cloned()
+        .collect();
+    Cow::Owned(DuplicateGroup::new(group.hash, group.size, loose))
+}
+
+/// Path of the store object for the given content hash
+fn store_object_path(store: &Path, hash: &Digest) -> PathBuf {
+    let hex = hash.to_hex();
+    store.join(&hex[..2]).join(hex.as_str())
+}
+
+/// Link a group's kept copy into the store (unless it is already there) and link all
+/// files of the group to the store object.
+fn store_group(
+    group: &DuplicateGroup,
+    store: &Path,
+    options: &ActionOptions,
+    mounts: &MountCapabilities,
+    result: &mut ActionResult,
+) {
+    use std::os::unix::fs::MetadataExt;
+
+    let object_path = store_object_path(store, &group.hash);
+
+    let meta_object = match fs::metadata(&object_path) {
+        Ok(m) => m,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let Some(original) = select_keeper(group, options, result) else {
+                return;
+            };
+
+            if options.verbose {
+                println!(
+                    "{} {} -> {}",
+                    if options.dry_run {
+                        "[dry-run]".yellow()
+                    } else {
+                        "[stored]".green()
+                    },
+                    original.path.display(),
+                    object_path.display()
+                );
+            }
+
+            // Nothing is stored in a dry run, preview linking to the copy that would be stored
+            if options.dry_run {
+                link_group_to(group, original, options, mounts, result);
+                return;
+            }
+
+            match fs::metadata(&original.path) {
+                Ok(m) if !is_unchanged(original, &m) => {
+                    result.changed.push(original.path.clone());
+                    return;
+                }
+                Ok(_) => {}
                 Err(e) => {
-                    result.errors.push((path.clone(), e.to_string()));
-                    continue;
+                    result.errors.push((original.path.clone(), e.to_string()));
+                    return;
                 }
-            };
-            let meta_original = match fs::metadata(original) {
+            }
+
+            let stored = object_path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::hard_link(&original.path, &object_path))
+                .and_then(|_| fs::metadata(&object_path));
+
+            match stored {
                 Ok(m) => m,
                 Err(e) => {
-                    result.errors.push((original.clone(), e.to_string()));
-                    continue;
+                    result.errors.push((object_path, e.to_string()));
+                    return;
                 }
-            };
+            }
+        }
+        Err(e) => {
+            result.errors.push((object_path, e.to_string()));
+            return;
+        }
+    };
+
+    if meta_object.len() != group.size {
+        result.errors.push((
+            object_path,
+            format!(
+                "store object is {} bytes, expected {}",
+                meta_object.len(),
+                group.size
+            ),
+        ));
+        return;
+    }
+
+    let object = FileEntry {
+        path: object_path,
+        size: meta_object.len(),
+        allocated: meta_object.blocks() * 512,
+        modified: meta_object.modified().ok(),
+        dev: meta_object.dev(),
+        ino: meta_object.ino(),
+        archived: None,
+        partial: None,
+        reference: false,
+    };
+
+    link_group_to(group, &object, options, mounts, result);
+}
+
+/// Replace all files of a group with hardlinks to `original`, which may or may not be
+/// one of the group's files.
+fn link_group_to(
+    group: &DuplicateGroup,
+    original: &FileEntry,
+    options: &ActionOptions,
+    mounts: &MountCapabilities,
+    result: &mut ActionResult,
+) {
+    use std::os::unix::fs::MetadataExt;
+
+    let meta_original = match fs::metadata(&original.path) {
+        Ok(m) => m,
+        Err(e) => {
+            result.errors.push((original.path.clone(), e.to_string()));
+            return;
+        }
+    };
+
+    if !is_unchanged(original, &meta_original) {
+        if options.verbose {
+            println!(
+                "{} {} changed since it was scanned",
+                "[skipped]".blue(),
+                original.path.display()
+            );
+        }
+        result.changed.push(original.path.clone());
+        return;
+    }
+
+    // Inodes that are kept, a group is split across several when the hardlink limit is hit
+    let mut keeper = original;
+    let mut keepers = vec![(meta_original.dev(), meta_original.ino())];
+
+    let use_journal = options.per_group_atomic && !options.dry_run;
+    let mut journal = GroupJournal {
+        force: options.force,
+        ..Default::default()
+    };
+    let mut group_errors = Vec::new();
+    let mut files_linked = 0;
+
+    for file in &group.files {
+        let path = &file.path;
+        if path == &original.path {
+            continue;
+        }
+
+        let meta_path = match fs::metadata(path) {
+            Ok(m) => m,
+            Err(e) => {
+                group_errors.push((path.clone(), e.to_string()));
+                if use_journal {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        if keepers.contains(&(meta_path.dev(), meta_path.ino())) {
+            if options.verbose {
+                println!(
+                    "{} {} is already hardlinked to {}",
+                    "[skipped]".blue(),
+                    path.display(),
+                    keeper.path.display()
+                );
+            }
+            continue;
+        }
+
+        if !is_unchanged(file, &meta_path) {
+            if options.verbose {
+                println!(
+                    "{} {} changed since it was scanned",
+                    "[skipped]".blue(),
+                    path.display()
+                );
+            }
+            result.changed.push(path.clone());
+            continue;
+        }
+
+        // Hardlinks can't cross filesystems
+        let skip_reason = if meta_path.dev() != keepers[0].0 {
+            Some("not on the same filesystem as the kept copy".to_string())
+        } else {
+            mounts.unsupported(meta_path.dev())
+        };
+        if let Some(reason) = skip_reason {
+            if options.verbose {
+                println!("{} {}: {}", "[skipped]".blue(), path.display(), reason);
+            }
+            result.skip_on_mount(meta_path.dev(), path, reason);
+            continue;
+        }
+
+        if options.verbose {
+            println!(
+                "{} {} -> {}",
+                "[dry-run]".yellow(),
+                path.display(),
+                keeper.path.display()
+            );
+        }
+
+        if options.dry_run {
+            files_linked += 1;
+            continue;
+        }
+
+        let outcome = if use_journal {
+            with_write_access(path, options.force, || {
+                backup_and_replace(path, || replace_with_hardlink(path, &keeper.path))
+            })
+            .map(|backup| journal.backups.push((path.clone(), backup)))
+        } else {
+            with_write_access(path, options.force, || {
+                replace_with_hardlink(path, &keeper.path)
+            })
+        };
 
-            if meta_path.ino() == meta_original.ino() && meta_path.dev() == meta_original.dev() {
-                if print_verbose_logs {
+        match outcome {
+            Ok(()) => {
+                files_linked += 1;
+                if options.verbose {
                     println!(
-                        "{} {} is already hardlinked to {}",
-                        "[skipped]".blue(),
+                        "{} {} -> {}",
+                        "[linked]".green(),
                         path.display(),
-                        original.display()
+                        keeper.path.display()
                     );
                 }
+            }
+            Err(e) if is_link_limit_error(&e) => {
+                if options.verbose {
+                    println!(
+                        "{} {} reached the hardlink limit, keeping {} as a new original",
+                        "[split]".blue(),
+                        keeper.path.display(),
+                        path.display()
+                    );
+                }
+                keeper = file;
+                keepers.push((meta_path.dev(), meta_path.ino()));
+                result.link_limit_splits += 1;
+            }
+            Err(e) if let Some(reason) = mounts.learn(meta_path.dev(), &e) => {
+                result.skip_on_mount(meta_path.dev(), path, reason);
+            }
+            Err(e) => {
+                group_errors.push((
+                    path.clone(),
+                    protection::describe_error(path, &e, options.force),
+                ));
+                if use_journal {
+                    break;
+                }
+            }
+        }
+    }
+
+    if use_journal && !group_errors.is_empty() {
+        if options.verbose {
+            println!(
+                "{} {} changes to group of {}",
+                "[rolled back]".red(),
+                journal.backups.len(),
+                original.path.display()
+            );
+        }
+        group_errors.extend(journal.rollback());
+        result.groups_rolled_back += 1;
+        files_linked = 0;
+    } else {
+        group_errors.extend(journal.commit());
+    }
+
+    result.files_linked += files_linked;
+    result.bytes_saved += group.size * files_linked as u64;
+    result.errors.extend(group_errors);
+}
+
+/// Contents of a stub file replacing a duplicate of `original`.
+///
+/// Stubs are small text files with one `key: value` pair per line after a version header:
+///
+/// ```text
+/// dedup-stub 1
+/// hash: <hex hash of the content>
+/// algorithm: <algorithm of the hash (`--hash`)>
+/// size: <size in bytes>
+/// original: <absolute path of the kept copy>
+/// ```
+fn stub_contents(hash: &Digest, size: u64, original: &Path) -> String {
+    format!(
+        "dedup-stub 1\nhash: {}\nalgorithm: {}\nsize: {}\noriginal: {}\n",
+        hash.to_hex(),
+        hasher::algorithm().name(),
+        size,
+        original.display()
+    )
+}
+
+/// Replace all files of a group except `original` with stub files pointing to it.
+fn stub_group(
+    group: &DuplicateGroup,
+    original: &FileEntry,
+    extension: &str,
+    options: &ActionOptions,
+    mounts: &MountCapabilities,
+    result: &mut ActionResult,
+) {
+    use std::os::unix::fs::MetadataExt;
+
+    let meta_original = match fs::metadata(&original.path) {
+        Ok(m) => m,
+        Err(e) => {
+            result.errors.push((original.path.clone(), e.to_string()));
+            return;
+        }
+    };
+
+    if !is_unchanged(original, &meta_original) {
+        if options.verbose {
+            println!(
+                "{} {} changed since it was scanned",
+                "[skipped]".blue(),
+                original.path.display()
+            );
+        }
+        result.changed.push(original.path.clone());
+        return;
+    }
+
+    // Stubs must stay valid regardless of the directory dedup was run from
+    let original_path = fs::canonicalize(&original.path).unwrap_or_else(|_| original.path.clone());
+    let contents = stub_contents(&group.hash, group.size, &original_path);
+    let original_inode = (meta_original.dev(), meta_original.ino());
+
+    let use_journal = options.per_group_atomic && !options.dry_run;
+    let mut journal = GroupJournal {
+        force: options.force,
+        ..Default::default()
+    };
+    let mut group_errors = Vec::new();
+    let mut files_stubbed = 0;
+
+    for file in &group.files {
+        let path = &file.path;
+        if path == &original.path {
+            continue;
+        }
+
+        let meta_path = match fs::metadata(path) {
+            Ok(m) => m,
+            Err(e) => {
+                group_errors.push((path.clone(), e.to_string()));
+                if use_journal {
+                    break;
+                }
                 continue;
             }
+        };
+
+        // Removing another link to the original would not free any space
+        if (meta_path.dev(), meta_path.ino()) == original_inode {
+            if options.verbose {
+                println!(
+                    "{} {} is already hardlinked to {}",
+                    "[skipped]".blue(),
+                    path.display(),
+                    original.path.display()
+                );
+            }
+            continue;
+        }
 
-            if print_verbose_logs {
+        if !is_unchanged(file, &meta_path) {
+            if options.verbose {
+                println!(
+                    "{} {} changed since it was scanned",
+                    "[skipped]".blue(),
+                    path.display()
+                );
+            }
+            result.changed.push(path.clone());
+            continue;
+        }
+
+        if let Some(reason) = mounts.unsupported(meta_path.dev()) {
+            if options.verbose {
+                println!("{} {}: {}", "[skipped]".blue(), path.display(), reason);
+            }
+            result.skip_on_mount(meta_path.dev(), path, reason);
+            continue;
+        }
+
+        let stub_path = sibling_path(path, extension);
+
+        if options.dry_run {
+            if options.verbose {
                 println!(
                     "{} {} -> {}",
                     "[dry-run]".yellow(),
                     path.display(),
-                    original.display()
+                    stub_path.display()
                 );
             }
-            result.files_linked += 1;
-            result.bytes_saved += group.size;
-
-            if !dry_run {
-                // TODO: better handling of files on different filesystems when hardlinking
-                match replace_with_hardlink(path, original) {
-                    Ok(()) => {
-                        if print_verbose_logs {
-                            println!(
-                                "{} {} -> {}",
-                                "[linked]".green(),
-                                path.display(),
-                                original.display()
-                            );
-                        }
-                    }
-                    Err(e) => {
-                        result.errors.push((path.clone(), e.to_string()));
-                    }
+            files_stubbed += 1;
+            continue;
+        }
+
+        let outcome = if use_journal {
+            with_write_access(path, options.force, || {
+                backup_and_replace(path, || replace_with_stub(path, &stub_path, &contents))
+            })
+            .map(|backup| {
+                journal.backups.push((path.clone(), backup));
+                journal.created.push(stub_path.clone());
+            })
+        } else {
+            with_write_access(path, options.force, || {
+                replace_with_stub(path, &stub_path, &contents)
+            })
+        };
+
+        match outcome {
+            Ok(()) => {
+                files_stubbed += 1;
+                if options.verbose {
+                    println!(
+                        "{} {} -> {}",
+                        "[stubbed]".green(),
+                        path.display(),
+                        stub_path.display()
+                    );
+                }
+            }
+            Err(e) if let Some(reason) = mounts.learn(meta_path.dev(), &e) => {
+                result.skip_on_mount(meta_path.dev(), path, reason);
+            }
+            Err(e) => {
+                group_errors.push((
+                    path.clone(),
+                    protection::describe_error(path, &e, options.force),
+                ));
+                if use_journal {
+                    break;
                 }
             }
         }
     }
 
+    if use_journal && !group_errors.is_empty() {
+        if options.verbose {
+            println!(
+                "{} {} changes to group of {}",
+                "[rolled back]".red(),
+                journal.backups.len(),
+                original.path.display()
+            );
+        }
+        group_errors.extend(journal.rollback());
+        result.groups_rolled_back += 1;
+        files_stubbed = 0;
+    } else {
+        group_errors.extend(journal.commit());
+    }
+
+    let saved_per_file = group.size.saturating_sub(contents.len() as u64);
+    result.files_stubbed += files_stubbed;
+    result.bytes_saved += saved_per_file * files_stubbed as u64;
+    result.errors.extend(group_errors);
+}
+
+/// Build a sibling path of `path` by appending `suffix` to its extension.
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    path.with_extension(format!(
+        "{}.{}",
+        path.extension().unwrap_or_default().to_string_lossy(),
+        suffix
+    ))
+}
+
+/// Remove a leftover file from a previous interrupted run, if there is one.
+fn remove_leftover(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Replace a file with a hardlink to another file.
+fn replace_with_hardlink(path: &Path, original: &Path) -> io::Result<()> {
+    // Create hardlink with temporary name in the same directory,
+    // then rename to avoid data loss if interrupted in between.
+    let temp_path = sibling_path(path, "dedup_tmp");
+
+    // Hardlinking will fail if temp_path already exists from a previous interrupted run.
+    remove_leftover(&temp_path)?;
+
+    fs::hard_link(original, &temp_path)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// Run an operation replacing or removing `path`.
+///
+/// With `force`, write protection of the path and its parent directory is lifted for the
+/// duration of the operation and put back afterwards.
+fn with_write_access<T, F>(path: &Path, force: bool, op: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T>,
+{
+    if !force {
+        return op();
+    }
+
+    let unprotected = protection::unprotect(path)?;
+    let result = op();
+    if let Err(e) = unprotected.restore() {
+        util::warn(format_args!(
+            "could not restore write protection of '{}': {}",
+            path.display(),
+            e
+        ));
+    }
     result
 }
 
-/// Replace a file with a hardlink to another file.
-fn replace_with_hardlink(path: &PathBuf, original: &PathBuf) -> io::Result<()> {
-    // Create hardlink with temporary name in the same directory,
-    // then rename to avoid data loss if interrupted in between.
-    let temp_path = path.with_extension(format!(
-        "{}.dedup_tmp",
-        path.extension().unwrap_or_default().to_string_lossy()
-    ));
+/// Write a stub file next to a duplicate, then remove the duplicate.
+///
+/// An existing file at the stub path is never overwritten.
+fn replace_with_stub(path: &Path, stub_path: &Path, contents: &str) -> io::Result<()> {
+    use std::io::Write;
+
+    let written = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(stub_path)
+        .and_then(|mut stub| {
+            stub.write_all(contents.as_bytes())?;
+            stub.sync_all()
+        });
+    match written {
+        // Don't remove a file that was already there
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(e),
+        Err(e) => {
+            let _ = fs::remove_file(stub_path);
+            return Err(e);
+        }
+        Ok(()) => {}
+    }
+
+    if let Err(e) = fs::remove_file(path) {
+        let _ = fs::remove_file(stub_path);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Replace a file with `replace`, keeping a hardlink to the replaced inode so the change
+/// can be rolled back. Returns the path of that backup link.
+fn backup_and_replace<F>(path: &Path, replace: F) -> io::Result<PathBuf>
+where
+    F: FnOnce() -> io::Result<()>,
+{
+    let backup_path = sibling_path(path, "dedup_bak");
+    remove_leftover(&backup_path)?;
+
+    fs::hard_link(path, &backup_path)?;
+    if let Err(e) = replace() {
+        let _ = fs::remove_file(&backup_path);
+        return Err(e);
+    }
+    Ok(backup_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::hash_bytes;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    fn create_file(dir: &std::path::Path, name: &str, content: &[u8]) -> PathBuf {
+        let path = dir.join(name);
+        let mut file = fs::File::create(&path).unwrap();
+        file.write_all(content).unwrap();
+        path
+    }
+
+    /// Build a file entry from the current on-disk state, as the hashing stage would
+    fn file_entry(path: &std::path::Path) -> FileEntry {
+        let metadata = fs::metadata(path).unwrap();
+        FileEntry {
+            path: path.to_path_buf(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+            ..Default::default()
+        }
+    }
+
+    /// Build a group from files as the hashing stage would
+    fn group(files: Vec<FileEntry>) -> DuplicateGroup {
+        let hash = fs::read(&files[0].path)
+            .map(|content| hash_bytes(&content))
+            .unwrap_or_else(|_| hash_bytes(b""));
+        DuplicateGroup::new(hash, files[0].size, files)
+    }
+
+    fn dry_run() -> ActionOptions {
+        ActionOptions {
+            dry_run: true,
+            ..Default::default()
+        }
+    }
+
+    fn entries(paths: &[&str]) -> Vec<FileEntry> {
+        paths
+            .iter()
+            .map(|p| FileEntry {
+                path: PathBuf::from(p),
+                size: 0,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_select_original_shortest_path() {
+        let files = entries(&["/a/b/c/file.txt", "/a/file.txt", "/a/b/file.txt"]);
+
+        let original = select_original(&files);
+        assert_eq!(original.path, PathBuf::from("/a/file.txt"));
+    }
+
+    #[test]
+    fn test_select_original_prefers_reference() {
+        let mut files = entries(&["/card/a.jpg", "/archive/2024/a.jpg"]);
+        files[1].reference = true;
+
+        assert_eq!(
+            select_original(&files).path,
+            PathBuf::from("/archive/2024/a.jpg")
+        );
+
+        // A protected reference file is never swapped for a copy outside the reference
+        let mut result = ActionResult::default();
+        let options = protected_keeper(ProtectedKeeper::NextBest);
+        assert!(choose_keeper(&files, &options, &mut result, |_| true).is_none());
+        assert!(
+            choose_keeper(&files, &options, &mut result, |p| p.starts_with("/archive")).is_none()
+        );
+        assert_eq!(result.protected_keeper_groups, 2);
+    }
+
+    #[test]
+    fn test_select_original_single_file() {
+        let files = entries(&["/only/file.txt"]);
+        let original = select_original(&files);
+        assert_eq!(original.path, PathBuf::from("/only/file.txt"));
+    }
+
+    fn protected_keeper(policy: ProtectedKeeper) -> ActionOptions {
+        ActionOptions {
+            on_protected_keeper: policy,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_choose_keeper_unprotected() {
+        let files = entries(&["/a/long/file.txt", "/a/file.txt"]);
+        let mut result = ActionResult::default();
+
+        let keeper = choose_keeper(
+            &files,
+            &protected_keeper(ProtectedKeeper::Fail),
+            &mut result,
+            |_| false,
+        );
+
+        assert_eq!(keeper.unwrap().path, PathBuf::from("/a/file.txt"));
+        assert!(result.errors.is_empty());
+    }
+
+    #[test]
+    fn test_choose_keeper_next_best() {
+        let files = entries(&["/a/longest/file.txt", "/a/file.txt", "/a/b/file.txt"]);
+        let mut result = ActionResult::default();
+        let options = protected_keeper(ProtectedKeeper::NextBest);
+
+        let keeper = choose_keeper(&files, &options, &mut result, |p| {
+            p == Path::new("/a/file.txt")
+        });
+        assert_eq!(keeper.unwrap().path, PathBuf::from("/a/b/file.txt"));
+        assert_eq!(result.protected_keeper_groups, 0);
+
+        let keeper = choose_keeper(&files, &options, &mut result, |_| true);
+        assert!(keeper.is_none());
+        assert_eq!(result.protected_keeper_groups, 1);
+    }
+
+    #[test]
+    fn test_choose_keeper_skip_and_fail() {
+        let files = entries(&["/a/b/file.txt", "/a/file.txt"]);
+        let is_protected = |p: &Path| p == Path::new("/a/file.txt");
+
+        let mut result = ActionResult::default();
+        let options = protected_keeper(ProtectedKeeper::Skip);
+        assert!(choose_keeper(&files, &options, &mut result, is_protected).is_none());
+        assert_eq!(result.protected_keeper_groups, 1);
+        assert!(result.errors.is_empty());
+
+        let mut result = ActionResult::default();
+        let options = protected_keeper(ProtectedKeeper::Fail);
+        assert!(choose_keeper(&files, &options, &mut result, is_protected).is_none());
+        assert_eq!(result.protected_keeper_groups, 0);
+        assert_eq!(result.errors[0].0, PathBuf::from("/a/file.txt"));
+    }
+
+    #[test]
+    fn test_choose_kept_file_matches_actions() {
+        let files = entries(&["/a/longest/file.txt", "/a/file.txt", "/a/b/file.txt"]);
+        let is_protected = |p: &Path| p == Path::new("/a/file.txt");
+
+        let kept = choose_kept_file(&files, ProtectedKeeper::NextBest, is_protected);
+        assert_eq!(kept.path, PathBuf::from("/a/b/file.txt"));
+        // The actions leave the group untouched, so the preferred file stays
+        let kept = choose_kept_file(&files, ProtectedKeeper::Skip, is_protected);
+        assert_eq!(kept.path, PathBuf::from("/a/file.txt"));
+        let kept = choose_kept_file(&files, ProtectedKeeper::NextBest, |_| false);
+        assert_eq!(kept.path, PathBuf::from("/a/file.txt"));
+    }
 
-    // Hardlinking will fail if temp_path already exists from a previous interrupted run.
-    match fs::remove_file(&temp_path) {
-        Ok(_) => (),
-        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
-        Err(e) => return Err(e),
-    };
+    #[test]
+    fn test_link_limit_error_detection() {
+        assert!(is_link_limit_error(&io::Error::from(
+            io::ErrorKind::TooManyLinks
+        )));
+        assert!(!is_link_limit_error(&io::Error::from(
+            io::ErrorKind::PermissionDenied
+        )));
+    }
 
-    fs::hard_link(original, &temp_path)?;
-    fs::rename(&temp_path, path)?;
-    Ok(())
-}
+    #[test]
+    fn test_mount_capabilities_learn_filesystem_errors() {
+        let mounts = MountCapabilities::default();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Write;
-    use tempfile::TempDir;
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert_eq!(mounts.learn(1, &denied), None);
+        assert_eq!(mounts.unsupported(1), None);
 
-    fn create_file(dir: &std::path::Path, name: &str, content: &[u8]) -> PathBuf {
-        let path = dir.join(name);
-        let mut file = fs::File::create(&path).unwrap();
-        file.write_all(content).unwrap();
-        path
+        let read_only = io::Error::from(io::ErrorKind::ReadOnlyFilesystem);
+        assert!(mounts.learn(1, &read_only).is_some());
+        assert_eq!(mounts.unsupported(1), Some(read_only.to_string()));
+        assert_eq!(mounts.unsupported(2), None);
     }
 
     #[test]
-    fn test_select_original_shortest_path() {
-        let files = vec![
-            PathBuf::from("/a/b/c/file.txt"),
-            PathBuf::from("/a/file.txt"),
-            PathBuf::from("/a/b/file.txt"),
-        ];
+    fn test_hardlink_skips_files_on_unsupported_mount() {
+        use std::os::unix::fs::MetadataExt;
 
-        let original = select_original(&files);
-        assert_eq!(original, &PathBuf::from("/a/file.txt"));
+        let temp = TempDir::new().unwrap();
+        let path1 = create_file(temp.path(), "a.txt", b"content");
+        let path2 = create_file(temp.path(), "b1.txt", b"content");
+        let path3 = create_file(temp.path(), "b2.txt", b"content");
+        let group = group(vec![
+            file_entry(&path1),
+            file_entry(&path2),
+            file_entry(&path3),
+        ]);
+
+        let dev = fs::metadata(&path1).unwrap().dev();
+        let mounts = MountCapabilities::default();
+        mounts.learn(dev, &io::Error::from(io::ErrorKind::ReadOnlyFilesystem));
+
+        let mut result = ActionResult::default();
+        let original = select_original(&group.files);
+        link_group_to(
+            &group,
+            original,
+            &ActionOptions::default(),
+            &mounts,
+            &mut result,
+        );
+
+        assert_eq!(result.files_linked, 0);
+        assert!(result.errors.is_empty());
+        assert_eq!(result.mount_skips[&dev].files, 2);
+        assert_ne!(
+            fs::metadata(&path1).unwrap().ino(),
+            fs::metadata(&path2).unwrap().ino()
+        );
     }
 
     #[test]
-    fn test_select_original_single_file() {
-        let files = vec![PathBuf::from("/only/file.txt")];
-        let original = select_original(&files);
-        assert_eq!(original, &PathBuf::from("/only/file.txt"));
+    fn test_merge_sums_mount_skips() {
+        let mut a = ActionResult::default();
+        a.skip_on_mount(1, Path::new("/a"), "read-only".to_string());
+        let mut b = ActionResult::default();
+        b.skip_on_mount(1, Path::new("/b"), "read-only".to_string());
+        b.skip_on_mount(2, Path::new("/c"), "read-only".to_string());
+
+        let merged = a.merge(b);
+
+        assert_eq!(merged.mount_skips[&1].files, 2);
+        assert_eq!(merged.mount_skips[&1].example, PathBuf::from("/a"));
+        assert_eq!(merged.mount_skips[&2].files, 1);
     }
 
     #[test]
@@ -177,12 +1271,9 @@ mod tests {
         let path1 = create_file(temp.path(), "file1.txt", content);
         let path2 = create_file(temp.path(), "file2.txt", content);
 
-        let groups = vec![DuplicateGroup {
-            size: content.len() as u64,
-            files: vec![path1.clone(), path2.clone()],
-        }];
+        let groups = vec![group(vec![file_entry(&path1), file_entry(&path2)])];
 
-        let result = hardlink_duplicates(&groups, true, false);
+        let result = hardlink_duplicates(&groups, &dry_run());
 
         assert_eq!(result.files_linked, 1);
         assert_eq!(result.bytes_saved, content.len() as u64);
@@ -202,12 +1293,9 @@ mod tests {
         let path1 = create_file(temp.path(), "file1.txt", content);
         let path2 = create_file(temp.path(), "file2.txt", content);
 
-        let groups = vec![DuplicateGroup {
-            size: content.len() as u64,
-            files: vec![path1.clone(), path2.clone()],
-        }];
+        let groups = vec![group(vec![file_entry(&path1), file_entry(&path2)])];
 
-        let result = hardlink_duplicates(&groups, false, false);
+        let result = hardlink_duplicates(&groups, &ActionOptions::default());
 
         assert_eq!(result.files_linked, 1);
         assert_eq!(result.bytes_saved, content.len() as u64);
@@ -235,12 +1323,13 @@ mod tests {
         let path2 = create_file(temp.path(), "b.txt", content);
         let path3 = create_file(temp.path(), "c.txt", content);
 
-        let groups = vec![DuplicateGroup {
-            size: content.len() as u64,
-            files: vec![path1.clone(), path2.clone(), path3.clone()],
-        }];
+        let groups = vec![group(vec![
+            file_entry(&path1),
+            file_entry(&path2),
+            file_entry(&path3),
+        ])];
 
-        let result = hardlink_duplicates(&groups, false, false);
+        let result = hardlink_duplicates(&groups, &ActionOptions::default());
 
         assert_eq!(result.files_linked, 2); // 2 files linked to original
         assert_eq!(result.bytes_saved, (content.len() * 2) as u64);
@@ -270,12 +1359,9 @@ mod tests {
         let ino_before = fs::metadata(&path1).unwrap().ino();
         assert_eq!(ino_before, fs::metadata(&path2).unwrap().ino());
 
-        let groups = vec![DuplicateGroup {
-            size: content.len() as u64,
-            files: vec![path1.clone(), path2.clone()],
-        }];
+        let groups = vec![group(vec![file_entry(&path1), file_entry(&path2)])];
 
-        let result = hardlink_duplicates(&groups, false, false);
+        let result = hardlink_duplicates(&groups, &ActionOptions::default());
 
         assert_eq!(result.files_linked, 0);
         assert_eq!(result.bytes_saved, 0);
@@ -302,12 +1388,9 @@ mod tests {
         fs::write(&leftover_temp, b"leftover").unwrap();
         assert!(leftover_temp.exists());
 
-        let groups = vec![DuplicateGroup {
-            size: content.len() as u64,
-            files: vec![path1.clone(), path2.clone()],
-        }];
+        let groups = vec![group(vec![file_entry(&path1), file_entry(&path2)])];
 
-        let result = hardlink_duplicates(&groups, false, false);
+        let result = hardlink_duplicates(&groups, &ActionOptions::default());
 
         assert_eq!(result.files_linked, 1);
         assert!(result.errors.is_empty());
@@ -323,4 +1406,480 @@ mod tests {
         assert_eq!(content1, content2);
         assert_eq!(content1, content);
     }
+
+    #[test]
+    fn test_hardlink_skips_changed_duplicate() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp = TempDir::new().unwrap();
+        let content = b"duplicate content";
+
+        let path1 = create_file(temp.path(), "file1.txt", content);
+        let path2 = create_file(temp.path(), "file2.txt", content);
+
+        let groups = vec![group(vec![file_entry(&path1), file_entry(&path2)])];
+
+        // Modified after hashing
+        fs::write(&path2, b"new content that must not be lost").unwrap();
+
+        let result = hardlink_duplicates(&groups, &ActionOptions::default());
+
+        assert_eq!(result.files_linked, 0);
+        assert_eq!(result.changed, vec![path2.clone()]);
+        assert!(result.errors.is_empty());
+
+        assert_ne!(
+            fs::metadata(&path1).unwrap().ino(),
+            fs::metadata(&path2).unwrap().ino()
+        );
+        assert_eq!(
+            fs::read(&path2).unwrap(),
+            b"new content that must not be lost"
+        );
+    }
+
+    #[test]
+    fn test_hardlink_skips_group_when_original_changed() {
+        let temp = TempDir::new().unwrap();
+        let content = b"duplicate content";
+
+        let path1 = create_file(temp.path(), "a.txt", content);
+        let path2 = create_file(temp.path(), "bb.txt", content);
+        let path3 = create_file(temp.path(), "ccc.txt", content);
+
+        let groups = vec![group(vec![
+            file_entry(&path1),
+            file_entry(&path2),
+            file_entry(&path3),
+        ])];
+
+        // The original (shortest path) is modified after hashing
+        fs::write(&path1, b"changed").unwrap();
+
+        let result = hardlink_duplicates(&groups, &ActionOptions::default());
+
+        assert_eq!(result.files_linked, 0);
+        assert_eq!(result.changed, vec![path1]);
+        assert_eq!(fs::read(&path2).unwrap(), content);
+        assert_eq!(fs::read(&path3).unwrap(), content);
+    }
+
+    #[test]
+    fn test_per_group_atomic_rolls_back_on_failure() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp = TempDir::new().unwrap();
+        let content = b"duplicate content";
+
+        let path1 = create_file(temp.path(), "a.txt", content);
+        let path2 = create_file(temp.path(), "bb.txt", content);
+        let path3 = create_file(temp.path(), "ccc.txt", content);
+
+        let groups = vec![group(vec![
+            file_entry(&path1),
+            file_entry(&path2),
+            file_entry(&path3),
+        ])];
+
+        let ino2_before = fs::metadata(&path2).unwrap().ino();
+
+        // The third file fails after the second one was already linked
+        fs::remove_file(&path3).unwrap();
+
+        let options = ActionOptions {
+            per_group_atomic: true,
+            ..Default::default()
+        };
+        let result = hardlink_duplicates(&groups, &options);
+
+        assert_eq!(result.files_linked, 0);
+        assert_eq!(result.bytes_saved, 0);
+        assert_eq!(result.groups_rolled_back, 1);
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].0, path3);
+
+        // The already linked duplicate is restored to its own inode
+        assert_eq!(fs::metadata(&path2).unwrap().ino(), ino2_before);
+        assert_ne!(
+            fs::metadata(&path1).unwrap().ino(),
+            fs::metadata(&path2).unwrap().ino()
+        );
+        assert_eq!(fs::read(&path2).unwrap(), content);
+        assert!(!temp.path().join("bb.txt.dedup_bak").exists());
+    }
+
+    #[test]
+    fn test_without_per_group_atomic_keeps_partial_progress() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp = TempDir::new().unwrap();
+        let content = b"duplicate content";
+
+        let path1 = create_file(temp.path(), "a.txt", content);
+        let path2 = create_file(temp.path(), "bb.txt", content);
+        let path3 = create_file(temp.path(), "ccc.txt", content);
+
+        let groups = vec![group(vec![
+            file_entry(&path1),
+            file_entry(&path2),
+            file_entry(&path3),
+        ])];
+
+        fs::remove_file(&path3).unwrap();
+
+        let result = hardlink_duplicates(&groups, &ActionOptions::default());
+
+        assert_eq!(result.files_linked, 1);
+        assert_eq!(result.groups_rolled_back, 0);
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(
+            fs::metadata(&path1).unwrap().ino(),
+            fs::metadata(&path2).unwrap().ino()
+        );
+    }
+
+    #[test]
+    fn test_per_group_atomic_removes_backups_on_success() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp = TempDir::new().unwrap();
+        let content = b"duplicate content";
+
+        let path1 = create_file(temp.path(), "a.txt", content);
+        let path2 = create_file(temp.path(), "bb.txt", content);
+
+        let groups = vec![group(vec![file_entry(&path1), file_entry(&path2)])];
+
+        let options = ActionOptions {
+            per_group_atomic: true,
+            ..Default::default()
+        };
+        let result = hardlink_duplicates(&groups, &options);
+
+        assert_eq!(result.files_linked, 1);
+        assert!(result.errors.is_empty());
+        assert_eq!(
+            fs::metadata(&path1).unwrap().ino(),
+            fs::metadata(&path2).unwrap().ino()
+        );
+        assert!(!temp.path().join("bb.txt.dedup_bak").exists());
+    }
+
+    #[test]
+    fn test_hardlink_many_groups_in_parallel() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp = TempDir::new().unwrap();
+
+        let groups: Vec<DuplicateGroup> = (0..50)
+            .map(|i| {
+                let content = format!("content of group {}", i);
+                let a = create_file(temp.path(), &format!("a{}.txt", i), content.as_bytes());
+                let b = create_file(temp.path(), &format!("bb{}.txt", i), content.as_bytes());
+                let c = create_file(temp.path(), &format!("ccc{}.txt", i), content.as_bytes());
+                group(vec![file_entry(&a), file_entry(&b), file_entry(&c)])
+            })
+            .collect();
+
+        let result = hardlink_duplicates(&groups, &ActionOptions::default());
+
+        assert_eq!(result.files_linked, 100);
+        assert_eq!(
+            result.bytes_saved,
+            groups.iter().map(|g| g.size * 2).sum::<u64>()
+        );
+        assert!(result.errors.is_empty());
+
+        for group in &groups {
+            let ino = fs::metadata(&group.files[0].path).unwrap().ino();
+            for file in &group.files {
+                assert_eq!(fs::metadata(&file.path).unwrap().ino(), ino);
+            }
+        }
+    }
+
+    #[test]
+    fn test_min_saving_skips_small_groups() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp = TempDir::new().unwrap();
+        let small = b"small";
+        let large = b"a much larger duplicated content";
+
+        let small1 = create_file(temp.path(), "small1.txt", small);
+        let small2 = create_file(temp.path(), "small2.txt", small);
+        let large1 = create_file(temp.path(), "large1.txt", large);
+        let large2 = create_file(temp.path(), "large2.txt", large);
+
+        let groups = vec![
+            group(vec![file_entry(&small1), file_entry(&small2)]),
+            group(vec![file_entry(&large1), file_entry(&large2)]),
+        ];
+
+        let options = ActionOptions {
+            min_saving: Some(10),
+            ..Default::default()
+        };
+        let result = hardlink_duplicates(&groups, &options);
+
+        assert_eq!(result.files_linked, 1);
+        assert_eq!(result.bytes_saved, large.len() as u64);
+        assert_eq!(result.groups_below_min_saving, 1);
+
+        assert_ne!(
+            fs::metadata(&small1).unwrap().ino(),
+            fs::metadata(&small2).unwrap().ino()
+        );
+        assert_eq!(
+            fs::metadata(&large1).unwrap().ino(),
+            fs::metadata(&large2).unwrap().ino()
+        );
+    }
+
+    #[test]
+    fn test_empty_groups_skipped_by_default() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp = TempDir::new().unwrap();
+        let empty1 = create_file(temp.path(), "empty1", b"");
+        let empty2 = create_file(temp.path(), "empty2", b"");
+
+        let groups = vec![group(vec![file_entry(&empty1), file_entry(&empty2)])];
+
+        let result = hardlink_duplicates(&groups, &ActionOptions::default());
+
+        assert_eq!(result.files_linked, 0);
+        assert_eq!(result.empty_groups_skipped, 1);
+        assert_ne!(
+            fs::metadata(&empty1).unwrap().ino(),
+            fs::metadata(&empty2).unwrap().ino()
+        );
+
+        let options = ActionOptions {
+            include_empty: true,
+            ..Default::default()
+        };
+        let result = hardlink_duplicates(&groups, &options);
+
+        assert_eq!(result.files_linked, 1);
+        assert_eq!(result.empty_groups_skipped, 0);
+        assert_eq!(
+            fs::metadata(&empty1).unwrap().ino(),
+            fs::metadata(&empty2).unwrap().ino()
+        );
+    }
+
+    #[test]
+    fn test_consolidate_into_store() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp = TempDir::new().unwrap();
+        let store = temp.path().join("store");
+        let content = b"duplicate content";
+
+        let path1 = create_file(temp.path(), "file1.txt", content);
+        let path2 = create_file(temp.path(), "file2.txt", content);
+
+        let groups = vec![group(vec![file_entry(&path1), file_entry(&path2)])];
+
+        let result = consolidate_into_store(&groups, &store, &ActionOptions::default());
+
+        assert_eq!(result.files_linked, 1);
+        assert_eq!(result.bytes_saved, content.len() as u64);
+        assert!(result.errors.is_empty());
+
+        let object = store_object_path(&store, &hash_bytes(content));
+        assert_eq!(fs::read(&object).unwrap(), content);
+
+        let ino = fs::metadata(&object).unwrap().ino();
+        assert_eq!(fs::metadata(&path1).unwrap().ino(), ino);
+        assert_eq!(fs::metadata(&path2).unwrap().ino(), ino);
+    }
+
+    #[test]
+    fn test_consolidate_into_existing_store_object() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp = TempDir::new().unwrap();
+        let store = temp.path().join("store");
+        let content = b"duplicate content";
+
+        // Object stored by an earlier run
+        let object = store_object_path(&store, &hash_bytes(content));
+        fs::create_dir_all(object.parent().unwrap()).unwrap();
+        fs::write(&object, content).unwrap();
+
+        let path1 = create_file(temp.path(), "file1.txt", content);
+        let path2 = create_file(temp.path(), "file2.txt", content);
+
+        let groups = vec![group(vec![file_entry(&path1), file_entry(&path2)])];
+
+        let result = consolidate_into_store(&groups, &store, &ActionOptions::default());
+
+        assert_eq!(result.files_linked, 2);
+        assert!(result.errors.is_empty());
+
+        let ino = fs::metadata(&object).unwrap().ino();
+        assert_eq!(fs::metadata(&path1).unwrap().ino(), ino);
+        assert_eq!(fs::metadata(&path2).unwrap().ino(), ino);
+    }
+
+    #[test]
+    fn test_consolidate_into_store_dry_run() {
+        let temp = TempDir::new().unwrap();
+        let store = temp.path().join("store");
+        let content = b"duplicate content";
+
+        let path1 = create_file(temp.path(), "file1.txt", content);
+        let path2 = create_file(temp.path(), "file2.txt", content);
+
+        let groups = vec![group(vec![file_entry(&path1), file_entry(&path2)])];
+
+        let result = consolidate_into_store(&groups, &store, &dry_run());
+
+        assert_eq!(result.files_linked, 1);
+        assert!(!store.exists());
+    }
+
+    #[test]
+    fn test_replace_with_stubs() {
+        let temp = TempDir::new().unwrap();
+        let content = vec![b'x'; 4096];
+
+        let path1 = create_file(temp.path(), "a.bin", &content);
+        let path2 = create_file(temp.path(), "copy.bin", &content);
+
+        let groups = vec![group(vec![file_entry(&path1), file_entry(&path2)])];
+
+        let result = replace_with_stubs(&groups, ".dedup-stub", &ActionOptions::default());
+
+        assert_eq!(result.files_stubbed, 1);
+        assert_eq!(result.files_linked, 0);
+        assert!(result.errors.is_empty());
+        assert!(result.bytes_saved > 0 && result.bytes_saved < content.len() as u64);
+
+        assert_eq!(fs::read(&path1).unwrap(), content);
+        assert!(!path2.exists());
+
+        let stub = fs::read_to_string(temp.path().join("copy.bin.dedup-stub")).unwrap();
+        assert_eq!(
+            stub,
+            stub_contents(
+                &hash_bytes(&content),
+                content.len() as u64,
+                &fs::canonicalize(&path1).unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_replace_with_stubs_dry_run() {
+        let temp = TempDir::new().unwrap();
+        let content = b"duplicate content";
+
+        let path1 = create_file(temp.path(), "a.txt", content);
+        let path2 = create_file(temp.path(), "bb.txt", content);
+
+        let groups = vec![group(vec![file_entry(&path1), file_entry(&path2)])];
+
+        let result = replace_with_stubs(&groups, "dedup-stub", &dry_run());
+
+        assert_eq!(result.files_stubbed, 1);
+        assert!(path2.exists());
+        assert!(!temp.path().join("bb.txt.dedup-stub").exists());
+    }
+
+    #[test]
+    fn test_replace_with_stubs_keeps_existing_stub_path() {
+        let temp = TempDir::new().unwrap();
+        let content = b"duplicate content";
+
+        let path1 = create_file(temp.path(), "a.txt", content);
+        let path2 = create_file(temp.path(), "bb.txt", content);
+        let unrelated = create_file(temp.path(), "bb.txt.dedup-stub", b"not a stub");
+
+        let groups = vec![group(vec![file_entry(&path1), file_entry(&path2)])];
+
+        let result = replace_with_stubs(&groups, "dedup-stub", &ActionOptions::default());
+
+        assert_eq!(result.files_stubbed, 0);
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(fs::read(&path2).unwrap(), content);
+        assert_eq!(fs::read(&unrelated).unwrap(), b"not a stub");
+    }
+
+    #[test]
+    fn test_replace_with_stubs_per_group_atomic_rolls_back() {
+        let temp = TempDir::new().unwrap();
+        let content = b"duplicate content";
+
+        let path1 = create_file(temp.path(), "a.txt", content);
+        let path2 = create_file(temp.path(), "bb.txt", content);
+        let path3 = create_file(temp.path(), "ccc.txt", content);
+
+        let groups = vec![group(vec![
+            file_entry(&path1),
+            file_entry(&path2),
+            file_entry(&path3),
+        ])];
+
+        // The third file fails after the second one was already replaced
+        fs::remove_file(&path3).unwrap();
+
+        let options = ActionOptions {
+            per_group_atomic: true,
+            ..Default::default()
+        };
+        let result = replace_with_stubs(&groups, "dedup-stub", &options);
+
+        assert_eq!(result.files_stubbed, 0);
+        assert_eq!(result.groups_rolled_back, 1);
+        assert_eq!(fs::read(&path2).unwrap(), content);
+        assert!(!temp.path().join("bb.txt.dedup-stub").exists());
+        assert!(!temp.path().join("bb.txt.dedup_bak").exists());
+    }
+
+    #[test]
+    fn test_hardlink_force_restores_read_only_parent() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let temp = TempDir::new().unwrap();
+        let content = b"duplicate content";
+
+        let path1 = create_file(temp.path(), "a.txt", content);
+        let dir = temp.path().join("ro");
+        fs::create_dir(&dir).unwrap();
+        let path2 = create_file(&dir, "copy.txt", content);
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o555)).unwrap();
+
+        let groups = vec![group(vec![file_entry(&path1), file_entry(&path2)])];
+        let options = ActionOptions {
+            force: true,
+            ..Default::default()
+        };
+        let result = hardlink_duplicates(&groups, &options);
+
+        assert_eq!(result.files_linked, 1);
+        assert!(result.errors.is_empty());
+        assert_eq!(
+            fs::metadata(&path1).unwrap().ino(),
+            fs::metadata(&path2).unwrap().ino()
+        );
+        assert_eq!(
+            fs::metadata(&dir).unwrap().permissions().mode() & 0o777,
+            0o555
+        );
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_store_object_path() {
+        let hash = hash_bytes(b"content");
+        let hex = hash.to_hex();
+        assert_eq!(
+            store_object_path(Path::new("/store"), &hash),
+            PathBuf::from(format!("/store/{}/{}", &hex[..2], hex))
+        );
+    }
 }
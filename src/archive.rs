@@ -0,0 +1,442 @@
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use flate2::read::GzDecoder;
+use tar::EntryType;
+use zip::ZipArchive;
+
+use crate::hasher;
+use crate::scanner::{FileEntry, ScanError};
+
+/// Separator between the path of an archive and the name of a member in it
+pub const SEPARATOR: &str = "!";
+
+/// Archive formats whose members `--scan-archives` compares
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl Format {
+    /// The format of an archive by its file name (`.zip`, `.tar`, `.tar.gz` or `.tgz`),
+    /// `None` for other files
+    pub fn of(path: &Path) -> Option<Format> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".zip") {
+            Some(Format::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Format::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Format::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Members found in an archive
+#[derive(Debug, Default)]
+pub struct Members {
+    /// Regular files passing the filter, with the hashes of their contents
+    pub files: Vec<FileEntry>,
+    /// Members, or the archive itself, that could not be read
+    pub errors: Vec<ScanError>,
+}
+
+/// Read the regular files stored in `archive` and hash the contents of those `keep`
+/// accepts, the entries it is given have no hashes yet.
+///
+/// Members are named `<archive>!<name in the archive>`. They share the device and
+/// modification time of the archive and have no inode, as they take no space of their own
+/// they have no allocated size either. Directories, links and encrypted members are skipped,
+/// nested archives are not looked into. The checksums of zip members and of gzip streams are
+/// verified, members whose contents don't match them are never reported.
+pub fn members(archive: &FileEntry, format: Format, keep: impl Fn(&FileEntry) -> bool) -> Members {
+    let mut reader = MemberReader {
+        archive,
+        keep,
+        members: Members::default(),
+    };
+    let read = File::open(&archive.path).and_then(|file| match format {
+        Format::Zip => reader.read_zip(BufReader::new(file)),
+        Format::Tar => reader.read_tar(BufReader::new(file)),
+        Format::TarGz => reader.read_tar_gz(GzDecoder::new(BufReader::new(file))),
+    });
+
+    let mut members = reader.members;
+    if let Err(e) = read {
+        members.errors.push(ScanError {
+            path: archive.path.clone(),
+            error: e.to_string(),
+        });
+    }
+    members
+}
+
+/// Hash the `size` bytes of `contents`, which must end right after them: the final read
+/// past the end is also what makes the zip reader check the member's CRC-32.
+fn hash_contents(mut contents: impl Read, size: u64) -> io::Result<hasher::ContentHashes> {
+    let mut limited = (&mut contents).take(size);
+    let hashes = hasher::hash_stream(&mut limited, size)?;
+    if limited.limit() > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "member is shorter than its recorded size",
+        ));
+    }
+    if contents.read(&mut [0u8; 1])? > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "member is longer than its recorded size",
+        ));
+    }
+    Ok(hashes)
+}
+
+/// Collects the members of one archive
+struct MemberReader<'a, F> {
+    archive: &'a FileEntry,
+    keep: F,
+    members: Members,
+}
+
+impl<F: Fn(&FileEntry) -> bool> MemberReader<'_, F> {
+    /// The entry of the member `name` with `size` bytes if `keep` accepts it, `None` for
+    /// directories and filtered members
+    fn entry(&self, name: &[u8], size: u64) -> Option<FileEntry> {
+        let mut name = name;
+        while let Some(rest) = name.strip_prefix(b"./").or(name.strip_prefix(b"/")) {
+            name = rest;
+        }
+        if name.is_empty() || name.ends_with(b"/") {
+            return None;
+        }
+
+        let mut path = self.archive.path.as_os_str().to_owned();
+        path.push(SEPARATOR);
+        path.push(OsStr::from_bytes(name));
+        let entry = FileEntry {
+            path: PathBuf::from(path),
+            size,
+            allocated: 0,
+            modified: self.archive.modified,
+            dev: self.archive.dev,
+            ino: 0,
+            archived: None,
+            partial: None,
+            reference: false,
+        };
+        (self.keep)(&entry).then_some(entry)
+    }
+
+    /// Read the members of a tar archive (POSIX ustar, with GNU and pax long names). The
+    /// archive is a single stream, an error reading a member ends it.
+    fn read_tar(&mut self, input: impl Read) -> io::Result<()> {
+        let mut archive = tar::Archive::new(input);
+        for member in archive.entries()? {
+            let member = member?;
+            let kind = member.header().entry_type();
+            if !matches!(kind, EntryType::Regular | EntryType::Continuous) {
+                continue;
+            }
+            let size = member.size();
+            let Some(mut entry) = self.entry(&member.path_bytes(), size) else {
+                continue;
+            };
+            entry.archived = Some(Arc::new(hash_contents(member, size)?));
+            self.members.files.push(entry);
+        }
+        Ok(())
+    }
+
+    /// Read the members of a gzip-compressed tar archive. The gzip stream is read to its
+    /// end, past the end of the tar archive, so its CRC-32 and length are checked: if they
+    /// don't match, none of its members are reported.
+    fn read_tar_gz(&mut self, mut input: GzDecoder<impl Read>) -> io::Result<()> {
+        let checked = self
+            .read_tar(&mut input)
+            .and_then(|()| io::copy(&mut input, &mut io::sink()).map(|_| ()));
+        if checked.is_err() {
+            self.members.files.clear();
+        }
+        checked
+    }
+
+    /// Read the members of a zip archive, including zip64. Members that can't be read or
+    /// don't match their CRC-32 are recorded as errors, the others are still reported.
+    fn read_zip(&mut self, input: BufReader<File>) -> io::Result<()> {
+        let mut archive = ZipArchive::new(input)?;
+        for index in 0..archive.len() {
+            let (name, size) = {
+                let member = archive.by_index_raw(index)?;
+                // Symbolic links store their target as contents
+                if member.is_dir() || member.is_symlink() {
+                    continue;
+                }
+                (member.name_raw().to_vec(), member.size())
+            };
+            let Some(mut entry) = self.entry(&name, size) else {
+                continue;
+            };
+
+            let hashed = archive
+                .by_index(index)
+                .map_err(io::Error::from)
+                .and_then(|member| hash_contents(member, size));
+            match hashed {
+                Ok(hashes) => {
+                    entry.archived = Some(Arc::new(hashes));
+                    self.members.files.push(entry);
+                }
+                Err(e) => self.members.errors.push(ScanError {
+                    path: entry.path,
+                    error: e.to_string(),
+                }),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::hash_bytes;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// A tar header for a regular file, or another entry type
+    fn tar_header(name: &[u8], size: usize, kind: u8) -> Vec<u8> {
+        let mut header = vec![0u8; 512];
+        header[..name.len()].copy_from_slice(name);
+        header[100..107].copy_from_slice(b"0000644");
+        header[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+        header[136..147].copy_from_slice(b"00000000000");
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[148..156].fill(b' ');
+        let sum: u32 = header.iter().map(|&b| b as u32).sum();
+        header[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+        header
+    }
+
+    /// A tar archive of the given entries: name, contents and type
+    fn tar(entries: &[(&[u8], &[u8], u8)]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        for (name, contents, kind) in entries {
+            archive.extend(tar_header(name, contents.len(), *kind));
+            archive.extend_from_slice(contents);
+            archive.resize(archive.len().div_ceil(512) * 512, 0);
+        }
+        archive.extend([0; 1024]);
+        archive
+    }
+
+    /// A zip archive of the given files, stored without compression
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        let mut directory = Vec::new();
+        for (name, contents) in files {
+            let offset = archive.len() as u32;
+            let mut crc = flate2::Crc::new();
+            crc.update(contents);
+            let mut common = vec![0u8; 26];
+            common[0..2].copy_from_slice(&20u16.to_le_bytes());
+            common[10..14].copy_from_slice(&crc.sum().to_le_bytes());
+            common[14..18].copy_from_slice(&(contents.len() as u32).to_le_bytes());
+            common[18..22].copy_from_slice(&(contents.len() as u32).to_le_bytes());
+            common[22..24].copy_from_slice(&(name.len() as u16).to_le_bytes());
+
+            archive.extend_from_slice(b"PK\x03\x04");
+            archive.extend_from_slice(&common);
+            archive.extend_from_slice(name.as_bytes());
+            archive.extend_from_slice(contents);
+
+            directory.extend_from_slice(b"PK\x01\x02");
+            directory.extend_from_slice(&20u16.to_le_bytes());
+            directory.extend_from_slice(&common);
+            directory.extend_from_slice(&[0; 10]);
+            directory.extend_from_slice(&offset.to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+        }
+
+        let directory_offset = archive.len() as u32;
+        archive.extend_from_slice(&directory);
+        archive.extend_from_slice(b"PK\x05\x06\0\0\0\0");
+        archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        archive.extend_from_slice(&directory_offset.to_le_bytes());
+        archive.extend_from_slice(&[0; 2]);
+        archive
+    }
+
+    /// A gzip stream of `data`
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn read(dir: &Path, name: &str, contents: &[u8]) -> Members {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        let archive = crate::scanner::stat_file(&path).unwrap();
+        members(&archive, Format::of(&path).unwrap(), |_| true)
+    }
+
+    fn names(members: &Members) -> Vec<String> {
+        let mut names: Vec<String> = members
+            .files
+            .iter()
+            .map(|f| {
+                let path = f.path.to_string_lossy();
+                path[path.find(SEPARATOR).unwrap() + 1..].to_string()
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_format_by_name() {
+        assert_eq!(Format::of(Path::new("/a/backup.ZIP")), Some(Format::Zip));
+        assert_eq!(Format::of(Path::new("a.tar")), Some(Format::Tar));
+        assert_eq!(Format::of(Path::new("a.tar.gz")), Some(Format::TarGz));
+        assert_eq!(Format::of(Path::new("a.tgz")), Some(Format::TarGz));
+        assert_eq!(Format::of(Path::new("a.gz")), None);
+        assert_eq!(Format::of(Path::new("zip")), None);
+    }
+
+    #[test]
+    fn test_tar_members() {
+        let temp = TempDir::new().unwrap();
+        let long_name = format!("{}/photo.jpg", "d".repeat(120));
+        let archive = tar(&[
+            (b"./photos/", b"", b'5'),
+            (b"./photos/a.jpg", b"image data", b'0'),
+            (b"././@LongLink", long_name.as_bytes(), b'L'),
+            (b"truncated", b"more image data", b'0'),
+            (b"link", b"", b'2'),
+        ]);
+        let members = read(temp.path(), "backup.tar", &archive);
+
+        assert!(members.errors.is_empty(), "{:?}", members.errors);
+        assert_eq!(names(&members), vec![long_name, "photos/a.jpg".to_string()]);
+        let photo = members.files.iter().find(|f| f.size == 10).unwrap();
+        assert_eq!(photo.path, temp.path().join("backup.tar!photos/a.jpg"));
+        assert_eq!(photo.ino, 0);
+        assert_eq!(
+            photo.archived.as_ref().unwrap().full,
+            hash_bytes(b"image data")
+        );
+    }
+
+    #[test]
+    fn test_pax_path() {
+        let temp = TempDir::new().unwrap();
+        let archive = tar(&[
+            (b"PaxHeader", b"22 path=docs/long.txt\n", b'x'),
+            (b"short", b"text", b'0'),
+        ]);
+        let members = read(temp.path(), "a.tar", &archive);
+        assert_eq!(names(&members), vec!["docs/long.txt"]);
+    }
+
+    #[test]
+    fn test_tar_gz_members() {
+        let temp = TempDir::new().unwrap();
+        let archive = tar(&[(b"a.txt", b"hello", b'0')]);
+        let members = read(temp.path(), "a.tgz", &gzip(&archive));
+        assert!(members.errors.is_empty(), "{:?}", members.errors);
+        assert_eq!(names(&members), vec!["a.txt"]);
+    }
+
+    #[test]
+    fn test_corrupted_tar_gz_trailer_rejects_members() {
+        let temp = TempDir::new().unwrap();
+        let mut file = gzip(&tar(&[(b"a.txt", b"hello", b'0')]));
+        // The CRC-32 of the uncompressed data, then its length
+        let crc = file.len() - 8;
+        file[crc] ^= 0xff;
+
+        let members = read(temp.path(), "a.tar.gz", &file);
+        assert!(members.files.is_empty());
+        assert_eq!(members.errors.len(), 1);
+        assert_eq!(members.errors[0].path, temp.path().join("a.tar.gz"));
+
+        let mut file = gzip(&tar(&[(b"a.txt", b"hello", b'0')]));
+        let length = file.len() - 4;
+        file[length] ^= 0xff;
+        let members = read(temp.path(), "b.tar.gz", &file);
+        assert!(members.files.is_empty());
+        assert_eq!(members.errors.len(), 1);
+    }
+
+    #[test]
+    fn test_corrupted_zip_member_is_an_error() {
+        let temp = TempDir::new().unwrap();
+        let mut archive = zip(&[("a.txt", b"hello"), ("b.txt", b"world")]);
+        // The contents of the first member follow its 30 byte header and name
+        archive[30 + "a.txt".len()] = b'j';
+
+        let members = read(temp.path(), "a.zip", &archive);
+        assert_eq!(names(&members), vec!["b.txt"]);
+        assert_eq!(members.errors.len(), 1);
+        assert_eq!(members.errors[0].path, temp.path().join("a.zip!a.txt"));
+        assert!(members.errors[0].error.contains("checksum"));
+    }
+
+    #[test]
+    fn test_zip_members() {
+        let temp = TempDir::new().unwrap();
+        let archive = zip(&[("dir/", b""), ("dir/a.txt", b"hello"), ("b.txt", b"world")]);
+        let members = read(temp.path(), "a.zip", &archive);
+
+        assert!(members.errors.is_empty(), "{:?}", members.errors);
+        assert_eq!(names(&members), vec!["b.txt", "dir/a.txt"]);
+        let hashes: Vec<_> = members
+            .files
+            .iter()
+            .map(|f| f.archived.as_ref().unwrap().full)
+            .collect();
+        assert!(hashes.contains(&hash_bytes(b"hello")));
+        assert!(hashes.contains(&hash_bytes(b"world")));
+    }
+
+    #[test]
+    fn test_filtered_members_are_not_hashed() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("a.zip");
+        fs::write(&path, zip(&[("a.txt", b"hello"), ("b.jpg", b"image")])).unwrap();
+        let archive = crate::scanner::stat_file(&path).unwrap();
+
+        let members = members(&archive, Format::Zip, |entry| {
+            entry.path.extension().is_some_and(|e| e == "jpg")
+        });
+        assert_eq!(names(&members), vec!["b.jpg"]);
+    }
+
+    #[test]
+    fn test_unreadable_archives_are_errors() {
+        let temp = TempDir::new().unwrap();
+
+        let members = read(temp.path(), "bad.zip", b"not a zip archive");
+        assert!(members.files.is_empty());
+        assert_eq!(members.errors.len(), 1);
+        assert_eq!(members.errors[0].path, temp.path().join("bad.zip"));
+
+        let mut archive = tar(&[(b"a.txt", b"hello", b'0')]);
+        archive.truncate(600);
+        let members = read(temp.path(), "short.tar", &archive);
+        assert_eq!(members.errors.len(), 1);
+    }
+}
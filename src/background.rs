@@ -0,0 +1,138 @@
+use std::io;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of threads in background mode, unless set with `--jobs`
+pub const BACKGROUND_JOBS: usize = 1;
+
+/// Maximum average read rate in background mode (32MB/s)
+const MAX_BYTES_PER_SEC: u64 = 32 * 1024 * 1024;
+
+/// How long to wait before checking the system load again while paused
+const LOAD_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Lower the CPU priority (nice 19) and, on Linux, the I/O priority (idle class) of the
+/// calling thread. Threads started afterwards inherit them, so this must run before the
+/// thread pool is built.
+#[cfg(unix)]
+pub fn lower_priority() -> io::Result<()> {
+    // SAFETY: plain syscall without pointers, 0 refers to the calling thread
+    let ret = unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 19) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    lower_io_priority()
+}
+
+/// Priorities can only be lowered on Unix.
+#[cfg(not(unix))]
+pub fn lower_priority() -> io::Result<()> {
+    Ok(())
+}
+
+/// Move the calling thread to the idle I/O scheduling class
+#[cfg(target_os = "linux")]
+fn lower_io_priority() -> io::Result<()> {
+    /// `IOPRIO_WHO_PROCESS` and `IOPRIO_CLASS_IDLE` from `linux/ioprio.h`
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+    // SAFETY: plain syscall without pointers, 0 refers to the calling thread
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// I/O scheduling classes only exist on Linux.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn lower_io_priority() -> io::Result<()> {
+    Ok(())
+}
+
+/// Slows hashing down in background mode: keeps the average read rate below
+/// `MAX_BYTES_PER_SEC` and pauses while the system is busy.
+#[derive(Debug)]
+pub struct Throttle {
+    started: Instant,
+    bytes_read: Mutex<u64>,
+    /// 1-minute load average above which hashing pauses
+    max_load: f64,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        let cores = thread::available_parallelism().map_or(1, |n| n.get());
+        Self {
+            started: Instant::now(),
+            bytes_read: Mutex::new(0),
+            max_load: cores as f64,
+        }
+    }
+}
+
+impl Throttle {
+    /// Account for `bytes` that were read and wait until hashing may continue
+    pub fn pause(&self, bytes: u64) {
+        let total = {
+            let mut bytes_read = self.bytes_read.lock().unwrap();
+            *bytes_read += bytes;
+            *bytes_read
+        };
+        let due = Duration::from_secs_f64(total as f64 / MAX_BYTES_PER_SEC as f64);
+        if let Some(ahead) = due.checked_sub(self.started.elapsed()) {
+            thread::sleep(ahead);
+        }
+
+        while load_average().is_some_and(|load| load > self.max_load) {
+            thread::sleep(LOAD_CHECK_INTERVAL);
+        }
+    }
+}
+
+/// 1-minute load average of the system, `None` where `/proc/loadavg` is not available
+fn load_average() -> Option<f64> {
+    parse_load_average(&std::fs::read_to_string("/proc/loadavg").ok()?)
+}
+
+fn parse_load_average(loadavg: &str) -> Option<f64> {
+    loadavg.split_whitespace().next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_load_average() {
+        assert_eq!(
+            parse_load_average("0.52 0.58 0.59 1/467 12345\n"),
+            Some(0.52)
+        );
+        assert_eq!(parse_load_average(""), None);
+        assert_eq!(parse_load_average("busy"), None);
+    }
+
+    #[test]
+    fn test_throttle_limits_read_rate() {
+        let throttle = Throttle {
+            started: Instant::now(),
+            bytes_read: Mutex::new(0),
+            max_load: f64::INFINITY,
+        };
+
+        throttle.pause(MAX_BYTES_PER_SEC / 20);
+
+        assert!(throttle.started.elapsed() >= Duration::from_millis(50));
+    }
+}
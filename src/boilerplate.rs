@@ -0,0 +1,85 @@
+use crate::hasher::Digest;
+
+/// BLAKE3 hashes of files that show up everywhere on developer machines.
+///
+/// Duplicates of these are expected and almost never worth acting on, so they can be
+/// dropped from the report with `--skip-common-boilerplate`.
+const COMMON_BOILERPLATE_HASHES: &[(&str, &str)] = &[
+    (
+        "empty file (.gitkeep, __init__.py, ...)",
+        "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+    ),
+    (
+        "single newline",
+        "295192ea1ec8566d563b1a7587e5f0198580cdbd043842f5090a4c197c20c67a",
+    ),
+    (
+        "single CRLF newline",
+        "3de3577a7c26681bdae91dbd9b8dcfebaef16fd2b3b82d3369b230feadf51961",
+    ),
+    (
+        "Apache License 2.0",
+        "83cb3a2fcf829b6138e095b083016c34ddcdfa07b68d38782722c14fcf85ace6",
+    ),
+    (
+        "GNU GPL v2",
+        "5886b01395916aaa9c9857f7365778ddc4fde3108a794211b61ae3b5afb22bcc",
+    ),
+    (
+        "GNU GPL v3",
+        "9531546decbed2aa21abd964d148ded0bbd272d98b13698629883de3abfa9b30",
+    ),
+    (
+        "GNU LGPL v2.1",
+        "3656891afc85e58f6a2167e395cd0d10cfa14677a4a3b0886e606020399bc249",
+    ),
+    (
+        "GNU LGPL v3",
+        "dbdd263824e5f6350ef959ec92a059900dc3498d29383c488d0811bc7f180a8c",
+    ),
+    (
+        "Mozilla Public License 2.0",
+        "0bf594418f6bfc3add122ef82b0a104af3976278d007bb0062e4e52a09797e2f",
+    ),
+    (
+        "CC0 1.0",
+        "b7a6a1ef44aa3647db780392b4fa023c613fd9fa482678bb7a729e5fe385ca00",
+    ),
+];
+
+/// Check if a content hash belongs to a well-known boilerplate file, only BLAKE3 hashes
+/// (`--hash blake3`) can match
+pub fn is_common_boilerplate(hash: &Digest) -> bool {
+    let hex = hash.to_hex();
+    COMMON_BOILERPLATE_HASHES
+        .iter()
+        .any(|(_, known)| *known == hex.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::hash_bytes;
+
+    #[test]
+    fn test_empty_content_is_boilerplate() {
+        assert!(is_common_boilerplate(&hash_bytes(b"")));
+        assert!(is_common_boilerplate(&hash_bytes(b"\n")));
+    }
+
+    #[test]
+    fn test_regular_content_is_not_boilerplate() {
+        assert!(!is_common_boilerplate(&hash_bytes(b"hello world")));
+    }
+
+    #[test]
+    fn test_known_hashes_are_valid_hex() {
+        for (name, hex) in COMMON_BOILERPLATE_HASHES {
+            assert!(
+                blake3::Hash::from_hex(hex).is_ok(),
+                "invalid hash for {}",
+                name
+            );
+        }
+    }
+}
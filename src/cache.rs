@@ -0,0 +1,887 @@
+use std::collections::hash_map::RandomState;
+use std::ffi::OsString;
+use std::fs;
+use std::hash::BuildHasher;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use rayon::prelude::*;
+use rusqlite::{Connection, OptionalExtension, Row, params};
+
+use crate::hasher::{self, Algorithm, Digest};
+use crate::incremental::{KnownFile, Snapshot};
+use crate::scanner::FileEntry;
+use crate::util;
+
+/// Version of the cache tables, a cache written by another version is cleared when opened
+const CACHE_VERSION: i32 = 4;
+
+/// How long to wait for another run writing to the same cache
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Hash stages whose results are cached. Sample hashes are cheap to recompute and not
+/// cached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Partial,
+    Full,
+}
+
+impl Stage {
+    fn name(self) -> &'static str {
+        match self {
+            Stage::Partial => "partial",
+            Stage::Full => "full",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "partial" => Some(Stage::Partial),
+            "full" => Some(Stage::Full),
+            _ => None,
+        }
+    }
+}
+
+/// What has to be unchanged for a cached hash to be used (`--cache-validation`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Validation {
+    /// Size, modification time, status change time (ctime) and inode
+    #[default]
+    Strict,
+    /// Size and modification time, for filesystems whose ctime or inode numbers change
+    /// without the contents changing (e.g. some network and FUSE filesystems)
+    Relaxed,
+}
+
+/// Which cached hash a file has: its path and how it was hashed
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CacheKey {
+    path: PathBuf,
+    stage: Stage,
+    algorithm: Algorithm,
+    skip: u64,
+}
+
+/// Metadata of a file when it was hashed, a cached hash is only used while it matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime_nanos: i64,
+    ctime_nanos: i64,
+}
+
+impl Stamp {
+    /// Current metadata of the file at `path`
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Stamp {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            size: metadata.len(),
+            mtime_nanos: metadata.mtime() * 1_000_000_000 + metadata.mtime_nsec(),
+            ctime_nanos: metadata.ctime() * 1_000_000_000 + metadata.ctime_nsec(),
+        })
+    }
+
+    /// Stamp stored in the columns of `row` starting at `first`
+    fn from_row(row: &Row, first: usize) -> rusqlite::Result<Self> {
+        Ok(Stamp {
+            dev: row.get::<_, i64>(first)? as u64,
+            ino: row.get::<_, i64>(first + 1)? as u64,
+            size: row.get::<_, i64>(first + 2)? as u64,
+            mtime_nanos: row.get(first + 3)?,
+            ctime_nanos: row.get(first + 4)?,
+        })
+    }
+
+    fn matches(&self, cached: &Stamp, validation: Validation) -> bool {
+        self.size == cached.size
+            && self.mtime_nanos == cached.mtime_nanos
+            && (validation == Validation::Relaxed
+                || (self.ctime_nanos == cached.ctime_nanos
+                    && self.dev == cached.dev
+                    && self.ino == cached.ino))
+    }
+}
+
+/// Hashes of files from earlier runs, stored in an SQLite database.
+///
+/// Hashes are found by path and only used while the file's metadata matches the
+/// [`Validation`]. New hashes are kept in memory and written in one transaction by
+/// [`HashCache::save`].
+#[derive(Debug)]
+pub struct HashCache {
+    db: Mutex<Connection>,
+    pending: Mutex<Vec<(CacheKey, Stamp, Digest)>>,
+    validation: Validation,
+    /// Share of the cached hashes that are computed again to check them, from 0 to 1
+    revalidate: f64,
+    /// Picks the files to revalidate, seeded differently on every run
+    sampler: RandomState,
+}
+
+impl HashCache {
+    /// Open the cache at `path`, creating it if needed. `revalidate_percent` of the hashes
+    /// found in it are computed again and compared, a mismatch is warned about.
+    pub fn open(
+        path: &Path,
+        validation: Validation,
+        revalidate_percent: u8,
+    ) -> rusqlite::Result<Self> {
+        let db = Connection::open(path)?;
+        db.busy_timeout(BUSY_TIMEOUT)?;
+        let version: i32 = db.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version != CACHE_VERSION {
+            db.execute_batch(&format!(
+                "DROP TABLE IF EXISTS hashes;
+                 DROP TABLE IF EXISTS snapshot;
+                 DROP TABLE IF EXISTS snapshot_settings;
+                 CREATE TABLE hashes (
+                     path BLOB NOT NULL,
+                     stage TEXT NOT NULL,
+                     algorithm TEXT NOT NULL,
+                     skip INTEGER NOT NULL,
+                     dev INTEGER NOT NULL,
+                     ino INTEGER NOT NULL,
+                     size INTEGER NOT NULL,
+                     mtime_nanos INTEGER NOT NULL,
+                     ctime_nanos INTEGER NOT NULL,
+                     hash BLOB NOT NULL,
+                     stored_at INTEGER NOT NULL,
+                     PRIMARY KEY (path, stage, algorithm, skip)
+                 ) WITHOUT ROWID;
+                 CREATE TABLE snapshot (
+                     path BLOB PRIMARY KEY,
+                     dev INTEGER NOT NULL,
+                     ino INTEGER NOT NULL,
+                     size INTEGER NOT NULL,
+                     mtime_nanos INTEGER NOT NULL,
+                     hash BLOB
+                 ) WITHOUT ROWID;
+                 CREATE TABLE snapshot_settings (settings TEXT NOT NULL);
+                 PRAGMA user_version = {};",
+                CACHE_VERSION
+            ))?;
+        }
+        Ok(HashCache {
+            db: Mutex::new(db),
+            pending: Mutex::new(Vec::new()),
+            validation,
+            revalidate: f64::from(revalidate_percent.min(100)) / 100.0,
+            sampler: RandomState::new(),
+        })
+    }
+
+    /// Hash `file` with `hash_fn`, unless it has a valid hash for `stage` from an earlier
+    /// run and isn't picked to be revalidated
+    pub fn get_or_hash<F>(
+        &self,
+        file: &mut FileEntry,
+        stage: Stage,
+        skip: u64,
+        hash_fn: F,
+    ) -> Option<Digest>
+    where
+        F: FnOnce(&mut FileEntry) -> Option<Digest>,
+    {
+        // Archive members can't be looked up by their path
+        if file.archived.is_some() {
+            return hash_fn(file);
+        }
+        let key = CacheKey {
+            path: file.path.clone(),
+            stage,
+            algorithm: hasher::algorithm(),
+            skip,
+        };
+        let Some(stamp) = Stamp::of(&file.path) else {
+            return hash_fn(file);
+        };
+        let cached = self
+            .lookup(&key)
+            .filter(|(cached, _)| stamp.matches(cached, self.validation))
+            .map(|(_, hash)| hash);
+        if let Some(hash) = cached
+            && !self.revalidates(&key.path)
+        {
+            return Some(hash);
+        }
+
+        let hash = hash_fn(file)?;
+        if let Some(cached) = cached
+            && cached != hash
+        {
+            let hint = match self.validation {
+                Validation::Relaxed => ", consider --cache-validation strict",
+                Validation::Strict => "",
+            };
+            util::warn(format_args!(
+                "the cached {} hash of {} was out of date although the file looks unchanged{}",
+                stage.name(),
+                file.path.display(),
+                hint
+            ));
+        }
+        // A file that changed while it was hashed is hashed again in the next run
+        if Stamp::of(&file.path) == Some(stamp) {
+            self.pending.lock().unwrap().push((key, stamp, hash));
+        }
+        Some(hash)
+    }
+
+    /// Whether the cached hash of `path` is checked by hashing the file again
+    fn revalidates(&self, path: &Path) -> bool {
+        self.revalidate > 0.0
+            && (self.sampler.hash_one(path) as f64) < self.revalidate * u64::MAX as f64
+    }
+
+    /// Hash stored for `key` with the metadata the file had when it was hashed
+    fn lookup(&self, key: &CacheKey) -> Option<(Stamp, Digest)> {
+        let db = self.db.lock().unwrap();
+        let mut statement = db
+            .prepare_cached(
+                "SELECT dev, ino, size, mtime_nanos, ctime_nanos, hash FROM hashes
+                 WHERE path = ?1 AND stage = ?2 AND algorithm = ?3 AND skip = ?4",
+            )
+            .ok()?;
+        let (stamp, hash) = statement
+            .query_row(
+                params![
+                    key.path.as_os_str().as_bytes(),
+                    key.stage.name(),
+                    key.algorithm.name(),
+                    key.skip as i64
+                ],
+                |row| Ok((Stamp::from_row(row, 0)?, row.get::<_, Vec<u8>>(5)?)),
+            )
+            .optional()
+            .ok()??;
+        Some((stamp, Digest::from_bytes(&hash)?))
+    }
+
+    /// Write the hashes computed since the last save, replacing older hashes of the same files
+    pub fn save(&self) -> rusqlite::Result<()> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        let stored_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs() as i64);
+        let mut db = self.db.lock().unwrap();
+        let transaction = db.transaction()?;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT OR REPLACE INTO hashes
+                 (path, stage, algorithm, skip, dev, ino, size, mtime_nanos, ctime_nanos, hash,
+                  stored_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            )?;
+            for (key, stamp, hash) in &pending {
+                statement.execute(params![
+                    key.path.as_os_str().as_bytes(),
+                    key.stage.name(),
+                    key.algorithm.name(),
+                    key.skip as i64,
+                    stamp.dev as i64,
+                    stamp.ino as i64,
+                    stamp.size as i64,
+                    stamp.mtime_nanos,
+                    stamp.ctime_nanos,
+                    hash.as_bytes(),
+                    stored_at
+                ])?;
+            }
+        }
+        transaction.commit()
+    }
+}
+
+/// Number of hashes in a cache, see [`HashCache::stats`]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub partial_hashes: usize,
+    pub full_hashes: usize,
+    /// Distinct paths with at least one hash
+    pub files: usize,
+}
+
+/// Hashes removed by [`HashCache::prune`]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Pruned {
+    /// Hashes of files that no longer exist or changed since they were hashed
+    pub stale: usize,
+    /// Oldest hashes beyond the limit
+    pub over_limit: usize,
+    /// Hashes left in the cache
+    pub left: usize,
+}
+
+/// Outcome of [`HashCache::verify`]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Verified {
+    /// Hashes computed again and compared
+    pub checked: usize,
+    /// Paths whose hash was out of date, now removed from the cache
+    pub outdated: Vec<PathBuf>,
+    /// Hashes of files that no longer exist or changed, or computed with another algorithm
+    pub skipped: usize,
+}
+
+impl HashCache {
+    /// Every hash in the cache with the metadata of the file when it was hashed
+    fn entries(&self) -> rusqlite::Result<Vec<(CacheKey, Stamp, Digest)>> {
+        let db = self.db.lock().unwrap();
+        let mut statement = db.prepare(
+            "SELECT path, stage, algorithm, skip, dev, ino, size, mtime_nanos, ctime_nanos, hash
+             FROM hashes",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, Vec<u8>>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+                Stamp::from_row(row, 4)?,
+                row.get::<_, Vec<u8>>(9)?,
+            ))
+        })?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            let (path, stage, algorithm, skip, stamp, hash) = row?;
+            // Rows are only written by this version, anything else is left alone
+            let (Some(stage), Ok(algorithm), Some(hash)) = (
+                Stage::from_name(&stage),
+                Algorithm::from_str(&algorithm, false),
+                Digest::from_bytes(&hash),
+            ) else {
+                continue;
+            };
+            let key = CacheKey {
+                path: PathBuf::from(OsString::from_vec(path)),
+                stage,
+                algorithm,
+                skip: skip as u64,
+            };
+            entries.push((key, stamp, hash));
+        }
+        Ok(entries)
+    }
+
+    /// Remove the hashes of `keys`
+    fn remove(&self, keys: &[CacheKey]) -> rusqlite::Result<()> {
+        let mut db = self.db.lock().unwrap();
+        let transaction = db.transaction()?;
+        {
+            let mut statement = transaction.prepare(
+                "DELETE FROM hashes WHERE path = ?1 AND stage = ?2 AND algorithm = ?3 AND skip = ?4",
+            )?;
+            for key in keys {
+                statement.execute(params![
+                    key.path.as_os_str().as_bytes(),
+                    key.stage.name(),
+                    key.algorithm.name(),
+                    key.skip as i64
+                ])?;
+            }
+        }
+        transaction.commit()
+    }
+
+    /// Number of hashes and files in the cache
+    pub fn stats(&self) -> rusqlite::Result<CacheStats> {
+        let db = self.db.lock().unwrap();
+        db.query_row(
+            "SELECT count(*) FILTER (WHERE stage = 'partial'),
+                    count(*) FILTER (WHERE stage = 'full'),
+                    count(DISTINCT path)
+             FROM hashes",
+            [],
+            |row| {
+                Ok(CacheStats {
+                    partial_hashes: row.get::<_, i64>(0)? as usize,
+                    full_hashes: row.get::<_, i64>(1)? as usize,
+                    files: row.get::<_, i64>(2)? as usize,
+                })
+            },
+        )
+    }
+
+    /// Remove the hashes of files that no longer exist or whose size or modification time
+    /// changed, they can't be used again. With `max_entries`, only that many of the most
+    /// recently stored hashes are kept. The database file is compacted afterwards.
+    pub fn prune(&self, max_entries: Option<u64>) -> rusqlite::Result<Pruned> {
+        let stale: Vec<CacheKey> = self
+            .entries()?
+            .into_par_iter()
+            .filter(|(key, stamp, _)| {
+                !Stamp::of(&key.path).is_some_and(|now| now.matches(stamp, Validation::Relaxed))
+            })
+            .map(|(key, _, _)| key)
+            .collect();
+        self.remove(&stale)?;
+
+        let db = self.db.lock().unwrap();
+        let over_limit = match max_entries {
+            Some(max) => db.execute(
+                "DELETE FROM hashes WHERE (path, stage, algorithm, skip) IN (
+                     SELECT path, stage, algorithm, skip FROM hashes
+                     ORDER BY stored_at DESC LIMIT -1 OFFSET ?1
+                 )",
+                [max as i64],
+            )?,
+            None => 0,
+        };
+        db.execute_batch("VACUUM")?;
+        let left: i64 = db.query_row("SELECT count(*) FROM hashes", [], |row| row.get(0))?;
+
+        Ok(Pruned {
+            stale: stale.len(),
+            over_limit,
+            left: left as usize,
+        })
+    }
+
+    /// Remove all hashes and the snapshot of `--incremental`, returning how many hashes
+    /// there were
+    pub fn clear(&self) -> rusqlite::Result<usize> {
+        let db = self.db.lock().unwrap();
+        let removed = db.execute("DELETE FROM hashes", [])?;
+        db.execute_batch("DELETE FROM snapshot; DELETE FROM snapshot_settings;")?;
+        db.execute_batch("VACUUM")?;
+        Ok(removed)
+    }
+
+    /// Hash the files in the cache again and remove the hashes that don't match anymore,
+    /// although size and modification time are unchanged. Only hashes computed with the
+    /// algorithm of the run are checked.
+    pub fn verify(&self) -> rusqlite::Result<Verified> {
+        let algorithm = hasher::algorithm();
+        let results: Vec<Option<(CacheKey, bool)>> = self
+            .entries()?
+            .into_par_iter()
+            .map(|(key, stamp, hash)| {
+                let unchanged = key.algorithm == algorithm
+                    && Stamp::of(&key.path)
+                        .is_some_and(|now| now.matches(&stamp, Validation::Relaxed));
+                if !unchanged {
+                    return None;
+                }
+                let fresh = match key.stage {
+                    Stage::Partial => hasher::partial_hash_file(&key.path, stamp.size, key.skip),
+                    Stage::Full => hasher::full_hash_path(&key.path, key.skip),
+                }?;
+                Some((key, fresh == hash))
+            })
+            .collect();
+
+        let mut verified = Verified::default();
+        let mut outdated = Vec::new();
+        for result in results {
+            match result {
+                Some((key, up_to_date)) => {
+                    verified.checked += 1;
+                    if !up_to_date {
+                        outdated.push(key);
+                    }
+                }
+                None => verified.skipped += 1,
+            }
+        }
+        self.remove(&outdated)?;
+        verified.outdated = outdated.into_iter().map(|key| key.path).collect();
+        verified.outdated.sort();
+        verified.outdated.dedup();
+        Ok(verified)
+    }
+}
+
+impl HashCache {
+    /// Snapshot of the last `--incremental` run, `None` if there was none or it compared
+    /// files with other `settings`
+    pub fn load_snapshot(&self, settings: &str) -> rusqlite::Result<Option<Snapshot>> {
+        let db = self.db.lock().unwrap();
+        let stored: Option<String> = db
+            .query_row("SELECT settings FROM snapshot_settings", [], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        if stored.as_deref() != Some(settings) {
+            return Ok(None);
+        }
+
+        let mut statement =
+            db.prepare("SELECT path, dev, ino, size, mtime_nanos, hash FROM snapshot")?;
+        let rows = statement.query_map([], |row| {
+            let hash: Option<Vec<u8>> = row.get(5)?;
+            Ok((
+                PathBuf::from(OsString::from_vec(row.get(0)?)),
+                KnownFile {
+                    dev: row.get::<_, i64>(1)? as u64,
+                    ino: row.get::<_, i64>(2)? as u64,
+                    size: row.get::<_, i64>(3)? as u64,
+                    mtime_nanos: row.get(4)?,
+                    hash: hash.as_deref().and_then(Digest::from_bytes),
+                },
+            ))
+        })?;
+        let mut snapshot = Snapshot::default();
+        for row in rows {
+            let (path, known) = row?;
+            snapshot.files.insert(path, known);
+        }
+        Ok(Some(snapshot))
+    }
+
+    /// Replace the snapshot of the last `--incremental` run
+    pub fn save_snapshot(&self, settings: &str, snapshot: &Snapshot) -> rusqlite::Result<()> {
+        let mut db = self.db.lock().unwrap();
+        let transaction = db.transaction()?;
+        transaction.execute_batch("DELETE FROM snapshot; DELETE FROM snapshot_settings;")?;
+        transaction.execute(
+            "INSERT INTO snapshot_settings (settings) VALUES (?1)",
+            [settings],
+        )?;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT INTO snapshot (path, dev, ino, size, mtime_nanos, hash)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            for (path, known) in &snapshot.files {
+                statement.execute(params![
+                    path.as_os_str().as_bytes(),
+                    known.dev as i64,
+                    known.ino as i64,
+                    known.size as i64,
+                    known.mtime_nanos,
+                    known.hash.as_ref().map(Digest::as_bytes)
+                ])?;
+            }
+        }
+        transaction.commit()
+    }
+}
+
+/// Cache of the current run, set once by [`open`]
+static CACHE: OnceLock<HashCache> = OnceLock::new();
+
+/// Look up and store hashes in the cache at `path` for the rest of the run (`--cache`)
+pub fn open(path: &Path, validation: Validation, revalidate_percent: u8) -> rusqlite::Result<()> {
+    let _ = CACHE.set(HashCache::open(path, validation, revalidate_percent)?);
+    Ok(())
+}
+
+/// Hash `file` with `hash_fn`, unless the cache of `--cache` has a valid hash for `stage`
+/// from an earlier run. New hashes are stored with the metadata the file had before it was
+/// hashed.
+pub fn cached_hash<F>(file: &mut FileEntry, stage: Stage, skip: u64, hash_fn: F) -> Option<Digest>
+where
+    F: FnOnce(&mut FileEntry) -> Option<Digest>,
+{
+    match CACHE.get() {
+        Some(cache) => cache.get_or_hash(file, stage, skip, hash_fn),
+        None => hash_fn(file),
+    }
+}
+
+/// Write the hashes computed in this run to the cache, if any
+pub fn save() -> rusqlite::Result<()> {
+    CACHE.get().map_or(Ok(()), HashCache::save)
+}
+
+/// Snapshot of the last `--incremental` run with the same `settings` in the cache, if any
+pub fn load_snapshot(settings: &str) -> rusqlite::Result<Option<Snapshot>> {
+    CACHE
+        .get()
+        .map_or(Ok(None), |cache| cache.load_snapshot(settings))
+}
+
+/// Store the snapshot of this `--incremental` run in the cache, if any
+pub fn save_snapshot(settings: &str, snapshot: &Snapshot) -> rusqlite::Result<()> {
+    CACHE
+        .get()
+        .map_or(Ok(()), |cache| cache.save_snapshot(settings, snapshot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::fs::{File, FileTimes, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    fn file_entry(dir: &Path, name: &str, content: &[u8]) -> FileEntry {
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        FileEntry {
+            size: content.len() as u64,
+            path,
+            ..Default::default()
+        }
+    }
+
+    /// Hash `file` through `cache`, counting the times it is actually read in `reads`
+    fn hash(cache: &HashCache, file: &mut FileEntry, reads: &Cell<usize>) -> Option<Digest> {
+        cache.get_or_hash(file, Stage::Full, 0, |file| {
+            reads.set(reads.get() + 1);
+            Some(hasher::hash_bytes(&fs::read(&file.path).ok()?))
+        })
+    }
+
+    #[test]
+    fn test_hashes_survive_reopening() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("cache.sqlite");
+        let mut file = file_entry(temp.path(), "file", b"content");
+        let reads = Cell::new(0);
+
+        let cache = HashCache::open(&path, Validation::Strict, 0).unwrap();
+        assert_eq!(
+            hash(&cache, &mut file, &reads),
+            Some(hasher::hash_bytes(b"content"))
+        );
+        cache.save().unwrap();
+        drop(cache);
+
+        let cache = HashCache::open(&path, Validation::Strict, 0).unwrap();
+        assert_eq!(
+            hash(&cache, &mut file, &reads),
+            Some(hasher::hash_bytes(b"content"))
+        );
+        assert_eq!(reads.get(), 1);
+        // Other stages and header skips are hashed separately
+        let key = |stage, skip| CacheKey {
+            path: file.path.clone(),
+            stage,
+            algorithm: hasher::algorithm(),
+            skip,
+        };
+        assert!(cache.lookup(&key(Stage::Partial, 0)).is_none());
+        assert!(cache.lookup(&key(Stage::Full, 4)).is_none());
+    }
+
+    #[test]
+    fn test_changed_files_are_hashed_again() {
+        let temp = TempDir::new().unwrap();
+        let cache =
+            HashCache::open(&temp.path().join("cache.sqlite"), Validation::Relaxed, 0).unwrap();
+        let mut file = file_entry(temp.path(), "file", b"old");
+        let reads = Cell::new(0);
+        hash(&cache, &mut file, &reads);
+        cache.save().unwrap();
+
+        fs::write(&file.path, b"new").unwrap();
+        let later = SystemTime::now() + Duration::from_secs(1);
+        File::options()
+            .write(true)
+            .open(&file.path)
+            .unwrap()
+            .set_times(FileTimes::new().set_modified(later))
+            .unwrap();
+
+        assert_eq!(
+            hash(&cache, &mut file, &reads),
+            Some(hasher::hash_bytes(b"new"))
+        );
+        assert_eq!(reads.get(), 2);
+    }
+
+    #[test]
+    fn test_strict_validation_checks_ctime() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("cache.sqlite");
+        let mut file = file_entry(temp.path(), "file", b"content");
+        let reads = Cell::new(0);
+        let cache = HashCache::open(&path, Validation::Strict, 0).unwrap();
+        hash(&cache, &mut file, &reads);
+        cache.save().unwrap();
+        drop(cache);
+
+        // Changing the permissions changes the ctime but not the mtime, the coarse clock
+        // behind both only moves every few milliseconds
+        std::thread::sleep(Duration::from_millis(50));
+        fs::set_permissions(&file.path, Permissions::from_mode(0o600)).unwrap();
+
+        let relaxed = HashCache::open(&path, Validation::Relaxed, 0).unwrap();
+        hash(&relaxed, &mut file, &reads);
+        assert_eq!(reads.get(), 1);
+        drop(relaxed);
+        let strict = HashCache::open(&path, Validation::Strict, 0).unwrap();
+        hash(&strict, &mut file, &reads);
+        assert_eq!(reads.get(), 2);
+    }
+
+    #[test]
+    fn test_revalidation_replaces_stale_hashes() {
+        let temp = TempDir::new().unwrap();
+        let mut file = file_entry(temp.path(), "file", b"content");
+        let reads = Cell::new(0);
+        let mut unchanged = file.clone();
+        let mut stale = |revalidate_percent| {
+            let cache = HashCache::open(
+                &temp.path().join("cache.sqlite"),
+                Validation::Strict,
+                revalidate_percent,
+            )
+            .unwrap();
+            cache.get_or_hash(&mut unchanged, Stage::Full, 0, |_| {
+                Some(hasher::hash_bytes(b"stale"))
+            });
+            cache.save().unwrap();
+            cache
+        };
+
+        let cache = stale(0);
+        assert_eq!(
+            hash(&cache, &mut file, &reads),
+            Some(hasher::hash_bytes(b"stale"))
+        );
+        assert_eq!(reads.get(), 0);
+
+        let cache = stale(100);
+        assert_eq!(
+            hash(&cache, &mut file, &reads),
+            Some(hasher::hash_bytes(b"content"))
+        );
+        assert_eq!(reads.get(), 1);
+        cache.save().unwrap();
+        let cache = stale(0);
+        assert_eq!(
+            hash(&cache, &mut file, &reads),
+            Some(hasher::hash_bytes(b"content"))
+        );
+    }
+
+    #[test]
+    fn test_stats_and_clear() {
+        let temp = TempDir::new().unwrap();
+        let cache =
+            HashCache::open(&temp.path().join("cache.sqlite"), Validation::Strict, 0).unwrap();
+        let mut file = file_entry(temp.path(), "file", b"content");
+        let mut other = file_entry(temp.path(), "other", b"other");
+        let reads = Cell::new(0);
+        hash(&cache, &mut file, &reads);
+        hash(&cache, &mut other, &reads);
+        cache.get_or_hash(&mut file, Stage::Partial, 0, |file| {
+            hasher::partial_hash_file(&file.path, file.size, 0)
+        });
+        cache.save().unwrap();
+
+        let stats = cache.stats().unwrap();
+        assert_eq!(
+            stats,
+            CacheStats {
+                partial_hashes: 1,
+                full_hashes: 2,
+                files: 2
+            }
+        );
+        assert_eq!(cache.clear().unwrap(), 3);
+        assert_eq!(cache.stats().unwrap(), CacheStats::default());
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("cache.sqlite");
+        let known = |hash| KnownFile {
+            dev: 1,
+            ino: 2,
+            size: 3,
+            mtime_nanos: -4,
+            hash,
+        };
+        let mut snapshot = Snapshot::default();
+        snapshot.files.insert(
+            PathBuf::from("/dup"),
+            known(Some(hasher::hash_bytes(b"dup"))),
+        );
+        snapshot.files.insert(PathBuf::from("/unique"), known(None));
+
+        let cache = HashCache::open(&path, Validation::Strict, 0).unwrap();
+        assert!(cache.load_snapshot("blake3").unwrap().is_none());
+        cache.save_snapshot("blake3", &snapshot).unwrap();
+        drop(cache);
+
+        let cache = HashCache::open(&path, Validation::Strict, 0).unwrap();
+        let loaded = cache.load_snapshot("blake3").unwrap().unwrap();
+        assert_eq!(loaded.files, snapshot.files);
+        // A snapshot compared with other settings can't be used
+        assert!(cache.load_snapshot("sha256").unwrap().is_none());
+
+        cache.clear().unwrap();
+        assert!(cache.load_snapshot("blake3").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_prune_removes_stale_and_oldest_hashes() {
+        let temp = TempDir::new().unwrap();
+        let cache =
+            HashCache::open(&temp.path().join("cache.sqlite"), Validation::Strict, 0).unwrap();
+        let reads = Cell::new(0);
+        for name in ["a", "b", "c", "deleted"] {
+            hash(
+                &cache,
+                &mut file_entry(temp.path(), name, name.as_bytes()),
+                &reads,
+            );
+        }
+        cache.save().unwrap();
+        fs::remove_file(temp.path().join("deleted")).unwrap();
+        fs::write(temp.path().join("c"), b"changed").unwrap();
+
+        assert_eq!(
+            cache.prune(None).unwrap(),
+            Pruned {
+                stale: 2,
+                over_limit: 0,
+                left: 2
+            }
+        );
+        assert_eq!(
+            cache.prune(Some(1)).unwrap(),
+            Pruned {
+                stale: 0,
+                over_limit: 1,
+                left: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_verify_removes_outdated_hashes() {
+        let temp = TempDir::new().unwrap();
+        let cache =
+            HashCache::open(&temp.path().join("cache.sqlite"), Validation::Strict, 0).unwrap();
+        let reads = Cell::new(0);
+        let mut file = file_entry(temp.path(), "file", b"content");
+        hash(&cache, &mut file, &reads);
+        let mut wrong = file_entry(temp.path(), "wrong", b"content");
+        cache.get_or_hash(&mut wrong, Stage::Full, 0, |_| {
+            Some(hasher::hash_bytes(b"other"))
+        });
+        let mut deleted = file_entry(temp.path(), "deleted", b"content");
+        hash(&cache, &mut deleted, &reads);
+        cache.save().unwrap();
+        fs::remove_file(&deleted.path).unwrap();
+
+        let verified = cache.verify().unwrap();
+        assert_eq!(
+            verified,
+            Verified {
+                checked: 2,
+                outdated: vec![wrong.path.clone()],
+                skipped: 1
+            }
+        );
+        assert_eq!(cache.stats().unwrap().full_hashes, 2);
+        assert!(cache.verify().unwrap().outdated.is_empty());
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cache;
+use crate::deferral;
+use crate::grouping::SizeGroups;
+use crate::hasher::{Algorithm, Digest, HashGroup, HashGroups};
+use crate::output;
+use crate::scanner::{self, FileEntry};
+use crate::util;
+
+/// How often the progress of a run is saved to its `--checkpoint` file
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A file of a confirmed duplicate group, as it was when it was hashed
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedFile {
+    path: PathBuf,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedGroup {
+    hash: String,
+    files: Vec<SavedFile>,
+}
+
+/// Contents of a checkpoint file
+#[derive(Debug, Serialize, Deserialize)]
+struct State {
+    /// Hash algorithm of the duplicate groups
+    algorithm: String,
+    /// Candidate groups that were not hashed yet
+    pending: Vec<Vec<PathBuf>>,
+    /// Duplicate groups confirmed so far
+    duplicates: Vec<SavedGroup>,
+}
+
+impl State {
+    fn new<'a>(
+        algorithm: Algorithm,
+        pending: impl Iterator<Item = &'a Vec<PathBuf>>,
+        duplicates: &[HashGroup],
+    ) -> Self {
+        State {
+            algorithm: algorithm.name().to_string(),
+            pending: pending.cloned().collect(),
+            duplicates: duplicates
+                .iter()
+                .map(|group| SavedGroup {
+                    hash: group.hash.to_hex(),
+                    files: group
+                        .files
+                        .iter()
+                        .map(|file| SavedFile {
+                            path: file.path.clone(),
+                            size: file.size,
+                            modified: file.modified,
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        output::write_atomically(path, |out| {
+            serde_json::to_writer(&mut *out, self)?;
+            Ok(())
+        })
+    }
+}
+
+/// Progress of the candidate groups being hashed, saved to a file every
+/// [`CHECKPOINT_INTERVAL`] so a killed run can be continued with `--resume`.
+///
+/// A candidate group counts as pending until all of it was hashed, only then are its
+/// duplicates added. A run continued from the file hashes the pending groups again.
+#[derive(Debug)]
+pub struct Checkpoint {
+    path: PathBuf,
+    algorithm: Algorithm,
+    interval: Duration,
+    progress: Mutex<Progress>,
+}
+
+#[derive(Debug)]
+struct Progress {
+    pending: BTreeMap<usize, Vec<PathBuf>>,
+    duplicates: HashGroups,
+    saved: Instant,
+}
+
+impl Checkpoint {
+    /// Track the hashing of `size_groups`, whose duplicates are added to the `confirmed`
+    /// groups of an earlier run. The scanned candidates are saved right away.
+    pub fn new(
+        path: &Path,
+        algorithm: Algorithm,
+        interval: Duration,
+        size_groups: &[Vec<FileEntry>],
+        confirmed: &[HashGroup],
+    ) -> io::Result<Self> {
+        let pending: BTreeMap<usize, Vec<PathBuf>> = size_groups
+            .iter()
+            .map(|group| group.iter().map(|file| file.path.clone()).collect())
+            .enumerate()
+            .collect();
+        State::new(algorithm, pending.values(), confirmed).save(path)?;
+        Ok(Checkpoint {
+            path: path.to_path_buf(),
+            algorithm,
+            interval,
+            progress: Mutex::new(Progress {
+                pending,
+                duplicates: confirmed.to_vec(),
+                saved: Instant::now(),
+            }),
+        })
+    }
+
+    /// Record the duplicates of the candidate group at `index` of the tracked groups, all of
+    /// which were hashed. Saves the progress if the last save is older than the interval,
+    /// along with the hashes computed for the `--cache`.
+    pub fn finish(&self, index: usize, duplicates: &[HashGroup]) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.pending.remove(&index);
+        progress.duplicates.extend_from_slice(duplicates);
+        if progress.saved.elapsed() < self.interval {
+            return;
+        }
+
+        let state = State::new(
+            self.algorithm,
+            progress.pending.values(),
+            &progress.duplicates,
+        );
+        if let Err(e) = state.save(&self.path) {
+            util::warn(format_args!(
+                "could not save the checkpoint {}: {}",
+                self.path.display(),
+                e
+            ));
+        }
+        if let Err(e) = cache::save() {
+            util::warn(format_args!("could not update the cache: {}", e));
+        }
+        progress.saved = Instant::now();
+    }
+
+    /// Save the final state of a run that stopped early: the `deferred` candidate groups
+    /// and all `duplicates` found. A finished run removes the checkpoint instead.
+    pub fn finish_run(
+        &self,
+        deferred: &[Vec<FileEntry>],
+        duplicates: &[HashGroup],
+    ) -> io::Result<()> {
+        if deferred.is_empty() {
+            return match fs::remove_file(&self.path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        let pending: Vec<Vec<PathBuf>> = deferred
+            .iter()
+            .map(|group| group.iter().map(|file| file.path.clone()).collect())
+            .collect();
+        State::new(self.algorithm, pending.iter(), duplicates).save(&self.path)
+    }
+}
+
+/// Load the checkpoint at `path`: the candidate groups still to be hashed, split by size
+/// again if `split_by_size` is set, and the duplicate groups confirmed before.
+///
+/// Files that no longer exist are dropped, as are confirmed duplicates whose size or
+/// modification time changed since they were hashed.
+pub fn load(
+    path: &Path,
+    algorithm: Algorithm,
+    split_by_size: bool,
+) -> Result<(SizeGroups, HashGroups), String> {
+    let contents =
+        fs::read(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let state: State = serde_json::from_slice(&contents)
+        .map_err(|e| format!("{} is not a checkpoint file: {}", path.display(), e))?;
+    if state.algorithm != algorithm.name() {
+        return Err(format!(
+            "{} was written with --hash {}, resume it with the same algorithm",
+            path.display(),
+            state.algorithm
+        ));
+    }
+
+    let duplicates = state
+        .duplicates
+        .into_iter()
+        .filter_map(|group| {
+            let hash = Digest::from_hex(&group.hash)?;
+            let files: Vec<FileEntry> = group
+                .files
+                .iter()
+                .filter_map(|saved| {
+                    scanner::stat_file(&saved.path)
+                        .filter(|file| file.size == saved.size && file.modified == saved.modified)
+                })
+                .collect();
+            (files.len() >= 2).then_some(HashGroup { hash, files })
+        })
+        .collect();
+    Ok((
+        deferral::stat_groups(state.pending, split_by_size),
+        duplicates,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher;
+    use tempfile::TempDir;
+
+    fn create_file(dir: &Path, name: &str, content: &[u8]) -> FileEntry {
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        scanner::stat_file(&path).unwrap()
+    }
+
+    fn names(group: &[FileEntry]) -> Vec<&str> {
+        group
+            .iter()
+            .map(|file| file.path.file_name().unwrap().to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_resume_from_last_checkpoint() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("checkpoint.json");
+        let size_groups = vec![
+            vec![
+                create_file(temp.path(), "a1", b"aaa"),
+                create_file(temp.path(), "a2", b"aaa"),
+            ],
+            vec![
+                create_file(temp.path(), "b1", b"bb"),
+                create_file(temp.path(), "b2", b"bb"),
+            ],
+        ];
+        let checkpoint =
+            Checkpoint::new(&path, Algorithm::Blake3, Duration::ZERO, &size_groups, &[]).unwrap();
+
+        // Killed right after the scan
+        let (pending, duplicates) = load(&path, Algorithm::Blake3, true).unwrap();
+        assert_eq!(pending.len(), 2);
+        assert!(duplicates.is_empty());
+
+        let group = HashGroup {
+            hash: hasher::hash_bytes(b"aaa"),
+            files: size_groups[0].clone(),
+        };
+        checkpoint.finish(0, std::slice::from_ref(&group));
+        let (pending, duplicates) = load(&path, Algorithm::Blake3, true).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(names(&pending[0]), ["b1", "b2"]);
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].hash, group.hash);
+        assert_eq!(names(&duplicates[0].files), ["a1", "a2"]);
+
+        // Changed files leave their confirmed group
+        fs::write(temp.path().join("a2"), b"aaaa").unwrap();
+        let (_, duplicates) = load(&path, Algorithm::Blake3, true).unwrap();
+        assert!(duplicates.is_empty());
+
+        assert!(
+            load(&path, Algorithm::Sha256, true)
+                .unwrap_err()
+                .contains("--hash blake3")
+        );
+    }
+
+    #[test]
+    fn test_finish_run() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("checkpoint.json");
+        let group = vec![
+            create_file(temp.path(), "a1", b"aaa"),
+            create_file(temp.path(), "a2", b"aaa"),
+        ];
+        let checkpoint = Checkpoint::new(
+            &path,
+            Algorithm::Blake3,
+            CHECKPOINT_INTERVAL,
+            std::slice::from_ref(&group),
+            &[],
+        )
+        .unwrap();
+
+        checkpoint
+            .finish_run(std::slice::from_ref(&group), &[])
+            .unwrap();
+        let (pending, _) = load(&path, Algorithm::Blake3, false).unwrap();
+        assert_eq!(pending.len(), 1);
+
+        checkpoint.finish_run(&[], &[]).unwrap();
+        assert!(!path.exists());
+    }
+}
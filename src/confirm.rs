@@ -0,0 +1,455 @@
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use clap::ValueEnum;
+use rayon::prelude::*;
+
+use crate::grouping;
+use crate::hasher::{self, Digest, HashGroup, HashGroups, Hasher, read_full};
+use crate::iomode::{self, HashFile};
+use crate::scanner::FileEntry;
+
+/// Final step of duplicate detection, deciding which candidates are duplicates of each other.
+pub trait Confirmer: Send + Sync {
+    /// Split candidates into groups of duplicates.
+    /// Returns only groups with 2+ files.
+    ///
+    /// The size and modification time of returned entries describe the compared content.
+    fn confirm(&self, candidates: Vec<FileEntry>) -> HashGroups;
+
+    /// Whether confirmed duplicates are byte-for-byte identical (after any skipped header).
+    ///
+    /// Only exact strategies can be narrowed down by size and partial hash beforehand,
+    /// and only their groups can be acted on.
+    fn is_exact(&self) -> bool {
+        true
+    }
+}
+
+/// Available confirmation strategies
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Strategy {
+    /// Compare hashes of the full contents (algorithm from --hash)
+    #[default]
+    FullHash,
+    /// Compare the contents byte by byte, independent of hash collisions
+    ByteCompare,
+    /// Compare hashes of the contents with line endings normalized (report only)
+    NormalizedHash,
+}
+
+impl Strategy {
+    /// Confirmer comparing the contents after the first `skip_header_bytes` bytes of each file
+    pub fn confirmer(self, skip_header_bytes: u64) -> Box<dyn Confirmer> {
+        match self {
+            Strategy::FullHash => Box::new(FullHash { skip_header_bytes }),
+            Strategy::ByteCompare => Box::new(ByteCompare { skip_header_bytes }),
+            Strategy::NormalizedHash => Box::new(NormalizedHash { skip_header_bytes }),
+        }
+    }
+}
+
+/// Files are duplicates if the hashes of their full contents (algorithm from `--hash`) match.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FullHash {
+    /// Number of leading bytes left out of the hash
+    pub skip_header_bytes: u64,
+}
+
+impl Confirmer for FullHash {
+    fn confirm(&self, candidates: Vec<FileEntry>) -> HashGroups {
+        hasher::group_by_full_hash(candidates, self.skip_header_bytes)
+    }
+}
+
+/// Files are duplicates if their contents are equal byte by byte.
+///
+/// Each candidate is compared against one file of every group found so far, the group's
+/// hash is computed from that file afterwards.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ByteCompare {
+    /// Number of leading bytes left out of the comparison
+    pub skip_header_bytes: u64,
+}
+
+impl Confirmer for ByteCompare {
+    fn confirm(&self, candidates: Vec<FileEntry>) -> HashGroups {
+        let mut groups: Vec<Vec<FileEntry>> = Vec::new();
+
+        for mut file in candidates {
+            if refresh_entry(&mut file).is_none() {
+                hasher::record_read_failure(&file.path);
+                continue;
+            }
+
+            // Hardlinks to the same inode don't need to be read
+            let existing = groups.iter_mut().find(|group| {
+                let first = &group[0];
+                (first.ino != 0 && (first.dev, first.ino) == (file.dev, file.ino))
+                    || files_equal(first, &file, self.skip_header_bytes).unwrap_or(false)
+            });
+            match existing {
+                Some(group) => group.push(file),
+                None => groups.push(vec![file]),
+            }
+        }
+
+        groups
+            .into_par_iter()
+            .filter(|files| files.len() >= 2)
+            .filter_map(|files| {
+                let _permit = iomode::io_permit(files[0].dev);
+                let hash = hasher::hash_reader(open_after_header(
+                    &files[0].path,
+                    self.skip_header_bytes,
+                )?)?;
+                Some(HashGroup { hash, files })
+            })
+            .collect()
+    }
+}
+
+/// Compare the files of a group confirmed by hash byte by byte (`--paranoid`), each against
+/// the group's first file. Files that differ from it (a hash collision, or a file changed or
+/// unreadable since it was hashed) are compared among themselves the same way.
+/// Returns only groups with 2+ files.
+pub fn verify_group(group: HashGroup, skip_header_bytes: u64) -> HashGroups {
+    let mut groups = Vec::new();
+    let mut remaining = group.files;
+
+    while remaining.len() >= 2 {
+        let first = remaining.remove(0);
+        let (mut equal, different): (Vec<FileEntry>, Vec<FileEntry>) =
+            remaining.into_par_iter().partition(|file| {
+                (first.ino != 0 && (first.dev, first.ino) == (file.dev, file.ino))
+                    || files_equal(&first, file, skip_header_bytes).unwrap_or(false)
+            });
+        if !equal.is_empty() {
+            equal.insert(0, first);
+            groups.push(HashGroup {
+                hash: group.hash,
+                files: equal,
+            });
+        }
+        remaining = different;
+    }
+
+    groups
+}
+
+/// Files are duplicates if they have the same file name (`--match name`), their contents
+/// are never read. The hash of a group is the hash of the shared name.
+///
+/// Files in a group may differ, so they must not be replaced with each other.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SameName {
+    /// Compare names regardless of case
+    pub ignore_case: bool,
+}
+
+impl Confirmer for SameName {
+    fn confirm(&self, candidates: Vec<FileEntry>) -> HashGroups {
+        grouping::group_by_name(candidates, self.ignore_case)
+            .into_iter()
+            .map(|files| {
+                let name = grouping::name_key(&files[0].path, self.ignore_case).unwrap_or_default();
+                HashGroup {
+                    hash: hasher::hash_bytes(name.as_bytes()),
+                    files,
+                }
+            })
+            .collect()
+    }
+
+    fn is_exact(&self) -> bool {
+        false
+    }
+}
+
+/// Files are duplicates if their contents match after normalizing line endings
+/// (`\r\n` and `\r` to `\n`), e.g. the same text file saved on Windows and Linux.
+///
+/// Files in a group may differ, so they must not be replaced with each other.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NormalizedHash {
+    /// Number of leading bytes left out of the hash
+    pub skip_header_bytes: u64,
+}
+
+impl Confirmer for NormalizedHash {
+    fn confirm(&self, candidates: Vec<FileEntry>) -> HashGroups {
+        hasher::group_by_hash(candidates, |entry| {
+            normalized_hash_entry(entry, self.skip_header_bytes)
+        })
+    }
+
+    fn is_exact(&self) -> bool {
+        false
+    }
+}
+
+/// Refresh an entry's size and modification time from the file.
+fn refresh_entry(entry: &mut FileEntry) -> Option<()> {
+    let metadata = std::fs::metadata(&entry.path).ok()?;
+    entry.size = metadata.len();
+    entry.modified = metadata.modified().ok();
+    Some(())
+}
+
+/// Open a file positioned after its first `skip` bytes
+fn open_after_header(path: &Path, skip: u64) -> Option<HashFile> {
+    let mut file = HashFile::open(path).ok()?;
+    file.seek(SeekFrom::Start(skip)).ok()?;
+    Some(file)
+}
+
+/// Check if two files have the same contents after their first `skip` bytes. Both are read
+/// under the I/O limit of the device of `a`.
+fn files_equal(a: &FileEntry, b: &FileEntry, skip: u64) -> io::Result<bool> {
+    let _permit = iomode::io_permit_files(a.dev, 2);
+    let mut file_a = HashFile::open(&a.path)?;
+    let mut file_b = HashFile::open(&b.path)?;
+    file_a.seek(SeekFrom::Start(skip))?;
+    file_b.seek(SeekFrom::Start(skip))?;
+    let mut reader_a = BufReader::new(file_a);
+    let mut reader_b = BufReader::new(file_b);
+    let mut buffer_a = vec![0u8; 64 * 1024];
+    let mut buffer_b = vec![0u8; 64 * 1024];
+
+    loop {
+        let read_a = read_full(&mut reader_a, &mut buffer_a)?;
+        let read_b = read_full(&mut reader_b, &mut buffer_b)?;
+        if buffer_a[..read_a] != buffer_b[..read_b] {
+            return Ok(false);
+        }
+        if read_a == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Compute the hash of a file's contents after the first `skip` bytes with line endings
+/// normalized to `\n`, refreshing the entry's size and modification time from the opened file.
+fn normalized_hash_entry(entry: &mut FileEntry, skip: u64) -> Option<Digest> {
+    let file = open_after_header(&entry.path, skip)?;
+    let metadata = file.metadata().ok()?;
+    entry.size = metadata.len();
+    entry.modified = metadata.modified().ok();
+
+    let mut reader = BufReader::new(file);
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut normalized = Vec::with_capacity(buffer.len());
+    let mut hasher = Hasher::new(hasher::algorithm());
+    // A `\r` at the end of a chunk may be followed by a `\n` in the next one
+    let mut pending_cr = false;
+
+    loop {
+        let bytes_read = reader.read(&mut buffer).ok()?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher::count_bytes_read(bytes_read as u64);
+
+        normalized.clear();
+        for &byte in &buffer[..bytes_read] {
+            if pending_cr {
+                normalized.push(b'\n');
+                pending_cr = false;
+                if byte == b'\n' {
+                    continue;
+                }
+            }
+            if byte == b'\r' {
+                pending_cr = true;
+            } else {
+                normalized.push(byte);
+            }
+        }
+        hasher.update(&normalized);
+    }
+
+    if pending_cr {
+        hasher.update(b"\n");
+    }
+
+    Some(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::hash_bytes;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    fn create_file(dir: &Path, name: &str, content: &[u8]) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn file_entry(path: PathBuf) -> FileEntry {
+        FileEntry {
+            path,
+            ..Default::default()
+        }
+    }
+
+    /// Sorted file names of each group, groups sorted by their first name
+    fn names(groups: HashGroups) -> Vec<Vec<String>> {
+        let mut names: Vec<Vec<String>> = groups
+            .into_iter()
+            .map(|g| {
+                let mut names: Vec<String> = g
+                    .files
+                    .iter()
+                    .map(|f| f.path.file_name().unwrap().to_string_lossy().into_owned())
+                    .collect();
+                names.sort();
+                names
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_strategies_find_identical_files() {
+        let temp = TempDir::new().unwrap();
+        let a = create_file(temp.path(), "a", b"same content");
+        let b = create_file(temp.path(), "b", b"same content");
+        let c = create_file(temp.path(), "c", b"diff content");
+
+        for strategy in Strategy::value_variants() {
+            let candidates = vec![
+                file_entry(a.clone()),
+                file_entry(b.clone()),
+                file_entry(c.clone()),
+            ];
+            let groups = strategy.confirmer(0).confirm(candidates);
+            assert_eq!(names(groups), vec![vec!["a", "b"]], "{:?}", strategy);
+        }
+    }
+
+    #[test]
+    fn test_byte_compare_multiple_groups() {
+        let temp = TempDir::new().unwrap();
+        let candidates = vec![
+            file_entry(create_file(temp.path(), "a1", b"aaaa")),
+            file_entry(create_file(temp.path(), "b1", b"bbbb")),
+            file_entry(create_file(temp.path(), "a2", b"aaaa")),
+            file_entry(create_file(temp.path(), "b2", b"bbbb")),
+            file_entry(create_file(temp.path(), "c1", b"cccc")),
+        ];
+
+        let groups = ByteCompare::default().confirm(candidates);
+
+        assert_eq!(names(groups), vec![vec!["a1", "a2"], vec!["b1", "b2"]]);
+    }
+
+    #[test]
+    fn test_verify_group_splits_differing_files() {
+        let temp = TempDir::new().unwrap();
+        // Pretend the hashes of all files collided
+        let group = HashGroup {
+            hash: hash_bytes(b"aaaa"),
+            files: vec![
+                file_entry(create_file(temp.path(), "a1", b"aaaa")),
+                file_entry(create_file(temp.path(), "b1", b"bbbb")),
+                file_entry(create_file(temp.path(), "a2", b"aaaa")),
+                file_entry(create_file(temp.path(), "b2", b"bbbb")),
+                file_entry(create_file(temp.path(), "c1", b"cccc")),
+                file_entry(temp.path().join("missing")),
+            ],
+        };
+
+        let groups = verify_group(group, 0);
+
+        assert_eq!(names(groups), vec![vec!["a1", "a2"], vec!["b1", "b2"]]);
+    }
+
+    #[test]
+    fn test_byte_compare_hash_matches_content() {
+        let temp = TempDir::new().unwrap();
+        let candidates = vec![
+            file_entry(create_file(temp.path(), "a", b"content")),
+            file_entry(create_file(temp.path(), "b", b"content")),
+        ];
+
+        let groups = ByteCompare::default().confirm(candidates);
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].hash, hash_bytes(b"content"));
+        assert_eq!(groups[0].files[0].size, 7);
+    }
+
+    #[test]
+    fn test_files_equal_different_lengths() {
+        let temp = TempDir::new().unwrap();
+        let a = FileEntry {
+            path: create_file(temp.path(), "a", b"content"),
+            ..Default::default()
+        };
+        let b = FileEntry {
+            path: create_file(temp.path(), "b", b"content and more"),
+            ..Default::default()
+        };
+
+        assert!(!files_equal(&a, &b, 0).unwrap());
+        assert!(files_equal(&a, &a, 0).unwrap());
+    }
+
+    #[test]
+    fn test_strategies_skip_header() {
+        let temp = TempDir::new().unwrap();
+        let a = create_file(temp.path(), "a", b"v1 same content");
+        let b = create_file(temp.path(), "b", b"v2 same content");
+
+        for strategy in Strategy::value_variants() {
+            let candidates = vec![file_entry(a.clone()), file_entry(b.clone())];
+            assert!(strategy.confirmer(0).confirm(candidates).is_empty());
+
+            let candidates = vec![file_entry(a.clone()), file_entry(b.clone())];
+            let groups = strategy.confirmer(2).confirm(candidates);
+            assert_eq!(names(groups), vec![vec!["a", "b"]], "{:?}", strategy);
+        }
+    }
+
+    #[test]
+    fn test_normalized_hash_ignores_line_endings() {
+        let temp = TempDir::new().unwrap();
+        let candidates = vec![
+            file_entry(create_file(temp.path(), "unix", b"line 1\nline 2\n")),
+            file_entry(create_file(temp.path(), "windows", b"line 1\r\nline 2\r\n")),
+            file_entry(create_file(temp.path(), "mac", b"line 1\rline 2\r")),
+            file_entry(create_file(temp.path(), "other", b"line 1\n\nline 2\n")),
+        ];
+
+        let groups = NormalizedHash::default().confirm(candidates);
+
+        assert_eq!(names(groups), vec![vec!["mac", "unix", "windows"]]);
+    }
+
+    #[test]
+    fn test_same_name_ignores_contents() {
+        // The files don't exist, their contents are never read
+        let candidates = vec![
+            file_entry(PathBuf::from("/first/photo.jpg")),
+            file_entry(PathBuf::from("/second/photo.jpg")),
+            file_entry(PathBuf::from("/second/other.jpg")),
+        ];
+
+        let groups = SameName::default().confirm(candidates);
+
+        assert_eq!(groups[0].hash, hash_bytes(b"photo.jpg"));
+        assert_eq!(names(groups), vec![vec!["photo.jpg", "photo.jpg"]]);
+    }
+
+    #[test]
+    fn test_only_full_hash_and_byte_compare_are_exact() {
+        assert!(FullHash::default().is_exact());
+        assert!(ByteCompare::default().is_exact());
+        assert!(!NormalizedHash::default().is_exact());
+        assert!(!SameName::default().is_exact());
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use colored::Colorize;
+use rayon::prelude::*;
+
+use crate::actions::{self, ActionOptions, ActionResult};
+use crate::confirm::Strategy;
+use crate::grouping;
+use crate::hasher;
+use crate::interrupt;
+use crate::output::DuplicateGroup;
+use crate::scanner::{self, FileEntry, ScanError, ScanFilters};
+
+/// What merging a source tree into a destination tree (`dedup merge`) does
+#[derive(Debug, Default)]
+pub struct MergePlan {
+    /// Source files with a copy in the destination, each group holds one destination copy
+    /// (the kept copy) and the source files, see [`grouping::against_reference`]
+    pub duplicates: Vec<DuplicateGroup>,
+    /// The other source files and the path they are moved to, at the same relative path
+    /// under the destination
+    pub moves: Vec<(FileEntry, PathBuf)>,
+    /// Files and directories that could not be scanned
+    pub errors: Vec<ScanError>,
+}
+
+/// Options controlling how a merge is applied
+#[derive(Debug, Default, Clone)]
+pub struct MergeOptions {
+    /// Only report what would happen without modifying files
+    pub dry_run: bool,
+    /// Print a line for every file that is moved, removed or linked
+    pub verbose: bool,
+    /// Replace source files that are already in the destination with hardlinks instead of
+    /// removing them
+    pub link: bool,
+}
+
+/// Result of merging a source tree into a destination tree
+#[derive(Debug, Default)]
+pub struct MergeResult {
+    /// Number of files moved into the destination
+    pub files_moved: usize,
+    pub bytes_moved: u64,
+    /// Number of source files removed because the destination has a copy
+    pub files_removed: usize,
+    pub bytes_freed: u64,
+    /// Result of linking the source files to their copy instead of removing them
+    pub linked: Option<ActionResult>,
+    /// Files skipped because their size or modification time changed after hashing
+    pub changed: Vec<PathBuf>,
+    /// Files left in the source because the merge was interrupted
+    pub interrupted_files: usize,
+    /// Errors encountered (path, error message)
+    pub errors: Vec<(PathBuf, String)>,
+}
+
+/// Scan `source` and `destination` and decide what happens to every source file.
+///
+/// Source files whose contents are somewhere in the destination are duplicates, all other
+/// files are moved. Empty files are always moved, they often matter by their location (e.g.
+/// `__init__.py`) rather than their contents. Symbolic links are left in the source.
+pub fn plan(source: &Path, destination: &Path) -> Result<MergePlan, String> {
+    for dir in [source, destination] {
+        if !dir.is_dir() {
+            return Err(format!("{} is not a directory", dir.display()));
+        }
+    }
+    if scanner::overlapping_root(&[source.to_path_buf()], destination).is_some() {
+        return Err(format!(
+            "{} and {} are inside each other, merge directories outside of each other",
+            source.display(),
+            destination.display()
+        ));
+    }
+
+    let filters = ScanFilters {
+        include_empty: true,
+        ..Default::default()
+    };
+    let scanned = scanner::scan_directory(source, &filters);
+    let reference = scanner::scan_reference(destination, &filters, &Arc::default());
+    let mut errors = scanned.errors;
+    errors.extend(reference.errors);
+
+    let (empty, mut files): (Vec<FileEntry>, Vec<FileEntry>) =
+        scanned.files.into_iter().partition(|f| f.size == 0);
+    let source_files: Vec<FileEntry> = files.clone();
+    files.extend(reference.files.into_iter().filter(|f| f.size > 0));
+
+    let confirmer = Strategy::FullHash.confirmer(0);
+    let mut duplicates: Vec<DuplicateGroup> = grouping::group_by_size(files)
+        .into_par_iter()
+        .filter(|group| grouping::mixes_reference(group))
+        .flat_map(|group| hasher::group_by_partial_hash(group, 0))
+        .flat_map(|group| confirmer.confirm(group))
+        .filter_map(|group| {
+            let files = grouping::against_reference(group.files)?;
+            Some(DuplicateGroup::new(group.hash, files[0].size, files))
+        })
+        .collect();
+    duplicates.sort_by(|a, b| a.files[0].path.cmp(&b.files[0].path));
+
+    let removed: HashSet<&Path> = duplicates
+        .iter()
+        .flat_map(|group| &group.files)
+        .filter(|f| !f.reference)
+        .map(|f| f.path.as_path())
+        .collect();
+    let mut moves: Vec<(FileEntry, PathBuf)> = source_files
+        .into_iter()
+        .chain(empty)
+        .filter(|file| !removed.contains(file.path.as_path()))
+        .filter_map(|file| {
+            let relative = file.path.strip_prefix(source).ok()?.to_path_buf();
+            Some((file, destination.join(relative)))
+        })
+        .collect();
+    moves.sort_by(|a, b| a.0.path.cmp(&b.0.path));
+
+    Ok(MergePlan {
+        duplicates,
+        moves,
+        errors,
+    })
+}
+
+/// Apply a merge plan: remove (or link) the duplicates, then move the other files.
+///
+/// Duplicates are only removed if neither they nor their copy in the destination changed
+/// since they were hashed. Files are never moved over an existing file, a destination path
+/// that is already taken by a different file is reported as an error and the source file
+/// stays where it is.
+///
+/// Once the run is interrupted, the file being moved or removed is finished and the others
+/// are left in the source.
+pub fn merge_trees(plan: &MergePlan, options: &MergeOptions) -> MergeResult {
+    let mut result = MergeResult::default();
+
+    if options.link {
+        let action_options = ActionOptions {
+            dry_run: options.dry_run,
+            verbose: options.verbose,
+            ..Default::default()
+        };
+        result.linked = Some(actions::hardlink_duplicates(
+            &plan.duplicates,
+            &action_options,
+        ));
+    } else {
+        for group in &plan.duplicates {
+            let keeper = actions::select_original(&group.files);
+            for file in group.files.iter().filter(|f| !f.reference) {
+                if interrupt::requested() {
+                    result.interrupted_files += 1;
+                    continue;
+                }
+                remove_duplicate(file, keeper, options, &mut result);
+            }
+        }
+    }
+
+    for (file, destination) in &plan.moves {
+        if interrupt::requested() {
+            result.interrupted_files += 1;
+            continue;
+        }
+        if options.verbose {
+            println!(
+                "{} {} -> {}",
+                if options.dry_run {
+                    "[dry-run]".yellow()
+                } else {
+                    "[moved]".green()
+                },
+                file.path.display(),
+                destination.display()
+            );
+        }
+        let moved = if options.dry_run {
+            Ok(())
+        } else {
+            move_file(&file.path, destination)
+        };
+        match moved {
+            Ok(()) => {
+                result.files_moved += 1;
+                result.bytes_moved += file.size;
+            }
+            Err(e) => result.errors.push((file.path.clone(), e.to_string())),
+        }
+    }
+
+    result
+}
+
+/// Remove a source file whose contents `keeper` in the destination already has
+fn remove_duplicate(
+    file: &FileEntry,
+    keeper: &FileEntry,
+    options: &MergeOptions,
+    result: &mut MergeResult,
+) {
+    for checked in [keeper, file] {
+        match fs::metadata(&checked.path) {
+            Ok(m) if !actions::is_unchanged(checked, &m) => {
+                result.changed.push(checked.path.clone());
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                result.errors.push((checked.path.clone(), e.to_string()));
+                return;
+            }
+        }
+    }
+
+    if options.verbose {
+        println!(
+            "{} {} (copy of {})",
+            if options.dry_run {
+                "[dry-run]".yellow()
+            } else {
+                "[removed]".green()
+            },
+            file.path.display(),
+            keeper.path.display()
+        );
+    }
+    let removed = if options.dry_run {
+        Ok(())
+    } else {
+        fs::remove_file(&file.path)
+    };
+    match removed {
+        Ok(()) => {
+            result.files_removed += 1;
+            result.bytes_freed += file.size;
+        }
+        Err(e) => result.errors.push((file.path.clone(), e.to_string())),
+    }
+}
+
+/// Move a file to a path that must not exist yet, creating its parent directories. Across
+/// filesystems the file is copied with its permissions and modification time, then removed.
+fn move_file(source: &Path, destination: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(destination).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} already exists with different contents",
+                destination.display()
+            ),
+        ));
+    }
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match fs::rename(source, destination) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            if let Err(e) = copy_file(source, destination) {
+                let _ = fs::remove_file(destination);
+                return Err(e);
+            }
+            fs::remove_file(source)
+        }
+        result => result,
+    }
+}
+
+/// Copy a file to a new path, keeping its permissions and modification time
+fn copy_file(source: &Path, destination: &Path) -> io::Result<()> {
+    let mut from = File::open(source)?;
+    let metadata = from.metadata()?;
+    let mut to = File::create_new(destination)?;
+    io::copy(&mut from, &mut to)?;
+    to.set_permissions(metadata.permissions())?;
+    to.set_modified(metadata.modified()?)?;
+    to.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn create_file(dir: &Path, name: &str, content: &[u8]) -> PathBuf {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_plan_splits_duplicates_and_moves() {
+        let temp = TempDir::new().unwrap();
+        let src = temp.path().join("src");
+        let dst = temp.path().join("dst");
+        create_file(&src, "photos/a.jpg", b"photo a");
+        create_file(&src, "photos/b.jpg", b"photo b");
+        create_file(&src, "pkg/__init__.py", b"");
+        create_file(&dst, "2024/a.jpg", b"photo a");
+        create_file(&dst, "pkg/__init__.py", b"");
+
+        let plan = plan(&src, &dst).unwrap();
+
+        assert_eq!(plan.duplicates.len(), 1);
+        let group = &plan.duplicates[0];
+        assert_eq!(
+            actions::select_original(&group.files).path,
+            dst.join("2024/a.jpg")
+        );
+        assert!(
+            group
+                .files
+                .iter()
+                .any(|f| f.path == src.join("photos/a.jpg"))
+        );
+
+        let moves: Vec<(&Path, &Path)> = plan
+            .moves
+            .iter()
+            .map(|(file, to)| (file.path.as_path(), to.as_path()))
+            .collect();
+        assert_eq!(
+            moves,
+            [
+                (
+                    src.join("photos/b.jpg").as_path(),
+                    dst.join("photos/b.jpg").as_path()
+                ),
+                (
+                    src.join("pkg/__init__.py").as_path(),
+                    dst.join("pkg/__init__.py").as_path()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_rejects_nested_directories() {
+        let temp = TempDir::new().unwrap();
+        fs::create_dir(temp.path().join("dst")).unwrap();
+
+        assert!(plan(temp.path(), &temp.path().join("dst")).is_err());
+        assert!(plan(&temp.path().join("missing"), temp.path()).is_err());
+    }
+
+    #[test]
+    fn test_move_file_never_overwrites() {
+        let temp = TempDir::new().unwrap();
+        let source = create_file(temp.path(), "a.txt", b"new");
+        let taken = create_file(temp.path(), "b.txt", b"old");
+
+        assert!(move_file(&source, &taken).is_err());
+        assert_eq!(fs::read(&taken).unwrap(), b"old");
+        assert!(source.exists());
+
+        let destination = temp.path().join("sub/dir/a.txt");
+        move_file(&source, &destination).unwrap();
+        assert!(!source.exists());
+        assert_eq!(fs::read(&destination).unwrap(), b"new");
+    }
+
+    #[test]
+    fn test_copy_file_keeps_modification_time() {
+        let temp = TempDir::new().unwrap();
+        let source = create_file(temp.path(), "a.txt", b"content");
+        let modified =
+            std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        File::options()
+            .write(true)
+            .open(&source)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        let destination = temp.path().join("b.txt");
+        copy_file(&source, &destination).unwrap();
+        assert_eq!(fs::read(&destination).unwrap(), b"content");
+        assert_eq!(
+            fs::metadata(&destination).unwrap().modified().unwrap(),
+            modified
+        );
+        assert!(copy_file(&source, &destination).is_err());
+    }
+}
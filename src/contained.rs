@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::hasher::{self, Digest, Hasher};
+use crate::iomode::{self, HashFile};
+use crate::scanner::FileEntry;
+
+/// Smallest file checked for being the start of a larger one, smaller files match the
+/// start of unrelated files too easily (e.g. common headers)
+pub const MIN_CONTAINED_SIZE: u64 = 4096;
+
+/// Size of the chunks files are read in, the first one finds the files that may contain
+/// each other
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A larger file starting with the contents of a [`ContainedFile`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Container {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// A file whose whole contents are the start of larger files, e.g. an interrupted download
+/// next to the complete one (`--find-contained`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ContainedFile {
+    pub path: PathBuf,
+    pub size: u64,
+    /// The larger files, sorted by path
+    pub contained_in: Vec<Container>,
+}
+
+/// Hash of the first `len` bytes of `file`, the I/O permit is taken by
+/// [`hasher::group_by_hash`]
+fn first_bytes_hash(file: &FileEntry, len: u64) -> Option<Digest> {
+    let mut reader = HashFile::open(&file.path).ok()?.take(len);
+    let mut buffer = vec![0; CHUNK_SIZE.min(len as usize)];
+    let mut hasher = Hasher::new(hasher::algorithm());
+    loop {
+        match hasher::read_full(&mut reader, &mut buffer).ok()? {
+            0 => break,
+            n => hasher.update(&buffer[..n]),
+        }
+    }
+    Some(hasher.finalize())
+}
+
+/// Hashes of the first `prefixes` bytes of `file`, in the order of `prefixes` (ascending),
+/// computed while reading the file once: the running hash is finalized at each length.
+fn prefix_hashes(file: &FileEntry, prefixes: &[u64]) -> Option<Vec<Digest>> {
+    let _permit = iomode::io_permit(file.dev);
+    let mut reader = HashFile::open(&file.path).ok()?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut hasher = Hasher::new(hasher::algorithm());
+    let mut hashes = Vec::with_capacity(prefixes.len());
+    let mut position = 0;
+    for &prefix in prefixes {
+        while position < prefix {
+            let len = (prefix - position).min(CHUNK_SIZE as u64) as usize;
+            let read = reader.read(&mut buffer[..len]).ok()?;
+            if read == 0 {
+                // Truncated since it was scanned
+                return None;
+            }
+            hasher.update(&buffer[..read]);
+            position += read as u64;
+        }
+        hashes.push(hasher.finalize());
+    }
+    hasher::count_bytes_read(position);
+    Some(hashes)
+}
+
+/// Find the files among `files` whose contents are the start of larger ones.
+///
+/// Files are grouped by the hash of their first 4KB, only files starting the same way are
+/// read further. Each file of a group is then read once up to its own size, and the running
+/// hash of its contents is taken at the size of every smaller file of the group: a smaller
+/// file is contained in the larger one if its full hash matches the larger one's hash at
+/// that length. Files of the same size are exact duplicates, they are reported as such.
+pub fn find_contained(files: Vec<FileEntry>) -> Vec<ContainedFile> {
+    let files: Vec<FileEntry> = files
+        .into_iter()
+        .filter(|file| file.size >= MIN_CONTAINED_SIZE && file.archived.is_none())
+        .collect();
+    let starts = hasher::group_by_hash(files, |file| first_bytes_hash(file, MIN_CONTAINED_SIZE));
+
+    let mut contained: Vec<ContainedFile> = starts
+        .into_par_iter()
+        .flat_map_iter(|group| contained_in_group(group.files))
+        .collect();
+    contained.sort_by(|a, b| a.path.cmp(&b.path));
+    contained
+}
+
+/// The files of `group`, all starting the same way, that are the start of larger files of
+/// the group
+fn contained_in_group(group: Vec<FileEntry>) -> Vec<ContainedFile> {
+    let mut sizes: Vec<u64> = group.iter().map(|file| file.size).collect();
+    sizes.sort_unstable();
+    sizes.dedup();
+    if sizes.len() < 2 {
+        return Vec::new();
+    }
+
+    // Hash of each file at every size of the group up to its own, the last is its full hash
+    let hashed: Vec<(FileEntry, HashMap<u64, Digest>)> = group
+        .into_par_iter()
+        .filter_map(|file| {
+            let prefixes: Vec<u64> = sizes
+                .iter()
+                .copied()
+                .take_while(|&size| size <= file.size)
+                .collect();
+            let hashes = prefix_hashes(&file, &prefixes)?;
+            Some((file, prefixes.into_iter().zip(hashes).collect()))
+        })
+        .collect();
+
+    let mut contained = Vec::new();
+    for (file, hashes) in &hashed {
+        let full = hashes[&file.size];
+        let mut contained_in: Vec<Container> = hashed
+            .iter()
+            .filter(|(larger, larger_hashes)| {
+                larger.size > file.size && larger_hashes.get(&file.size) == Some(&full)
+            })
+            .map(|(larger, _)| Container {
+                path: larger.path.clone(),
+                size: larger.size,
+            })
+            .collect();
+        if contained_in.is_empty() {
+            continue;
+        }
+        contained_in.sort_by(|a, b| a.path.cmp(&b.path));
+        contained.push(ContainedFile {
+            path: file.path.clone(),
+            size: file.size,
+            contained_in,
+        });
+    }
+    contained
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    fn create_file(dir: &Path, name: &str, content: &[u8]) -> FileEntry {
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        scanner::stat_file(&path).unwrap()
+    }
+
+    fn content(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_finds_truncated_copies() {
+        let temp = TempDir::new().unwrap();
+        let complete = content(200_000);
+        let files = vec![
+            create_file(temp.path(), "movie.mkv", &complete),
+            create_file(temp.path(), "movie.mkv.part", &complete[..150_000]),
+            create_file(temp.path(), "movie.mkv.crdownload", &complete[..70_001]),
+            create_file(temp.path(), "copy.mkv", &complete),
+        ];
+
+        let contained = find_contained(files);
+
+        let names: Vec<_> = contained
+            .iter()
+            .map(|file| file.path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, ["movie.mkv.crdownload", "movie.mkv.part"]);
+        let part = &contained[1];
+        assert_eq!(part.size, 150_000);
+        assert_eq!(
+            part.contained_in,
+            [
+                Container {
+                    path: temp.path().join("copy.mkv"),
+                    size: 200_000
+                },
+                Container {
+                    path: temp.path().join("movie.mkv"),
+                    size: 200_000
+                },
+            ]
+        );
+        // The partial download is the start of the other one too
+        assert_eq!(contained[0].contained_in.len(), 3);
+    }
+
+    #[test]
+    fn test_same_start_with_other_contents_is_not_contained() {
+        let temp = TempDir::new().unwrap();
+        let complete = content(100_000);
+        let mut changed = complete[..50_000].to_vec();
+        changed[40_000] ^= 1;
+        let files = vec![
+            create_file(temp.path(), "a", &complete),
+            create_file(temp.path(), "b", &changed),
+            // Too small to be checked
+            create_file(temp.path(), "c", &complete[..1000]),
+        ];
+
+        assert!(find_contained(files).is_empty());
+    }
+}
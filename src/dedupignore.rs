@@ -0,0 +1,183 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+use crate::util;
+
+/// Name of the files with exclusions for the directory they are in and everything below
+pub const FILE_NAME: &str = ".dedupignore";
+
+/// Patterns of one `.dedupignore` file
+#[derive(Debug)]
+struct IgnoreFile {
+    /// Directory of the file, patterns are matched against paths relative to it
+    base: PathBuf,
+    patterns: GlobSet,
+    /// Per pattern: whether it re-includes paths (`!pattern`) and whether it only applies to
+    /// directories (`pattern/`)
+    rules: Vec<(bool, bool)>,
+    /// The file of a directory further up
+    parent: Option<Arc<IgnoreFile>>,
+}
+
+/// The `.dedupignore` files that apply to a directory, from the closest one up.
+///
+/// Patterns follow gitignore: a pattern without a slash matches names at any depth, one with
+/// a slash is relative to the directory of the file (a leading slash is dropped), a trailing
+/// slash only matches directories and `!` re-includes what an earlier pattern excluded. The
+/// last matching pattern of the closest file decides.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreStack(Option<Arc<IgnoreFile>>);
+
+impl IgnoreStack {
+    /// The stack for the subdirectories of `dir`, with its `.dedupignore` file on top if it
+    /// has one. Files that can't be read or patterns that are invalid are warned about.
+    pub fn enter(&self, dir: &Path) -> IgnoreStack {
+        let path = dir.join(FILE_NAME);
+        match fs::read_to_string(&path) {
+            Ok(contents) => self.with_patterns(dir, &contents, &path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => self.clone(),
+            Err(e) => {
+                util::warn(format_args!("could not read {}: {}", path.display(), e));
+                self.clone()
+            }
+        }
+    }
+
+    /// The stack with the patterns in `contents` on top, applying to `dir`. `source` is only
+    /// used in warnings.
+    fn with_patterns(&self, dir: &Path, contents: &str, source: &Path) -> IgnoreStack {
+        let mut builder = GlobSetBuilder::new();
+        let mut rules = Vec::new();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, pattern) = match line.strip_prefix('!') {
+                Some(pattern) => (true, pattern),
+                None => (false, line),
+            };
+            let (dir_only, pattern) = match pattern.strip_suffix('/') {
+                Some(pattern) => (true, pattern),
+                None => (false, pattern),
+            };
+            let pattern = match pattern.strip_prefix('/') {
+                Some(anchored) => anchored.to_string(),
+                None if pattern.contains('/') => pattern.to_string(),
+                None => format!("**/{}", pattern),
+            };
+
+            match GlobBuilder::new(&pattern).literal_separator(true).build() {
+                Ok(glob) => {
+                    builder.add(glob);
+                    rules.push((negated, dir_only));
+                }
+                Err(e) => util::warn(format_args!(
+                    "invalid pattern '{}' in {}: {}",
+                    line,
+                    source.display(),
+                    e
+                )),
+            }
+        }
+
+        match builder.build() {
+            Ok(patterns) if !rules.is_empty() => IgnoreStack(Some(Arc::new(IgnoreFile {
+                base: dir.to_path_buf(),
+                patterns,
+                rules,
+                parent: self.0.clone(),
+            }))),
+            _ => self.clone(),
+        }
+    }
+
+    /// Whether `path` is excluded by the patterns of the stack
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut file = self.0.as_deref();
+        while let Some(current) = file {
+            if let Ok(relative) = path.strip_prefix(&current.base) {
+                let last_match = current
+                    .patterns
+                    .matches(relative)
+                    .into_iter()
+                    .filter(|&i| is_dir || !current.rules[i].1)
+                    .max();
+                if let Some(i) = last_match {
+                    return !current.rules[i].0;
+                }
+            }
+            file = current.parent.as_deref();
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn stack(dir: &str, contents: &str) -> IgnoreStack {
+        IgnoreStack::default().with_patterns(Path::new(dir), contents, Path::new("test"))
+    }
+
+    #[test]
+    fn test_names_match_at_any_depth() {
+        let ignore = stack("/p", "# build output\n*.o\n\ntarget\n");
+
+        assert!(ignore.is_ignored(Path::new("/p/main.o"), false));
+        assert!(ignore.is_ignored(Path::new("/p/src/deep/lib.o"), false));
+        assert!(ignore.is_ignored(Path::new("/p/sub/target"), true));
+        assert!(!ignore.is_ignored(Path::new("/p/main.c"), false));
+        assert!(!ignore.is_ignored(Path::new("/other/main.o"), false));
+    }
+
+    #[test]
+    fn test_patterns_with_slash_are_anchored() {
+        let ignore = stack("/p", "/build\ndocs/*.pdf\n");
+
+        assert!(ignore.is_ignored(Path::new("/p/build"), true));
+        assert!(!ignore.is_ignored(Path::new("/p/sub/build"), true));
+        assert!(ignore.is_ignored(Path::new("/p/docs/a.pdf"), false));
+        assert!(!ignore.is_ignored(Path::new("/p/docs/old/a.pdf"), false));
+    }
+
+    #[test]
+    fn test_directory_only_patterns() {
+        let ignore = stack("/p", "cache/\n");
+
+        assert!(ignore.is_ignored(Path::new("/p/cache"), true));
+        assert!(!ignore.is_ignored(Path::new("/p/cache"), false));
+    }
+
+    #[test]
+    fn test_negation_and_precedence() {
+        let outer = stack("/p", "*.log\n!keep.log\n");
+        assert!(outer.is_ignored(Path::new("/p/a.log"), false));
+        assert!(!outer.is_ignored(Path::new("/p/keep.log"), false));
+
+        // The closer file decides over the outer one
+        let inner = outer.with_patterns(Path::new("/p/sub"), "!*.log\n", Path::new("test"));
+        assert!(!inner.is_ignored(Path::new("/p/sub/a.log"), false));
+        assert!(inner.is_ignored(Path::new("/p/other/a.log"), false));
+    }
+
+    #[test]
+    fn test_enter_reads_file() {
+        let temp = TempDir::new().unwrap();
+        fs::write(temp.path().join(FILE_NAME), "*.tmp\n").unwrap();
+
+        let ignore = IgnoreStack::default().enter(temp.path());
+        assert!(ignore.is_ignored(&temp.path().join("a.tmp"), false));
+
+        // Directories without a file keep the stack
+        let sub = temp.path().join("sub");
+        fs::create_dir(&sub).unwrap();
+        assert!(ignore.enter(&sub).is_ignored(&sub.join("b.tmp"), false));
+    }
+}
@@ -0,0 +1,131 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::grouping::{self, SizeGroups};
+use crate::output;
+use crate::scanner::{self, FileEntry};
+
+/// Candidate groups that were not hashed before `--timeout`, saved for a later run
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DeferredGroups {
+    groups: Vec<Vec<PathBuf>>,
+}
+
+/// Save the paths of candidate groups to `path`, replacing it atomically.
+pub fn save(path: &Path, groups: &[Vec<FileEntry>]) -> io::Result<()> {
+    let deferred = DeferredGroups {
+        groups: groups
+            .iter()
+            .map(|group| group.iter().map(|f| f.path.clone()).collect())
+            .collect(),
+    };
+
+    output::write_atomically(path, |out| {
+        serde_json::to_writer(&mut *out, &deferred)?;
+        Ok(())
+    })
+}
+
+/// Load candidate groups saved by [`save`], with the current size and modification time
+/// of their files.
+///
+/// Files that no longer exist are dropped. If `split_by_size` is set, groups are split
+/// by size again since files may have changed since they were deferred.
+pub fn load(path: &Path, split_by_size: bool) -> Result<SizeGroups, String> {
+    let contents =
+        fs::read(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let deferred: DeferredGroups = serde_json::from_slice(&contents)
+        .map_err(|e| format!("{} is not a deferral file: {}", path.display(), e))?;
+
+    Ok(stat_groups(deferred.groups, split_by_size))
+}
+
+/// Candidate groups of the files at `groups` with their current size and modification
+/// time, see [`load`]
+pub fn stat_groups(groups: Vec<Vec<PathBuf>>, split_by_size: bool) -> SizeGroups {
+    groups
+        .into_iter()
+        .flat_map(|paths| {
+            let files: Vec<FileEntry> =
+                paths.iter().filter_map(|p| scanner::stat_file(p)).collect();
+            if split_by_size {
+                grouping::group_by_size(files)
+            } else if files.len() >= 2 {
+                vec![files]
+            } else {
+                Vec::new()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn create_file(dir: &Path, name: &str, content: &[u8]) -> FileEntry {
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        scanner::stat_file(&path).unwrap()
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let temp = TempDir::new().unwrap();
+        let groups = vec![
+            vec![
+                create_file(temp.path(), "a1", b"aaa"),
+                create_file(temp.path(), "a2", b"aaa"),
+            ],
+            vec![
+                create_file(temp.path(), "b1", b"bb"),
+                create_file(temp.path(), "b2", b"bb"),
+            ],
+        ];
+        let file = temp.path().join("deferred.json");
+
+        save(&file, &groups).unwrap();
+        let mut loaded = load(&file, true).unwrap();
+        loaded.sort_by_key(|g| g[0].size);
+
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].len(), 2);
+        assert_eq!(loaded[0][0].size, 2);
+        assert_eq!(loaded[1][0].size, 3);
+    }
+
+    #[test]
+    fn test_load_drops_changed_and_missing_files() {
+        let temp = TempDir::new().unwrap();
+        let group = vec![
+            create_file(temp.path(), "a1", b"aaa"),
+            create_file(temp.path(), "a2", b"aaa"),
+            create_file(temp.path(), "a3", b"aaa"),
+        ];
+        let file = temp.path().join("deferred.json");
+        save(&file, &[group]).unwrap();
+
+        fs::remove_file(temp.path().join("a1")).unwrap();
+        fs::write(temp.path().join("a2"), b"longer").unwrap();
+
+        assert!(load(&file, true).unwrap().is_empty());
+        assert!(load(&file, false).unwrap()[0].len() == 2);
+    }
+
+    #[test]
+    fn test_load_invalid_file() {
+        let temp = TempDir::new().unwrap();
+        let file = temp.path().join("deferred.json");
+        fs::write(&file, b"not json").unwrap();
+
+        assert!(
+            load(&file, true)
+                .unwrap_err()
+                .contains("not a deferral file")
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::hasher::Digest;
+use crate::merge::{ReportFile, read_report};
+use crate::output::group_id;
+
+/// A duplicate group found in only one of the reports
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GroupDiff {
+    pub id: String,
+    pub hash: String,
+    pub size: u64,
+    pub files: Vec<PathBuf>,
+    pub wasted_bytes: u64,
+}
+
+/// A duplicate group in both reports whose files differ
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChangedGroup {
+    pub id: String,
+    pub hash: String,
+    pub size: u64,
+    /// Number of files in the old and the new report
+    pub files_before: usize,
+    pub files_after: usize,
+    /// Files only listed in the new report
+    pub added: Vec<PathBuf>,
+    /// Files only listed in the old report
+    pub removed: Vec<PathBuf>,
+}
+
+/// Differences between the duplicate groups of two reports, matched by content hash
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct ReportDiff {
+    /// Groups only in the new report
+    pub new: Vec<GroupDiff>,
+    /// Groups only in the old report
+    pub resolved: Vec<GroupDiff>,
+    /// Groups in both reports with other files
+    pub changed: Vec<ChangedGroup>,
+    /// Groups in both reports with the same files
+    pub unchanged: usize,
+    pub wasted_bytes_before: u64,
+    pub wasted_bytes_after: u64,
+}
+
+/// Files of each content hash in a report, with their size
+type Groups = HashMap<Digest, (u64, BTreeSet<PathBuf>)>;
+
+fn groups_by_hash(report: ReportFile) -> Result<Groups, String> {
+    let mut groups = Groups::new();
+    for group in report.groups {
+        let hash =
+            Digest::from_hex(&group.hash).ok_or_else(|| format!("invalid hash {}", group.hash))?;
+        let entry = groups.entry(hash).or_insert((group.size, BTreeSet::new()));
+        entry.1.extend(group.files);
+    }
+    Ok(groups)
+}
+
+fn wasted_bytes(size: u64, files: usize) -> u64 {
+    size * files.saturating_sub(1) as u64
+}
+
+fn group_diff(hash: &Digest, size: u64, files: BTreeSet<PathBuf>) -> GroupDiff {
+    GroupDiff {
+        id: group_id(hash),
+        hash: hash.to_hex(),
+        size,
+        wasted_bytes: wasted_bytes(size, files.len()),
+        files: files.into_iter().collect(),
+    }
+}
+
+/// Read two JSON reports and compare their groups
+pub fn diff_report_files(old: &Path, new: &Path) -> Result<ReportDiff, String> {
+    diff_reports(read_report(old)?, read_report(new)?)
+}
+
+/// Compare the groups of the `old` report with those of the `new` one. Groups are matched
+/// by their content hash, so both reports must have been hashed with the same algorithm.
+fn diff_reports(old: ReportFile, new: ReportFile) -> Result<ReportDiff, String> {
+    if old.hash_algorithm != new.hash_algorithm {
+        return Err(
+            "the reports were hashed with different algorithms (--hash), their groups can't be matched"
+                .to_string(),
+        );
+    }
+    let mut old = groups_by_hash(old)?;
+    let new = groups_by_hash(new)?;
+
+    let mut diff = ReportDiff::default();
+    for (hash, (size, files)) in new {
+        diff.wasted_bytes_after += wasted_bytes(size, files.len());
+        let Some((old_size, old_files)) = old.remove(&hash) else {
+            diff.new.push(group_diff(&hash, size, files));
+            continue;
+        };
+        diff.wasted_bytes_before += wasted_bytes(old_size, old_files.len());
+        if files == old_files {
+            diff.unchanged += 1;
+            continue;
+        }
+        diff.changed.push(ChangedGroup {
+            id: group_id(&hash),
+            hash: hash.to_hex(),
+            size,
+            files_before: old_files.len(),
+            files_after: files.len(),
+            added: files.difference(&old_files).cloned().collect(),
+            removed: old_files.difference(&files).cloned().collect(),
+        });
+    }
+    for (hash, (size, files)) in old {
+        diff.wasted_bytes_before += wasted_bytes(size, files.len());
+        diff.resolved.push(group_diff(&hash, size, files));
+    }
+
+    // Most wasted space first
+    diff.new
+        .sort_by(|a, b| b.wasted_bytes.cmp(&a.wasted_bytes).then(a.id.cmp(&b.id)));
+    diff.resolved
+        .sort_by(|a, b| b.wasted_bytes.cmp(&a.wasted_bytes).then(a.id.cmp(&b.id)));
+    diff.changed.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::hash_bytes;
+
+    fn report(groups: &[(&[u8], u64, &[&str])]) -> ReportFile {
+        let groups: Vec<_> = groups
+            .iter()
+            .map(|(hash, size, files)| {
+                serde_json::json!({
+                    "hash": hash_bytes(hash).to_hex(),
+                    "size": size,
+                    "files": files,
+                })
+            })
+            .collect();
+        serde_json::from_value(serde_json::json!({
+            "stats": {"total_files": 10, "duplicate_files": 0, "wasted_bytes": 0},
+            "groups": groups,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_diff_new_resolved_and_changed_groups() {
+        let old = report(&[
+            (b"kept", 10, &["/k1", "/k2"]),
+            (b"gone", 100, &["/g1", "/g2", "/g3"]),
+            (b"grew", 5, &["/c1", "/c2"]),
+        ]);
+        let new = report(&[
+            (b"kept", 10, &["/k2", "/k1"]),
+            (b"grew", 5, &["/c1", "/c3", "/c4"]),
+            (b"small", 1, &["/s1", "/s2"]),
+            (b"large", 50, &["/l1", "/l2"]),
+        ]);
+
+        let diff = diff_reports(old, new).unwrap();
+
+        assert_eq!(diff.unchanged, 1);
+        let new_files: Vec<_> = diff.new.iter().map(|g| g.files[0].clone()).collect();
+        assert_eq!(new_files, [PathBuf::from("/l1"), PathBuf::from("/s1")]);
+        assert_eq!(diff.resolved.len(), 1);
+        assert_eq!(diff.resolved[0].wasted_bytes, 200);
+        assert_eq!(diff.resolved[0].id, group_id(&hash_bytes(b"gone")));
+        assert_eq!(
+            diff.changed,
+            [ChangedGroup {
+                id: group_id(&hash_bytes(b"grew")),
+                hash: hash_bytes(b"grew").to_hex(),
+                size: 5,
+                files_before: 2,
+                files_after: 3,
+                added: vec![PathBuf::from("/c3"), PathBuf::from("/c4")],
+                removed: vec![PathBuf::from("/c2")],
+            }]
+        );
+        assert_eq!(diff.wasted_bytes_before, 10 + 200 + 5);
+        assert_eq!(diff.wasted_bytes_after, 10 + 10 + 1 + 50);
+    }
+
+    #[test]
+    fn test_diff_rejects_other_algorithms() {
+        let old = report(&[]);
+        let mut new = report(&[]);
+        new.hash_algorithm = crate::hasher::Algorithm::Sha256;
+
+        assert!(
+            diff_reports(old, new)
+                .unwrap_err()
+                .contains("different algorithms")
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::hasher::ReadFailure;
+use crate::output;
+use crate::scanner::ScanError;
+
+/// What happened to a path recorded in the `--error-log`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorKind {
+    /// Could not be read while scanning, e.g. a directory without permission
+    Scan,
+    /// Scanned but could not be read to hash it
+    Unreadable,
+    /// Deleted between the scan and hashing
+    Vanished,
+    /// Skipped by the action because it changed after it was hashed
+    Changed,
+    /// The action failed for it
+    Action,
+    /// A command from `--exec-per-group` or `--exec-per-duplicate` failed for it
+    Command,
+}
+
+/// A path that was skipped or failed during a run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LoggedError {
+    pub kind: ErrorKind,
+    /// The file or directory, or the command line for `--exec-per-group`
+    pub path: PathBuf,
+    pub error: String,
+}
+
+/// Every path skipped or failed during a run, written to the `--error-log` file
+#[derive(Debug, Default)]
+pub struct ErrorLog {
+    entries: Vec<LoggedError>,
+}
+
+impl ErrorLog {
+    /// Record that `path` was skipped or failed
+    pub fn add(&mut self, kind: ErrorKind, path: impl Into<PathBuf>, error: impl Into<String>) {
+        self.entries.push(LoggedError {
+            kind,
+            path: path.into(),
+            error: error.into(),
+        });
+    }
+
+    /// Record the files and directories that could not be scanned
+    pub fn add_scan_errors(&mut self, errors: &[ScanError]) {
+        for error in errors {
+            self.add(ErrorKind::Scan, &error.path, &error.error);
+        }
+    }
+
+    /// Record the scanned files that could not be read to hash them
+    pub fn add_read_failures(&mut self, failures: &[(PathBuf, ReadFailure)]) {
+        for (path, failure) in failures {
+            match failure {
+                ReadFailure::Vanished => self.add(
+                    ErrorKind::Vanished,
+                    path,
+                    "deleted before it could be hashed",
+                ),
+                ReadFailure::Unreadable(error) => self.add(ErrorKind::Unreadable, path, error),
+            }
+        }
+    }
+
+    /// Write one JSON object per line, in the order they were recorded
+    fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        for entry in &self.entries {
+            serde_json::to_writer(&mut *out, entry)?;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    /// Write the log to `path`, replacing it atomically. An empty file means nothing failed.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        output::write_atomically(path, |out| self.write(out))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writes_one_json_line_per_error() {
+        let mut log = ErrorLog::default();
+        log.add_scan_errors(&[ScanError {
+            path: PathBuf::from("/private"),
+            error: "Permission denied (os error 13)".to_string(),
+        }]);
+        log.add_read_failures(&[
+            (PathBuf::from("/gone"), ReadFailure::Vanished),
+            (
+                PathBuf::from("/locked"),
+                ReadFailure::Unreadable("Permission denied (os error 13)".to_string()),
+            ),
+        ]);
+        log.add(
+            ErrorKind::Action,
+            "/a",
+            "Read-only file system (os error 30)",
+        );
+
+        let mut out = Vec::new();
+        log.write(&mut out).unwrap();
+
+        let lines: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let kinds: Vec<_> = lines.iter().map(|line| line["kind"].clone()).collect();
+        assert_eq!(kinds, ["scan", "vanished", "unreadable", "action"]);
+        assert_eq!(lines[0]["path"], "/private");
+        assert_eq!(lines[3]["error"], "Read-only file system (os error 30)");
+    }
+}
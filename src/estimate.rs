@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::grouping::SizeGroups;
+use crate::output::DuplicateGroup;
+
+/// Standard normal quantile of the two-sided 95% confidence interval
+const Z_95: f64 = 1.96;
+
+/// Size groups picked for hashing by [`sample`], and what is needed to extrapolate from them
+#[derive(Debug, Clone)]
+pub struct Sample {
+    /// Probability of each size group to be picked
+    rate: f64,
+    candidate_groups: usize,
+    sampled_groups: usize,
+    /// Upper limits if every candidate turned out to be a duplicate
+    max_duplicate_files: u64,
+    max_wasted_bytes: u64,
+}
+
+/// Expected duplicates of the whole tree, extrapolated from a sample (`--estimate`)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Estimate {
+    /// Fraction of the candidate size groups picked for hashing
+    pub sample_rate: f64,
+    /// Size groups with at least two files
+    pub candidate_groups: usize,
+    /// Size groups that were hashed
+    pub sampled_groups: usize,
+    pub duplicate_files: Bounds,
+    pub wasted_bytes: Bounds,
+}
+
+/// Extrapolated value with its 95% confidence interval
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Bounds {
+    pub estimate: u64,
+    pub low: u64,
+    pub high: u64,
+}
+
+/// Pick each size group with probability `rate`, the rest is not hashed.
+///
+/// Groups are picked by a hash of their file size, so the same tree gives the same sample on
+/// every run while the pick is independent of anything that correlates with duplication.
+pub fn sample(groups: SizeGroups, rate: f64) -> (SizeGroups, Sample) {
+    let candidate_groups = groups.len();
+    let max_duplicate_files = groups.iter().map(|g| g.len() as u64).sum();
+    let max_wasted_bytes = groups
+        .iter()
+        .map(|g| g[0].size * (g.len() as u64 - 1))
+        .sum();
+
+    let sampled: SizeGroups = groups
+        .into_iter()
+        .filter(|group| is_picked(group[0].size, rate))
+        .collect();
+
+    let sample = Sample {
+        rate,
+        candidate_groups,
+        sampled_groups: sampled.len(),
+        max_duplicate_files,
+        max_wasted_bytes,
+    };
+    (sampled, sample)
+}
+
+/// Whether the size group of files with `size` bytes is part of a sample with `rate`
+fn is_picked(size: u64, rate: f64) -> bool {
+    let hash = blake3::hash(&size.to_le_bytes());
+    let value = u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap());
+    (value as f64) < rate * u64::MAX as f64
+}
+
+impl Sample {
+    /// Extrapolate the duplicates confirmed in the sampled size groups to all candidates.
+    ///
+    /// Each size group counts as one sampling unit. The totals are Horvitz-Thompson
+    /// estimates (sampled totals divided by the rate) with normal-approximation bounds,
+    /// clamped to what was actually found and to the most the candidates could hold. The
+    /// bounds get narrow once a good number of sampled groups contain duplicates.
+    pub fn extrapolate(&self, groups: &[DuplicateGroup]) -> Estimate {
+        let mut by_size: HashMap<u64, (u64, u64)> = HashMap::new();
+        for group in groups {
+            let unit = by_size.entry(group.size).or_default();
+            unit.0 += group.total_files() as u64;
+            unit.1 += group.wasted_bytes();
+        }
+
+        let files: Vec<f64> = by_size.values().map(|unit| unit.0 as f64).collect();
+        let bytes: Vec<f64> = by_size.values().map(|unit| unit.1 as f64).collect();
+        Estimate {
+            sample_rate: self.rate,
+            candidate_groups: self.candidate_groups,
+            sampled_groups: self.sampled_groups,
+            duplicate_files: self.bounds(&files, self.max_duplicate_files),
+            wasted_bytes: self.bounds(&bytes, self.max_wasted_bytes),
+        }
+    }
+
+    fn bounds(&self, values: &[f64], max: u64) -> Bounds {
+        let found: f64 = values.iter().sum();
+        let estimate = found / self.rate;
+        let variance: f64 =
+            (1.0 - self.rate) / (self.rate * self.rate) * values.iter().map(|v| v * v).sum::<f64>();
+        let margin = Z_95 * variance.sqrt();
+
+        let clamp = |value: f64| (value.max(found).min(max as f64)).round() as u64;
+        Bounds {
+            estimate: clamp(estimate),
+            low: clamp(estimate - margin),
+            high: clamp(estimate + margin),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::hash_bytes;
+    use crate::scanner::FileEntry;
+
+    fn size_group(size: u64, files: usize) -> Vec<FileEntry> {
+        (0..files)
+            .map(|i| FileEntry {
+                path: format!("/{}/{}", size, i).into(),
+                size,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn duplicates(group: Vec<FileEntry>) -> DuplicateGroup {
+        DuplicateGroup::new(
+            hash_bytes(&group[0].size.to_le_bytes()),
+            group[0].size,
+            group,
+        )
+    }
+
+    #[test]
+    fn test_sample_is_deterministic() {
+        let groups: SizeGroups = (1..=1000).map(|size| size_group(size, 2)).collect();
+
+        let (first, sample) = sample(groups.clone(), 0.1);
+        let (second, _) = super::sample(groups, 0.1);
+
+        assert_eq!(sample.candidate_groups, 1000);
+        assert_eq!(sample.sampled_groups, first.len());
+        assert!((50..150).contains(&first.len()), "{}", first.len());
+        let sizes = |groups: &SizeGroups| groups.iter().map(|g| g[0].size).collect::<Vec<_>>();
+        assert_eq!(sizes(&first), sizes(&second));
+    }
+
+    #[test]
+    fn test_full_sample_is_exact() {
+        let groups: SizeGroups = (1..=10).map(|size| size_group(size, 3)).collect();
+        let (sampled, sample) = sample(groups, 1.0);
+        assert_eq!(sampled.len(), 10);
+
+        let found: Vec<_> = sampled.into_iter().take(4).map(duplicates).collect();
+        let estimate = sample.extrapolate(&found);
+
+        let files = Bounds {
+            estimate: 12,
+            low: 12,
+            high: 12,
+        };
+        assert_eq!(estimate.duplicate_files, files);
+        let wasted: u64 = found.iter().map(|g| g.wasted_bytes()).sum();
+        assert_eq!(estimate.wasted_bytes.estimate, wasted);
+        assert_eq!(estimate.wasted_bytes.high, wasted);
+    }
+
+    #[test]
+    fn test_extrapolation_bounds() {
+        let groups: SizeGroups = (1..=1000).map(|size| size_group(size, 2)).collect();
+        let (sampled, sample) = sample(groups, 0.2);
+        let picked = sampled.len() as u64;
+        let estimate = sample.extrapolate(&sampled.into_iter().map(duplicates).collect::<Vec<_>>());
+
+        // Every candidate is a duplicate, so the estimate is close to all 2000 files
+        let files = estimate.duplicate_files;
+        assert_eq!(
+            files.estimate,
+            (picked as f64 * 2.0 / 0.2).round().min(2000.0) as u64
+        );
+        assert!(files.low >= picked * 2 && files.low <= files.estimate);
+        assert!(files.high >= files.estimate && files.high <= 2000);
+    }
+}
@@ -1,19 +1,31 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
 
 use crate::scanner::FileEntry;
 
 /// A collection of size groups, where each group contains files of the same size
-pub type SizeGroups = Vec<Vec<PathBuf>>;
+pub type SizeGroups = Vec<Vec<FileEntry>>;
+
+/// What files must have in common to be reported as duplicates
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum MatchMode {
+    /// The same contents
+    #[default]
+    Content,
+    /// The same file name, whatever their contents (report only)
+    Name,
+}
 
 /// Groups files by size and returns size groups (each group contains files of the same size).
 ///
 /// This is the first stage of duplicate detection - files can only be duplicates
 /// if they have the same size, so we filter out unique-sized files early.
 pub fn group_by_size(files: Vec<FileEntry>) -> SizeGroups {
-    let mut size_map: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    let mut size_map: HashMap<u64, Vec<FileEntry>> = HashMap::new();
     for file in files {
-        size_map.entry(file.size).or_default().push(file.path);
+        size_map.entry(file.size).or_default().push(file);
     }
 
     size_map
@@ -23,6 +35,124 @@ pub fn group_by_size(files: Vec<FileEntry>) -> SizeGroups {
         .collect()
 }
 
+/// The name a file is grouped by with `--match name`, lowercased if `ignore_case` is set
+pub fn name_key(path: &Path, ignore_case: bool) -> Option<String> {
+    let name = path.file_name()?.to_string_lossy();
+    Some(if ignore_case {
+        name.to_lowercase()
+    } else {
+        name.into_owned()
+    })
+}
+
+/// Groups files by their file name, regardless of case if `ignore_case` is set.
+///
+/// Used instead of [`group_by_size`] to find scattered copies like `IMG_0001.JPG` on several
+/// drives, which may have been edited or re-encoded. Their contents are never compared.
+pub fn group_by_name(files: Vec<FileEntry>, ignore_case: bool) -> SizeGroups {
+    let mut name_map: HashMap<String, Vec<FileEntry>> = HashMap::new();
+    for file in files {
+        if let Some(name) = name_key(&file.path, ignore_case) {
+            name_map.entry(name).or_default().push(file);
+        }
+    }
+
+    name_map
+        .into_values()
+        .filter(|files| files.len() >= 2)
+        .collect()
+}
+
+/// Whether the files live in more than one directory (`--cross-dir-only`)
+pub fn spans_directories(files: &[FileEntry]) -> bool {
+    let first = files.first().and_then(|f| f.path.parent());
+    files.iter().any(|f| f.path.parent() != first)
+}
+
+/// Split the files by the directory they are in, keeping directories with at least two
+/// of them (`--same-dir-only`)
+pub fn split_by_directory(files: Vec<FileEntry>) -> SizeGroups {
+    let mut dir_map: HashMap<PathBuf, Vec<FileEntry>> = HashMap::new();
+    for file in files {
+        let dir = file.path.parent().unwrap_or(Path::new("")).to_path_buf();
+        dir_map.entry(dir).or_default().push(file);
+    }
+
+    dir_map
+        .into_values()
+        .filter(|files| files.len() >= 2)
+        .collect()
+}
+
+/// Split the files by the root they were found under, keeping roots with at least two of
+/// them (`--isolate`). A root inside another root belongs to the outer one, like when
+/// scanning, and files outside of all roots are grouped together.
+pub fn split_by_root(files: Vec<FileEntry>, roots: &[PathBuf]) -> SizeGroups {
+    let mut root_map: HashMap<Option<&Path>, Vec<FileEntry>> = HashMap::new();
+    for file in files {
+        let root = roots
+            .iter()
+            .filter(|root| file.path.starts_with(root))
+            .min_by_key(|root| root.components().count())
+            .map(PathBuf::as_path);
+        root_map.entry(root).or_default().push(file);
+    }
+
+    root_map
+        .into_values()
+        .filter(|files| files.len() >= 2)
+        .collect()
+}
+
+/// Whether the files include both a reference file (`--compare-to`) and another file to
+/// compare with it
+pub fn mixes_reference(files: &[FileEntry]) -> bool {
+    files.iter().any(|f| f.reference) && files.iter().any(|f| !f.reference)
+}
+
+/// Reduce a group confirmed with `--compare-to` to the files outside the reference directory
+/// and the reference copy they are reported against, `None` if either is missing.
+///
+/// Only one reference copy (the shortest path) is kept, so every other file of the group is
+/// outside the reference directory and actions or commands never touch it.
+pub fn against_reference(files: Vec<FileEntry>) -> Option<Vec<FileEntry>> {
+    let (references, mut targets): (Vec<FileEntry>, Vec<FileEntry>) =
+        files.into_iter().partition(|f| f.reference);
+    let reference = references
+        .into_iter()
+        .min_by_key(|f| f.path.as_os_str().len())?;
+    if targets.is_empty() {
+        return None;
+    }
+    targets.push(reference);
+    Some(targets)
+}
+
+/// Pairs each file under `root` with the file at the same relative path under `mirror`.
+///
+/// Used instead of [`group_by_size`] when verifying mirrored trees: only files at the same
+/// location are compared, and pairs with different sizes are dropped right away.
+pub fn group_by_relative_path(
+    files: Vec<FileEntry>,
+    root: &Path,
+    mirror_files: Vec<FileEntry>,
+    mirror: &Path,
+) -> SizeGroups {
+    let mut by_relative_path: HashMap<PathBuf, FileEntry> = files
+        .into_iter()
+        .filter_map(|file| Some((file.path.strip_prefix(root).ok()?.to_path_buf(), file)))
+        .collect();
+
+    mirror_files
+        .into_iter()
+        .filter_map(|mirror_file| {
+            let relative_path = mirror_file.path.strip_prefix(mirror).ok()?;
+            let file = by_relative_path.remove(relative_path)?;
+            (file.size == mirror_file.size).then(|| vec![file, mirror_file])
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -31,6 +161,7 @@ mod tests {
         FileEntry {
             path: PathBuf::from(path),
             size,
+            ..Default::default()
         }
     }
 
@@ -100,9 +231,153 @@ mod tests {
         let groups = group_by_size(files);
 
         assert_eq!(groups.len(), 1);
-        let paths: Vec<&str> = groups[0].iter().map(|p| p.to_str().unwrap()).collect();
+        let paths: Vec<&str> = groups[0].iter().map(|f| f.path.to_str().unwrap()).collect();
 
         assert!(paths.contains(&"/path/to/file1.txt"));
         assert!(paths.contains(&"/another/path/file2.txt"));
     }
+
+    #[test]
+    fn test_group_by_name() {
+        let files = vec![
+            file_entry("/a/IMG_0001.JPG", 100),
+            file_entry("/b/IMG_0001.JPG", 120),
+            file_entry("/c/img_0001.jpg", 100),
+            file_entry("/c/IMG_0002.JPG", 100),
+        ];
+
+        let groups = group_by_name(files.clone(), false);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].len(), 2);
+
+        let groups = group_by_name(files, true);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].len(), 3);
+    }
+
+    #[test]
+    fn test_spans_directories() {
+        let siblings = vec![file_entry("/a/x.pdf", 1), file_entry("/a/x (1).pdf", 1)];
+        assert!(!spans_directories(&siblings));
+
+        let spread = vec![file_entry("/a/x.pdf", 1), file_entry("/a/b/x.pdf", 1)];
+        assert!(spans_directories(&spread));
+    }
+
+    #[test]
+    fn test_split_by_root() {
+        let files = vec![
+            file_entry("/data/a/x", 1),
+            file_entry("/data/b/x", 1),
+            file_entry("/database/x", 1),
+            file_entry("/other/x", 1),
+            file_entry("/other/y", 1),
+        ];
+        let roots = [
+            PathBuf::from("/data/a"),
+            PathBuf::from("/data"),
+            PathBuf::from("/database"),
+        ];
+
+        let groups = split_by_root(files, &roots);
+        let mut paths: Vec<Vec<&str>> = groups
+            .iter()
+            .map(|g| g.iter().map(|f| f.path.to_str().unwrap()).collect())
+            .collect();
+        paths.iter_mut().for_each(|g| g.sort());
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![vec!["/data/a/x", "/data/b/x"], vec!["/other/x", "/other/y"]]
+        );
+    }
+
+    #[test]
+    fn test_against_reference() {
+        let reference = |path: &str| FileEntry {
+            reference: true,
+            ..file_entry(path, 1)
+        };
+        let files = vec![
+            file_entry("/card/a.jpg", 1),
+            reference("/archive/2024/a.jpg"),
+            file_entry("/card/copy/a.jpg", 1),
+            reference("/archive/a.jpg"),
+        ];
+        assert!(mixes_reference(&files));
+
+        let mut paths: Vec<PathBuf> = against_reference(files)
+            .unwrap()
+            .into_iter()
+            .map(|f| f.path)
+            .collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            ["/archive/a.jpg", "/card/a.jpg", "/card/copy/a.jpg"].map(PathBuf::from)
+        );
+
+        let targets = vec![file_entry("/card/a.jpg", 1), file_entry("/card/b.jpg", 1)];
+        assert!(!mixes_reference(&targets));
+        assert!(against_reference(targets).is_none());
+        assert!(against_reference(vec![reference("/a"), reference("/b")]).is_none());
+    }
+
+    #[test]
+    fn test_split_by_directory() {
+        let files = vec![
+            file_entry("/a/x.pdf", 1),
+            file_entry("/a/x (1).pdf", 1),
+            file_entry("/a/x (2).pdf", 1),
+            file_entry("/b/x.pdf", 1),
+            file_entry("/c/x.pdf", 1),
+            file_entry("/c/copy of x.pdf", 1),
+        ];
+
+        let mut sizes: Vec<usize> = split_by_directory(files).iter().map(|g| g.len()).collect();
+        sizes.sort();
+        assert_eq!(sizes, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_relative_path_pairs_same_location() {
+        let files = vec![
+            file_entry("/data/a.txt", 100),
+            file_entry("/data/sub/b.txt", 200),
+            file_entry("/data/only_here.txt", 100),
+        ];
+        let mirror_files = vec![
+            file_entry("/backup/a.txt", 100),
+            file_entry("/backup/sub/b.txt", 200),
+            file_entry("/backup/elsewhere/a.txt", 100),
+        ];
+
+        let groups = group_by_relative_path(
+            files,
+            Path::new("/data"),
+            mirror_files,
+            Path::new("/backup"),
+        );
+
+        assert_eq!(groups.len(), 2);
+        for group in &groups {
+            assert_eq!(group.len(), 2);
+            assert_eq!(
+                group[0].path.strip_prefix("/data").unwrap(),
+                group[1].path.strip_prefix("/backup").unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_relative_path_drops_different_sizes() {
+        let groups = group_by_relative_path(
+            vec![file_entry("/data/a.txt", 100)],
+            Path::new("/data"),
+            vec![file_entry("/backup/a.txt", 101)],
+            Path::new("/backup"),
+        );
+
+        assert!(groups.is_empty());
+    }
 }
@@ -1,87 +1,836 @@
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{self, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
 
+use clap::ValueEnum;
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::Digest as _;
 
-/// Size of partial hash in bytes (8KB)
+use crate::cache::{self, Stage};
+use crate::hashlist;
+use crate::iomode::{self, AlignedBuffer, HashFile, IoMode};
+use crate::profile;
+use crate::scanner::FileEntry;
+
+/// Size of each chunk hashed by the partial hash in bytes (8KB)
 const PARTIAL_HASH_SIZE: usize = 8 * 1024;
 
+/// Number of chunks the partial hash covers: the start, the middle and the end of a file
+const PARTIAL_HASH_CHUNKS: u64 = 3;
+
+/// Files larger than this are sampled before their full hash is computed (64MB)
+pub const SAMPLE_HASH_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// Number of evenly spaced samples read from a large file
+const SAMPLE_COUNT: u64 = 16;
+
+/// Size of each sample in bytes (1MB)
+const SAMPLE_SIZE: usize = 1024 * 1024;
+
+/// Files up to this size are hashed by the small-file path, read in one go into a stack
+/// buffer (4KB)
+const SMALL_FILE_SIZE: u64 = 4 * 1024;
+
+/// Number of small files hashed per parallel task, so per-task overhead doesn't dominate
+/// on trees with millions of tiny files (e.g. maildirs)
+const SMALL_FILE_BATCH: usize = 256;
+
+/// Size of the chunks files are read in when hashed fully (64KB)
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Files larger than this are hashed on all threads when hashed fully with BLAKE3 (256MB)
+const PARALLEL_HASH_THRESHOLD: u64 = 256 * 1024 * 1024;
+
+/// Size of the chunks files are read in to be hashed on all threads (16MB)
+const PARALLEL_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Size of the reads of files hashed with O_DIRECT, a multiple of `iomode::DIRECT_ALIGNMENT`
+/// (1MB)
+const DIRECT_READ_SIZE: usize = 1024 * 1024;
+
+/// Files larger than this are memory-mapped when hashed fully with `--mmap` (16MB)
+const MMAP_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// Whether large files are memory-mapped to be hashed, set once by [`set_mmap`]
+static MMAP: AtomicBool = AtomicBool::new(false);
+
+/// Memory-map files larger than `MMAP_THRESHOLD` to hash them fully (`--mmap`)
+pub fn set_mmap(enabled: bool) {
+    MMAP.store(enabled, Ordering::Relaxed);
+}
+
+/// Bytes read from files to hash or compare them, over the whole run
+static BYTES_READ: AtomicU64 = AtomicU64::new(0);
+
+/// Total bytes read from files to hash or compare them so far
+pub fn bytes_read() -> u64 {
+    BYTES_READ.load(Ordering::Relaxed)
+}
+
+/// Why a scanned file could not be read to hash it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadFailure {
+    /// Deleted since it was scanned
+    Vanished,
+    /// Still there but not readable, with the error
+    Unreadable(String),
+}
+
+/// Scanned files that could not be read to hash them, over the whole run
+static READ_FAILURES: LazyLock<Mutex<HashMap<PathBuf, ReadFailure>>> =
+    LazyLock::new(Default::default);
+
+/// Record why the scanned file at `path` could not be read: deleted since it was scanned, or
+/// the error opening it (e.g. permissions). Returns whether it was deleted.
+pub(crate) fn record_read_failure(path: &Path) -> bool {
+    let failure = match std::fs::symlink_metadata(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => ReadFailure::Vanished,
+        Err(e) => ReadFailure::Unreadable(e.to_string()),
+        Ok(_) => ReadFailure::Unreadable(match File::open(path) {
+            Err(e) => e.to_string(),
+            Ok(_) => "could not be read".to_string(),
+        }),
+    };
+    let vanished = failure == ReadFailure::Vanished;
+    READ_FAILURES
+        .lock()
+        .unwrap()
+        .insert(path.to_path_buf(), failure);
+    vanished
+}
+
+/// Number of scanned files that were deleted before they could be hashed
+pub fn vanished_files() -> usize {
+    READ_FAILURES
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|failure| **failure == ReadFailure::Vanished)
+        .count()
+}
+
+/// Scanned files that could not be read to hash them, sorted by path
+pub fn read_failures() -> Vec<(PathBuf, ReadFailure)> {
+    let mut failures: Vec<_> = READ_FAILURES
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(path, failure)| (path.clone(), failure.clone()))
+        .collect();
+    failures.sort_by(|a, b| a.0.cmp(&b.0));
+    failures
+}
+
+thread_local! {
+    /// Bytes read from files by this thread, to attribute reads to the file being hashed
+    static BYTES_READ_BY_THREAD: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Total bytes read from files by the current thread so far
+pub fn bytes_read_by_thread() -> u64 {
+    BYTES_READ_BY_THREAD.get()
+}
+
+/// Account for `bytes` read from a file, waiting while reading faster than `--max-bandwidth`
+pub(crate) fn count_bytes_read(bytes: u64) {
+    BYTES_READ.fetch_add(bytes, Ordering::Relaxed);
+    BYTES_READ_BY_THREAD.set(BYTES_READ_BY_THREAD.get() + bytes);
+    iomode::throttle(bytes);
+}
+
+/// Hash function used to compare file contents (`--hash`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    /// BLAKE3, fast and cryptographically secure
+    #[default]
+    Blake3,
+    /// 128-bit XXH3, faster but not collision resistant against crafted files (trusted data only)
+    Xxh3,
+    /// SHA-256, slower but matches the hashes of existing manifests (e.g. sha256sum)
+    Sha256,
+}
+
+impl Algorithm {
+    /// Name as given to `--hash` and written to reports
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Blake3 => "blake3",
+            Algorithm::Xxh3 => "xxh3",
+            Algorithm::Sha256 => "sha256",
+        }
+    }
+}
+
+/// Algorithm of the current run, set once by [`set_algorithm`]
+static ALGORITHM: AtomicU8 = AtomicU8::new(Algorithm::Blake3 as u8);
+
+/// Hash all contents with `algorithm` for the rest of the run
+pub fn set_algorithm(algorithm: Algorithm) {
+    ALGORITHM.store(algorithm as u8, Ordering::Relaxed);
+}
+
+/// Algorithm contents are hashed with in this run
+pub fn algorithm() -> Algorithm {
+    match ALGORITHM.load(Ordering::Relaxed) {
+        1 => Algorithm::Xxh3,
+        2 => Algorithm::Sha256,
+        _ => Algorithm::Blake3,
+    }
+}
+
+/// Longest digest of all algorithms in bytes
+const MAX_DIGEST_LEN: usize = 32;
+
+/// Hash of some contents, the raw bytes of whichever [`Algorithm`] computed it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Digest {
+    bytes: [u8; MAX_DIGEST_LEN],
+    len: u8,
+}
+
+impl Digest {
+    /// Digest of up to `MAX_DIGEST_LEN` bytes
+    fn new(bytes: &[u8]) -> Self {
+        let mut digest = Digest {
+            bytes: [0; MAX_DIGEST_LEN],
+            len: bytes.len() as u8,
+        };
+        digest.bytes[..bytes.len()].copy_from_slice(bytes);
+        digest
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    /// Digest of the raw bytes returned by [`Digest::as_bytes`]
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        (bytes.len() <= MAX_DIGEST_LEN).then(|| Digest::new(bytes))
+    }
+
+    /// Lowercase hex representation, as written to reports
+    pub fn to_hex(self) -> String {
+        self.as_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// Parse the hex representation written by [`Digest::to_hex`]
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if !hex.len().is_multiple_of(2) || hex.len() > MAX_DIGEST_LEN * 2 || !hex.is_ascii() {
+            return None;
+        }
+        let bytes: Option<Vec<u8>> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect();
+        Some(Digest::new(&bytes?))
+    }
+}
+
+impl From<blake3::Hash> for Digest {
+    fn from(hash: blake3::Hash) -> Self {
+        Digest::new(hash.as_bytes())
+    }
+}
+
+/// Incremental hasher of one of the [`Algorithm`]s
+#[derive(Clone)]
+pub enum Hasher {
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+    Sha256(sha2::Sha256),
+}
+
+impl Hasher {
+    pub fn new(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Blake3 => Hasher::Blake3(Box::default()),
+            Algorithm::Xxh3 => Hasher::Xxh3(Box::default()),
+            Algorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+        }
+    }
+
+    fn algorithm(&self) -> Algorithm {
+        match self {
+            Hasher::Blake3(_) => Algorithm::Blake3,
+            Hasher::Xxh3(_) => Algorithm::Xxh3,
+            Hasher::Sha256(_) => Algorithm::Sha256,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            Hasher::Xxh3(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Like [`Hasher::update`], spreading large inputs over all threads where the algorithm
+    /// allows it (BLAKE3)
+    pub fn update_parallel(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Blake3(hasher) => {
+                hasher.update_rayon(data);
+            }
+            hasher => hasher.update(data),
+        }
+    }
+
+    pub fn finalize(&self) -> Digest {
+        match self {
+            Hasher::Blake3(hasher) => hasher.finalize().into(),
+            // Big-endian like the canonical form printed by `xxhsum -H2`
+            Hasher::Xxh3(hasher) => Digest::new(&hasher.digest128().to_be_bytes()),
+            Hasher::Sha256(hasher) => Digest::new(&hasher.clone().finalize()),
+        }
+    }
+
+    fn reset(&mut self) {
+        match self {
+            Hasher::Blake3(hasher) => {
+                hasher.reset();
+            }
+            Hasher::Xxh3(hasher) => hasher.reset(),
+            Hasher::Sha256(hasher) => sha2::Digest::reset(hasher),
+        }
+    }
+}
+
+/// Hash `data` with the algorithm of the run
+pub fn hash_bytes(data: &[u8]) -> Digest {
+    hash_bytes_with(algorithm(), data)
+}
+
+/// Hash `data` with `algorithm`
+pub fn hash_bytes_with(algorithm: Algorithm, data: &[u8]) -> Digest {
+    let mut hasher = Hasher::new(algorithm);
+    hasher.update(data);
+    hasher.finalize()
+}
+
+thread_local! {
+    /// Read buffer and hasher reused by all hash operations on a thread, so hashing many
+    /// files doesn't allocate a fresh buffer for each of them
+    static HASH_STATE: RefCell<(Vec<u8>, Hasher)> =
+        RefCell::new((Vec::new(), Hasher::new(algorithm())));
+}
+
+/// Run `hash` with this thread's read buffer, grown to at least `size` bytes, and a reset hasher.
+///
+/// Must not be nested, `hash` can't call other functions using the shared state.
+fn with_hash_state<T>(size: usize, hash: impl FnOnce(&mut [u8], &mut Hasher) -> T) -> T {
+    HASH_STATE.with_borrow_mut(|(buffer, hasher)| {
+        if buffer.len() < size {
+            buffer.resize(size, 0);
+        }
+        if hasher.algorithm() == algorithm() {
+            hasher.reset();
+        } else {
+            *hasher = Hasher::new(algorithm());
+        }
+        hash(&mut buffer[..size], hasher)
+    })
+}
+
 /// A group of files that share the same hash
-pub type HashGroup = Vec<PathBuf>;
+#[derive(Debug, Clone)]
+pub struct HashGroup {
+    /// Hash shared by all files in the group
+    pub hash: Digest,
+    /// All files in the group
+    pub files: Vec<FileEntry>,
+}
 
 /// A collection of hash groups
 pub type HashGroups = Vec<HashGroup>;
 
-/// Compute Blake3 hash of the first 8KB of a file
-fn partial_hash_file(path: &Path) -> Option<blake3::Hash> {
-    let file = File::open(path).ok()?;
-    let mut reader = BufReader::new(file);
-    let mut buffer = vec![0u8; PARTIAL_HASH_SIZE];
+/// Open a file positioned after its first `skip` bytes, the header ignored by hashing
+fn open_after_header(path: &Path, skip: u64) -> Option<HashFile> {
+    let mut file = HashFile::open(path).ok()?;
+    if skip > 0 {
+        file.seek(SeekFrom::Start(skip)).ok()?;
+    }
+    Some(file)
+}
+
+/// Compute the hash of the first, middle and last 8KB of a file of `size` bytes after
+/// skipping `skip` header bytes, see [`partial_ranges`]
+pub(crate) fn partial_hash_file(path: &Path, size: u64, skip: u64) -> Option<Digest> {
+    let mut file = HashFile::open(path).ok()?;
 
-    let bytes_read = reader.read(&mut buffer).ok()?;
-    buffer.truncate(bytes_read);
+    with_hash_state(
+        PARTIAL_HASH_SIZE * PARTIAL_HASH_CHUNKS as usize,
+        |buffer, hasher| {
+            for (start, end) in partial_ranges(size, skip) {
+                file.seek(SeekFrom::Start(start)).ok()?;
+                let bytes_read =
+                    read_full(&mut file, &mut buffer[..(end - start) as usize]).ok()?;
+                hasher.update(&buffer[..bytes_read]);
+            }
 
-    Some(blake3::hash(&buffer))
+            Some(hasher.finalize())
+        },
+    )
 }
 
-/// Compute Blake3 hash of entire file contents
-fn full_hash_file(path: &Path) -> Option<blake3::Hash> {
-    let file = File::open(path).ok()?;
-    let mut reader = BufReader::new(file);
-    let mut buffer = vec![0u8; 64 * 1024];
+/// Byte ranges (start, end) covered by the partial hash of a file of `size` bytes: a chunk
+/// at the start, in the middle and at the end of the contents after the `skip` header bytes.
+///
+/// Logs, exports and media files with identical headers often only differ near their end,
+/// which the first chunk alone would miss. Contents that fit in the chunks are covered as a
+/// whole.
+fn partial_ranges(size: u64, skip: u64) -> Vec<(u64, u64)> {
+    let start = skip.min(size);
+    let chunk = PARTIAL_HASH_SIZE as u64;
+    if partial_hash_covers(size, skip) {
+        return vec![(start, size)];
+    }
+    sample_offsets(size, chunk, skip, PARTIAL_HASH_CHUNKS)
+        .map(|offset| (offset, offset + chunk))
+        .collect()
+}
 
-    let mut hasher = blake3::Hasher::new();
+/// Read until the buffer is full or the end of the file is reached.
+pub(crate) fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    count_bytes_read(filled as u64);
+    Ok(filled)
+}
+
+/// Compute the hash of `SAMPLE_COUNT` evenly spaced samples of `sample_size` bytes, the
+/// first one right after the `skip` header bytes and the last one at the end of the file.
+fn sample_hash_file(path: &Path, size: u64, sample_size: usize, skip: u64) -> Option<Digest> {
+    let mut file = HashFile::open(path).ok()?;
 
-    // Read in chunks
+    with_hash_state(sample_size, |buffer, hasher| {
+        for offset in sample_offsets(size, sample_size as u64, skip, SAMPLE_COUNT) {
+            file.seek(SeekFrom::Start(offset)).ok()?;
+            let bytes_read = read_full(&mut file, buffer).ok()?;
+            hasher.update(&buffer[..bytes_read]);
+        }
+
+        Some(hasher.finalize())
+    })
+}
+
+/// Offsets of `count` evenly spaced samples of `sample_size` bytes, the first one right after
+/// the `skip` header bytes and the last one at the end of the file
+fn sample_offsets(size: u64, sample_size: u64, skip: u64, count: u64) -> impl Iterator<Item = u64> {
+    let first_offset = skip.min(size);
+    let last_offset = size.saturating_sub(sample_size).max(first_offset);
+    (0..count).map(move |i| first_offset + (last_offset - first_offset) * i / (count - 1))
+}
+
+/// Whether the partial hash of a file of `size` bytes covers all of its contents after the
+/// `skip` header bytes, so its full hash would read the same bytes again
+pub fn partial_hash_covers(size: u64, skip: u64) -> bool {
+    size - skip.min(size) <= PARTIAL_HASH_SIZE as u64 * PARTIAL_HASH_CHUNKS
+}
+
+/// Compute the hash of a small file's contents after the first `skip` header bytes,
+/// refreshing the entry's size and modification time like [`full_hash_entry`].
+///
+/// Reads the whole file with a single read into a stack buffer, files that grew beyond
+/// `SMALL_FILE_SIZE` since they were scanned are hashed as usual.
+fn small_hash_entry(entry: &mut FileEntry, skip: u64) -> Option<Digest> {
+    let mut file = open_after_header(&entry.path, skip)?;
+    let metadata = file.metadata().ok()?;
+    entry.size = metadata.len();
+    entry.modified = metadata.modified().ok();
+    if entry.size > SMALL_FILE_SIZE {
+        return hash_reader(file);
+    }
+
+    // One extra byte tells a file that grew while it was read apart from one that fits exactly
+    let mut buffer = [0u8; SMALL_FILE_SIZE as usize + 1];
+    let bytes_read = read_full(&mut file, &mut buffer).ok()?;
+    if bytes_read == buffer.len() {
+        let mut hasher = Hasher::new(algorithm());
+        hasher.update(&buffer);
+        let mut rest = Vec::new();
+        file.read_to_end(&mut rest).ok()?;
+        count_bytes_read(rest.len() as u64);
+        hasher.update(&rest);
+        return Some(hasher.finalize());
+    }
+    Some(hash_bytes(&buffer[..bytes_read]))
+}
+
+/// Compute the hash of the file contents after the first `skip` header bytes, refreshing
+/// the entry's size and modification time from the opened file so they describe the hashed
+/// content.
+fn full_hash_entry(entry: &mut FileEntry, skip: u64) -> Option<Digest> {
+    let file = open_after_header(&entry.path, skip)?;
+    let metadata = file.metadata().ok()?;
+    entry.size = metadata.len();
+    entry.modified = metadata.modified().ok();
+    if iomode::io_mode() == IoMode::Direct
+        && let Some(hash) = direct_hash(&entry.path, skip)
+    {
+        return Some(hash);
+    }
+    // Mapped files are hashed on all threads too
+    if MMAP.load(Ordering::Relaxed)
+        && entry.size > MMAP_THRESHOLD
+        && !iomode::io_limited(entry.dev)
+        && let Some(hash) = mmap_hash(&file, skip)
+    {
+        return Some(hash);
+    }
+    // Hashing on all threads would wait for them while holding an I/O permit
+    if entry.size > PARALLEL_HASH_THRESHOLD
+        && algorithm() == Algorithm::Blake3
+        && !iomode::io_limited(entry.dev)
+    {
+        return parallel_hash_reader(file, PARALLEL_CHUNK_SIZE);
+    }
+    hash_reader(file)
+}
+
+/// Compute the full hash of the file at `path` after `skip` header bytes, as
+/// [`group_by_full_hash`] does
+pub fn full_hash_path(path: &Path, skip: u64) -> Option<Digest> {
+    let mut entry = FileEntry {
+        path: path.to_path_buf(),
+        ..Default::default()
+    };
+    full_hash_entry(&mut entry, skip)
+}
+
+/// Compute the hash of everything readable from a huge file in chunks of `chunk_size` bytes,
+/// each hashed on all threads, so a single file doesn't keep one thread busy for minutes
+/// while the others idle.
+///
+/// The chunks are split up on the thread pool that hashes the other files: idle threads join
+/// in, busy ones finish their own files first, so no threads are added. Like [`mmap_hash`]
+/// it uses a hasher of its own rather than the thread's shared state.
+fn parallel_hash_reader(mut file: impl Read, chunk_size: usize) -> Option<Digest> {
+    let mut buffer = vec![0u8; chunk_size];
+    let mut hasher = Hasher::new(algorithm());
     loop {
-        let bytes_read = reader.read(&mut buffer).ok()?;
+        let bytes_read = read_full(&mut file, &mut buffer).ok()?;
         if bytes_read == 0 {
             break;
         }
-        hasher.update(&buffer[..bytes_read]);
+        hasher.update_parallel(&buffer[..bytes_read]);
+    }
+    Some(hasher.finalize())
+}
+
+/// Compute the hash of a file's contents after the first `skip` bytes read with O_DIRECT
+/// (`--io-mode direct`), bypassing the page cache. Returns `None` if the file can't be read
+/// that way (e.g. on tmpfs or other systems than Linux), it is then read through the cache.
+fn direct_hash(path: &Path, skip: u64) -> Option<Digest> {
+    let mut file = iomode::open_direct(path).ok()?;
+    let mut buffer = AlignedBuffer::new(DIRECT_READ_SIZE);
+    let mut hasher = Hasher::new(algorithm());
+
+    // Reads must start at aligned offsets, so the header is read and left out of the hash.
+    // Only the last read at the end of the file returns less than the buffer size.
+    let mut position = 0;
+    loop {
+        let bytes_read = file.read(buffer.as_mut_slice()).ok()?;
+        let chunk = &buffer.as_slice()[..bytes_read];
+        let header = skip.saturating_sub(position).min(bytes_read as u64) as usize;
+        hasher.update(&chunk[header..]);
+        position += bytes_read as u64;
+        count_bytes_read(bytes_read as u64);
+        if bytes_read < DIRECT_READ_SIZE {
+            break;
+        }
     }
 
     Some(hasher.finalize())
 }
 
+/// Compute the hash of a file's contents after the first `skip` bytes from a memory mapping,
+/// without copying them into a read buffer. Returns `None` if the file can't be mapped.
+///
+/// Uses a hasher of its own rather than the thread's shared state, BLAKE3 hashes the mapping
+/// on all threads and this one may pick up other hashing work while it waits.
+fn mmap_hash(file: &File, skip: u64) -> Option<Digest> {
+    // SAFETY: the mapping is only read. A file truncated by another process while it is
+    // hashed makes reading the missing pages fault, which is why mapping is opt-in.
+    let map = unsafe { memmap2::Mmap::map(file) }.ok()?;
+    #[cfg(unix)]
+    let _ = map.advise(memmap2::Advice::Sequential);
+
+    let contents = map.get(skip.min(map.len() as u64) as usize..)?;
+    let mut hasher = Hasher::new(algorithm());
+    hasher.update_parallel(contents);
+    count_bytes_read(contents.len() as u64);
+    Some(hasher.finalize())
+}
+
+/// Compute the hash of everything readable from a file, starting at its current position
+pub fn hash_reader(mut file: impl Read) -> Option<Digest> {
+    with_hash_state(READ_BUFFER_SIZE, |buffer, hasher| {
+        // Read in chunks
+        loop {
+            let bytes_read = file.read(buffer).ok()?;
+            if bytes_read == 0 {
+                break;
+            }
+            count_bytes_read(bytes_read as u64);
+            hasher.update(&buffer[..bytes_read]);
+        }
+
+        Some(hasher.finalize())
+    })
+}
+
+/// Hashes of contents that can only be read once, e.g. members of compressed archives,
+/// computed in a single pass while they are streamed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentHashes {
+    /// Hash of the start, middle and end, as compared by [`group_by_partial_hash`]
+    pub partial: Digest,
+    /// Hash of the samples compared by [`group_by_sample_hash`], only for contents larger
+    /// than `SAMPLE_HASH_THRESHOLD`
+    pub sample: Option<Digest>,
+    /// Hash of the whole contents
+    pub full: Digest,
+}
+
+/// Compute the partial, sample and full hash of `size` bytes read from `reader` in one
+/// pass. They match the hashes of a file with the same contents.
+pub fn hash_stream(mut reader: impl Read, size: u64) -> io::Result<ContentHashes> {
+    let samples: Vec<(u64, u64)> = if size > SAMPLE_HASH_THRESHOLD {
+        sample_offsets(size, SAMPLE_SIZE as u64, 0, SAMPLE_COUNT)
+            .map(|offset| (offset, offset + SAMPLE_SIZE as u64))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let partial_ranges = partial_ranges(size, 0);
+    let mut partial = Hasher::new(algorithm());
+    let mut sample = Hasher::new(algorithm());
+    let (mut next_partial, mut next_sample) = (0, 0);
+
+    with_hash_state(READ_BUFFER_SIZE, |buffer, full| {
+        let mut position = 0;
+        loop {
+            let bytes_read = match reader.read(buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let chunk = &buffer[..bytes_read];
+
+            hash_ranges(
+                &mut partial,
+                &partial_ranges,
+                &mut next_partial,
+                position,
+                chunk,
+            );
+            hash_ranges(&mut sample, &samples, &mut next_sample, position, chunk);
+            full.update(chunk);
+            position += bytes_read as u64;
+        }
+
+        count_bytes_read(position);
+        Ok(ContentHashes {
+            partial: partial.finalize(),
+            sample: (!samples.is_empty()).then(|| sample.finalize()),
+            full: full.finalize(),
+        })
+    })
+}
+
+/// Hash the parts of `chunk`, read at `position` of a stream, that fall into `ranges`
+/// starting at `next`. Ranges can span chunks, the current one is continued with the next
+/// chunk.
+fn hash_ranges(
+    hasher: &mut Hasher,
+    ranges: &[(u64, u64)],
+    next: &mut usize,
+    position: u64,
+    chunk: &[u8],
+) {
+    let end = position + chunk.len() as u64;
+    while let Some(&(start, range_end)) = ranges.get(*next)
+        && start < end
+    {
+        let from = start.max(position) - position;
+        let to = range_end.min(end) - position;
+        hasher.update(&chunk[from as usize..to as usize]);
+        if range_end > end {
+            break;
+        }
+        *next += 1;
+    }
+}
+
 /// Generic grouping by hash
 /// Returns only groups with 2+ files.
-fn group_by_hash<F>(files: Vec<PathBuf>, hash_fn: F) -> HashGroups
+///
+/// Paths that are hardlinks to the same inode are hashed once, the result is used for all of them.
+pub fn group_by_hash<F>(files: Vec<FileEntry>, hash_fn: F) -> HashGroups
 where
-    F: Fn(&Path) -> Option<blake3::Hash> + Sync,
+    F: Fn(&mut FileEntry) -> Option<Digest> + Sync,
 {
-    let hashes: Vec<(PathBuf, blake3::Hash)> = files
+    let mut inodes: HashMap<(u64, u64), Vec<FileEntry>> = HashMap::new();
+    let mut unknown_inodes = Vec::new();
+    for file in files {
+        if file.ino == 0 {
+            unknown_inodes.push(vec![file]);
+        } else {
+            inodes.entry((file.dev, file.ino)).or_default().push(file);
+        }
+    }
+
+    let links: Vec<Vec<FileEntry>> = inodes.into_values().chain(unknown_inodes).collect();
+    let batch = if links.iter().all(|l| l[0].size <= SMALL_FILE_SIZE) {
+        SMALL_FILE_BATCH
+    } else {
+        1
+    };
+
+    let hashes: Vec<(Vec<FileEntry>, Digest)> = links
         .into_par_iter()
-        .filter_map(|path| {
-            let hash = hash_fn(&path)?;
-            Some((path, hash))
+        .with_min_len(batch)
+        .filter_map(|mut links| {
+            // A deleted link leaves the inode to the others
+            let hash = loop {
+                let _permit = iomode::io_permit(links[0].dev);
+                let started = profile::start();
+                let hash = hash_fn(&mut links[0]);
+                profile::finish(&links[0].path, started);
+                match hash {
+                    Some(hash) => break hash,
+                    None if links.len() > 1 && record_read_failure(&links[0].path) => {
+                        links.remove(0);
+                    }
+                    None => {
+                        record_read_failure(&links[0].path);
+                        return None;
+                    }
+                }
+            };
+            let (size, modified) = (links[0].size, links[0].modified);
+            for link in &mut links[1..] {
+                link.size = size;
+                link.modified = modified;
+            }
+            Some((links, hash))
         })
         .collect();
 
-    let mut hash_map: HashMap<blake3::Hash, Vec<PathBuf>> = HashMap::new();
-    for (path, hash) in hashes {
-        hash_map.entry(hash).or_default().push(path);
+    let mut hash_map: HashMap<Digest, Vec<FileEntry>> = HashMap::new();
+    for (links, hash) in hashes {
+        hash_map.entry(hash).or_default().extend(links);
     }
 
     hash_map
         .into_iter()
         .filter(|(_, paths)| paths.len() >= 2)
-        .map(|(_, files)| files)
+        .map(|(hash, files)| HashGroup { hash, files })
         .collect()
 }
 
-/// Group files by their partial hash (first 8KB)
+/// Group files by their partial hash (first, middle and last 8KB after `skip` header bytes)
 /// Returns only groups with 2+ files (potential duplicates)
-pub fn group_by_partial_hash(files: Vec<PathBuf>) -> HashGroups {
-    group_by_hash(files, partial_hash_file)
+///
+/// Archive members are grouped by the hashes computed when they were scanned, without a
+/// header skipped, as in the other stages. Files hashed while the tree was scanned keep
+/// that hash, see [`crate::pipeline`]. Hashes of unchanged files come from the cache, see
+/// [`cache::cached_hash`].
+pub fn group_by_partial_hash(files: Vec<FileEntry>, skip: u64) -> Vec<Vec<FileEntry>> {
+    group_by_hash(files, |file| match file.archived {
+        Some(ref hashes) => Some(hashes.partial),
+        None if file.partial.is_some() => file.partial,
+        None => cache::cached_hash(file, Stage::Partial, skip, |file| {
+            partial_hash_file(&file.path, file.size, skip)
+        }),
+    })
+    .into_iter()
+    .map(|group| group.files)
+    .collect()
+}
+
+/// Compute the partial hash of a file before it is grouped by [`group_by_partial_hash`],
+/// e.g. while the tree is still being scanned
+pub fn partial_hash_entry(file: &mut FileEntry, skip: u64) -> Option<Digest> {
+    cache::cached_hash(file, Stage::Partial, skip, |file| {
+        let _permit = iomode::io_permit(file.dev);
+        let started = profile::start();
+        let hash = partial_hash_file(&file.path, file.size, skip);
+        profile::finish(&file.path, started);
+        hash
+    })
+}
+
+/// Compute the full hash of a single file as [`group_by_full_hash`] would, e.g. to export
+/// it without comparing it to anything
+pub fn full_hash_cached(file: &mut FileEntry, skip: u64) -> Option<Digest> {
+    cache::cached_hash(file, Stage::Full, skip, |file| {
+        let _permit = iomode::io_permit(file.dev);
+        let started = profile::start();
+        let hash = full_hash_entry(file, skip);
+        profile::finish(&file.path, started);
+        hash
+    })
+}
+
+/// Split a group of large files by the hash of evenly spaced samples of their contents,
+/// so large files that only differ between the chunks of the partial hash (e.g. databases
+/// and disk images)
+/// are told apart without reading them fully.
+///
+/// Groups with files of at most `SAMPLE_HASH_THRESHOLD` bytes are returned unchanged,
+/// sampling them would read about as much as hashing them.
+/// Returns only groups with 2+ files (potential duplicates)
+pub fn group_by_sample_hash(files: Vec<FileEntry>, skip: u64) -> Vec<Vec<FileEntry>> {
+    if files.iter().any(|file| file.size <= SAMPLE_HASH_THRESHOLD) {
+        return vec![files];
+    }
+
+    group_by_hash(files, |file| match file.archived {
+        Some(ref hashes) => hashes.sample,
+        None => sample_hash_file(&file.path, file.size, SAMPLE_SIZE, skip),
+    })
+    .into_iter()
+    .map(|group| group.files)
+    .collect()
 }
 
-/// Group files by their full content hash
+/// Group files by the hash of their contents after `skip` header bytes
 /// Returns only groups with 2+ files (confirmed duplicates)
-pub fn group_by_full_hash(files: Vec<PathBuf>) -> HashGroups {
-    group_by_hash(files, full_hash_file)
+///
+/// Hashes of files unchanged since an earlier run are taken from the cache, see
+/// [`cache::cached_hash`], or from the hashes imported from another machine, see
+/// [`hashlist::imported_hash`].
+pub fn group_by_full_hash(files: Vec<FileEntry>, skip: u64) -> HashGroups {
+    group_by_hash(files, |file| {
+        if let Some(ref hashes) = file.archived {
+            Some(hashes.full)
+        } else if let Some(hash) = hashlist::imported_hash(file) {
+            Some(hash)
+        } else if file.size <= SMALL_FILE_SIZE {
+            cache::cached_hash(file, Stage::Full, skip, |file| small_hash_entry(file, skip))
+        } else {
+            cache::cached_hash(file, Stage::Full, skip, |file| full_hash_entry(file, skip))
+        }
+    })
 }
 
 #[cfg(test)]
@@ -89,6 +838,7 @@ mod tests {
     use super::*;
     use std::fs::File;
     use std::io::Write;
+    use std::path::PathBuf;
     use tempfile::TempDir;
 
     fn create_file(dir: &Path, name: &str, content: &[u8]) -> PathBuf {
@@ -98,6 +848,25 @@ mod tests {
         path
     }
 
+    fn full_hash_file(path: &Path) -> Option<Digest> {
+        hash_reader(File::open(path).ok()?)
+    }
+
+    fn file_entry(path: PathBuf) -> FileEntry {
+        let size = std::fs::metadata(&path).unwrap().len();
+        FileEntry {
+            path,
+            size,
+            ..Default::default()
+        }
+    }
+
+    /// Partial hash of a file at its current size
+    fn partial_hash(path: &Path, skip: u64) -> Option<Digest> {
+        let size = std::fs::metadata(path).map_or(0, |m| m.len());
+        partial_hash_file(path, size, skip)
+    }
+
     #[test]
     fn test_partial_hash_identical_files() {
         let temp = TempDir::new().unwrap();
@@ -106,8 +875,8 @@ mod tests {
         let path1 = create_file(temp.path(), "file1.txt", content);
         let path2 = create_file(temp.path(), "file2.txt", content);
 
-        let hash1 = partial_hash_file(&path1).unwrap();
-        let hash2 = partial_hash_file(&path2).unwrap();
+        let hash1 = partial_hash(&path1, 0).unwrap();
+        let hash2 = partial_hash(&path2, 0).unwrap();
 
         assert_eq!(hash1, hash2);
     }
@@ -119,8 +888,8 @@ mod tests {
         let path1 = create_file(temp.path(), "file1.txt", b"hello");
         let path2 = create_file(temp.path(), "file2.txt", b"world");
 
-        let hash1 = partial_hash_file(&path1).unwrap();
-        let hash2 = partial_hash_file(&path2).unwrap();
+        let hash1 = partial_hash(&path1, 0).unwrap();
+        let hash2 = partial_hash(&path2, 0).unwrap();
 
         assert_ne!(hash1, hash2);
     }
@@ -130,38 +899,134 @@ mod tests {
         let temp = TempDir::new().unwrap();
         let path = create_file(temp.path(), "empty.txt", b"");
 
-        let hash = partial_hash_file(&path);
+        let hash = partial_hash(&path, 0);
         assert!(hash.is_some());
     }
 
     #[test]
-    fn test_partial_hash_large_file_only_reads_8kb() {
+    fn test_partial_hash_covers_start_middle_and_end() {
         let temp = TempDir::new().unwrap();
+        let size = 100 * 1024;
+        let content = vec![b'A'; size];
 
-        // Create two files: same first 8KB, different after
-        let mut content1 = vec![b'A'; PARTIAL_HASH_SIZE];
-        content1.extend_from_slice(b"DIFFERENT_ENDING_1");
+        // Same head, different endings, like logs or exports
+        let mut ending = content.clone();
+        ending[size - 1] = b'B';
+        let mut middle = content.clone();
+        middle[size / 2] = b'B';
+        // Between the first and the middle chunk
+        let mut between = content.clone();
+        between[PARTIAL_HASH_SIZE + 1] = b'B';
 
-        let mut content2 = vec![b'A'; PARTIAL_HASH_SIZE];
-        content2.extend_from_slice(b"DIFFERENT_ENDING_2");
+        let path = create_file(temp.path(), "file.bin", &content);
+        let hash = partial_hash(&path, 0).unwrap();
+        for (name, changed) in [("ending.bin", &ending), ("middle.bin", &middle)] {
+            let changed = create_file(temp.path(), name, changed);
+            assert_ne!(partial_hash(&changed, 0).unwrap(), hash);
+        }
+        let between = create_file(temp.path(), "between.bin", &between);
+        assert_eq!(partial_hash(&between, 0).unwrap(), hash);
+        assert_ne!(full_hash_file(&between), full_hash_file(&path));
+    }
+
+    #[test]
+    fn test_partial_hash_covers() {
+        let covered = PARTIAL_HASH_SIZE as u64 * PARTIAL_HASH_CHUNKS;
+        assert!(partial_hash_covers(0, 0));
+        assert!(partial_hash_covers(covered, 0));
+        assert!(!partial_hash_covers(covered + 1, 0));
+        assert!(partial_hash_covers(covered + 1, 1));
+        assert!(partial_hash_covers(10, 100));
+    }
+
+    #[test]
+    fn test_partial_ranges() {
+        let chunk = PARTIAL_HASH_SIZE as u64;
+        assert_eq!(partial_ranges(0, 0), [(0, 0)]);
+        assert_eq!(partial_ranges(3 * chunk, 0), [(0, 3 * chunk)]);
+        assert_eq!(partial_ranges(3 * chunk + 8, 8), [(8, 3 * chunk + 8)]);
+        assert_eq!(partial_ranges(4, 8), [(4, 4)]);
+        assert_eq!(
+            partial_ranges(5 * chunk, 0),
+            [(0, chunk), (2 * chunk, 3 * chunk), (4 * chunk, 5 * chunk)]
+        );
+    }
+
+    #[test]
+    fn test_sample_hash_detects_differences_after_prefix() {
+        let temp = TempDir::new().unwrap();
+        let size = 64 * 1024;
+        let content = vec![b'A'; size];
+        let mut changed = content.clone();
+        // Between the first and the middle chunk of the partial hash
+        changed[size / 4] = b'B';
+
+        let path1 = create_file(temp.path(), "file1.bin", &content);
+        let path2 = create_file(temp.path(), "file2.bin", &content);
+        let path3 = create_file(temp.path(), "file3.bin", &changed);
+
+        let sample_size = size / SAMPLE_COUNT as usize;
+        let hash1 = sample_hash_file(&path1, size as u64, sample_size, 0).unwrap();
+        let hash2 = sample_hash_file(&path2, size as u64, sample_size, 0).unwrap();
+        let hash3 = sample_hash_file(&path3, size as u64, sample_size, 0).unwrap();
+
+        assert_eq!(partial_hash(&path1, 0), partial_hash(&path3, 0));
+        assert_eq!(hash1, hash2);
+        assert_ne!(hash1, hash3);
+    }
+
+    #[test]
+    fn test_sample_hash_reads_last_bytes() {
+        let temp = TempDir::new().unwrap();
+        let path1 = create_file(temp.path(), "file1.bin", b"0123456789");
+        let path2 = create_file(temp.path(), "file2.bin", b"012345678X");
+
+        let hash1 = sample_hash_file(&path1, 10, 2, 0).unwrap();
+        let hash2 = sample_hash_file(&path2, 10, 2, 0).unwrap();
+
+        assert_ne!(hash1, hash2);
+    }
+
+    #[test]
+    fn test_group_by_sample_hash_skips_small_files() {
+        let temp = TempDir::new().unwrap();
+        let path1 = create_file(temp.path(), "file1.txt", b"content 1");
+        let path2 = create_file(temp.path(), "file2.txt", b"content 2");
+
+        let groups = group_by_sample_hash(vec![file_entry(path1), file_entry(path2)], 0);
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].len(), 2);
+    }
+
+    #[test]
+    fn test_hashes_skip_header() {
+        let temp = TempDir::new().unwrap();
+        let mut content1 = b"HEADER-1".to_vec();
+        let mut content2 = b"HEADER-2".to_vec();
+        let payload: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+        content1.extend_from_slice(&payload);
+        content2.extend_from_slice(&payload);
 
         let path1 = create_file(temp.path(), "file1.bin", &content1);
         let path2 = create_file(temp.path(), "file2.bin", &content2);
+        let size = content1.len() as u64;
 
-        // Partial hashes should match (same first 8KB)
-        let hash1 = partial_hash_file(&path1).unwrap();
-        let hash2 = partial_hash_file(&path2).unwrap();
-        assert_eq!(hash1, hash2);
+        assert_ne!(partial_hash(&path1, 0), partial_hash(&path2, 0));
+        assert_eq!(partial_hash(&path1, 8), partial_hash(&path2, 8));
+        assert_eq!(
+            sample_hash_file(&path1, size, 1024, 8),
+            sample_hash_file(&path2, size, 1024, 8)
+        );
 
-        // Full hashes should differ
-        let full1 = full_hash_file(&path1).unwrap();
-        let full2 = full_hash_file(&path2).unwrap();
-        assert_ne!(full1, full2);
+        let groups = group_by_full_hash(vec![file_entry(path1), file_entry(path2)], 8);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].hash, hash_bytes(&payload));
     }
 
     #[test]
     fn test_partial_hash_nonexistent_file() {
-        let hash = partial_hash_file(Path::new("/nonexistent/file.txt"));
+        let hash = partial_hash(Path::new("/nonexistent/file.txt"), 0);
         assert!(hash.is_none());
     }
 
@@ -174,8 +1039,8 @@ mod tests {
         let path2 = create_file(temp.path(), "dup2.txt", content);
         let _unique = create_file(temp.path(), "unique.txt", b"different");
 
-        let files = vec![path1, path2];
-        let groups = group_by_partial_hash(files);
+        let files = vec![file_entry(path1), file_entry(path2)];
+        let groups = group_by_partial_hash(files, 0);
 
         assert_eq!(groups.len(), 1);
         assert_eq!(groups[0].len(), 2);
@@ -189,13 +1054,47 @@ mod tests {
         let path2 = create_file(temp.path(), "b.txt", b"content b");
         let path3 = create_file(temp.path(), "c.txt", b"content c");
 
-        let files = vec![path1, path2, path3];
-        let groups = group_by_partial_hash(files);
+        let files = vec![file_entry(path1), file_entry(path2), file_entry(path3)];
+        let groups = group_by_partial_hash(files, 0);
 
         // All unique, no groups
         assert!(groups.is_empty());
     }
 
+    #[test]
+    fn test_hash_algorithms() {
+        assert_eq!(
+            hash_bytes_with(Algorithm::Sha256, b"abc").to_hex(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hash_bytes_with(Algorithm::Xxh3, b"abc").as_bytes(),
+            xxhash_rust::xxh3::xxh3_128(b"abc").to_be_bytes()
+        );
+        assert_eq!(
+            hash_bytes_with(Algorithm::Blake3, b"abc"),
+            Digest::from(blake3::hash(b"abc"))
+        );
+
+        let mut hasher = Hasher::new(Algorithm::Sha256);
+        hasher.update(b"a");
+        hasher.update(b"bc");
+        assert_eq!(
+            hasher.finalize(),
+            hash_bytes_with(Algorithm::Sha256, b"abc")
+        );
+    }
+
+    #[test]
+    fn test_digest_hex_round_trip() {
+        for algorithm in [Algorithm::Blake3, Algorithm::Xxh3, Algorithm::Sha256] {
+            let digest = hash_bytes_with(algorithm, b"content");
+            assert_eq!(Digest::from_hex(&digest.to_hex()), Some(digest));
+        }
+        assert_eq!(Digest::from_hex("abc"), None);
+        assert_eq!(Digest::from_hex("zz"), None);
+    }
+
     #[test]
     fn test_full_hash_file() {
         let temp = TempDir::new().unwrap();
@@ -210,6 +1109,84 @@ mod tests {
         assert_eq!(hash1, hash2);
     }
 
+    #[test]
+    fn test_stream_hashes_match_file_hashes() {
+        let temp = TempDir::new().unwrap();
+        let small: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+        let path = create_file(temp.path(), "small.bin", &small);
+
+        let hashes = hash_stream(&small[..], small.len() as u64).unwrap();
+        assert_eq!(Some(hashes.partial), partial_hash(&path, 0));
+        assert_eq!(Some(hashes.full), full_hash_file(&path));
+        assert_eq!(hashes.sample, None);
+
+        // Large enough to be sampled, with a sample spanning read chunks
+        let size = SAMPLE_HASH_THRESHOLD as usize + 3 * READ_BUFFER_SIZE / 2;
+        let mut large = vec![0u8; size];
+        large[size / 2] = 1;
+        large[size - 1] = 2;
+        let path = create_file(temp.path(), "large.bin", &large);
+
+        let hashes = hash_stream(&large[..], size as u64).unwrap();
+        assert_eq!(Some(hashes.partial), partial_hash(&path, 0));
+        assert_eq!(
+            hashes.sample,
+            sample_hash_file(&path, size as u64, SAMPLE_SIZE, 0)
+        );
+        assert_eq!(Some(hashes.full), full_hash_file(&path));
+    }
+
+    #[test]
+    fn test_mmap_hash_matches_read_hash() {
+        let temp = TempDir::new().unwrap();
+        let content: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        let path = create_file(temp.path(), "file.bin", &content);
+        let file = File::open(&path).unwrap();
+
+        assert_eq!(mmap_hash(&file, 0), full_hash_file(&path));
+        assert_eq!(mmap_hash(&file, 1000), Some(hash_bytes(&content[1000..])));
+        assert_eq!(mmap_hash(&file, 1_000_000), Some(hash_bytes(b"")));
+    }
+
+    #[test]
+    fn test_direct_hash_matches_read_hash() {
+        let temp = TempDir::new().unwrap();
+        let content: Vec<u8> = (0..(DIRECT_READ_SIZE as u32 * 2 + 100))
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let path = create_file(temp.path(), "file.bin", &content);
+
+        // Not every filesystem supports O_DIRECT (e.g. tmpfs)
+        if let Some(hash) = direct_hash(&path, 0) {
+            assert_eq!(Some(hash), full_hash_file(&path));
+            assert_eq!(direct_hash(&path, 5000), Some(hash_bytes(&content[5000..])));
+        }
+    }
+
+    #[test]
+    fn test_parallel_hash_matches_read_hash() {
+        let temp = TempDir::new().unwrap();
+        let content: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        let path = create_file(temp.path(), "file.bin", &content);
+
+        let hash = parallel_hash_reader(File::open(&path).unwrap(), 100_000);
+        assert_eq!(hash, full_hash_file(&path));
+        let hash = parallel_hash_reader(File::open(&path).unwrap(), 7_000);
+        assert_eq!(hash, full_hash_file(&path));
+    }
+
+    #[test]
+    fn test_bytes_read_counted() {
+        let temp = TempDir::new().unwrap();
+        let path = create_file(temp.path(), "file.txt", &[7u8; 100_000]);
+
+        // Other tests hash files at the same time, the counter only ever grows
+        let before = bytes_read();
+        full_hash_file(&path).unwrap();
+
+        assert!(bytes_read() - before >= 100_000);
+    }
+
     #[test]
     fn test_group_by_full_hash_large_files() {
         let temp = TempDir::new().unwrap();
@@ -220,29 +1197,30 @@ mod tests {
         let path1 = create_file(temp.path(), "dup1.bin", &content);
         let path2 = create_file(temp.path(), "dup2.bin", &content);
 
-        let files = vec![path1, path2];
-        let groups = group_by_full_hash(files);
+        let files = vec![file_entry(path1), file_entry(path2)];
+        let groups = group_by_full_hash(files, 0);
 
         assert_eq!(groups.len(), 1);
-        assert_eq!(groups[0].len(), 2);
+        assert_eq!(groups[0].files.len(), 2);
+        assert_eq!(groups[0].hash, hash_bytes(&content));
     }
 
     #[test]
     fn test_full_hash_detects_late_differences() {
         let temp = TempDir::new().unwrap();
 
-        // Two files: identical first 8KB, different after
-        let mut content1 = vec![b'X'; PARTIAL_HASH_SIZE + 1000];
-        let mut content2 = vec![b'X'; PARTIAL_HASH_SIZE + 1000];
+        // Two files: identical chunks of the partial hash, different between them
+        let mut content1 = vec![b'X'; PARTIAL_HASH_SIZE * 5];
+        let mut content2 = vec![b'X'; PARTIAL_HASH_SIZE * 5];
         content1[PARTIAL_HASH_SIZE + 500] = b'A';
         content2[PARTIAL_HASH_SIZE + 500] = b'B';
 
         let path1 = create_file(temp.path(), "file1.bin", &content1);
         let path2 = create_file(temp.path(), "file2.bin", &content2);
 
-        // Partial hashes match (same first 8KB)
-        let partial1 = partial_hash_file(&path1).unwrap();
-        let partial2 = partial_hash_file(&path2).unwrap();
+        // Partial hashes match
+        let partial1 = partial_hash(&path1, 0).unwrap();
+        let partial2 = partial_hash(&path2, 0).unwrap();
         assert_eq!(partial1, partial2);
 
         // Full hashes differ
@@ -251,8 +1229,183 @@ mod tests {
         assert_ne!(full1, full2);
 
         // group_by_full_hash should NOT group them
-        let files = vec![path1, path2];
-        let groups = group_by_full_hash(files);
+        let files = vec![file_entry(path1), file_entry(path2)];
+        let groups = group_by_full_hash(files, 0);
         assert!(groups.is_empty());
     }
+
+    #[test]
+    fn test_small_hash_matches_full_hash() {
+        let temp = TempDir::new().unwrap();
+        for size in [
+            0,
+            1,
+            SMALL_FILE_SIZE - 1,
+            SMALL_FILE_SIZE,
+            SMALL_FILE_SIZE + 1,
+        ] {
+            let content: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+            let path = create_file(temp.path(), &format!("file{}", size), &content);
+
+            // The scanned size may be outdated, e.g. the file grew in the meantime
+            let mut entry = FileEntry {
+                path,
+                size: 0,
+                ..Default::default()
+            };
+            assert_eq!(
+                small_hash_entry(&mut entry, 0),
+                Some(hash_bytes(&content)),
+                "size {}",
+                size
+            );
+            assert_eq!(entry.size, size);
+        }
+    }
+
+    #[test]
+    fn test_group_by_full_hash_many_small_files() {
+        let temp = TempDir::new().unwrap();
+        let files: Vec<FileEntry> = (0..SMALL_FILE_BATCH * 2 + 1)
+            .map(|i| {
+                let content = format!("content {}", i % 3);
+                file_entry(create_file(
+                    temp.path(),
+                    &format!("{}.txt", i),
+                    content.as_bytes(),
+                ))
+            })
+            .collect();
+
+        let groups = group_by_full_hash(files, 0);
+
+        assert_eq!(groups.len(), 3);
+        assert_eq!(
+            groups.iter().map(|g| g.files.len()).sum::<usize>(),
+            SMALL_FILE_BATCH * 2 + 1
+        );
+    }
+
+    #[test]
+    fn test_hash_state_reused_between_files() {
+        let temp = TempDir::new().unwrap();
+        let long: Vec<u8> = (0..READ_BUFFER_SIZE * 2 + 7)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let path1 = create_file(temp.path(), "long.bin", &long);
+        let path2 = create_file(temp.path(), "short.bin", b"short");
+
+        // A previous hash must not leak into the next one through the shared state
+        assert_eq!(full_hash_file(&path1), Some(hash_bytes(&long)));
+        assert_eq!(full_hash_file(&path2), Some(hash_bytes(b"short")));
+        assert_eq!(
+            sample_hash_file(&path2, 5, 2, 0),
+            sample_hash_file(&path2, 5, 2, 0)
+        );
+        assert_eq!(full_hash_file(&path1), Some(hash_bytes(&long)));
+    }
+
+    #[test]
+    fn test_full_hash_captures_metadata() {
+        let temp = TempDir::new().unwrap();
+        let path = create_file(temp.path(), "file.txt", b"content");
+        let metadata = std::fs::metadata(&path).unwrap();
+
+        let mut entry = FileEntry {
+            path,
+            size: 0,
+            ..Default::default()
+        };
+        full_hash_entry(&mut entry, 0).unwrap();
+
+        assert_eq!(entry.size, metadata.len());
+        assert_eq!(entry.modified, metadata.modified().ok());
+    }
+
+    #[test]
+    fn test_hardlinks_hashed_once() {
+        use std::os::unix::fs::MetadataExt;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let temp = TempDir::new().unwrap();
+        let path1 = create_file(temp.path(), "file1.txt", b"content");
+        let path2 = temp.path().join("link.txt");
+        std::fs::hard_link(&path1, &path2).unwrap();
+        let path3 = create_file(temp.path(), "file3.txt", b"content");
+
+        let entry = |path: PathBuf| {
+            let metadata = std::fs::metadata(&path).unwrap();
+            FileEntry {
+                path,
+                size: metadata.len(),
+                dev: metadata.dev(),
+                ino: metadata.ino(),
+                archived: None,
+                ..Default::default()
+            }
+        };
+        let files = vec![entry(path1), entry(path2), entry(path3)];
+
+        let hashed = AtomicUsize::new(0);
+        let groups = group_by_hash(files, |file| {
+            hashed.fetch_add(1, Ordering::Relaxed);
+            full_hash_entry(file, 0)
+        });
+
+        assert_eq!(hashed.load(Ordering::Relaxed), 2);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].files.len(), 3);
+        assert!(groups[0].files.iter().all(|f| f.modified.is_some()));
+    }
+
+    #[test]
+    fn test_vanished_files_are_left_out() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp = TempDir::new().unwrap();
+        let path1 = create_file(temp.path(), "file1.txt", b"content");
+        let path2 = temp.path().join("link.txt");
+        std::fs::hard_link(&path1, &path2).unwrap();
+        let path3 = create_file(temp.path(), "file3.txt", b"content");
+        let path4 = create_file(temp.path(), "file4.txt", b"content");
+        let entry = |path: &Path| {
+            let metadata = std::fs::metadata(path).unwrap();
+            FileEntry {
+                path: path.to_path_buf(),
+                size: metadata.len(),
+                dev: metadata.dev(),
+                ino: metadata.ino(),
+                ..Default::default()
+            }
+        };
+        let files = vec![entry(&path1), entry(&path2), entry(&path3), entry(&path4)];
+        std::fs::remove_file(&path1).unwrap();
+        std::fs::remove_file(&path4).unwrap();
+
+        let groups = group_by_full_hash(files, 0);
+
+        assert_eq!(groups.len(), 1);
+        let mut paths: Vec<_> = groups[0].files.iter().map(|f| f.path.clone()).collect();
+        paths.sort();
+        assert_eq!(paths, [path3, path2]);
+        let failures = READ_FAILURES.lock().unwrap();
+        assert_eq!(failures.get(&path1), Some(&ReadFailure::Vanished));
+        assert_eq!(failures.get(&path4), Some(&ReadFailure::Vanished));
+    }
+
+    #[test]
+    fn test_unreadable_files_have_not_vanished() {
+        let temp = TempDir::new().unwrap();
+        let path = create_file(temp.path(), "file.txt", b"content");
+        let missing = temp.path().join("missing");
+
+        assert!(!record_read_failure(&path));
+        assert!(record_read_failure(&missing));
+        let failures = read_failures();
+        assert!(matches!(
+            failures.iter().find(|(p, _)| *p == path),
+            Some((_, ReadFailure::Unreadable(_)))
+        ));
+        assert!(failures.contains(&(missing, ReadFailure::Vanished)));
+    }
 }
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::UNIX_EPOCH;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::hasher::{self, Algorithm, Digest};
+use crate::output;
+use crate::scanner::{self, FileEntry};
+
+/// A file of an exported hash list, its path relative to the exported directory
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportedFile {
+    path: PathBuf,
+    size: u64,
+    /// Modification time in whole seconds since the Unix epoch, network filesystems often
+    /// don't keep more
+    modified: i64,
+    hash: String,
+}
+
+/// Contents of a file written by `dedup export-hashes`
+#[derive(Debug, Serialize, Deserialize)]
+struct Exported {
+    /// Directory the hashes were computed under, on the machine that exported them
+    root: PathBuf,
+    algorithm: String,
+    skip_header_bytes: u64,
+    files: Vec<ExportedFile>,
+}
+
+/// Full hashes of the files under a directory, computed on another machine.
+///
+/// A hash is used for a file at the same path relative to [`HashList::root`] as long as its
+/// size and modification time (in whole seconds) are the same.
+#[derive(Debug)]
+pub struct HashList {
+    /// Where the exported directory is found on this machine
+    pub root: PathBuf,
+    files: HashMap<PathBuf, (u64, i64, Digest)>,
+}
+
+/// Modification time of `file` in whole seconds since the Unix epoch
+fn modified_secs(file: &FileEntry) -> Option<i64> {
+    let modified = file.modified?.duration_since(UNIX_EPOCH).ok()?;
+    i64::try_from(modified.as_secs()).ok()
+}
+
+impl HashList {
+    /// Imported hash of `file`, if it is under the root and unchanged since it was exported
+    pub fn hash_of(&self, file: &FileEntry) -> Option<Digest> {
+        let path = std::path::absolute(&file.path).ok()?;
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let &(size, modified, hash) = self.files.get(relative)?;
+        (file.size == size && modified_secs(file) == Some(modified)).then_some(hash)
+    }
+}
+
+/// Compute the full hashes of all files under `root` and write them to `path`, returning
+/// the number of files exported
+pub fn export(root: &Path, skip: u64, path: &Path) -> Result<usize, String> {
+    if !root.is_dir() {
+        return Err(format!("{} is not a directory", root.display()));
+    }
+    let mut scan = scanner::scan_directory(root, &scanner::ScanFilters::default());
+    let files: Vec<ExportedFile> = scan
+        .files
+        .par_iter_mut()
+        .filter_map(|file| {
+            let hash = hasher::full_hash_cached(file, skip)?;
+            Some(ExportedFile {
+                path: file.path.strip_prefix(root).ok()?.to_path_buf(),
+                size: file.size,
+                modified: modified_secs(file)?,
+                hash: hash.to_hex(),
+            })
+        })
+        .collect();
+
+    let exported = Exported {
+        root: std::path::absolute(root).unwrap_or_else(|_| root.to_path_buf()),
+        algorithm: hasher::algorithm().name().to_string(),
+        skip_header_bytes: skip,
+        files,
+    };
+    output::write_atomically(path, |out| {
+        serde_json::to_writer(&mut *out, &exported)?;
+        Ok(())
+    })
+    .map_err(|e| format!("could not write {}: {}", path.display(), e))?;
+    Ok(exported.files.len())
+}
+
+/// Load the hashes exported to `path`, for the directory found at `root` on this machine
+/// (where it was exported from by default). They must have been computed like the hashes
+/// of this run.
+pub fn load(
+    path: &Path,
+    root: Option<&Path>,
+    algorithm: Algorithm,
+    skip: u64,
+) -> Result<HashList, String> {
+    let contents =
+        fs::read(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let exported: Exported = serde_json::from_slice(&contents)
+        .map_err(|e| format!("{} is not an exported hash list: {}", path.display(), e))?;
+    if exported.algorithm != algorithm.name() || exported.skip_header_bytes != skip {
+        return Err(format!(
+            "{} holds hashes computed with --hash {} and --skip-header-bytes {}, use the same options",
+            path.display(),
+            exported.algorithm,
+            exported.skip_header_bytes
+        ));
+    }
+
+    let files = exported
+        .files
+        .into_iter()
+        .filter_map(|file| {
+            let hash = Digest::from_hex(&file.hash)?;
+            Some((file.path, (file.size, file.modified, hash)))
+        })
+        .collect();
+    let root = match root {
+        Some(root) => std::path::absolute(root)
+            .map_err(|e| format!("could not resolve {}: {}", root.display(), e))?,
+        None => exported.root,
+    };
+    Ok(HashList { root, files })
+}
+
+/// Hashes imported for the current run, set once by [`import`]
+static IMPORTED: OnceLock<HashList> = OnceLock::new();
+
+/// Use the hashes of `list` for the rest of the run (`--import-hashes`)
+pub fn import(list: HashList) {
+    let _ = IMPORTED.set(list);
+}
+
+/// Imported hash of `file`, if any and the file is unchanged since it was exported
+pub fn imported_hash(file: &FileEntry) -> Option<Digest> {
+    IMPORTED.get()?.hash_of(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn create_file(dir: &Path, name: &str, content: &[u8]) -> PathBuf {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_export_and_load_under_another_root() {
+        let temp = TempDir::new().unwrap();
+        let nas = temp.path().join("nas");
+        create_file(&nas, "a.txt", b"content a");
+        create_file(&nas, "sub/b.txt", b"content b");
+        let list = temp.path().join("hashes.json");
+
+        assert_eq!(export(&nas, 0, &list).unwrap(), 2);
+
+        // The same tree, copied with its modification times to where it's mounted locally
+        let mount = temp.path().join("mnt");
+        fs::create_dir_all(mount.join("sub")).unwrap();
+        for name in ["a.txt", "sub/b.txt"] {
+            fs::copy(nas.join(name), mount.join(name)).unwrap();
+            let modified = fs::metadata(nas.join(name)).unwrap().modified().unwrap();
+            fs::File::options()
+                .write(true)
+                .open(mount.join(name))
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+        let hashes = load(&list, Some(&mount), hasher::algorithm(), 0).unwrap();
+
+        let file = scanner::stat_file(&mount.join("sub/b.txt")).unwrap();
+        assert_eq!(
+            hashes.hash_of(&file),
+            Some(hasher::hash_bytes(b"content b"))
+        );
+        // Changed files are hashed again
+        let mut changed = file.clone();
+        changed.size += 1;
+        assert_eq!(hashes.hash_of(&changed), None);
+        // So are files outside the imported directory
+        let outside = temp.path().join("sub/b.txt");
+        let mut elsewhere = file;
+        elsewhere.path = outside;
+        assert_eq!(hashes.hash_of(&elsewhere), None);
+    }
+
+    #[test]
+    fn test_load_rejects_other_settings() {
+        let temp = TempDir::new().unwrap();
+        create_file(temp.path(), "dir/a.txt", b"content");
+        let list = temp.path().join("hashes.json");
+        export(&temp.path().join("dir"), 0, &list).unwrap();
+
+        let loaded = load(&list, None, hasher::algorithm(), 0).unwrap();
+        assert_eq!(loaded.root, temp.path().join("dir"));
+        assert!(
+            load(&list, None, hasher::algorithm(), 4)
+                .unwrap_err()
+                .contains("--skip-header-bytes 0")
+        );
+
+        fs::write(&list, b"not json").unwrap();
+        assert!(
+            load(&list, None, hasher::algorithm(), 0)
+                .unwrap_err()
+                .contains("not an exported hash list")
+        );
+    }
+}
@@ -0,0 +1,465 @@
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::process::{Command, ExitStatus};
+
+use colored::Colorize;
+use rayon::prelude::*;
+
+use crate::actions::{self, ProtectedKeeper};
+use crate::interrupt;
+use crate::output::DuplicateGroup;
+
+/// Result of running user commands for duplicates
+#[derive(Debug, Default)]
+pub struct HookResult {
+    /// Number of commands that were run (or would be run in a dry run)
+    pub commands_run: usize,
+    /// Commands that could not be started or exited unsuccessfully
+    /// (command or the file it ran for, error message)
+    pub failures: Vec<(String, String)>,
+    /// Number of commands per unsuccessful exit code
+    pub exit_codes: BTreeMap<i32, usize>,
+}
+
+impl HookResult {
+    /// Combine the results of two independently run sets of commands
+    fn merge(mut self, other: HookResult) -> HookResult {
+        self.commands_run += other.commands_run;
+        self.failures.extend(other.failures);
+        for (code, count) in other.exit_codes {
+            *self.exit_codes.entry(code).or_default() += count;
+        }
+        self
+    }
+
+    /// Record the outcome of a command that was run for `subject`
+    fn record(&mut self, subject: String, status: std::io::Result<ExitStatus>) {
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => {
+                if let Some(code) = status.code() {
+                    *self.exit_codes.entry(code).or_default() += 1;
+                }
+                self.failures.push((subject, status.to_string()));
+            }
+            Err(e) => self.failures.push((subject, e.to_string())),
+        }
+    }
+}
+
+/// Quote a string for use as a single word in a POSIX shell command.
+fn shell_quote(s: &OsStr) -> Vec<u8> {
+    let mut quoted = vec![b'\''];
+    for &b in s.as_bytes() {
+        if b == b'\'' {
+            quoted.extend_from_slice(b"'\\''");
+        } else {
+            quoted.push(b);
+        }
+    }
+    quoted.push(b'\'');
+    quoted
+}
+
+/// Join quoted paths with spaces.
+fn quote_all<'a>(paths: impl Iterator<Item = &'a OsStr>) -> Vec<u8> {
+    paths.map(shell_quote).collect::<Vec<_>>().join(&b' ')
+}
+
+/// Expand the placeholders of a per-group command template. Archive members must already
+/// be left out of `group`, see [`run_per_group`].
+///
+/// Supported placeholders:
+/// - `{files}`: all files of the group
+/// - `{keeper}`: the file that is kept by the actions, following `on_protected_keeper`
+/// - `{duplicates}`: all files except the keeper
+/// - `{hash}`: the content hash (algorithm from `--hash`)
+/// - `{size}`: size of each file in bytes
+///
+/// Paths are shell-quoted.
+pub fn expand_template(
+    template: &str,
+    group: &DuplicateGroup,
+    on_protected_keeper: ProtectedKeeper,
+) -> OsString {
+    let keeper = actions::kept_file(&group.files, on_protected_keeper);
+    let duplicates = group.files.iter().filter(|f| f.path != keeper.path);
+
+    let replacements: [(&str, Vec<u8>); 5] = [
+        (
+            "{files}",
+            quote_all(group.files.iter().map(|f| f.path.as_os_str())),
+        ),
+        ("{keeper}", shell_quote(keeper.path.as_os_str())),
+        (
+            "{duplicates}",
+            quote_all(duplicates.map(|f| f.path.as_os_str())),
+        ),
+        ("{hash}", group.hash.to_hex().as_bytes().to_vec()),
+        ("{size}", group.size.to_string().into_bytes()),
+    ];
+
+    let mut command = Vec::new();
+    let mut rest = template;
+    'outer: while !rest.is_empty() {
+        for (placeholder, value) in &replacements {
+            if let Some(after) = rest.strip_prefix(placeholder) {
+                command.extend_from_slice(value);
+                rest = after;
+                continue 'outer;
+            }
+        }
+        let c = rest.chars().next().unwrap();
+        command.extend_from_slice(&rest.as_bytes()[..c.len_utf8()]);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    OsString::from_vec(command)
+}
+
+/// Run a shell command for each duplicate group, one group at a time. Once the run is
+/// interrupted, no further commands are started.
+///
+/// Archive members (`--scan-archives`) are left out like the actions do, groups with fewer
+/// than two loose files get no command.
+///
+/// If `dry_run` is true, only prints the commands that would be run.
+pub fn run_per_group(
+    groups: &[DuplicateGroup],
+    template: &str,
+    on_protected_keeper: ProtectedKeeper,
+    dry_run: bool,
+    verbose: bool,
+) -> HookResult {
+    let mut result = HookResult::default();
+
+    let groups = groups
+        .iter()
+        .map(actions::without_archive_members)
+        .filter(|g| g.files.len() >= 2);
+    for group in groups {
+        if interrupt::requested() {
+            break;
+        }
+        let command = expand_template(template, &group, on_protected_keeper);
+        let display = command.to_string_lossy().into_owned();
+
+        if dry_run || verbose {
+            println!(
+                "{} {}",
+                if dry_run {
+                    "[dry-run]".yellow()
+                } else {
+                    "[exec]".green()
+                },
+                display
+            );
+        }
+
+        result.commands_run += 1;
+        if dry_run {
+            continue;
+        }
+
+        let status = Command::new("sh").arg("-c").arg(&command).status();
+        result.record(display, status);
+    }
+
+    result
+}
+
+/// Run a shell command for each duplicate, with the details passed in environment variables:
+///
+/// - `DEDUP_KEEPER`: the file that is kept by the actions, following `on_protected_keeper`
+/// - `DEDUP_DUPLICATE`: the duplicate of the keeper
+/// - `DEDUP_SIZE`: size of each file in bytes
+/// - `DEDUP_HASH`: the content hash (algorithm from `--hash`)
+///
+/// Archive members (`--scan-archives`) are left out like in [`run_per_group`].
+///
+/// Commands are run in parallel, at most as many at a time as there are worker threads.
+/// Once the run is interrupted, no further commands are started.
+///
+/// If `dry_run` is true, only prints the duplicates the command would be run for.
+pub fn run_per_duplicate(
+    groups: &[DuplicateGroup],
+    command: &str,
+    on_protected_keeper: ProtectedKeeper,
+    dry_run: bool,
+    verbose: bool,
+) -> HookResult {
+    let groups: Vec<_> = groups
+        .iter()
+        .map(actions::without_archive_members)
+        .filter(|g| g.files.len() >= 2)
+        .collect();
+    let duplicates: Vec<_> = groups
+        .iter()
+        .flat_map(|group| {
+            let keeper = actions::kept_file(&group.files, on_protected_keeper);
+            group
+                .files
+                .iter()
+                .filter(move |f| f.path != keeper.path)
+                .map(move |duplicate| (group, keeper, duplicate))
+        })
+        .collect();
+
+    duplicates
+        .into_par_iter()
+        .map(|(group, keeper, duplicate)| {
+            if interrupt::requested() {
+                return HookResult::default();
+            }
+            let mut result = HookResult {
+                commands_run: 1,
+                ..Default::default()
+            };
+
+            if dry_run || verbose {
+                println!(
+                    "{} {} -> {}",
+                    if dry_run {
+                        "[dry-run]".yellow()
+                    } else {
+                        "[exec]".green()
+                    },
+                    duplicate.path.display(),
+                    keeper.path.display()
+                );
+            }
+
+            if !dry_run {
+                let status = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .env("DEDUP_KEEPER", &keeper.path)
+                    .env("DEDUP_DUPLICATE", &duplicate.path)
+                    .env("DEDUP_SIZE", group.size.to_string())
+                    .env("DEDUP_HASH", group.hash.to_hex().as_str())
+                    .status();
+                result.record(duplicate.path.display().to_string(), status);
+            }
+
+            result
+        })
+        .reduce(HookResult::default, HookResult::merge)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::{ContentHashes, hash_bytes};
+    use crate::scanner::FileEntry;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn group(paths: &[&str]) -> DuplicateGroup {
+        let files = paths
+            .iter()
+            .map(|p| FileEntry {
+                path: PathBuf::from(p),
+                size: 3,
+                ..Default::default()
+            })
+            .collect();
+        DuplicateGroup::new(hash_bytes(b"abc"), 3, files)
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote(OsStr::new("a b")), b"'a b'");
+        assert_eq!(shell_quote(OsStr::new("it's")), b"'it'\\''s'");
+    }
+
+    #[test]
+    fn test_expand_template() {
+        let group = group(&["/a/long/copy.txt", "/a/x.txt"]);
+
+        let command = expand_template(
+            "tag {keeper} -- {duplicates} ({size})",
+            &group,
+            ProtectedKeeper::NextBest,
+        );
+        assert_eq!(command, "tag '/a/x.txt' -- '/a/long/copy.txt' (3)");
+
+        let command = expand_template("echo {files} {hash}", &group, ProtectedKeeper::NextBest);
+        assert_eq!(
+            command.to_string_lossy(),
+            format!(
+                "echo '/a/long/copy.txt' '/a/x.txt' {}",
+                hash_bytes(b"abc").to_hex()
+            )
+        );
+    }
+
+    #[test]
+    fn test_expand_template_keeps_unknown_braces() {
+        let group = group(&["/a", "/b"]);
+        let command = expand_template(
+            "awk '{print}' {nope} {keeper}",
+            &group,
+            ProtectedKeeper::NextBest,
+        );
+        assert_eq!(command, "awk '{print}' {nope} '/a'");
+    }
+
+    #[test]
+    fn test_run_per_group() {
+        let temp = TempDir::new().unwrap();
+        let log = temp.path().join("log");
+        let template = format!("echo {{keeper}} >> '{}'", log.display());
+
+        let groups = vec![group(&["/a", "/a2"]), group(&["/b", "/b2"])];
+        let result = run_per_group(&groups, &template, ProtectedKeeper::NextBest, false, false);
+
+        assert_eq!(result.commands_run, 2);
+        assert!(result.failures.is_empty());
+        assert_eq!(fs::read_to_string(&log).unwrap(), "/a\n/b\n");
+    }
+
+    /// Mark the files of `group` whose path contains `!` as archive members
+    fn with_archive_members(mut group: DuplicateGroup) -> DuplicateGroup {
+        for file in &mut group.files {
+            if file.path.to_string_lossy().contains('!') {
+                file.archived = Some(Arc::new(ContentHashes {
+                    partial: group.hash,
+                    sample: None,
+                    full: group.hash,
+                }));
+            }
+        }
+        group
+    }
+
+    #[test]
+    fn test_archive_members_are_left_out() {
+        let temp = TempDir::new().unwrap();
+        let log = temp.path().join("log");
+        let groups = vec![
+            with_archive_members(group(&["/z.zip!a", "/sub/long1", "/sub/long2"])),
+            with_archive_members(group(&["/y.zip!b", "/b"])),
+        ];
+
+        let template = format!(
+            "echo keep {{keeper}} dup {{duplicates}} >> '{}'",
+            log.display()
+        );
+        let result = run_per_group(&groups, &template, ProtectedKeeper::NextBest, false, false);
+        assert_eq!(result.commands_run, 1);
+        assert_eq!(
+            fs::read_to_string(&log).unwrap(),
+            "keep /sub/long1 dup /sub/long2\n"
+        );
+
+        fs::remove_file(&log).unwrap();
+        let command = format!(
+            "echo \"$DEDUP_KEEPER $DEDUP_DUPLICATE\" >> '{}'",
+            log.display()
+        );
+        let result = run_per_duplicate(&groups, &command, ProtectedKeeper::NextBest, false, false);
+        assert_eq!(result.commands_run, 1);
+        assert_eq!(fs::read_to_string(&log).unwrap(), "/sub/long1 /sub/long2\n");
+    }
+
+    #[test]
+    fn test_run_per_group_dry_run() {
+        let temp = TempDir::new().unwrap();
+        let log = temp.path().join("log");
+        let template = format!("echo {{keeper}} >> '{}'", log.display());
+
+        let result = run_per_group(
+            &[group(&["/a", "/a2"])],
+            &template,
+            ProtectedKeeper::NextBest,
+            true,
+            false,
+        );
+
+        assert_eq!(result.commands_run, 1);
+        assert!(!log.exists());
+    }
+
+    #[test]
+    fn test_run_per_group_reports_failures() {
+        let result = run_per_group(
+            &[group(&["/a", "/a2"])],
+            "exit 3",
+            ProtectedKeeper::NextBest,
+            false,
+            false,
+        );
+
+        assert_eq!(result.commands_run, 1);
+        assert_eq!(result.failures.len(), 1);
+        assert_eq!(result.failures[0].0, "exit 3");
+        assert_eq!(result.exit_codes.get(&3), Some(&1));
+    }
+
+    #[test]
+    fn test_run_per_duplicate_sets_environment() {
+        let temp = TempDir::new().unwrap();
+        let log = temp.path().join("log");
+        let command = format!(
+            "echo \"$DEDUP_KEEPER $DEDUP_DUPLICATE $DEDUP_SIZE $DEDUP_HASH\" >> '{}'",
+            log.display()
+        );
+
+        let result = run_per_duplicate(
+            &[group(&["/a", "/a2", "/a3"])],
+            &command,
+            ProtectedKeeper::NextBest,
+            false,
+            false,
+        );
+
+        assert_eq!(result.commands_run, 2);
+        assert!(result.failures.is_empty());
+
+        let hash = hash_bytes(b"abc").to_hex();
+        let mut lines: Vec<String> = fs::read_to_string(&log)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec![format!("/a /a2 3 {}", hash), format!("/a /a3 3 {}", hash)]
+        );
+    }
+
+    #[test]
+    fn test_run_per_duplicate_aggregates_exit_codes() {
+        let groups = vec![group(&["/a", "/a2", "/a3"]), group(&["/b", "/b2"])];
+        let command = "case \"$DEDUP_DUPLICATE\" in /a*) exit 2 ;; esac";
+
+        let result = run_per_duplicate(&groups, command, ProtectedKeeper::NextBest, false, false);
+
+        assert_eq!(result.commands_run, 3);
+        assert_eq!(result.failures.len(), 2);
+        assert_eq!(result.exit_codes.get(&2), Some(&2));
+        assert_eq!(result.exit_codes.len(), 1);
+    }
+
+    #[test]
+    fn test_run_per_duplicate_dry_run() {
+        let temp = TempDir::new().unwrap();
+        let log = temp.path().join("log");
+        let command = format!("touch '{}'", log.display());
+
+        let result = run_per_duplicate(
+            &[group(&["/a", "/a2"])],
+            &command,
+            ProtectedKeeper::NextBest,
+            true,
+            false,
+        );
+
+        assert_eq!(result.commands_run, 1);
+        assert!(!log.exists());
+    }
+}
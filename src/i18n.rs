@@ -0,0 +1,374 @@
+use std::fmt::Display;
+
+use clap::ValueEnum;
+
+/// Language of the human-readable output. Machine formats (JSON, ...) are always English.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Lang {
+    /// English
+    #[default]
+    En,
+    /// Turkish
+    Tr,
+}
+
+impl Lang {
+    /// Message catalog for this language
+    pub fn messages(self) -> &'static Messages {
+        match self {
+            Lang::En => &EN,
+            Lang::Tr => &TR,
+        }
+    }
+}
+
+/// Catalog of human-readable messages.
+///
+/// `{}` placeholders are filled in order with [`fill`].
+#[derive(Debug)]
+pub struct Messages {
+    pub report_title: &'static str,
+    pub partial_report: &'static str,
+    pub estimate_title: &'static str,
+    pub estimate_files: &'static str,
+    pub estimate_savings: &'static str,
+    pub scanned_files: &'static str,
+    pub duplicate_files: &'static str,
+    pub potential_savings: &'static str,
+    pub allocated_savings: &'static str,
+    pub savings_disclaimer: &'static str,
+    pub already_deduplicated: &'static str,
+    pub already_linked: &'static str,
+    pub vanished_files: &'static str,
+    pub tree_structure: &'static str,
+    pub performance: &'static str,
+    pub scan_errors: &'static str,
+    pub cross_filesystem_groups: &'static str,
+    pub directories_title: &'static str,
+    pub directory_waste: &'static str,
+    pub slowest_files_title: &'static str,
+    pub slowest_directories_title: &'static str,
+    pub file_hot_spot: &'static str,
+    pub directory_hot_spot: &'static str,
+    pub contained_title: &'static str,
+    pub contained_file: &'static str,
+    pub contained_in: &'static str,
+    pub no_duplicates: &'static str,
+    pub group_header: &'static str,
+    pub group_files: &'static str,
+    pub group_each: &'static str,
+    pub group_omitted: &'static str,
+    pub group_cross_filesystem: &'static str,
+    pub scanning_files: &'static str,
+    pub scan_progress: &'static str,
+    pub files_compared: &'static str,
+    pub dry_run_summary: &'static str,
+    pub linked_summary: &'static str,
+    pub dry_run_stub_summary: &'static str,
+    pub stubbed_summary: &'static str,
+    pub skipped_changed: &'static str,
+    pub rolled_back: &'static str,
+    pub below_min_saving: &'static str,
+    pub empty_groups_skipped: &'static str,
+    pub link_limit_splits: &'static str,
+    pub mount_skipped: &'static str,
+    pub protected_keeper_skipped: &'static str,
+    pub interrupted_groups: &'static str,
+    pub archive_members_skipped: &'static str,
+    pub dry_run_merge_moved: &'static str,
+    pub merge_moved: &'static str,
+    pub dry_run_merge_removed: &'static str,
+    pub merge_removed: &'static str,
+    pub merge_interrupted: &'static str,
+    pub errors: &'static str,
+    pub action_duration: &'static str,
+    pub hook_summary: &'static str,
+    pub hook_exit_code: &'static str,
+    pub hook_failures: &'static str,
+    pub cache_stats: &'static str,
+    pub cache_pruned: &'static str,
+    pub cache_cleared: &'static str,
+    pub cache_verified: &'static str,
+    pub cache_not_checked: &'static str,
+    pub hashes_exported: &'static str,
+    pub diff_new_groups: &'static str,
+    pub diff_resolved_groups: &'static str,
+    pub diff_changed_groups: &'static str,
+    pub diff_group: &'static str,
+    pub diff_changed_group: &'static str,
+    pub diff_unchanged: &'static str,
+    pub diff_wasted: &'static str,
+}
+
+const EN: Messages = Messages {
+    report_title: "Duplicate Report",
+    partial_report: "Interrupted, only the duplicates confirmed so far are listed",
+    estimate_title: "Estimate from a {}% sample ({} of {} candidate groups hashed, the numbers below only cover the sample):",
+    estimate_files: "Duplicate files: ~{} (95%: {} to {})",
+    estimate_savings: "Potential savings: ~{} (95%: {} to {})",
+    scanned_files: "Scanned: {} files",
+    duplicate_files: "Duplicate files: {}",
+    potential_savings: "Potential savings: {}",
+    allocated_savings: "Allocated on disk: {} (sparse files and hardlinks count with the space they use)",
+    savings_disclaimer: "Real savings may vary depending on existing links between files.",
+    already_deduplicated: "Already deduplicated: {} groups (hardlinked)",
+    already_linked: "Already hardlinked: {} of duplicates, not counted as savings",
+    vanished_files: "Vanished: {} files (deleted before they were hashed)",
+    tree_structure: "Structure: {} unique inodes, {} hardlinked, {} symlinks",
+    scan_errors: "Could not read {} files or directories, duplicates in them may be missing",
+    performance: "Timings: scan {}s, size groups {}s, partial hashes {}s, full hashes {}s (summed over threads), {} hashed in {}s at {} MB/s",
+    cross_filesystem_groups: "Groups spanning multiple filesystems: {} (can't be fully hardlinked)",
+    directories_title: "Wasted space by directory:",
+    directory_waste: "{} in {} duplicates: {}",
+    slowest_files_title: "Slowest files to hash:",
+    slowest_directories_title: "Slowest directories to hash:",
+    file_hot_spot: "{}s, {} at {} MB/s: {}",
+    directory_hot_spot: "{}s, {} in {} files at {} MB/s: {}",
+    contained_title: "Files that are the start of larger files (e.g. interrupted downloads):",
+    contained_file: "{} ({}) is the start of:",
+    contained_in: "{} ({})",
+    no_duplicates: "No duplicates found.",
+    group_header: "Group {}:",
+    group_files: "{} files",
+    group_each: "({} each)",
+    group_omitted: "... and {} more files (see --max-group-size)",
+    group_cross_filesystem: "[spans {} filesystems]",
+    scanning_files: "Scanning files...",
+    scan_progress: "{} files in {} directories",
+    files_compared: "files compared",
+    dry_run_summary: "[dry-run] Would link {} files, saving {}",
+    linked_summary: "Linked {} files, saved {}",
+    dry_run_stub_summary: "[dry-run] Would replace {} files with stubs, saving {}",
+    stubbed_summary: "Replaced {} files with stubs, saved {}",
+    skipped_changed: "Skipped {} files that changed since they were scanned:",
+    rolled_back: "Rolled back {} groups after failures",
+    below_min_saving: "Left {} groups untouched below the minimum saving",
+    empty_groups_skipped: "Left {} groups of empty files untouched (see --include-empty-in-actions)",
+    link_limit_splits: "Kept {} additional originals because of the filesystem hardlink limit",
+    mount_skipped: "Skipped {} files on the filesystem of {}: {}",
+    protected_keeper_skipped: "Left {} groups untouched because their kept copy is immutable (see --on-protected-keeper)",
+    interrupted_groups: "Interrupted, left {} groups untouched",
+    archive_members_skipped: "Left {} archive members untouched, they are only reported",
+    dry_run_merge_moved: "[dry-run] Would move {} files ({}) into {}",
+    merge_moved: "Moved {} files ({}) into {}",
+    dry_run_merge_removed: "[dry-run] Would remove {} files already in {}, freeing {}",
+    merge_removed: "Removed {} files already in {}, freed {}",
+    merge_interrupted: "Interrupted, left {} files in the source directory",
+    errors: "Errors ({}):",
+    action_duration: "Action took {}s",
+    hook_summary: "Ran {} commands, {} failed",
+    hook_exit_code: "exit code {}: {} commands",
+    hook_failures: "Failed commands ({}):",
+    cache_stats: "{} cached hashes of {} files ({} partial, {} full), {} on disk",
+    cache_pruned: "Removed {} hashes of missing or changed files and {} over the limit, {} left",
+    cache_cleared: "Removed {} hashes",
+    cache_verified: "Checked {} hashes, {} were out of date and have been removed",
+    cache_not_checked: "{} hashes of missing or changed files, or computed with another --hash, were not checked",
+    hashes_exported: "Exported the hashes of {} files under {} to {}",
+    diff_new_groups: "New duplicate groups ({}):",
+    diff_resolved_groups: "Resolved duplicate groups ({}):",
+    diff_changed_groups: "Changed duplicate groups ({}):",
+    diff_group: "{}: {} files of {}, {} wasted",
+    diff_changed_group: "{}: {} -> {} files of {}",
+    diff_unchanged: "{} groups unchanged",
+    diff_wasted: "Wasted space: {} -> {} ({})",
+};
+
+const TR: Messages = Messages {
+    report_title: "Kopya Dosya Raporu",
+    partial_report: "Kesintiye uğradı, yalnızca şimdiye kadar doğrulanan kopyalar listelendi",
+    estimate_title: "%{} örneklemden tahmin ({} / {} aday grup hashlendi, aşağıdaki sayılar yalnızca örneklemi kapsar):",
+    estimate_files: "Kopya dosyalar: ~{} (%95: {} ile {} arası)",
+    estimate_savings: "Olası kazanç: ~{} (%95: {} ile {} arası)",
+    scanned_files: "Taranan: {} dosya",
+    duplicate_files: "Kopya dosyalar: {}",
+    potential_savings: "Olası kazanç: {}",
+    allocated_savings: "Diskte ayrılan: {} (seyrek dosyalar ve sabit bağlantılar kullandıkları alanla sayılır)",
+    savings_disclaimer: "Gerçek kazanç, dosyalar arasındaki mevcut bağlantılara göre değişebilir.",
+    already_deduplicated: "Zaten tekilleştirilmiş: {} grup (sabit bağlı)",
+    already_linked: "Zaten sabit bağlı: {} kopya, tasarrufa sayılmadı",
+    vanished_files: "Kaybolan: {} dosya (karma hesaplanmadan silindi)",
+    tree_structure: "Yapı: {} benzersiz inode, {} sabit bağlı, {} sembolik bağ",
+    scan_errors: "{} dosya veya dizin okunamadı, içlerindeki kopyalar eksik olabilir",
+    performance: "Süreler: tarama {}sn, boyut grupları {}sn, kısmi özetler {}sn, tam özetler {}sn (iş parçacıklarının toplamı), {} {}sn içinde {} MB/sn hızla özetlendi",
+    cross_filesystem_groups: "Birden fazla dosya sistemine yayılan gruplar: {} (tamamen bağlanamaz)",
+    directories_title: "Dizinlere göre boşa harcanan alan:",
+    directory_waste: "{} ({} kopya): {}",
+    slowest_files_title: "Özetlenmesi en uzun süren dosyalar:",
+    slowest_directories_title: "Özetlenmesi en uzun süren dizinler:",
+    file_hot_spot: "{}sn, {} ({} MB/sn): {}",
+    directory_hot_spot: "{}sn, {} ({} dosya, {} MB/sn): {}",
+    contained_title: "Daha büyük dosyaların başlangıcı olan dosyalar (ör. yarım kalmış indirmeler):",
+    contained_file: "{} ({}) şu dosyaların başlangıcı:",
+    contained_in: "{} ({})",
+    no_duplicates: "Kopya bulunamadı.",
+    group_header: "Grup {}:",
+    group_files: "{} dosya",
+    group_each: "(her biri {})",
+    group_omitted: "... ve {} dosya daha (bkz. --max-group-size)",
+    group_cross_filesystem: "[{} dosya sistemine yayılıyor]",
+    scanning_files: "Dosyalar taranıyor...",
+    scan_progress: "{} dizinde {} dosya",
+    files_compared: "dosya karşılaştırıldı",
+    dry_run_summary: "[dry-run] {} dosya bağlanacaktı, kazanç: {}",
+    linked_summary: "{} dosya bağlandı, kazanç: {}",
+    dry_run_stub_summary: "[dry-run] {} dosya yer tutucuyla değiştirilecekti, kazanç: {}",
+    stubbed_summary: "{} dosya yer tutucuyla değiştirildi, kazanç: {}",
+    skipped_changed: "Tarandıktan sonra değişen {} dosya atlandı:",
+    rolled_back: "Hatalar nedeniyle {} grup geri alındı",
+    below_min_saving: "Asgari kazancın altında kalan {} gruba dokunulmadı",
+    empty_groups_skipped: "Boş dosyalardan oluşan {} gruba dokunulmadı (bkz. --include-empty-in-actions)",
+    link_limit_splits: "Dosya sistemi bağlantı sınırı nedeniyle {} ek orijinal korundu",
+    mount_skipped: "{} dosya, {} dosya sisteminde atlandı: {}",
+    protected_keeper_skipped: "Korunacak kopyası değiştirilemez olduğu için {} gruba dokunulmadı (bkz. --on-protected-keeper)",
+    interrupted_groups: "Kesintiye uğradı, {} gruba dokunulmadı",
+    archive_members_skipped: "{} arşiv üyesine dokunulmadı, yalnızca raporlanırlar",
+    dry_run_merge_moved: "[dry-run] {} dosya ({}) {} içine taşınacaktı",
+    merge_moved: "{} dosya ({}) {} içine taşındı",
+    dry_run_merge_removed: "[dry-run] {} dosya zaten {} içinde olduğu için silinecekti, kazanç: {}",
+    merge_removed: "{} dosya zaten {} içinde olduğu için silindi, kazanç: {}",
+    merge_interrupted: "Kesintiye uğradı, {} dosya kaynak dizinde bırakıldı",
+    errors: "Hatalar ({}):",
+    action_duration: "İşlem {}sn sürdü",
+    hook_summary: "{} komut çalıştırıldı, {} başarısız",
+    hook_exit_code: "çıkış kodu {}: {} komut",
+    hook_failures: "Başarısız komutlar ({}):",
+    cache_stats: "Önbellekte {} özet, {} dosya ({} kısmi, {} tam), diskte {}",
+    cache_pruned: "Silinmiş veya değişmiş dosyaların {} özeti ve sınırı aşan {} özet kaldırıldı, {} kaldı",
+    cache_cleared: "{} özet kaldırıldı",
+    cache_verified: "{} özet denetlendi, {} tanesi güncel değildi ve kaldırıldı",
+    cache_not_checked: "Silinmiş veya değişmiş dosyaların ya da başka bir --hash ile hesaplanan {} özet denetlenmedi",
+    hashes_exported: "{} dosyanın özetleri ({} altında) {} dosyasına aktarıldı",
+    diff_new_groups: "Yeni kopya grupları ({}):",
+    diff_resolved_groups: "Giderilen kopya grupları ({}):",
+    diff_changed_groups: "Değişen kopya grupları ({}):",
+    diff_group: "{}: {} dosya, her biri {}, boşa giden {}",
+    diff_changed_group: "{}: {} -> {} dosya, her biri {}",
+    diff_unchanged: "{} grup değişmedi",
+    diff_wasted: "Boşa giden alan: {} -> {} ({})",
+};
+
+/// Fill the `{}` placeholders of a message in order
+pub fn fill(template: &str, args: &[&dyn Display]) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut args = args.iter();
+    let mut parts = template.split("{}").peekable();
+
+    while let Some(part) = parts.next() {
+        result.push_str(part);
+        if parts.peek().is_some()
+            && let Some(arg) = args.next()
+        {
+            result.push_str(&arg.to_string());
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_placeholders_in_order() {
+        assert_eq!(
+            fill("Linked {} files, saved {}", &[&3, &"12 bytes"]),
+            "Linked 3 files, saved 12 bytes"
+        );
+    }
+
+    #[test]
+    fn test_fill_without_placeholders() {
+        assert_eq!(fill("No duplicates found.", &[]), "No duplicates found.");
+    }
+
+    #[test]
+    fn test_fill_missing_arguments() {
+        assert_eq!(fill("Group {}:", &[]), "Group :");
+    }
+
+    #[test]
+    fn test_catalogs_have_matching_placeholders() {
+        fn placeholders(m: &Messages) -> Vec<usize> {
+            [
+                m.report_title,
+                m.partial_report,
+                m.estimate_title,
+                m.estimate_files,
+                m.estimate_savings,
+                m.scanned_files,
+                m.duplicate_files,
+                m.potential_savings,
+                m.allocated_savings,
+                m.savings_disclaimer,
+                m.already_deduplicated,
+                m.already_linked,
+                m.vanished_files,
+                m.tree_structure,
+                m.performance,
+                m.scan_errors,
+                m.cross_filesystem_groups,
+                m.directories_title,
+                m.directory_waste,
+                m.slowest_files_title,
+                m.slowest_directories_title,
+                m.file_hot_spot,
+                m.directory_hot_spot,
+                m.contained_title,
+                m.contained_file,
+                m.contained_in,
+                m.no_duplicates,
+                m.group_header,
+                m.group_files,
+                m.group_each,
+                m.group_omitted,
+                m.group_cross_filesystem,
+                m.scanning_files,
+                m.scan_progress,
+                m.files_compared,
+                m.dry_run_summary,
+                m.linked_summary,
+                m.dry_run_stub_summary,
+                m.stubbed_summary,
+                m.skipped_changed,
+                m.rolled_back,
+                m.below_min_saving,
+                m.empty_groups_skipped,
+                m.link_limit_splits,
+                m.mount_skipped,
+                m.protected_keeper_skipped,
+                m.interrupted_groups,
+                m.archive_members_skipped,
+                m.dry_run_merge_moved,
+                m.merge_moved,
+                m.dry_run_merge_removed,
+                m.merge_removed,
+                m.merge_interrupted,
+                m.errors,
+                m.action_duration,
+                m.hook_summary,
+                m.hook_exit_code,
+                m.hook_failures,
+                m.cache_stats,
+                m.cache_pruned,
+                m.cache_cleared,
+                m.cache_verified,
+                m.cache_not_checked,
+                m.hashes_exported,
+                m.diff_new_groups,
+                m.diff_resolved_groups,
+                m.diff_changed_groups,
+                m.diff_group,
+                m.diff_changed_group,
+                m.diff_unchanged,
+                m.diff_wasted,
+            ]
+            .iter()
+            .map(|s| s.matches("{}").count())
+            .collect()
+        }
+
+        assert_eq!(placeholders(&EN), placeholders(&TR));
+    }
+}
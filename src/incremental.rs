@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+use crate::hasher::{Digest, HashGroup, HashGroups};
+use crate::scanner::FileEntry;
+
+/// Metadata of a file when its duplicates were determined, and its content hash if it had
+/// any
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KnownFile {
+    pub dev: u64,
+    pub ino: u64,
+    pub size: u64,
+    pub mtime_nanos: i64,
+    pub hash: Option<Digest>,
+}
+
+impl KnownFile {
+    /// `file` as scanned, `None` for archive members and files without a modification time
+    /// to recognize them by
+    pub fn of(file: &FileEntry, hash: Option<Digest>) -> Option<Self> {
+        if file.archived.is_some() {
+            return None;
+        }
+        let mtime = file.modified?.duration_since(UNIX_EPOCH).ok()?;
+        Some(KnownFile {
+            dev: file.dev,
+            ino: file.ino,
+            size: file.size,
+            mtime_nanos: i64::try_from(mtime.as_nanos()).ok()?,
+            hash,
+        })
+    }
+}
+
+/// The candidates an `--incremental` run compared, with the hashes of those that turned out
+/// to be duplicates.
+///
+/// The candidates of a size group were all compared with each other, so as long as none of
+/// them changed and no file joined the group, its duplicates are still the same.
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    pub files: HashMap<PathBuf, KnownFile>,
+}
+
+impl Snapshot {
+    /// Record the candidates of a run, `hashes` are the content hashes of those found in
+    /// duplicate groups
+    pub fn record(candidates: &[FileEntry], hashes: &HashMap<PathBuf, Digest>) -> Self {
+        Snapshot {
+            files: candidates
+                .iter()
+                .filter_map(|file| {
+                    let known = KnownFile::of(file, hashes.get(&file.path).copied())?;
+                    Some((file.path.clone(), known))
+                })
+                .collect(),
+        }
+    }
+
+    /// Duplicate groups of `size_group` as found by the recorded run, `None` if any of its
+    /// files is new or changed since, so the group has to be compared again
+    pub fn known_groups(&self, size_group: &[FileEntry]) -> Option<HashGroups> {
+        let mut by_hash: HashMap<Digest, Vec<FileEntry>> = HashMap::new();
+        for file in size_group {
+            let known = self.files.get(&file.path)?;
+            if KnownFile::of(file, known.hash)? != *known {
+                return None;
+            }
+            if let Some(hash) = known.hash {
+                by_hash.entry(hash).or_default().push(file.clone());
+            }
+        }
+
+        Some(
+            by_hash
+                .into_iter()
+                .filter(|(_, files)| files.len() >= 2)
+                .map(|(hash, files)| HashGroup { hash, files })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::hash_bytes;
+    use std::time::{Duration, SystemTime};
+
+    fn file_entry(path: &str, ino: u64) -> FileEntry {
+        FileEntry {
+            path: PathBuf::from(path),
+            size: 100,
+            modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            dev: 1,
+            ino,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_known_groups_of_unchanged_files() {
+        let files = [
+            file_entry("/a", 1),
+            file_entry("/b", 2),
+            file_entry("/c", 3),
+            file_entry("/unique", 4),
+        ];
+        let hash = hash_bytes(b"content");
+        let hashes: HashMap<PathBuf, Digest> = files[..3]
+            .iter()
+            .map(|file| (file.path.clone(), hash))
+            .collect();
+        let snapshot = Snapshot::record(&files, &hashes);
+
+        let groups = snapshot.known_groups(&files).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].hash, hash);
+        assert_eq!(groups[0].files.len(), 3);
+
+        // Deleted files drop out of their group
+        let groups = snapshot.known_groups(&[files[0].clone(), files[3].clone()]);
+        assert_eq!(groups.unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_new_or_changed_files_need_comparing() {
+        let files = [file_entry("/a", 1), file_entry("/b", 2)];
+        let snapshot = Snapshot::record(&files, &HashMap::new());
+        assert!(snapshot.known_groups(&files).is_some());
+
+        let new = file_entry("/new", 3);
+        assert!(snapshot.known_groups(&[files[0].clone(), new]).is_none());
+
+        let mut touched = files[1].clone();
+        touched.modified = Some(SystemTime::now());
+        assert!(
+            snapshot
+                .known_groups(&[files[0].clone(), touched])
+                .is_none()
+        );
+
+        let mut replaced = files[1].clone();
+        replaced.ino = 5;
+        assert!(
+            snapshot
+                .known_groups(&[files[0].clone(), replaced])
+                .is_none()
+        );
+    }
+}
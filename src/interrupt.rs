@@ -0,0 +1,43 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Exit code of an interrupted run, as for processes killed by SIGINT (128 + 2)
+pub const EXIT_CODE: i32 = 130;
+
+/// Set by the first SIGINT (Ctrl-C) once [`install`] was called
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the run was interrupted. Work already started, e.g. hashing a candidate group or
+/// changing the files of a duplicate group, is finished, new work is not started.
+pub fn requested() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Stop cleanly on the first SIGINT, see [`requested`]. A second SIGINT terminates the
+/// process right away.
+pub fn install() {
+    sys::install();
+}
+
+#[cfg(unix)]
+mod sys {
+    use super::INTERRUPTED;
+    use std::sync::atomic::Ordering;
+
+    /// Only touches an atomic and restores the default disposition, both async-signal-safe
+    extern "C" fn handle(_signal: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        // SAFETY: restores the default disposition
+        unsafe { libc::signal(libc::SIGINT, libc::SIG_DFL) };
+    }
+
+    pub fn install() {
+        // SAFETY: the handler only stores to an atomic and resets the disposition
+        unsafe { libc::signal(libc::SIGINT, handle as *const () as libc::sighandler_t) };
+    }
+}
+
+/// Signals are only handled on Unix, elsewhere Ctrl-C terminates the process.
+#[cfg(not(unix))]
+mod sys {
+    pub fn install() {}
+}
@@ -0,0 +1,462 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+
+/// Alignment of buffers, offsets and lengths of O_DIRECT reads, at least the logical block
+/// size of common filesystems
+pub const DIRECT_ALIGNMENT: usize = 4096;
+
+/// How files are read while they are hashed (`--io-mode`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum IoMode {
+    /// Read through the page cache, hashed files stay cached
+    #[default]
+    Cached,
+    /// Drop the pages of each file from the page cache once it is hashed
+    Dontneed,
+    /// Read full hashes with O_DIRECT, bypassing the page cache, and drop the pages of other reads
+    Direct,
+}
+
+/// Mode of the current run, set once by [`set_io_mode`]
+static IO_MODE: AtomicU8 = AtomicU8::new(IoMode::Cached as u8);
+
+/// Read files in `mode` for the rest of the run
+pub fn set_io_mode(mode: IoMode) {
+    IO_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// Mode files are read in this run
+pub fn io_mode() -> IoMode {
+    match IO_MODE.load(Ordering::Relaxed) {
+        1 => IoMode::Dontneed,
+        2 => IoMode::Direct,
+        _ => IoMode::Cached,
+    }
+}
+
+/// Limits how many files are read at the same time, independent of the number of threads
+/// hashing them
+#[derive(Debug)]
+pub struct IoLimit {
+    available: Mutex<usize>,
+    released: Condvar,
+    /// Number of times a reader had to wait for a permit
+    waits: AtomicUsize,
+}
+
+/// Permission to read files, given back to its [`IoLimit`] when dropped
+#[derive(Debug)]
+pub struct IoPermit<'a> {
+    limit: &'a IoLimit,
+    count: usize,
+}
+
+impl IoLimit {
+    pub fn new(workers: usize) -> Self {
+        IoLimit {
+            available: Mutex::new(workers),
+            released: Condvar::new(),
+            waits: AtomicUsize::new(0),
+        }
+    }
+
+    /// Wait until fewer than the allowed number of readers hold a permit
+    pub fn acquire(&self) -> IoPermit<'_> {
+        self.acquire_many(1)
+    }
+
+    /// Wait until `count` more readers are allowed, taking them all at once
+    pub fn acquire_many(&self, count: usize) -> IoPermit<'_> {
+        let mut available = self.available.lock().unwrap();
+        if *available < count {
+            self.waits.fetch_add(1, Ordering::Relaxed);
+        }
+        while *available < count {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= count;
+        IoPermit { limit: self, count }
+    }
+
+    /// Number of times a reader had to wait for a permit so far
+    pub fn waits(&self) -> usize {
+        self.waits.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for IoPermit<'_> {
+    fn drop(&mut self) {
+        *self.limit.available.lock().unwrap() += self.count;
+        self.limit.released.notify_all();
+    }
+}
+
+/// Limit of the current run, set once by [`set_io_workers`]
+static IO_LIMIT: OnceLock<IoLimit> = OnceLock::new();
+
+/// Limits of single devices, by device ID, set once by [`set_device_workers`]
+static DEVICE_LIMITS: OnceLock<HashMap<u64, IoLimit>> = OnceLock::new();
+
+/// Read at most `workers` files at the same time for the rest of the run (`--io-workers`)
+pub fn set_io_workers(workers: usize) {
+    let _ = IO_LIMIT.set(IoLimit::new(workers));
+}
+
+/// Read at most the given number of files at the same time from each device for the rest
+/// of the run, see [`crate::storage`]
+pub fn set_device_workers(workers: HashMap<u64, usize>) {
+    let limits = workers
+        .into_iter()
+        .map(|(dev, workers)| (dev, IoLimit::new(workers)))
+        .collect();
+    let _ = DEVICE_LIMITS.set(limits);
+}
+
+fn device_limit(dev: u64) -> Option<&'static IoLimit> {
+    DEVICE_LIMITS.get()?.get(&dev)
+}
+
+/// Whether the number of files read at the same time from device `dev` is limited
+pub fn io_limited(dev: u64) -> bool {
+    IO_LIMIT.get().is_some() || device_limit(dev).is_some()
+}
+
+/// File descriptors kept for everything but the files being hashed: the standard streams,
+/// the cache database, directories being walked, ...
+const RESERVED_FDS: u64 = 128;
+
+/// Files that may be open for hashing at the same time, set once by [`set_fd_limit`]
+static FD_BUDGET: OnceLock<(IoLimit, usize)> = OnceLock::new();
+
+/// Raise the soft limit of open file descriptors (`RLIMIT_NOFILE`, `ulimit -n`) to the hard
+/// limit, returning the new soft limit. Many threads each opening files would otherwise
+/// run into a low default like 1024.
+#[cfg(unix)]
+pub fn raise_fd_limit() -> io::Result<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: getrlimit and setrlimit only access the struct passed to them
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if limit.rlim_cur < limit.rlim_max {
+        let raised = libc::rlimit {
+            rlim_cur: limit.rlim_max,
+            rlim_max: limit.rlim_max,
+        };
+        if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &raised) } == 0 {
+            limit = raised;
+        }
+    }
+    #[allow(clippy::unnecessary_cast)] // rlim_t is 32 bits on some targets
+    Ok(limit.rlim_cur as u64)
+}
+
+/// The limit is only raised on Unix.
+#[cfg(not(unix))]
+pub fn raise_fd_limit() -> io::Result<u64> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Keep the files open for hashing within `limit` open file descriptors for the rest of the
+/// run, leaving [`RESERVED_FDS`] for everything else
+pub fn set_fd_limit(limit: u64) {
+    let budget = limit.saturating_sub(RESERVED_FDS).clamp(2, 1 << 20) as usize;
+    let _ = FD_BUDGET.set((IoLimit::new(budget), budget));
+}
+
+/// Number of times files waited to be opened until another was closed, and the number of
+/// files that may be open at the same time, if limited
+pub fn fd_waits() -> Option<(usize, usize)> {
+    FD_BUDGET
+        .get()
+        .map(|(limit, budget)| (limit.waits(), *budget))
+}
+
+/// Permissions to read files, from the limit of their device, the one of the whole run and
+/// the open file descriptors
+#[derive(Debug)]
+pub struct IoPermits {
+    _device: Option<IoPermit<'static>>,
+    _run: Option<IoPermit<'static>>,
+    _fds: Option<IoPermit<'static>>,
+}
+
+/// Wait for permission to read a file on device `dev`, held until the file is no longer read.
+/// Always granted right away without `--io-workers`, a limit for the device or too many open
+/// files.
+///
+/// Holders must not wait for work on other threads of the pool (e.g. `update_rayon`): the
+/// waiting thread may pick up a task that waits for a permit in turn.
+pub fn io_permit(dev: u64) -> IoPermits {
+    io_permit_files(dev, 1)
+}
+
+/// [`io_permit`] for reading `files` files at the same time, e.g. to compare two of them
+pub fn io_permit_files(dev: u64, files: usize) -> IoPermits {
+    // Always in the same order, so no reader holds a permit of a later limit while it waits
+    let device = device_limit(dev).map(IoLimit::acquire);
+    let run = IO_LIMIT.get().map(IoLimit::acquire);
+    IoPermits {
+        _device: device,
+        _run: run,
+        _fds: FD_BUDGET.get().map(|(limit, _)| limit.acquire_many(files)),
+    }
+}
+
+/// Limits the rate files are read at across all threads: a token bucket refilled at
+/// `bytes_per_sec` and holding at most one second worth of reads
+#[derive(Debug)]
+pub struct RateLimit {
+    bytes_per_sec: f64,
+    /// Bytes that can be read right away, negative while readers wait, and when it was updated
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimit {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = bytes_per_sec as f64;
+        RateLimit {
+            bytes_per_sec,
+            bucket: Mutex::new((bytes_per_sec, Instant::now())),
+        }
+    }
+
+    /// Take `bytes` that were just read from the bucket. Returns how long to wait before
+    /// reading on, reads that empty the bucket are paid back by the next readers waiting too.
+    pub fn take(&self, bytes: u64) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+        let (tokens, updated) = &mut *bucket;
+        let now = Instant::now();
+        *tokens = (*tokens + now.duration_since(*updated).as_secs_f64() * self.bytes_per_sec)
+            .min(self.bytes_per_sec);
+        *updated = now;
+        *tokens -= bytes as f64;
+        Duration::from_secs_f64((-*tokens).max(0.0) / self.bytes_per_sec)
+    }
+}
+
+/// Rate limit of the current run, set once by [`set_max_bandwidth`]
+static RATE_LIMIT: OnceLock<RateLimit> = OnceLock::new();
+
+/// Read at most `bytes_per_sec` bytes per second for the rest of the run (`--max-bandwidth`)
+pub fn set_max_bandwidth(bytes_per_sec: u64) {
+    let _ = RATE_LIMIT.set(RateLimit::new(bytes_per_sec));
+}
+
+/// Account for `bytes` read from files and wait while reading faster than `--max-bandwidth`
+pub fn throttle(bytes: u64) {
+    if let Some(limit) = RATE_LIMIT.get() {
+        let wait = limit.take(bytes);
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// A file opened to be hashed or compared. Unless the run reads through the cache, its pages
+/// are dropped from the page cache when it is closed, so hashing a huge tree doesn't evict
+/// everything else the system has cached.
+#[derive(Debug)]
+pub struct HashFile {
+    file: File,
+}
+
+impl HashFile {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(HashFile {
+            file: File::open(path)?,
+        })
+    }
+}
+
+impl Deref for HashFile {
+    type Target = File;
+
+    fn deref(&self) -> &File {
+        &self.file
+    }
+}
+
+impl Read for HashFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Seek for HashFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl Drop for HashFile {
+    fn drop(&mut self) {
+        if io_mode() != IoMode::Cached {
+            drop_cached_pages(&self.file);
+        }
+    }
+}
+
+/// Ask the kernel to drop the cached pages of a file, they are read from disk again when
+/// needed
+#[cfg(target_os = "linux")]
+fn drop_cached_pages(file: &File) {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: plain syscall on an open descriptor, a length of 0 covers the whole file
+    unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+}
+
+/// The page cache can only be controlled on Linux.
+#[cfg(not(target_os = "linux"))]
+fn drop_cached_pages(_file: &File) {}
+
+/// Open a file for reading with O_DIRECT, bypassing the page cache. Reads must go to an
+/// [`AlignedBuffer`] and cover multiples of `DIRECT_ALIGNMENT` bytes.
+#[cfg(target_os = "linux")]
+pub fn open_direct(path: &Path) -> io::Result<File> {
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)
+}
+
+/// Direct I/O is only supported on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn open_direct(_path: &Path) -> io::Result<File> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// A zeroed buffer whose start is aligned to `DIRECT_ALIGNMENT`, as O_DIRECT reads require
+#[derive(Debug)]
+pub struct AlignedBuffer {
+    storage: Vec<u8>,
+    offset: usize,
+    len: usize,
+}
+
+impl AlignedBuffer {
+    /// A buffer of `len` bytes, which must be a multiple of `DIRECT_ALIGNMENT`
+    pub fn new(len: usize) -> Self {
+        let storage = vec![0u8; len + DIRECT_ALIGNMENT];
+        let offset = storage.as_ptr().align_offset(DIRECT_ALIGNMENT);
+        AlignedBuffer {
+            storage,
+            offset,
+            len,
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.storage[self.offset..self.offset + self.len]
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.storage[self.offset..self.offset + self.len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_io_limit_waits_for_permits() {
+        use std::sync::atomic::AtomicBool;
+        use std::time::Duration;
+
+        let limit = IoLimit::new(1);
+        let acquired = AtomicBool::new(false);
+        let permit = limit.acquire();
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let _permit = limit.acquire();
+                acquired.store(true, Ordering::SeqCst);
+            });
+            std::thread::sleep(Duration::from_millis(50));
+            assert!(!acquired.load(Ordering::SeqCst));
+            drop(permit);
+        });
+
+        assert!(acquired.load(Ordering::SeqCst));
+        assert_eq!(limit.waits(), 1);
+    }
+
+    #[test]
+    fn test_io_limit_takes_several_permits_at_once() {
+        use std::sync::atomic::AtomicBool;
+        use std::time::Duration;
+
+        let limit = IoLimit::new(2);
+        let acquired = AtomicBool::new(false);
+        let one = limit.acquire();
+        assert_eq!(limit.waits(), 0);
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let _both = limit.acquire_many(2);
+                acquired.store(true, Ordering::SeqCst);
+            });
+            // One of the two is free, but both are needed
+            std::thread::sleep(Duration::from_millis(50));
+            assert!(!acquired.load(Ordering::SeqCst));
+            drop(one);
+        });
+
+        assert!(acquired.load(Ordering::SeqCst));
+        assert_eq!(*limit.available.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_rate_limit_takes_tokens() {
+        let limit = RateLimit::new(1000);
+
+        // A full second worth of reads is available right away
+        assert!(limit.take(1000) < Duration::from_millis(10));
+        let wait = limit.take(500);
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+        // The next reader waits for the debt of the previous one too
+        assert!(limit.take(500) > Duration::from_millis(900));
+    }
+
+    #[test]
+    fn test_aligned_buffer() {
+        for len in [DIRECT_ALIGNMENT, 16 * DIRECT_ALIGNMENT] {
+            let mut buffer = AlignedBuffer::new(len);
+            assert_eq!(buffer.as_mut_slice().len(), len);
+            assert_eq!(buffer.as_slice().as_ptr() as usize % DIRECT_ALIGNMENT, 0);
+        }
+    }
+
+    #[test]
+    fn test_hash_file_reads_and_seeks() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("file.txt");
+        std::fs::write(&path, b"header:content").unwrap();
+
+        let mut file = HashFile::open(&path).unwrap();
+        file.seek(SeekFrom::Start(7)).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+
+        assert_eq!(contents, "content");
+        assert_eq!(file.metadata().unwrap().len(), 14);
+    }
+}
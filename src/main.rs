@@ -1,29 +1,157 @@
 mod actions;
+mod archive;
+mod background;
+mod boilerplate;
+mod cache;
+mod checkpoint;
+mod confirm;
+mod consolidate;
+mod contained;
+mod dedupignore;
+mod deferral;
+mod diff;
+mod errorlog;
+mod estimate;
 mod grouping;
 mod hasher;
+mod hashlist;
+mod hooks;
+mod i18n;
+mod incremental;
+mod interrupt;
+mod iomode;
+mod merge;
 mod output;
+mod owner;
+mod pause;
+mod pipeline;
+mod profile;
+mod protection;
 mod scanner;
+mod schema;
+mod spill;
+mod storage;
+mod template;
+mod timespec;
 mod util;
 
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicUsize, Ordering};
-
-use clap::{Parser, ValueEnum};
-use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use clap::{CommandFactory, Parser, ValueEnum};
+use indicatif::{ProgressBar, ProgressState, ProgressStyle};
 use rayon::prelude::*;
 
 #[derive(Parser, Debug)]
 #[command(name = "dedup")]
 #[command(version, about, long_about = None)]
+#[command(
+    args_conflicts_with_subcommands = true,
+    subcommand_precedence_over_arg = true
+)]
 struct Cli {
-    /// Directory to scan for duplicates
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Directories to scan for duplicates, files are compared across all of them
     #[arg(default_value = ".")]
-    path: PathBuf,
+    paths: Vec<PathBuf>,
+
+    /// Only consider the files listed in FILE (one per line, `-` for stdin) instead of scanning directories
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["paths", "same_relative_path", "resume_deferred"]
+    )]
+    files_from: Option<PathBuf>,
+
+    /// Paths in --files-from are separated by NUL characters (e.g. `find -print0`)
+    #[arg(short = '0', long, requires = "files_from")]
+    null: bool,
+
+    /// Only compare each file with the file at the same relative path under this directory (e.g. to verify a backup)
+    #[arg(long, value_name = "MIRROR")]
+    same_relative_path: Option<PathBuf>,
+
+    /// Only group files with files found under the same path, for paths holding independent
+    /// data that must not be linked to each other
+    #[arg(long, conflicts_with_all = ["files_from", "same_relative_path", "compare_to"])]
+    isolate: bool,
+
+    /// Only report files that already have a copy under REF_DIR, which is never modified
+    /// (e.g. to check that a memory card is backed up)
+    #[arg(
+        long,
+        value_name = "REF_DIR",
+        conflicts_with_all = ["same_relative_path", "files_from", "deferred_file", "resume_deferred"]
+    )]
+    compare_to: Option<PathBuf>,
 
     /// Output format
     #[arg(short, long, value_enum, default_value_t = OutputFormat::Human)]
     format: OutputFormat,
 
+    /// Order of the groups in the report [default: path, or wasted with --top]
+    #[arg(long, value_enum, value_name = "KEY")]
+    sort: Option<output::SortKey>,
+
+    /// Reverse the order of the groups
+    #[arg(long)]
+    reverse: bool,
+
+    /// Only hash a sample of PERCENT of the candidates and extrapolate the duplicates of the
+    /// whole tree, with 95% bounds [default: 5]
+    #[arg(long, value_name = "PERCENT", num_args = 0..=1, require_equals = true, default_missing_value = "5", value_parser = parse_percent, conflicts_with_all = ["same_relative_path", "exec_per_group", "exec_per_duplicate"])]
+    estimate: Option<f64>,
+
+    /// Only report and act on the N groups that waste the most space
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    top: Option<u64>,
+
+    /// Only report and act on the first N files of each group, the rest are counted in the stats
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(2..))]
+    max_group_size: Option<u64>,
+
+    /// Print one line per duplicate file formatted by this template instead of a report,
+    /// e.g. '{hash}\t{size}\t{path}' (placeholders: {group}, {id}, {hash}, {size}, {path}, {is_original})
+    #[arg(long, value_name = "TEMPLATE", conflicts_with = "format")]
+    format_template: Option<template::Template>,
+
+    /// Add a section with the wasted space per directory to the report
+    #[arg(long)]
+    by_directory: bool,
+
+    /// Add sections with the files and directories that took the longest to hash to the
+    /// report, e.g. to find a failing disk
+    #[arg(long)]
+    profile_io: bool,
+
+    /// Print paths in the report relative to the scanned directory (or their common parent)
+    #[arg(long, conflicts_with = "same_relative_path")]
+    relative_paths: bool,
+
+    /// Print the JSON Schema of the --format json report and exit
+    #[arg(long)]
+    print_schema: bool,
+
+    /// Write the report to this file instead of stdout, progress and summaries still go to the terminal
+    #[arg(short, long, value_name = "FILE", global = true)]
+    output: Option<PathBuf>,
+
+    /// Split JSON output into pages of this many groups
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    page_size: Option<u64>,
+
+    /// Page of groups to output when --page-size is set (starting at 1)
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "page_size", value_parser = clap::value_parser!(u64).range(1..))]
+    page: u64,
+
     /// Minimum file size in bytes to consider (smaller files are skipped)
     #[arg(short = 's', long)]
     min_size: Option<u64>,
@@ -32,14 +160,201 @@ struct Cli {
     #[arg(short = 'S', long)]
     max_size: Option<u64>,
 
+    /// Minimum bytes allocated on disk to consider, skips sparse files that are mostly holes
+    #[arg(long, value_name = "BYTES")]
+    min_allocated: Option<u64>,
+
+    /// Skip hidden files and directories (names starting with a dot, e.g. .cache)
+    #[arg(long)]
+    skip_hidden: bool,
+
+    /// Skip files that already have more than one hardlink, they are likely deduplicated
+    #[arg(long)]
+    skip_hardlinked: bool,
+
+    /// Only consider files owned by this user (name or uid)
+    #[arg(long, value_name = "USER")]
+    owner: Option<String>,
+
+    /// Only consider files owned by this group (name or gid)
+    #[arg(long, value_name = "GROUP")]
+    group: Option<String>,
+
+    /// Only consider files modified within this age (e.g. 30d, 12h) or after this UTC timestamp (e.g. 2024-01-31)
+    #[arg(long, value_name = "TIME")]
+    newer_than: Option<timespec::TimeSpec>,
+
+    /// Only consider files not modified within this age (e.g. 30d, 12h) or before this UTC timestamp (e.g. 2024-01-31)
+    #[arg(long, value_name = "TIME")]
+    older_than: Option<timespec::TimeSpec>,
+
+    /// Write every path that was skipped or failed to this file, one JSON object per line
+    /// with the kind of error
+    #[arg(long, value_name = "FILE")]
+    error_log: Option<PathBuf>,
+
+    /// Abort before hashing if fewer files than this were found (e.g. an unmounted drive)
+    #[arg(long, value_name = "N")]
+    expect_min_files: Option<usize>,
+
+    /// Abort before hashing if more files than this were found (e.g. a mistyped root)
+    #[arg(long, value_name = "N")]
+    expect_max_files: Option<usize>,
+
+    /// What files must have in common to be duplicates, `name` doesn't read their contents
+    #[arg(long = "match", value_name = "MODE", value_enum, default_value_t = grouping::MatchMode::Content, conflicts_with = "same_relative_path")]
+    match_mode: grouping::MatchMode,
+
+    /// How candidates are confirmed to be duplicates
+    #[arg(long, value_enum, default_value_t = confirm::Strategy::FullHash)]
+    confirm: confirm::Strategy,
+
+    /// Hash function used to compare contents, the reported hashes are computed with it
+    #[arg(long = "hash", value_name = "ALGORITHM", value_enum, default_value_t = hasher::Algorithm::Blake3)]
+    hash_algorithm: hasher::Algorithm,
+
+    /// Compare the files of each group byte by byte after their hashes matched, for those who
+    /// don't act on hash equality alone
+    #[arg(long, conflicts_with = "scan_archives")]
+    paranoid: bool,
+
+    /// How files are read while hashing: `dontneed` and `direct` keep them from evicting the
+    /// rest of the page cache (Linux only)
+    #[arg(long, value_name = "MODE", value_enum, default_value_t = iomode::IoMode::Cached)]
+    io_mode: iomode::IoMode,
+
+    /// Read at most N files at the same time, however many threads hash them, instead of the
+    /// limits picked for spinning disks and network filesystems found under the paths
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    io_workers: Option<u64>,
+
+    /// Read files at most this fast, shared by all threads, e.g. 50MB/s (units: K, M, G)
+    #[arg(long, value_name = "RATE", value_parser = parse_bandwidth)]
+    max_bandwidth: Option<u64>,
+
+    /// Memory-map files larger than 16MB to hash them, faster for multi-gigabyte files but
+    /// only safe if no file is truncated during the run
+    #[arg(long, conflicts_with = "io_workers")]
+    mmap: bool,
+
+    /// Hold at most about this much of the scanned files in memory, e.g. 2G (units: K, M, G),
+    /// and group the rest by size in temporary files, for trees with too many files to fit
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_memory,
+        conflicts_with_all = ["files_from", "scan_archives", "compare_to", "same_relative_path", "isolate", "estimate", "checkpoint", "incremental", "resume_deferred"]
+    )]
+    max_memory: Option<u64>,
+
+    /// Also report files whose contents are the start of larger files, e.g. interrupted
+    /// downloads next to the complete ones (experimental)
+    #[arg(long, conflicts_with_all = ["max_memory", "resume", "resume_deferred"])]
+    find_contained: bool,
+
+    /// Keep the hashes of files in this database and reuse them in later runs while a file's
+    /// size and modification time are unchanged
+    #[arg(long, value_name = "FILE")]
+    cache: Option<PathBuf>,
+
+    /// What must be unchanged to use a cached hash: `strict` (size, mtime, ctime and inode) or
+    /// `relaxed` (size and mtime)
+    #[arg(long, value_name = "MODE", value_enum, default_value_t = cache::Validation::Strict, requires = "cache")]
+    cache_validation: cache::Validation,
+
+    /// Hash this percentage of the files found in the cache again, warning about cached hashes
+    /// that turn out to be out of date
+    #[arg(long, value_name = "N", default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=100), requires = "cache")]
+    revalidate_percent: u8,
+
+    /// Reuse the duplicates found by the last --incremental run with the same cache, only
+    /// candidates that are new or changed since are compared again
+    #[arg(long, requires = "cache", conflicts_with_all = ["estimate", "resume_deferred", "same_relative_path"])]
+    incremental: bool,
+
+    /// Use the hashes written by `dedup export-hashes` on another machine for the files that
+    /// are unchanged since, instead of reading them
+    #[arg(long, value_name = "FILE")]
+    import_hashes: Option<PathBuf>,
+
+    /// Where the directory of --import-hashes is found on this machine [default: the
+    /// directory it was exported from]
+    #[arg(long, value_name = "DIR", requires = "import_hashes")]
+    import_root: Option<PathBuf>,
+
+    /// Ignore the first N bytes of each file when comparing, e.g. headers with volatile
+    /// timestamps or serial numbers (report only)
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    skip_header_bytes: u64,
+
     /// Action to take on duplicates
     #[arg(short, long, value_enum, default_value_t = Action::None)]
     action: Action,
 
+    /// Content-addressed store directory for the cas action (must be on the same filesystem)
+    #[arg(long, value_name = "DIR", required_if_eq("action", "cas"))]
+    store: Option<PathBuf>,
+
+    /// Extension appended to the file name of duplicates replaced by the stub action
+    #[arg(long, value_name = "EXT", default_value = "dedup-stub")]
+    stub_extension: String,
+
+    /// Only act on duplicate groups that would reclaim at least this many bytes (smaller groups are still reported)
+    #[arg(long, value_name = "BYTES")]
+    min_saving: Option<u64>,
+
+    /// Shell command to run for each duplicate group, with {files}, {keeper}, {duplicates},
+    /// {hash} and {size} placeholders
+    #[arg(long, value_name = "CMD")]
+    exec_per_group: Option<String>,
+
+    /// Shell command to run for each duplicate, with DEDUP_KEEPER, DEDUP_DUPLICATE, DEDUP_SIZE
+    /// and DEDUP_HASH set (runs up to --jobs commands in parallel)
+    #[arg(long, value_name = "CMD")]
+    exec_per_duplicate: Option<String>,
+
+    /// Also scan and act on empty files (skipped by default, linking them reclaims no space)
+    #[arg(long)]
+    include_empty_in_actions: bool,
+
     /// Preview changes without actually modifying files
     #[arg(long)]
     dry_run: bool,
 
+    /// Undo the changes made to a duplicate group if any file in it fails
+    #[arg(long)]
+    per_group_atomic: bool,
+
+    /// Temporarily lift write protection (read-only parent directory, immutable attribute)
+    /// from duplicates the action replaces
+    #[arg(long)]
+    force: bool,
+
+    /// What to do when the preferred kept copy is immutable and can't be linked to
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = actions::ProtectedKeeper::NextBest)]
+    on_protected_keeper: actions::ProtectedKeeper,
+
+    /// Stop hashing new candidate groups after this many seconds
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+
+    /// Save the candidate groups that were not hashed before --timeout to this file
+    #[arg(long, value_name = "FILE", requires = "timeout")]
+    deferred_file: Option<PathBuf>,
+
+    /// Only hash the candidate groups saved to FILE by an earlier run with --deferred-file
+    #[arg(long, value_name = "FILE", conflicts_with = "same_relative_path")]
+    resume_deferred: Option<PathBuf>,
+
+    /// Save the progress to this file every minute while hashing, so a killed run can be
+    /// continued with --resume
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["estimate", "same_relative_path", "compare_to", "resume_deferred"])]
+    checkpoint: Option<PathBuf>,
+
+    /// Continue the run saved to the --checkpoint file instead of scanning
+    #[arg(long, requires = "checkpoint", conflicts_with_all = ["paths", "files_from"])]
+    resume: bool,
+
     /// Show detailed output
     #[arg(short, long)]
     verbose: bool,
@@ -48,10 +363,24 @@ struct Cli {
     #[arg(long)]
     no_progress: bool,
 
-    /// Number of threads to use (defaults to number of CPU cores)
-    #[arg(short = 'j', long)]
+    /// Language of the human-readable output
+    #[arg(long, value_enum, default_value_t = i18n::Lang::En)]
+    lang: i18n::Lang,
+
+    /// Number of threads to use for scanning and hashing (defaults to number of CPU cores)
+    #[arg(short = 'j', long, visible_alias = "threads", value_name = "N")]
     jobs: Option<usize>,
 
+    /// Run unnoticed: lowest CPU and I/O priority, a single thread (unless --jobs is set),
+    /// limited read rate and pauses while the system is busy
+    #[arg(long)]
+    background: bool,
+
+    /// Run with the lowest CPU priority and, on Linux, the idle I/O scheduling class, without
+    /// the other limits of --background
+    #[arg(long)]
+    nice: bool,
+
     /// Glob patterns to exclude (can be specified multiple times)
     #[arg(short = 'e', long = "exclude", action = clap::ArgAction::Append)]
     exclude: Vec<String>,
@@ -60,6 +389,10 @@ struct Cli {
     #[arg(long = "exclude-file")]
     exclude_file: Option<PathBuf>,
 
+    /// Don't apply .dedupignore files found in the scanned directories
+    #[arg(long)]
+    no_dedupignore: bool,
+
     /// Glob patterns to include (can be specified multiple times). If specified, only matching files are scanned.
     #[arg(short = 'i', long = "include", action = clap::ArgAction::Append)]
     include: Vec<String>,
@@ -67,6 +400,118 @@ struct Cli {
     /// File containing include patterns (one per line)
     #[arg(long = "include-file")]
     include_file: Option<PathBuf>,
+
+    /// Match --exclude and --include patterns, and names with --match name, regardless of
+    /// case (e.g. *.jpg also matches IMG.JPG)
+    #[arg(long)]
+    ignore_case: bool,
+
+    /// Only scan files with these extensions, case-insensitive (e.g. jpg,png,mp4). Adds to --include.
+    #[arg(long = "ext", value_name = "EXTS", value_delimiter = ',', action = clap::ArgAction::Append)]
+    extensions: Vec<String>,
+
+    /// Ignore duplicates of well-known boilerplate files (empty files, standard LICENSE texts, ...)
+    #[arg(long)]
+    skip_common_boilerplate: bool,
+
+    /// Only report duplicates spread over several directories, not copies next to each other
+    #[arg(long, conflicts_with = "same_dir_only")]
+    cross_dir_only: bool,
+
+    /// Only report copies within the same directory (e.g. "file (1).pdf"), groups are split
+    /// by directory and copies elsewhere are left out
+    #[arg(long, conflicts_with = "compare_to")]
+    same_dir_only: bool,
+
+    /// Also compare the files inside zip, tar and tar.gz archives, reported as
+    /// ARCHIVE!MEMBER (report only, actions leave them untouched)
+    #[arg(long, conflicts_with = "same_relative_path")]
+    scan_archives: bool,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Merge JSON reports of separate runs (e.g. one per directory) into one JSON report
+    MergeReports {
+        /// JSON reports written with --format json
+        #[arg(required = true, num_args = 1..)]
+        reports: Vec<PathBuf>,
+    },
+    /// Compare two JSON reports and list the duplicate groups that are new, resolved or
+    /// changed in NEW, matched by content hash
+    Diff {
+        /// Earlier report written with --format json
+        #[arg(value_name = "OLD")]
+        old: PathBuf,
+        /// Later report written with --format json
+        #[arg(value_name = "NEW")]
+        new: PathBuf,
+        /// Write the differences as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Move the files of SRC into DST at the same relative paths, removing the files DST
+    /// already has a copy of instead
+    Merge {
+        /// Directory to empty
+        #[arg(value_name = "SRC")]
+        source: PathBuf,
+        /// Directory to merge into, never modified except for the moved files
+        #[arg(value_name = "DST")]
+        destination: PathBuf,
+        /// Replace files DST already has with hardlinks to its copy instead of removing them
+        #[arg(long)]
+        link: bool,
+        /// Preview changes without actually modifying files
+        #[arg(long)]
+        dry_run: bool,
+        /// Print every file that is moved, removed or linked
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Compute the hashes of all files under a directory and write them to a file, for
+    /// --import-hashes on another machine
+    ExportHashes {
+        /// Directory whose files are hashed
+        #[arg(value_name = "DIR")]
+        directory: PathBuf,
+        /// File the hashes are written to
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+    /// Inspect and maintain a hash cache written with --cache
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum CacheCommand {
+    /// Print the number of cached hashes and the size of the database
+    Stats {
+        #[arg(value_name = "FILE")]
+        cache: PathBuf,
+    },
+    /// Remove the hashes of files that no longer exist or changed, they are never used again
+    Prune {
+        #[arg(value_name = "FILE")]
+        cache: PathBuf,
+        /// Also remove the oldest hashes until at most N are left
+        #[arg(long, value_name = "N")]
+        max_entries: Option<u64>,
+    },
+    /// Remove all hashes
+    Clear {
+        #[arg(value_name = "FILE")]
+        cache: PathBuf,
+    },
+    /// Hash the files again and remove the cached hashes that are out of date, although
+    /// the files look unchanged (exit code 1 if there were any)
+    Verify {
+        #[arg(value_name = "FILE")]
+        cache: PathBuf,
+    },
 }
 
 /// Output format options
@@ -76,6 +521,13 @@ enum OutputFormat {
     Human,
     /// JSON output for scripting
     Json,
+    /// Paths of each group on separate lines, groups separated by blank lines (like fdupes)
+    Fdupes,
+    /// Paths of all duplicates except the kept copy, each followed by a NUL byte (for xargs -0)
+    #[value(name = "paths0")]
+    Paths0,
+    /// A single line with the number of duplicates, groups and wasted space (for cron mails and monitoring)
+    Summary,
     /// No output (useful with report-exit-code action)
     Quiet,
 }
@@ -89,6 +541,81 @@ enum Action {
     ReportExitCode,
     /// Replace duplicates with hardlinks
     Hardlink,
+    /// Move one copy of each group into a content-addressed store (--store) and hardlink all files to it
+    Cas,
+    /// Replace duplicates with small stub files containing the hash and path of the kept copy
+    Stub,
+}
+
+/// Include patterns matching the given file extensions in any case, e.g. `*.[jJ][pP][gG]`
+fn extension_globs(extensions: &[String]) -> Vec<String> {
+    extensions
+        .iter()
+        .map(|ext| ext.trim().trim_start_matches('.'))
+        .filter(|ext| !ext.is_empty())
+        .map(|ext| {
+            let pattern: String = ext
+                .chars()
+                .map(|c| {
+                    if c.is_alphabetic() {
+                        format!("[{}{}]", c.to_lowercase(), c.to_uppercase())
+                    } else {
+                        globset::escape(&c.to_string())
+                    }
+                })
+                .collect();
+            format!("*.{}", pattern)
+        })
+        .collect()
+}
+
+/// Parse a percentage above 0 and up to 100 (e.g. for `--estimate`)
+fn parse_percent(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(percent) if percent > 0.0 && percent <= 100.0 => Ok(percent),
+        _ => Err(format!(
+            "'{}' is not a percentage above 0 and up to 100",
+            value
+        )),
+    }
+}
+
+/// Parse a read rate in bytes per second with an optional binary unit and `/s` suffix
+/// (e.g. `50MB/s`, `512K`, `1G`), for `--max-bandwidth`
+fn parse_bandwidth(value: &str) -> Result<u64, String> {
+    let rate = value.trim();
+    parse_bytes(rate.strip_suffix("/s").unwrap_or(rate))
+        .ok_or_else(|| format!("'{}' is not a rate like 50MB/s, 512K or 1G", value))
+}
+
+/// Parse a memory budget like 512M or 2GB, at least [`spill::MIN_MEMORY`]
+fn parse_memory(value: &str) -> Result<u64, String> {
+    match parse_bytes(value.trim()) {
+        Some(bytes) if bytes >= spill::MIN_MEMORY => Ok(bytes),
+        Some(_) => Err(format!("'{}' is less than the minimum of 1M", value)),
+        None => Err(format!("'{}' is not a size like 512M or 2G", value)),
+    }
+}
+
+/// Parse a number of bytes with an optional K, M or G unit (1024-based) and B suffix
+fn parse_bytes(value: &str) -> Option<u64> {
+    let value = value.to_ascii_uppercase();
+    let value = value
+        .strip_suffix("IB")
+        .or_else(|| value.strip_suffix('B'))
+        .unwrap_or(&value);
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((i, 'K')) => (&value[..i], 1 << 10),
+        Some((i, 'M')) => (&value[..i], 1 << 20),
+        Some((i, 'G')) => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    match number.trim().parse::<f64>() {
+        Ok(number) if number * multiplier as f64 >= 1.0 => {
+            Some((number * multiplier as f64) as u64)
+        }
+        _ => None,
+    }
 }
 
 /// Parse a glob file (gitignore-style) and return patterns
@@ -101,254 +628,2301 @@ fn parse_glob_file(path: &std::path::Path) -> Vec<String> {
             .map(String::from)
             .collect(),
         Err(e) => {
-            eprintln!(
-                "Warning: could not read exclude file '{}': {}",
+            util::warn(format_args!(
+                "could not read exclude file '{}': {}",
                 path.display(),
                 e
-            );
+            ));
             Vec::new()
         }
     }
 }
 
-fn main() {
-    let cli = Cli::parse();
+/// Check the number of scanned files against the expected bounds
+fn check_file_count(
+    total_files: usize,
+    min: Option<usize>,
+    max: Option<usize>,
+) -> Result<(), String> {
+    if let Some(min) = min
+        && total_files < min
+    {
+        return Err(format!(
+            "found {} files, expected at least {}. Is the right directory mounted?",
+            total_files, min
+        ));
+    }
+    if let Some(max) = max
+        && total_files > max
+    {
+        return Err(format!(
+            "found {} files, expected at most {}. Is the path correct?",
+            total_files, max
+        ));
+    }
+    Ok(())
+}
 
-    if let Some(num_threads) = cli.jobs {
-        rayon::ThreadPoolBuilder::new()
-            .num_threads(num_threads)
-            .build_global()
-            .expect("Failed to initialize thread pool");
+/// Deepest directory containing all `paths`, empty if they have nothing in common (e.g.
+/// relative and absolute paths)
+fn common_ancestor(paths: &[PathBuf]) -> PathBuf {
+    let mut ancestor = paths.first().cloned().unwrap_or_default();
+    for path in paths.iter().skip(1) {
+        ancestor = ancestor
+            .components()
+            .zip(path.components())
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| a)
+            .collect();
     }
+    ancestor
+}
 
-    let human = matches!(cli.format, OutputFormat::Human);
-    let quiet = matches!(cli.format, OutputFormat::Quiet);
-    let show_progress = human && !cli.no_progress;
+/// Run a `dedup cache` subcommand, returns false if `verify` found out of date hashes
+fn run_cache_command(command: &CacheCommand, messages: &i18n::Messages) -> Result<bool, String> {
+    let (CacheCommand::Stats { cache: path }
+    | CacheCommand::Prune { cache: path, .. }
+    | CacheCommand::Clear { cache: path }
+    | CacheCommand::Verify { cache: path }) = command;
+    // Opening would create an empty cache
+    if !path.is_file() {
+        return Err(format!("{} is not a cache file", path.display()));
+    }
+    let failed = |e: rusqlite::Error| format!("could not read the cache {}: {}", path.display(), e);
+    let cache = cache::HashCache::open(path, cache::Validation::Strict, 0).map_err(failed)?;
+
+    match command {
+        CacheCommand::Stats { .. } => {
+            let stats = cache.stats().map_err(failed)?;
+            let size = std::fs::metadata(path).map_or(0, |m| m.len());
+            println!(
+                "{}",
+                i18n::fill(
+                    messages.cache_stats,
+                    &[
+                        &util::format_number(stats.partial_hashes + stats.full_hashes),
+                        &util::format_number(stats.files),
+                        &util::format_number(stats.partial_hashes),
+                        &util::format_number(stats.full_hashes),
+                        &util::format_bytes(size)
+                    ]
+                )
+            );
+        }
+        CacheCommand::Prune { max_entries, .. } => {
+            let pruned = cache.prune(*max_entries).map_err(failed)?;
+            println!(
+                "{}",
+                i18n::fill(
+                    messages.cache_pruned,
+                    &[
+                        &util::format_number(pruned.stale),
+                        &util::format_number(pruned.over_limit),
+                        &util::format_number(pruned.left)
+                    ]
+                )
+            );
+        }
+        CacheCommand::Clear { .. } => {
+            let removed = cache.clear().map_err(failed)?;
+            println!(
+                "{}",
+                i18n::fill(messages.cache_cleared, &[&util::format_number(removed)])
+            );
+        }
+        CacheCommand::Verify { .. } => {
+            let verified = cache.verify().map_err(failed)?;
+            for path in &verified.outdated {
+                println!("{}", path.display());
+            }
+            println!(
+                "{}",
+                i18n::fill(
+                    messages.cache_verified,
+                    &[
+                        &util::format_number(verified.checked),
+                        &util::format_number(verified.outdated.len())
+                    ]
+                )
+            );
+            if verified.skipped > 0 {
+                println!(
+                    "{}",
+                    i18n::fill(
+                        messages.cache_not_checked,
+                        &[&util::format_number(verified.skipped)]
+                    )
+                );
+            }
+            return Ok(verified.outdated.is_empty());
+        }
+    }
+    Ok(true)
+}
+
+/// Write the differences between two reports for humans, the groups with the most wasted
+/// space first
+fn write_report_diff(
+    diff: &diff::ReportDiff,
+    messages: &i18n::Messages,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    for (title, groups) in [
+        (messages.diff_new_groups, &diff.new),
+        (messages.diff_resolved_groups, &diff.resolved),
+    ] {
+        if groups.is_empty() {
+            continue;
+        }
+        writeln!(out, "{}", i18n::fill(title, &[&groups.len()]))?;
+        for group in groups {
+            writeln!(
+                out,
+                "  {}",
+                i18n::fill(
+                    messages.diff_group,
+                    &[
+                        &group.id,
+                        &group.files.len(),
+                        &util::format_bytes(group.size),
+                        &util::format_bytes(group.wasted_bytes)
+                    ]
+                )
+            )?;
+            for file in &group.files {
+                writeln!(out, "    {}", file.display())?;
+            }
+        }
+        writeln!(out)?;
+    }
+    if !diff.changed.is_empty() {
+        writeln!(
+            out,
+            "{}",
+            i18n::fill(messages.diff_changed_groups, &[&diff.changed.len()])
+        )?;
+        for group in &diff.changed {
+            writeln!(
+                out,
+                "  {}",
+                i18n::fill(
+                    messages.diff_changed_group,
+                    &[
+                        &group.id,
+                        &group.files_before,
+                        &group.files_after,
+                        &util::format_bytes(group.size)
+                    ]
+                )
+            )?;
+            for file in &group.added {
+                writeln!(out, "    + {}", file.display())?;
+            }
+            for file in &group.removed {
+                writeln!(out, "    - {}", file.display())?;
+            }
+        }
+        writeln!(out)?;
+    }
+
+    writeln!(
+        out,
+        "{}",
+        i18n::fill(messages.diff_unchanged, &[&diff.unchanged])
+    )?;
+    let change = match diff.wasted_bytes_after.cmp(&diff.wasted_bytes_before) {
+        std::cmp::Ordering::Less => format!(
+            "-{}",
+            util::format_bytes(diff.wasted_bytes_before - diff.wasted_bytes_after)
+        ),
+        _ => format!(
+            "+{}",
+            util::format_bytes(diff.wasted_bytes_after - diff.wasted_bytes_before)
+        ),
+    };
+    writeln!(
+        out,
+        "{}",
+        i18n::fill(
+            messages.diff_wasted,
+            &[
+                &util::format_bytes(diff.wasted_bytes_before),
+                &util::format_bytes(diff.wasted_bytes_after),
+                &change
+            ]
+        )
+    )
+}
+
+fn print_merge_summary(
+    result: &consolidate::MergeResult,
+    plan: &consolidate::MergePlan,
+    destination: &std::path::Path,
+    dry_run: bool,
+    messages: &i18n::Messages,
+) {
+    let (moved, removed) = if dry_run {
+        (messages.dry_run_merge_moved, messages.dry_run_merge_removed)
+    } else {
+        (messages.merge_moved, messages.merge_removed)
+    };
+    println!(
+        "{}",
+        i18n::fill(
+            moved,
+            &[
+                &result.files_moved,
+                &util::format_bytes(result.bytes_moved),
+                &destination.display()
+            ]
+        )
+    );
+    match result.linked {
+        Some(ref linked) => print_action_summary(linked, Action::Hardlink, dry_run, messages),
+        None => println!(
+            "{}",
+            i18n::fill(
+                removed,
+                &[
+                    &result.files_removed,
+                    &destination.display(),
+                    &util::format_bytes(result.bytes_freed)
+                ]
+            )
+        ),
+    }
+
+    if result.interrupted_files > 0 {
+        eprintln!(
+            "{}",
+            i18n::fill(messages.merge_interrupted, &[&result.interrupted_files])
+        );
+    }
+
+    if !result.changed.is_empty() {
+        eprintln!(
+            "\n{}",
+            i18n::fill(messages.skipped_changed, &[&result.changed.len()])
+        );
+        for path in &result.changed {
+            eprintln!("  {}", path.display());
+        }
+    }
+
+    let errors = plan.errors.len() + result.errors.len();
+    if errors > 0 {
+        eprintln!("\n{}", i18n::fill(messages.errors, &[&errors]));
+        for error in &plan.errors {
+            eprintln!("  {}: {}", error.path.display(), error.error);
+        }
+        for (path, err) in &result.errors {
+            eprintln!("  {}: {}", path.display(), err);
+        }
+    }
+}
+
+/// Print the outcome of a file-modifying action
+fn print_action_summary(
+    result: &actions::ActionResult,
+    action: Action,
+    dry_run: bool,
+    messages: &i18n::Messages,
+) {
+    let (summary, files) = match (action, dry_run) {
+        (Action::Stub, true) => (messages.dry_run_stub_summary, result.files_stubbed),
+        (Action::Stub, false) => (messages.stubbed_summary, result.files_stubbed),
+        (_, true) => (messages.dry_run_summary, result.files_linked),
+        (_, false) => (messages.linked_summary, result.files_linked),
+    };
+    println!(
+        "\n{}",
+        i18n::fill(summary, &[&files, &util::format_bytes(result.bytes_saved)])
+    );
+
+    if result.groups_below_min_saving > 0 {
+        println!(
+            "{}",
+            i18n::fill(
+                messages.below_min_saving,
+                &[&result.groups_below_min_saving]
+            )
+        );
+    }
+
+    if result.empty_groups_skipped > 0 {
+        println!(
+            "{}",
+            i18n::fill(
+                messages.empty_groups_skipped,
+                &[&result.empty_groups_skipped]
+            )
+        );
+    }
+
+    if result.link_limit_splits > 0 {
+        println!(
+            "{}",
+            i18n::fill(messages.link_limit_splits, &[&result.link_limit_splits])
+        );
+    }
+
+    if result.protected_keeper_groups > 0 {
+        println!(
+            "{}",
+            i18n::fill(
+                messages.protected_keeper_skipped,
+                &[&result.protected_keeper_groups]
+            )
+        );
+    }
+
+    if result.archive_members > 0 {
+        println!(
+            "{}",
+            i18n::fill(messages.archive_members_skipped, &[&result.archive_members])
+        );
+    }
+
+    if result.interrupted_groups > 0 {
+        eprintln!(
+            "{}",
+            i18n::fill(messages.interrupted_groups, &[&result.interrupted_groups])
+        );
+    }
+
+    for skip in result.mount_skips.values() {
+        eprintln!(
+            "{}",
+            i18n::fill(
+                messages.mount_skipped,
+                &[&skip.files, &skip.example.display(), &skip.reason]
+            )
+        );
+    }
+
+    if !result.changed.is_empty() {
+        eprintln!(
+            "\n{}",
+            i18n::fill(messages.skipped_changed, &[&result.changed.len()])
+        );
+        for path in &result.changed {
+            eprintln!("  {}", path.display());
+        }
+    }
+
+    if result.groups_rolled_back > 0 {
+        eprintln!(
+            "\n{}",
+            i18n::fill(messages.rolled_back, &[&result.groups_rolled_back])
+        );
+    }
+
+    if !result.errors.is_empty() {
+        eprintln!("\n{}", i18n::fill(messages.errors, &[&result.errors.len()]));
+        for (path, err) in &result.errors {
+            eprintln!("  {}: {}", path.display(), err);
+        }
+    }
+}
+
+/// Write the paths that were skipped or failed to the `--error-log` file, if there is one
+fn save_error_log(cli: &Cli, log: &errorlog::ErrorLog) {
+    if let Some(ref path) = cli.error_log
+        && let Err(e) = log.save(path)
+    {
+        eprintln!(
+            "Error: could not write the error log to {}: {}",
+            path.display(),
+            e
+        );
+        std::process::exit(2);
+    }
+}
+
+/// Write the report in the selected output format
+fn write_report(
+    out: &mut impl Write,
+    report: &output::DuplicateReport,
+    cli: &Cli,
+    messages: &i18n::Messages,
+) -> io::Result<()> {
+    if let Some(ref template) = cli.format_template {
+        return report.write_template(out, template);
+    }
+    match cli.format {
+        OutputFormat::Human => report.write_human(out, cli.verbose, messages),
+        OutputFormat::Json => match cli.page_size {
+            Some(page_size) => report
+                .page(cli.page as usize, page_size as usize)
+                .write_json(out),
+            None => report.write_json(out),
+        },
+        OutputFormat::Fdupes => report.write_fdupes(out),
+        OutputFormat::Paths0 => report.write_paths0(out),
+        OutputFormat::Summary => report.write_summary(out),
+        OutputFormat::Quiet => Ok(()),
+    }
+}
+
+/// Print the outcome of user commands run for duplicates
+fn print_hook_result(result: &hooks::HookResult, human: bool, messages: &i18n::Messages) {
+    if human {
+        println!(
+            "\n{}",
+            i18n::fill(
+                messages.hook_summary,
+                &[&result.commands_run, &result.failures.len()]
+            )
+        );
+        for (code, count) in &result.exit_codes {
+            println!("  {}", i18n::fill(messages.hook_exit_code, &[code, count]));
+        }
+    }
+
+    if !result.failures.is_empty() {
+        eprintln!(
+            "\n{}",
+            i18n::fill(messages.hook_failures, &[&result.failures.len()])
+        );
+        for (command, err) in &result.failures {
+            eprintln!("  {}: {}", command, err);
+        }
+    }
+}
+
+/// Candidate groups for duplicates and what was found while scanning for them
+struct Candidates {
+    size_groups: grouping::SizeGroups,
+    /// Candidate groups left on disk by --max-memory, read while they are hashed
+    spilled: Option<spill::SpilledGroups>,
+    /// Files to check for being the start of larger ones with --find-contained
+    contained_candidates: Vec<scanner::FileEntry>,
+    /// Number of scanned files
+    total_files: usize,
+    /// Structure of the scanned tree
+    tree: scanner::TreeStats,
+    /// Files and directories that could not be scanned
+    errors: Vec<scanner::ScanError>,
+}
+
+/// Scan the directories and group the files into candidates for duplicates (stages 1 and 2).
+///
+/// The time both stages took is recorded in `performance`. Exits if the number of files is
+/// outside the expected range.
+fn scan_candidates(
+    cli: &Cli,
+    exact: bool,
+    show_progress: bool,
+    messages: &i18n::Messages,
+    performance: &mut output::PerformanceStats,
+) -> Candidates {
+    let scan_started = Instant::now();
 
     // Stage 1: Scan directory for all files
+    let progress = Arc::new(scanner::ScanProgress::default());
     let scan_spinner = if show_progress {
         let sp = ProgressBar::new_spinner();
+        let counted = Arc::clone(&progress);
+        let scan_progress = messages.scan_progress;
+        let counts = move |_: &ProgressState, out: &mut dyn std::fmt::Write| {
+            let files = counted.files.load(Ordering::Relaxed);
+            let directories = counted.directories.load(Ordering::Relaxed);
+            let _ = write!(
+                out,
+                "{}",
+                i18n::fill(
+                    scan_progress,
+                    &[
+                        &util::format_number(files),
+                        &util::format_number(directories)
+                    ]
+                )
+            );
+        };
         sp.set_style(
             ProgressStyle::default_spinner()
-                .template("{spinner:.green} {msg}")
+                .with_key("counts", counts)
+                .template("{spinner:.green} {msg} {counts}")
                 .unwrap(),
         );
-        sp.set_message("Scanning files...");
+        sp.set_message(messages.scanning_files);
         sp.enable_steady_tick(std::time::Duration::from_millis(100));
         Some(sp)
     } else {
         None
     };
 
-    // Combine exclude patterns from --exclude and --exclude-file
-    let mut exclude_patterns = cli.exclude.clone();
-    if let Some(ref exclude_file) = cli.exclude_file {
-        exclude_patterns.extend(parse_glob_file(exclude_file));
+    // Combine exclude patterns from --exclude and --exclude-file
+    let mut exclude_patterns = cli.exclude.clone();
+    if let Some(ref exclude_file) = cli.exclude_file {
+        exclude_patterns.extend(parse_glob_file(exclude_file));
+    }
+
+    // Combine include patterns from --include and --include-file
+    let mut include_patterns = cli.include.clone();
+    if let Some(ref include_file) = cli.include_file {
+        include_patterns.extend(parse_glob_file(include_file));
+    }
+    include_patterns.extend(extension_globs(&cli.extensions));
+
+    let owner = cli.owner.as_deref().map(owner::resolve_user).transpose();
+    let group = cli.group.as_deref().map(owner::resolve_group).transpose();
+    let (owner, group) = match (owner, group) {
+        (Ok(owner), Ok(group)) => (owner, group),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let now = SystemTime::now();
+    let filters = scanner::ScanFilters {
+        min_size: cli.min_size,
+        max_size: cli.max_size,
+        min_allocated: cli.min_allocated,
+        exclude_patterns,
+        include_patterns,
+        ignore_case: cli.ignore_case,
+        include_empty: cli.include_empty_in_actions,
+        skip_hidden: cli.skip_hidden,
+        skip_hardlinked: cli.skip_hardlinked,
+        modified_after: cli.newer_than.map(|time| time.resolve(now)),
+        modified_before: cli.older_than.map(|time| time.resolve(now)),
+        owner,
+        group,
+        dedupignore: !cli.no_dedupignore,
+        scan_archives: cli.scan_archives,
+    };
+
+    // With --max-memory the scanned files are grouped on disk instead of collected
+    let mut spill = None;
+    let scan = match (&cli.files_from, cli.max_memory) {
+        (Some(list), _) => {
+            let paths = if list.as_os_str() == "-" {
+                scanner::read_file_list(io::stdin().lock(), cli.null)
+            } else {
+                File::open(list)
+                    .and_then(|file| scanner::read_file_list(BufReader::new(file), cli.null))
+            };
+            let paths = paths.unwrap_or_else(|e| {
+                eprintln!("Error: could not read {}: {}", list.display(), e);
+                std::process::exit(2);
+            });
+            scanner::scan_file_list(paths, &filters, &progress)
+        }
+        (None, Some(max_memory)) => {
+            let (found, received) = mpsc::channel();
+            let spilling = thread::spawn(move || {
+                spill::SizeSpill::new(&std::env::temp_dir(), max_memory)
+                    .and_then(|spill| spill.collect(received))
+            });
+            let scan = scanner::send_directories(&cli.paths, &filters, &progress, found);
+            spill = Some(spilling.join().unwrap().unwrap_or_else(|e| {
+                eprintln!("Error: could not spill the scanned files to disk: {}", e);
+                std::process::exit(2);
+            }));
+            scan
+        }
+        // Candidates found by the walk are partially hashed while it goes on, unless the
+        // partial hash isn't used or only a subset of them is hashed afterwards
+        (None, None)
+            if exact
+                && cli.match_mode == grouping::MatchMode::Content
+                && cli.same_relative_path.is_none()
+                && cli.compare_to.is_none()
+                && !cli.isolate
+                && cli.estimate.is_none()
+                && cli.expect_max_files.is_none()
+                && !cli.incremental =>
+        {
+            let (found, received) = mpsc::channel();
+            let skip = cli.skip_header_bytes;
+            let hashing = thread::spawn(move || pipeline::hash_while_scanning(received, skip));
+            let mut scan =
+                scanner::stream_directories(&cli.paths, &filters, &progress, Some(found));
+            pipeline::attach(&mut scan.files, hashing.join().unwrap());
+            scan
+        }
+        (None, None) => scanner::scan_directories(&cli.paths, &filters, &progress),
+    };
+    let mut tree = scanner::TreeStats {
+        symlinks: scan.symlinks,
+        ..Default::default()
+    };
+    tree.add_files(&scan.files);
+    let mut files = scan.files;
+    let mut errors = scan.errors;
+
+    if let Some(ref reference) = cli.compare_to {
+        let scan = scanner::scan_reference(reference, &filters, &progress);
+        tree.symlinks += scan.symlinks;
+        tree.add_files(&scan.files);
+        errors.extend(scan.errors);
+        files.extend(scan.files);
+    }
+    let mut total_files = files.len() + spill.as_ref().map_or(0, |spill| spill.len());
+
+    let mirror_files = cli.same_relative_path.as_ref().map(|mirror| {
+        let scan = scanner::scan_directory(mirror, &filters);
+        tree.symlinks += scan.symlinks;
+        tree.add_files(&scan.files);
+        errors.extend(scan.errors);
+        scan.files
+    });
+    total_files += mirror_files.as_ref().map_or(0, |f| f.len());
+
+    if let Some(sp) = scan_spinner {
+        sp.finish_and_clear();
+    }
+
+    if let Err(e) = check_file_count(total_files, cli.expect_min_files, cli.expect_max_files) {
+        eprintln!("Error: {}", e);
+        std::process::exit(2);
+    }
+    performance.scan_secs = scan_started.elapsed().as_secs_f64();
+    let grouping_started = Instant::now();
+
+    let contained_candidates = if cli.find_contained {
+        files
+            .iter()
+            .filter(|file| file.size >= contained::MIN_CONTAINED_SIZE)
+            .cloned()
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    // Stage 2: Group by size (or relative path, or name) to find potential duplicates.
+    // Files matched by inexact strategies may differ in size, all of them are candidates.
+    let mut size_groups = match (&cli.same_relative_path, mirror_files) {
+        (Some(mirror), Some(mirror_files)) => {
+            grouping::group_by_relative_path(files, &cli.paths[0], mirror_files, mirror)
+        }
+        _ if cli.match_mode == grouping::MatchMode::Name => {
+            grouping::group_by_name(files, cli.ignore_case)
+        }
+        _ if !exact => vec![files],
+        _ => grouping::group_by_size(files),
+    };
+    if cli.compare_to.is_some() {
+        size_groups.retain(|group| grouping::mixes_reference(group));
+    }
+    if cli.isolate {
+        size_groups = size_groups
+            .into_iter()
+            .flat_map(|group| grouping::split_by_root(group, &cli.paths))
+            .collect();
+    }
+    let spilled = spill.map(|spill| {
+        spill.group(&mut tree).unwrap_or_else(|e| {
+            eprintln!("Error: could not group the spilled files by size: {}", e);
+            std::process::exit(2);
+        })
+    });
+    performance.size_group_secs = grouping_started.elapsed().as_secs_f64();
+
+    Candidates {
+        size_groups,
+        spilled,
+        contained_candidates,
+        total_files,
+        tree,
+        errors,
+    }
+}
+
+/// Candidates loaded from a file instead of scanned. The files of the `confirmed` duplicate
+/// groups of an earlier run count as scanned too.
+fn loaded_candidates(
+    size_groups: grouping::SizeGroups,
+    confirmed: &[hasher::HashGroup],
+) -> Candidates {
+    let mut tree = scanner::TreeStats::default();
+    tree.add_files(&size_groups.concat());
+    let mut total_files: usize = size_groups.iter().map(|g| g.len()).sum();
+    for group in confirmed {
+        tree.add_files(&group.files);
+        total_files += group.files.len();
+    }
+    Candidates {
+        size_groups,
+        spilled: None,
+        contained_candidates: Vec::new(),
+        total_files,
+        tree,
+        errors: Vec::new(),
+    }
+}
+
+impl Cli {
+    /// Check combinations of options that clap can't express
+    fn validate(&self) -> Result<(), clap::Error> {
+        if matches!(self.format, OutputFormat::Quiet) && self.verbose {
+            return Err(Cli::command().error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--verbose can't be used with --format quiet, which prints nothing",
+            ));
+        }
+        // After an option, clap takes a subcommand name for one of the paths
+        if let Some(name) = self.paths.iter().find_map(|path| {
+            Cli::command()
+                .find_subcommand(path.as_os_str())
+                .map(|command| command.get_name().to_string())
+        }) {
+            return Err(Cli::command().error(
+                clap::error::ErrorKind::ArgumentConflict,
+                format!("the {} command can't be combined with scan options", name),
+            ));
+        }
+        if self.same_relative_path.is_some() && self.paths.len() > 1 {
+            return Err(Cli::command().error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--same-relative-path compares a single directory with its mirror, give only one path",
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn main() {
+    let started = Instant::now();
+    let cli = Cli::parse();
+    if let Err(e) = cli.validate() {
+        e.exit();
+    }
+
+    if cli.print_schema {
+        let schema =
+            serde_json::to_string_pretty(&schema::report_schema()).expect("schema is valid JSON");
+        println!("{}", schema);
+        return;
+    }
+
+    if let Some(Command::MergeReports { reports }) = &cli.command {
+        let merged = merge::merge_report_files(reports).and_then(|report| {
+            let written = match &cli.output {
+                Some(path) => output::write_atomically(path, |out| report.write_json(out)),
+                None => report.write_json(&mut io::stdout().lock()),
+            };
+            written.map_err(|e| format!("could not write merged report: {}", e))
+        });
+        if let Err(e) = merged {
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        }
+        return;
+    }
+
+    if let Some(Command::Diff { old, new, json }) = &cli.command {
+        let diff = diff::diff_report_files(old, new).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        });
+        let mut out = io::stdout().lock();
+        let written = if *json {
+            serde_json::to_writer_pretty(&mut out, &diff)
+                .map_err(io::Error::from)
+                .and_then(|()| writeln!(out))
+        } else {
+            write_report_diff(&diff, cli.lang.messages(), &mut out)
+        };
+        if let Err(e) = written {
+            eprintln!("Error: could not write the diff: {}", e);
+            std::process::exit(2);
+        }
+        return;
+    }
+
+    if let Some(Command::Merge {
+        source,
+        destination,
+        link,
+        dry_run,
+        verbose,
+    }) = &cli.command
+    {
+        let plan = consolidate::plan(source, destination).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        });
+        let options = consolidate::MergeOptions {
+            dry_run: *dry_run,
+            verbose: *verbose,
+            link: *link,
+        };
+        interrupt::install();
+        let result = consolidate::merge_trees(&plan, &options);
+        print_merge_summary(&result, &plan, destination, *dry_run, cli.lang.messages());
+
+        if interrupt::requested() {
+            std::process::exit(interrupt::EXIT_CODE);
+        }
+        let link_errors = result.linked.as_ref().is_some_and(|r| !r.errors.is_empty());
+        if !result.errors.is_empty() || link_errors {
+            std::process::exit(4);
+        }
+        if !plan.errors.is_empty() {
+            std::process::exit(3);
+        }
+        return;
+    }
+
+    if let Some(Command::Cache { command }) = &cli.command {
+        hasher::set_algorithm(cli.hash_algorithm);
+        match run_cache_command(command, cli.lang.messages()) {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    if (cli.background || cli.nice)
+        && let Err(e) = background::lower_priority()
+    {
+        util::warn(format_args!("could not lower the process priority: {}", e));
+    }
+    let jobs = cli
+        .jobs
+        .or(cli.background.then_some(background::BACKGROUND_JOBS));
+    if let Some(num_threads) = jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build_global()
+            .expect("Failed to initialize thread pool");
+    }
+
+    hasher::set_algorithm(cli.hash_algorithm);
+    hasher::set_mmap(cli.mmap);
+    if cli.profile_io {
+        profile::enable();
+    }
+    if let Some(ref path) = cli.cache
+        && let Err(e) = cache::open(path, cli.cache_validation, cli.revalidate_percent)
+    {
+        eprintln!("Error: could not open the cache {}: {}", path.display(), e);
+        std::process::exit(2);
+    }
+    iomode::set_io_mode(cli.io_mode);
+    if let Ok(limit) = iomode::raise_fd_limit() {
+        iomode::set_fd_limit(limit);
+    }
+    if let Some(bytes_per_sec) = cli.max_bandwidth {
+        iomode::set_max_bandwidth(bytes_per_sec);
+    }
+    match cli.io_workers {
+        Some(workers) => iomode::set_io_workers(workers as usize),
+        // Spinning disks and network filesystems get fewer readers than there are threads
+        None => {
+            let roots: Vec<PathBuf> = cli
+                .paths
+                .iter()
+                .chain(&cli.compare_to)
+                .chain(&cli.same_relative_path)
+                .cloned()
+                .collect();
+            iomode::set_device_workers(storage::device_io_workers(&roots));
+        }
+    }
+
+    if let Some(Command::ExportHashes { directory, file }) = &cli.command {
+        let exported = hashlist::export(directory, cli.skip_header_bytes, file);
+        if let Err(e) = cache::save() {
+            util::warn(format_args!("could not update the cache: {}", e));
+        }
+        match exported {
+            Ok(files) => println!(
+                "{}",
+                i18n::fill(
+                    cli.lang.messages().hashes_exported,
+                    &[
+                        &util::format_number(files),
+                        &directory.display().to_string(),
+                        &file.display().to_string()
+                    ]
+                )
+            ),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(2);
+            }
+        }
+        return;
+    }
+    if let Some(ref path) = cli.import_hashes {
+        match hashlist::load(
+            path,
+            cli.import_root.as_deref(),
+            cli.hash_algorithm,
+            cli.skip_header_bytes,
+        ) {
+            Ok(list) => hashlist::import(list),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(2);
+            }
+        }
+    }
+    if cli.skip_common_boilerplate && cli.hash_algorithm != hasher::Algorithm::Blake3 {
+        eprintln!(
+            "Error: the known boilerplate files are identified by their BLAKE3 hashes, use --skip-common-boilerplate with --hash blake3"
+        );
+        std::process::exit(2);
+    }
+    if matches!(cli.action, Action::Cas) && cli.hash_algorithm != hasher::Algorithm::Blake3 {
+        eprintln!(
+            "Error: store objects are named and trusted by their BLAKE3 hashes, use --action cas with --hash blake3"
+        );
+        std::process::exit(2);
+    }
+
+    let modifies_files = matches!(cli.action, Action::Hardlink | Action::Cas | Action::Stub);
+    let confirmer: Box<dyn confirm::Confirmer> = match cli.match_mode {
+        grouping::MatchMode::Content => cli.confirm.confirmer(cli.skip_header_bytes),
+        grouping::MatchMode::Name => {
+            if modifies_files
+                || cli.confirm != confirm::Strategy::FullHash
+                || cli.skip_header_bytes > 0
+            {
+                eprintln!(
+                    "Error: files matched by name may differ and are not compared, use --match name without a file-modifying --action, --confirm or --skip-header-bytes"
+                );
+                std::process::exit(2);
+            }
+            Box::new(confirm::SameName {
+                ignore_case: cli.ignore_case,
+            })
+        }
+    };
+    if cli.max_memory.is_some() && !confirmer.is_exact() {
+        eprintln!(
+            "Error: --max-memory groups the scanned files by size, use it with --match content and --confirm full-hash or byte-compare"
+        );
+        std::process::exit(2);
+    }
+    if cli.skip_header_bytes > 0 && modifies_files {
+        eprintln!(
+            "Error: files matched with --skip-header-bytes may differ in their headers and can't be replaced with each other, use it without a file-modifying --action"
+        );
+        std::process::exit(2);
+    }
+    if !confirmer.is_exact() && modifies_files {
+        eprintln!(
+            "Error: files matched by --confirm {} may differ and can't be replaced with each other, use it without a file-modifying --action",
+            cli.confirm.to_possible_value().unwrap().get_name()
+        );
+        std::process::exit(2);
+    }
+
+    if cli.paranoid
+        && (cli.match_mode != grouping::MatchMode::Content
+            || cli.confirm != confirm::Strategy::FullHash)
+    {
+        eprintln!(
+            "Error: --paranoid compares files whose full hashes matched, use it with --match content and --confirm full-hash"
+        );
+        std::process::exit(2);
+    }
+
+    if cli.incremental && cli.match_mode != grouping::MatchMode::Content {
+        eprintln!(
+            "Error: --incremental reuses the duplicates found by comparing contents, use it with --match content"
+        );
+        std::process::exit(2);
+    }
+
+    if cli.scan_archives
+        && (cli.skip_header_bytes > 0 || cli.confirm != confirm::Strategy::FullHash)
+    {
+        eprintln!(
+            "Error: archive members are compared by the hashes computed while scanning, use --scan-archives without --skip-header-bytes and with --confirm full-hash"
+        );
+        std::process::exit(2);
+    }
+
+    if cli.compare_to.is_some() && matches!(cli.action, Action::Cas) {
+        eprintln!(
+            "Error: the cas action replaces every copy with a link to the store, use --compare-to with --action hardlink or stub to keep the reference files"
+        );
+        std::process::exit(2);
+    }
+    if let Some(ref reference) = cli.compare_to
+        && let Some(root) = scanner::overlapping_root(&cli.paths, reference)
+    {
+        eprintln!(
+            "Error: {} overlaps the --compare-to directory {}, give directories outside of each other",
+            root.display(),
+            reference.display()
+        );
+        std::process::exit(2);
+    }
+
+    if cli.estimate.is_some() && (modifies_files || !confirmer.is_exact()) {
+        eprintln!(
+            "Error: --estimate only hashes a sample of the candidates, use it without a file-modifying --action and with an exact --confirm strategy"
+        );
+        std::process::exit(2);
+    }
+
+    let quiet = matches!(cli.format, OutputFormat::Quiet);
+    util::set_quiet(quiet);
+    // With --output the report doesn't go to stdout, which is left to progress and summaries
+    let human = (matches!(cli.format, OutputFormat::Human) && cli.format_template.is_none())
+        || (cli.output.is_some() && !quiet);
+    let show_progress = human && !cli.no_progress;
+    let messages = cli.lang.messages();
+
+    let mut performance = output::PerformanceStats::default();
+    // Duplicates confirmed by the run continued with --resume
+    let mut resumed = hasher::HashGroups::new();
+    let candidates = match (&cli.checkpoint, &cli.resume_deferred) {
+        (Some(path), _) if cli.resume => {
+            match checkpoint::load(path, cli.hash_algorithm, confirmer.is_exact()) {
+                Ok((groups, confirmed)) => {
+                    resumed = confirmed;
+                    loaded_candidates(groups, &resumed)
+                }
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(2);
+                }
+            }
+        }
+        (_, Some(deferred)) => match deferral::load(deferred, confirmer.is_exact()) {
+            Ok(groups) => loaded_candidates(groups, &[]),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(2);
+            }
+        },
+        _ => scan_candidates(
+            &cli,
+            confirmer.is_exact(),
+            show_progress,
+            messages,
+            &mut performance,
+        ),
+    };
+    let (size_groups, sample) = match cli.estimate {
+        Some(percent) => {
+            let (sampled, sample) = estimate::sample(candidates.size_groups, percent / 100.0);
+            (sampled, Some(sample))
+        }
+        None => (candidates.size_groups, None),
+    };
+    let spilled = candidates.spilled;
+    let contained_candidates = candidates.contained_candidates;
+    let candidate_count: usize = size_groups.iter().map(|g| g.len()).sum::<usize>()
+        + spilled.as_ref().map_or(0, |spilled| spilled.files);
+
+    // Stage 3 & 4: Process each size group through partial hash -> sample hash (large
+    // files only) -> full hash pipeline
+    let progress_bar = if show_progress && candidate_count > 0 {
+        let pb = ProgressBar::new(candidate_count as u64);
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template(&format!(
+                    "{{spinner:.green}} [{{bar:40.cyan/blue}}] {{pos}}/{{len}} {}",
+                    messages.files_compared
+                ))
+                .unwrap()
+                .progress_chars("#>-"),
+        );
+        Some(pb)
+    } else {
+        None
+    };
+
+    let processed = AtomicUsize::new(0);
+    let throttle = cli.background.then(background::Throttle::default);
+
+    // Groups already being hashed are finished after the timeout or Ctrl-C, only new ones
+    // are deferred
+    let deadline = cli.timeout.map(|secs| started + Duration::from_secs(secs));
+    let stopped =
+        || interrupt::requested() || deadline.is_some_and(|deadline| Instant::now() >= deadline);
+    let deferred = Mutex::new(Vec::new());
+
+    // Nanoseconds spent in each hashing stage, summed over all threads
+    let partial_hash_nanos = AtomicU64::new(0);
+    let full_hash_nanos = AtomicU64::new(0);
+    let timed = |nanos: &AtomicU64, started: Instant| {
+        nanos.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    };
+
+    // With --incremental, size groups whose files are all unchanged since the last run keep
+    // the duplicates found then. The candidates compared in this run and the hashes of their
+    // duplicates are the snapshot for the next one.
+    let snapshot_settings = format!(
+        "{} {} {} {}",
+        cli.hash_algorithm.name(),
+        cli.skip_header_bytes,
+        cli.confirm.to_possible_value().unwrap().get_name(),
+        cli.isolate
+    );
+    let snapshot = if cli.incremental {
+        cache::load_snapshot(&snapshot_settings).unwrap_or_else(|e| {
+            util::warn(format_args!(
+                "could not read the last incremental run from the cache, all candidates are compared: {}",
+                e
+            ));
+            None
+        })
+    } else {
+        None
+    };
+    let compared = Mutex::new(Vec::new());
+    let duplicate_hashes = Mutex::new(HashMap::new());
+
+    let confirm_candidates = |size_group: Vec<scanner::FileEntry>| -> hasher::HashGroups {
+        if cli.incremental {
+            compared.lock().unwrap().extend(size_group.iter().cloned());
+        }
+        let known_groups = snapshot
+            .as_ref()
+            .and_then(|snapshot| snapshot.known_groups(&size_group));
+        let confirmed: hasher::HashGroups = match known_groups {
+            Some(groups) => groups,
+            None => {
+                // The full hash of files the partial hash would read completely tells them
+                // apart in a single read
+                let fully_read = cli.confirm == confirm::Strategy::FullHash
+                    && hasher::partial_hash_covers(size_group[0].size, cli.skip_header_bytes);
+                let partial_groups = if confirmer.is_exact() && !fully_read {
+                    let _busy = pause::wait();
+                    let started = Instant::now();
+                    let groups = hasher::group_by_partial_hash(size_group, cli.skip_header_bytes)
+                        .into_par_iter()
+                        .flat_map(|group| {
+                            hasher::group_by_sample_hash(group, cli.skip_header_bytes)
+                        })
+                        .collect();
+                    timed(&partial_hash_nanos, started);
+                    groups
+                } else {
+                    vec![size_group]
+                };
+
+                partial_groups
+                    .into_par_iter()
+                    .flat_map(|group| {
+                        if stopped() {
+                            deferred.lock().unwrap().push(group);
+                            return Vec::new();
+                        }
+                        let _busy = pause::wait();
+                        let started = Instant::now();
+                        let groups = confirmer.confirm(group);
+                        timed(&full_hash_nanos, started);
+                        groups
+                    })
+                    .collect()
+            }
+        };
+        if cli.incremental {
+            let mut hashes = duplicate_hashes.lock().unwrap();
+            for group in &confirmed {
+                for file in &group.files {
+                    hashes.insert(file.path.clone(), group.hash);
+                }
+            }
+        }
+
+        confirmed
+            .into_par_iter()
+            .flat_map(|group| {
+                if !cli.paranoid {
+                    return vec![group];
+                }
+                let _busy = pause::wait();
+                confirm::verify_group(group, cli.skip_header_bytes)
+            })
+            .filter(|group| {
+                !(cli.skip_common_boilerplate && boilerplate::is_common_boilerplate(&group.hash))
+            })
+            .filter_map(|group| {
+                if cli.compare_to.is_none() {
+                    return Some(group);
+                }
+                let hash = group.hash;
+                grouping::against_reference(group.files)
+                    .map(|files| hasher::HashGroup { hash, files })
+            })
+            .filter(|group| !cli.cross_dir_only || grouping::spans_directories(&group.files))
+            .flat_map_iter(|group| {
+                if !cli.same_dir_only {
+                    return vec![group];
+                }
+                let hash = group.hash;
+                grouping::split_by_directory(group.files)
+                    .into_iter()
+                    .map(|files| hasher::HashGroup { hash, files })
+                    .collect()
+            })
+            .collect()
+    };
+
+    // Candidate groups are hashed one after the other, between them no files are open.
+    // Ctrl-C stays handled until the end, so actions and commands stop between groups too.
+    interrupt::install();
+    pause::install();
+    let checkpoint = cli.checkpoint.as_ref().map(|path| {
+        checkpoint::Checkpoint::new(
+            path,
+            cli.hash_algorithm,
+            checkpoint::CHECKPOINT_INTERVAL,
+            &size_groups,
+            &resumed,
+        )
+        .unwrap_or_else(|e| {
+            eprintln!("Error: could not write {}: {}", path.display(), e);
+            std::process::exit(2);
+        })
+    });
+    let hash_started = Instant::now();
+    let bytes_read_before = hasher::bytes_read();
+    let hash_size_group = |(index, size_group): (usize, Vec<scanner::FileEntry>)| {
+        let group_size = size_group.len();
+        let group_bytes: u64 = size_group.iter().map(|f| f.size).sum();
+        let final_groups = if stopped() {
+            deferred.lock().unwrap().push(size_group);
+            Vec::new()
+        } else {
+            confirm_candidates(size_group)
+        };
+        // Once stopped, part of the group may have been deferred and it stays pending
+        if let Some(ref checkpoint) = checkpoint
+            && !stopped()
+        {
+            checkpoint.finish(index, &final_groups);
+        }
+
+        if let Some(ref pb) = progress_bar {
+            let prev = processed.fetch_add(group_size, Ordering::Relaxed);
+            // Files deleted since they were scanned are no longer left to hash
+            let vanished = hasher::vanished_files();
+            pb.set_length(candidate_count.saturating_sub(vanished) as u64);
+            pb.set_position((prev + group_size).saturating_sub(vanished) as u64);
+        }
+        if let Some(ref throttle) = throttle {
+            throttle.pause(group_bytes);
+        }
+
+        final_groups
+    };
+    // Groups spilled to disk are read as the threads get to them, a few at a time
+    let mut duplicate_groups: hasher::HashGroups = match spilled {
+        Some(spilled) => spilled
+            .enumerate()
+            .par_bridge()
+            .flat_map(hash_size_group)
+            .collect(),
+        None => size_groups
+            .into_par_iter()
+            .enumerate()
+            .flat_map(hash_size_group)
+            .collect(),
+    };
+    duplicate_groups.extend(resumed);
+
+    pause::uninstall();
+    let interrupted = interrupt::requested();
+    performance.hash_secs = hash_started.elapsed().as_secs_f64();
+    performance.bytes_hashed = hasher::bytes_read() - bytes_read_before;
+    performance.partial_hash_secs =
+        Duration::from_nanos(partial_hash_nanos.into_inner()).as_secs_f64();
+    performance.full_hash_secs = Duration::from_nanos(full_hash_nanos.into_inner()).as_secs_f64();
+    performance.compute_throughput();
+    if let Some((waits, budget)) = iomode::fd_waits()
+        && waits > 0
+    {
+        util::warn(format_args!(
+            "files waited {} times to be opened because at most {} can be open at once, raise the hard limit of open files (ulimit -Hn) to hash faster",
+            waits, budget
+        ));
+    }
+    if let Err(e) = cache::save() {
+        util::warn(format_args!("could not update the cache: {}", e));
+    }
+
+    let deferred = deferred.into_inner().unwrap();
+    if cli.incremental {
+        // Deferred candidates weren't compared, the next run compares them
+        let deferred_paths: HashSet<&Path> = deferred
+            .iter()
+            .flatten()
+            .map(|file| file.path.as_path())
+            .collect();
+        let mut compared = compared.into_inner().unwrap();
+        compared.retain(|file| !deferred_paths.contains(file.path.as_path()));
+        let snapshot =
+            incremental::Snapshot::record(&compared, &duplicate_hashes.into_inner().unwrap());
+        if let Err(e) = cache::save_snapshot(&snapshot_settings, &snapshot) {
+            util::warn(format_args!("could not update the cache: {}", e));
+        }
+    }
+    if let Some(ref path) = cli.deferred_file
+        && let Err(e) = deferral::save(path, &deferred)
+    {
+        eprintln!("Error: could not write {}: {}", path.display(), e);
+        std::process::exit(2);
+    }
+    if let Some(ref checkpoint) = checkpoint
+        && let Err(e) = checkpoint.finish_run(&deferred, &duplicate_groups)
+    {
+        eprintln!(
+            "Error: could not write {}: {}",
+            cli.checkpoint.as_ref().unwrap().display(),
+            e
+        );
+        std::process::exit(2);
+    }
+    if !deferred.is_empty() {
+        let files: usize = deferred.iter().map(|g| g.len()).sum();
+        let hint = match (&cli.deferred_file, &cli.checkpoint) {
+            (Some(path), _) => format!("saved to {} for --resume-deferred", path.display()),
+            (None, Some(path)) => format!("saved to {} for --resume", path.display()),
+            (None, None) => "use --deferred-file to save them for a later run".to_string(),
+        };
+        util::warn(format_args!(
+            "{}, {} candidate files in {} groups were not hashed ({})",
+            if interrupted {
+                "interrupted"
+            } else {
+                "timed out"
+            },
+            files,
+            deferred.len(),
+            hint
+        ));
+    }
+
+    let vanished_files = hasher::vanished_files();
+    let mut report = output::DuplicateReport::from_groups(
+        duplicate_groups,
+        candidates.total_files.saturating_sub(vanished_files),
+    );
+    report.stats.vanished_files = vanished_files;
+    report.stats.tree = candidates.tree;
+    let mut error_log = errorlog::ErrorLog::default();
+    error_log.add_scan_errors(&candidates.errors);
+    let read_failures = hasher::read_failures();
+    error_log.add_read_failures(&read_failures);
+    report.errors = candidates.errors;
+    // Files that could not be read to hash them may hide duplicates too
+    report.errors.extend(
+        read_failures
+            .into_iter()
+            .filter_map(|(path, failure)| match failure {
+                hasher::ReadFailure::Unreadable(error) => Some(scanner::ScanError { path, error }),
+                hasher::ReadFailure::Vanished => None,
+            }),
+    );
+    report.stats.performance = Some(performance);
+    report.partial = interrupted;
+    report.estimate = sample.map(|sample| sample.extrapolate(&report.groups));
+    if let Some(max) = cli.max_group_size {
+        report.limit_group_size(max as usize);
+    }
+    if let Some(n) = cli.top {
+        report.top(n as usize);
+    }
+    match (cli.sort, cli.top) {
+        (Some(key), _) => report.sort(key, cli.reverse),
+        (None, Some(_)) => report.sort(output::SortKey::Wasted, cli.reverse),
+        (None, None) => report.sort(output::SortKey::Path, cli.reverse),
+    }
+    if cli.by_directory {
+        report.roll_up_directories();
+    }
+    report.io_profile = profile::hot_spots();
+    if cli.find_contained && !interrupted {
+        report.contained = Some(contained::find_contained(contained_candidates));
+    }
+
+    if let Some(pb) = progress_bar {
+        pb.finish_and_clear();
+    }
+
+    // Actions and commands still need the real paths
+    let relative_report = cli.relative_paths.then(|| {
+        let mut roots = cli.paths.clone();
+        roots.extend(cli.compare_to.clone());
+        report.relative_to(&common_ancestor(&roots))
+    });
+    let shown_report = relative_report.as_ref().unwrap_or(&report);
+    let written = match &cli.output {
+        Some(path) => {
+            // Colors are only meant for terminals
+            colored::control::set_override(false);
+            let result = output::write_atomically(path, |out| {
+                write_report(out, shown_report, &cli, messages)
+            });
+            colored::control::unset_override();
+            result.map_err(|e| format!("could not write report to {}: {}", path.display(), e))
+        }
+        None => write_report(&mut io::stdout().lock(), shown_report, &cli, messages)
+            .map_err(|e| format!("could not write report: {}", e)),
+    };
+    if let Err(e) = written {
+        eprintln!("Error: {}", e);
+        std::process::exit(2);
+    }
+
+    if interrupted {
+        util::warn(
+            "interrupted, only the duplicates confirmed so far are reported and no actions or commands were run",
+        );
+        save_error_log(&cli, &error_log);
+        std::process::exit(interrupt::EXIT_CODE);
+    }
+
+    // Set if files could not be scanned or read, reported with exit code 3
+    let read_failed = !report.errors.is_empty();
+    // Set if an action or a command failed for some files, reported with exit code 4
+    let mut failed = false;
+
+    if let Some(ref template) = cli.exec_per_group {
+        let result = hooks::run_per_group(
+            &report.groups,
+            template,
+            cli.on_protected_keeper,
+            cli.dry_run,
+            cli.verbose && !quiet,
+        );
+        if !quiet {
+            print_hook_result(&result, human, messages);
+        }
+        failed |= !result.failures.is_empty();
+        for (subject, error) in &result.failures {
+            error_log.add(errorlog::ErrorKind::Command, subject, error);
+        }
+    }
+
+    if let Some(ref command) = cli.exec_per_duplicate {
+        let result = hooks::run_per_duplicate(
+            &report.groups,
+            command,
+            cli.on_protected_keeper,
+            cli.dry_run,
+            cli.verbose && !quiet,
+        );
+        if !quiet {
+            print_hook_result(&result, human, messages);
+        }
+        failed |= !result.failures.is_empty();
+        for (subject, error) in &result.failures {
+            error_log.add(errorlog::ErrorKind::Command, subject, error);
+        }
+    }
+
+    match cli.action {
+        Action::None | Action::ReportExitCode => {}
+        Action::Hardlink | Action::Cas | Action::Stub => {
+            let action_started = Instant::now();
+            let options = actions::ActionOptions {
+                dry_run: cli.dry_run,
+                verbose: cli.verbose && !quiet,
+                per_group_atomic: cli.per_group_atomic,
+                min_saving: cli.min_saving,
+                force: cli.force,
+                include_empty: cli.include_empty_in_actions,
+                on_protected_keeper: cli.on_protected_keeper,
+            };
+            let result = match (cli.action, &cli.store) {
+                (Action::Cas, Some(store)) => {
+                    actions::consolidate_into_store(&report.groups, store, &options)
+                }
+                (Action::Stub, _) => {
+                    actions::replace_with_stubs(&report.groups, &cli.stub_extension, &options)
+                }
+                _ => actions::hardlink_duplicates(&report.groups, &options),
+            };
+
+            if human {
+                print_action_summary(&result, cli.action, cli.dry_run, messages);
+                if cli.verbose {
+                    println!(
+                        "{}",
+                        i18n::fill(
+                            messages.action_duration,
+                            &[&format!("{:.2}", action_started.elapsed().as_secs_f64())]
+                        )
+                    );
+                }
+            }
+            failed |= !result.errors.is_empty();
+            for path in &result.changed {
+                error_log.add(
+                    errorlog::ErrorKind::Changed,
+                    path,
+                    "changed since it was hashed",
+                );
+            }
+            for (path, error) in &result.errors {
+                error_log.add(errorlog::ErrorKind::Action, path, error);
+            }
+        }
+    }
+
+    save_error_log(&cli, &error_log);
+    if interrupt::requested() {
+        std::process::exit(interrupt::EXIT_CODE);
+    }
+    if failed {
+        std::process::exit(4);
+    }
+    if read_failed {
+        std::process::exit(3);
+    }
+    if matches!(cli.action, Action::ReportExitCode) && !report.groups.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_cli_config() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_default_values() {
+        let cli = Cli::parse_from(["dedup"]);
+
+        assert_eq!(cli.paths, [PathBuf::from(".")]);
+        assert!(matches!(cli.format, OutputFormat::Human));
+        assert!(matches!(cli.action, Action::None));
+        assert_eq!(cli.min_size, None);
+        assert!(!cli.dry_run);
+        assert!(!cli.verbose);
+        assert!(!cli.no_progress);
+    }
+
+    #[test]
+    fn test_report_exit_code_action() {
+        let cli = Cli::parse_from(["dedup", "--action", "report-exit-code"]);
+        assert!(matches!(cli.action, Action::ReportExitCode));
+    }
+
+    #[test]
+    fn test_verbose_flag() {
+        let cli = Cli::parse_from(["dedup", "--verbose"]);
+        assert!(cli.verbose);
+
+        let cli = Cli::parse_from(["dedup", "-v"]);
+        assert!(cli.verbose);
+    }
+
+    #[test]
+    fn test_no_progress_flag() {
+        let cli = Cli::parse_from(["dedup", "--no-progress"]);
+        assert!(cli.no_progress);
+    }
+
+    #[test]
+    fn test_lang_flag() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.lang, i18n::Lang::En);
+
+        let cli = Cli::parse_from(["dedup", "--lang", "tr"]);
+        assert_eq!(cli.lang, i18n::Lang::Tr);
+    }
+
+    #[test]
+    fn test_custom_path() {
+        let cli = Cli::parse_from(["dedup", "/some/path"]);
+        assert_eq!(cli.paths, [PathBuf::from("/some/path")]);
+    }
+
+    #[test]
+    fn test_multiple_paths() {
+        let cli = Cli::parse_from(["dedup", "/data", "/backup", "/mnt/nas"]);
+        assert_eq!(
+            cli.paths,
+            [
+                PathBuf::from("/data"),
+                PathBuf::from("/backup"),
+                PathBuf::from("/mnt/nas")
+            ]
+        );
+        assert!(cli.validate().is_ok());
+
+        let cli = Cli::parse_from([
+            "dedup",
+            "/data",
+            "/other",
+            "--same-relative-path",
+            "/backup",
+        ]);
+        assert!(cli.validate().is_err());
+    }
+
+    #[test]
+    fn test_common_ancestor() {
+        let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+        assert_eq!(common_ancestor(&paths(&["/data"])), PathBuf::from("/data"));
+        assert_eq!(
+            common_ancestor(&paths(&["/mnt/a/photos", "/mnt/ab", "/mnt/a"])),
+            PathBuf::from("/mnt")
+        );
+        assert_eq!(
+            common_ancestor(&paths(&["/data", "/backup"])),
+            PathBuf::from("/")
+        );
+        assert_eq!(
+            common_ancestor(&paths(&["data", "/backup"])),
+            PathBuf::new()
+        );
+    }
+
+    #[test]
+    fn test_same_relative_path_flag() {
+        let cli = Cli::parse_from(["dedup", "/data", "--same-relative-path", "/backup"]);
+        assert_eq!(cli.paths, [PathBuf::from("/data")]);
+        assert_eq!(cli.same_relative_path, Some(PathBuf::from("/backup")));
+    }
+
+    #[test]
+    fn test_by_directory_flag() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert!(!cli.by_directory);
+
+        let cli = Cli::parse_from(["dedup", "--by-directory"]);
+        assert!(cli.by_directory);
+    }
+
+    #[test]
+    fn test_cache_option() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.cache, None);
+
+        let cli = Cli::parse_from(["dedup", "--cache", "hashes.sqlite"]);
+        assert_eq!(cli.cache, Some(PathBuf::from("hashes.sqlite")));
+        assert_eq!(cli.cache_validation, cache::Validation::Strict);
+        assert_eq!(cli.revalidate_percent, 0);
+
+        let cli = Cli::parse_from([
+            "dedup",
+            "--cache",
+            "hashes.sqlite",
+            "--cache-validation",
+            "relaxed",
+            "--revalidate-percent",
+            "5",
+        ]);
+        assert_eq!(cli.cache_validation, cache::Validation::Relaxed);
+        assert_eq!(cli.revalidate_percent, 5);
+
+        assert!(Cli::try_parse_from(["dedup", "--revalidate-percent", "5"]).is_err());
+        assert!(Cli::try_parse_from(["dedup", "--cache-validation", "relaxed"]).is_err());
+        assert!(
+            Cli::try_parse_from(["dedup", "--cache", "c", "--revalidate-percent", "101"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_incremental_flag() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert!(!cli.incremental);
+
+        let cli = Cli::parse_from(["dedup", "--cache", "c", "--incremental"]);
+        assert!(cli.incremental);
+
+        assert!(Cli::try_parse_from(["dedup", "--incremental"]).is_err());
+        assert!(
+            Cli::try_parse_from(["dedup", "--cache", "c", "--incremental", "--estimate"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_profile_io_flag() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert!(!cli.profile_io);
+
+        let cli = Cli::parse_from(["dedup", "--profile-io"]);
+        assert!(cli.profile_io);
+    }
+
+    #[test]
+    fn test_relative_paths_flag() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert!(!cli.relative_paths);
+
+        let cli = Cli::parse_from(["dedup", "/data", "--relative-paths"]);
+        assert!(cli.relative_paths);
+
+        let result = Cli::try_parse_from([
+            "dedup",
+            "/data",
+            "--relative-paths",
+            "--same-relative-path",
+            "/backup",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_json_format() {
+        let cli = Cli::parse_from(["dedup", "--format", "json"]);
+        assert!(matches!(cli.format, OutputFormat::Json));
+    }
+
+    #[test]
+    fn test_fdupes_format() {
+        let cli = Cli::parse_from(["dedup", "--format", "fdupes"]);
+        assert!(matches!(cli.format, OutputFormat::Fdupes));
+    }
+
+    #[test]
+    fn test_quiet_conflicts_with_verbose() {
+        let cli = Cli::parse_from(["dedup", "--format", "quiet"]);
+        assert!(cli.validate().is_ok());
+
+        let cli = Cli::parse_from(["dedup", "--format", "quiet", "--verbose"]);
+        assert!(cli.validate().is_err());
+    }
+
+    #[test]
+    fn test_timeout_and_deferral() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.timeout, None);
+        assert_eq!(cli.deferred_file, None);
+        assert_eq!(cli.resume_deferred, None);
+
+        let cli = Cli::parse_from(["dedup", "--timeout", "60", "--deferred-file", "d.json"]);
+        assert_eq!(cli.timeout, Some(60));
+        assert_eq!(cli.deferred_file, Some(PathBuf::from("d.json")));
+
+        assert!(Cli::try_parse_from(["dedup", "--deferred-file", "d.json"]).is_err());
+        assert!(
+            Cli::try_parse_from([
+                "dedup",
+                "--resume-deferred",
+                "d.json",
+                "--same-relative-path",
+                "m"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_export_and_import_hashes() {
+        let cli = Cli::parse_from(["dedup", "export-hashes", "/volume1/photos", "nas.json"]);
+        match cli.command {
+            Some(Command::ExportHashes { directory, file }) => {
+                assert_eq!(directory, PathBuf::from("/volume1/photos"));
+                assert_eq!(file, PathBuf::from("nas.json"));
+            }
+            _ => panic!("expected export-hashes"),
+        }
+
+        let cli = Cli::parse_from([
+            "dedup",
+            "--import-hashes",
+            "nas.json",
+            "--import-root",
+            "/mnt/nas/photos",
+        ]);
+        assert_eq!(cli.import_hashes, Some(PathBuf::from("nas.json")));
+        assert_eq!(cli.import_root, Some(PathBuf::from("/mnt/nas/photos")));
+
+        assert!(Cli::try_parse_from(["dedup", "--import-root", "/mnt/nas"]).is_err());
+    }
+
+    #[test]
+    fn test_checkpoint_and_resume() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.checkpoint, None);
+        assert!(!cli.resume);
+
+        let cli = Cli::parse_from(["dedup", "--checkpoint", "c.json", "--resume"]);
+        assert_eq!(cli.checkpoint, Some(PathBuf::from("c.json")));
+        assert!(cli.resume);
+
+        assert!(Cli::try_parse_from(["dedup", "--resume"]).is_err());
+        assert!(
+            Cli::try_parse_from(["dedup", "/data", "--checkpoint", "c.json", "--resume"]).is_err()
+        );
+        assert!(Cli::try_parse_from(["dedup", "--checkpoint", "c.json", "--estimate"]).is_err());
+    }
+
+    #[test]
+    fn test_sort() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.sort, None);
+        assert!(!cli.reverse);
+
+        let cli = Cli::parse_from(["dedup", "--sort", "wasted", "--reverse"]);
+        assert_eq!(cli.sort, Some(output::SortKey::Wasted));
+        assert!(cli.reverse);
+    }
+
+    #[test]
+    fn test_top() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.top, None);
+
+        let cli = Cli::parse_from(["dedup", "--top", "10"]);
+        assert_eq!(cli.top, Some(10));
+
+        assert!(Cli::try_parse_from(["dedup", "--top", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_estimate_flag() {
+        let cli = Cli::parse_from(["dedup", "--estimate", "/data"]);
+        assert_eq!(cli.estimate, Some(5.0));
+        assert_eq!(cli.paths, [PathBuf::from("/data")]);
+
+        let cli = Cli::parse_from(["dedup", "--estimate=0.5"]);
+        assert_eq!(cli.estimate, Some(0.5));
+
+        assert!(Cli::try_parse_from(["dedup", "--estimate=0"]).is_err());
+        assert!(Cli::try_parse_from(["dedup", "--estimate=101"]).is_err());
+        assert!(
+            Cli::try_parse_from(["dedup", "--estimate", "--exec-per-group", "echo {hash}"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_max_group_size() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.max_group_size, None);
+
+        let cli = Cli::parse_from(["dedup", "--max-group-size", "100"]);
+        assert_eq!(cli.max_group_size, Some(100));
+
+        assert!(Cli::try_parse_from(["dedup", "--max-group-size", "1"]).is_err());
+    }
+
+    #[test]
+    fn test_format_template() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.format_template, None);
+
+        let cli = Cli::parse_from(["dedup", "--format-template", "{hash} {path}"]);
+        assert!(cli.format_template.is_some());
+
+        assert!(Cli::try_parse_from(["dedup", "--format-template", "{nope}"]).is_err());
+        assert!(
+            Cli::try_parse_from(["dedup", "--format", "json", "--format-template", "{path}"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_paths0_format() {
+        let cli = Cli::parse_from(["dedup", "--format", "paths0"]);
+        assert!(matches!(cli.format, OutputFormat::Paths0));
+    }
+
+    #[test]
+    fn test_summary_format() {
+        let cli = Cli::parse_from(["dedup", "--format", "summary"]);
+        assert!(matches!(cli.format, OutputFormat::Summary));
+    }
+
+    #[test]
+    fn test_quiet_format() {
+        let cli = Cli::parse_from(["dedup", "--format", "quiet"]);
+        assert!(matches!(cli.format, OutputFormat::Quiet));
+    }
+
+    #[test]
+    fn test_page_flags() {
+        let cli = Cli::parse_from(["dedup", "--page-size", "100", "--page", "3"]);
+        assert_eq!(cli.page_size, Some(100));
+        assert_eq!(cli.page, 3);
+
+        let cli = Cli::parse_from(["dedup", "--page-size", "100"]);
+        assert_eq!(cli.page, 1);
+
+        assert!(Cli::try_parse_from(["dedup", "--page", "2"]).is_err());
+        assert!(Cli::try_parse_from(["dedup", "--page-size", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_short_format_flag() {
+        let cli = Cli::parse_from(["dedup", "-f", "json"]);
+        assert!(matches!(cli.format, OutputFormat::Json));
+    }
+
+    #[test]
+    fn test_hardlink_action() {
+        let cli = Cli::parse_from(["dedup", "--action", "hardlink"]);
+        assert!(matches!(cli.action, Action::Hardlink));
+    }
+
+    #[test]
+    fn test_min_size() {
+        let cli = Cli::parse_from(["dedup", "--min-size", "1024"]);
+        assert_eq!(cli.min_size, Some(1024));
+    }
+
+    #[test]
+    fn test_short_min_size() {
+        let cli = Cli::parse_from(["dedup", "-s", "4096"]);
+        assert_eq!(cli.min_size, Some(4096));
+    }
+
+    #[test]
+    fn test_max_size() {
+        let cli = Cli::parse_from(["dedup", "--max-size", "1048576"]);
+        assert_eq!(cli.max_size, Some(1048576));
+    }
+
+    #[test]
+    fn test_min_allocated() {
+        let cli = Cli::parse_from(["dedup", "--min-allocated", "4096"]);
+        assert_eq!(cli.min_allocated, Some(4096));
+    }
+
+    #[test]
+    fn test_short_max_size() {
+        let cli = Cli::parse_from(["dedup", "-S", "2048"]);
+        assert_eq!(cli.max_size, Some(2048));
+    }
+
+    #[test]
+    fn test_per_group_atomic_flag() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert!(!cli.per_group_atomic);
+
+        let cli = Cli::parse_from(["dedup", "--per-group-atomic"]);
+        assert!(cli.per_group_atomic);
+    }
+
+    #[test]
+    fn test_cas_action() {
+        let cli = Cli::parse_from(["dedup", "--action", "cas", "--store", "/store"]);
+        assert!(matches!(cli.action, Action::Cas));
+        assert_eq!(cli.store, Some(PathBuf::from("/store")));
+
+        assert!(Cli::try_parse_from(["dedup", "--action", "cas"]).is_err());
     }
 
-    // Combine include patterns from --include and --include-file
-    let mut include_patterns = cli.include.clone();
-    if let Some(ref include_file) = cli.include_file {
-        include_patterns.extend(parse_glob_file(include_file));
+    #[test]
+    fn test_min_saving() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.min_saving, None);
+
+        let cli = Cli::parse_from(["dedup", "--min-saving", "4096"]);
+        assert_eq!(cli.min_saving, Some(4096));
     }
 
-    let files = scanner::scan_directory(
-        &cli.path,
-        cli.min_size,
-        cli.max_size,
-        &exclude_patterns,
-        &include_patterns,
-    );
-    let total_files = files.len();
+    #[test]
+    fn test_stub_action() {
+        let cli = Cli::parse_from(["dedup", "--action", "stub"]);
+        assert!(matches!(cli.action, Action::Stub));
+        assert_eq!(cli.stub_extension, "dedup-stub");
 
-    if let Some(sp) = scan_spinner {
-        sp.finish_and_clear();
+        let cli = Cli::parse_from(["dedup", "-a", "stub", "--stub-extension", "stub"]);
+        assert_eq!(cli.stub_extension, "stub");
     }
 
-    // Stage 2: Group by size to find potential duplicates
-    let size_groups = grouping::group_by_size(files);
-    let candidate_count: usize = size_groups.iter().map(|g| g.len()).sum();
+    #[test]
+    fn test_exec_per_group() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.exec_per_group, None);
 
-    // Stage 3 & 4: Process each size group through partial hash -> full hash pipeline
-    let progress_bar = if show_progress && candidate_count > 0 {
-        let pb = ProgressBar::new(candidate_count as u64);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} files compared")
-                .unwrap()
-                .progress_chars("#>-"),
-        );
-        Some(pb)
-    } else {
-        None
-    };
+        let cli = Cli::parse_from(["dedup", "--exec-per-group", "echo {files}"]);
+        assert_eq!(cli.exec_per_group.as_deref(), Some("echo {files}"));
+    }
 
-    let processed = AtomicUsize::new(0);
+    #[test]
+    fn test_force() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert!(!cli.force);
 
-    let duplicate_groups: hasher::HashGroups = size_groups
-        .into_par_iter()
-        .flat_map(|size_group| {
-            let group_size = size_group.len();
-            let partial_groups = hasher::group_by_partial_hash(size_group);
+        let cli = Cli::parse_from(["dedup", "--action", "hardlink", "--force"]);
+        assert!(cli.force);
+    }
 
-            let final_groups = partial_groups
-                .into_par_iter()
-                .flat_map(hasher::group_by_full_hash);
+    #[test]
+    fn test_on_protected_keeper() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.on_protected_keeper, actions::ProtectedKeeper::NextBest);
 
-            if let Some(ref pb) = progress_bar {
-                let prev = processed.fetch_add(group_size, Ordering::Relaxed);
-                pb.set_position((prev + group_size) as u64);
-            }
+        let cli = Cli::parse_from(["dedup", "--on-protected-keeper", "skip"]);
+        assert_eq!(cli.on_protected_keeper, actions::ProtectedKeeper::Skip);
 
-            final_groups
-        })
-        .collect();
+        let cli = Cli::parse_from(["dedup", "--on-protected-keeper", "fail"]);
+        assert_eq!(cli.on_protected_keeper, actions::ProtectedKeeper::Fail);
+    }
 
-    let report = output::DuplicateReport::from_groups(duplicate_groups, total_files);
+    #[test]
+    fn test_merge_command() {
+        let cli = Cli::parse_from(["dedup", "merge", "/old-drive", "/archive", "--dry-run"]);
+        match cli.command {
+            Some(Command::Merge {
+                source,
+                destination,
+                link,
+                dry_run,
+                ..
+            }) => {
+                assert_eq!(source, PathBuf::from("/old-drive"));
+                assert_eq!(destination, PathBuf::from("/archive"));
+                assert!(!link);
+                assert!(dry_run);
+            }
+            _ => panic!("expected merge"),
+        }
 
-    if let Some(pb) = progress_bar {
-        pb.finish_and_clear();
+        assert!(Cli::try_parse_from(["dedup", "merge", "/old-drive"]).is_err());
     }
 
-    match cli.format {
-        OutputFormat::Human => report.print_human(cli.verbose),
-        OutputFormat::Json => report.print_json(),
-        OutputFormat::Quiet => {}
+    #[test]
+    fn test_cache_command() {
+        let cli = Cli::parse_from([
+            "dedup",
+            "cache",
+            "prune",
+            "hashes.sqlite",
+            "--max-entries",
+            "1000",
+        ]);
+        match cli.command {
+            Some(Command::Cache {
+                command: CacheCommand::Prune { cache, max_entries },
+            }) => {
+                assert_eq!(cache, PathBuf::from("hashes.sqlite"));
+                assert_eq!(max_entries, Some(1000));
+            }
+            _ => panic!("expected cache prune"),
+        }
+
+        for command in ["stats", "clear", "verify"] {
+            assert!(Cli::try_parse_from(["dedup", "cache", command, "hashes.sqlite"]).is_ok());
+        }
+        assert!(Cli::try_parse_from(["dedup", "cache", "stats"]).is_err());
+        assert!(Cli::try_parse_from(["dedup", "cache", "compact", "hashes.sqlite"]).is_err());
     }
 
-    match cli.action {
-        Action::None => {}
-        Action::ReportExitCode => {
-            if !report.groups.is_empty() {
-                std::process::exit(1);
+    #[test]
+    fn test_merge_reports_command() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert!(cli.command.is_none());
+
+        let cli = Cli::parse_from(["dedup", "merge-reports", "a.json", "b.json", "-o", "m.json"]);
+        match cli.command {
+            Some(Command::MergeReports { reports }) => {
+                assert_eq!(reports, [PathBuf::from("a.json"), PathBuf::from("b.json")])
             }
+            _ => panic!("expected merge-reports"),
         }
-        Action::Hardlink => {
-            let result =
-                actions::hardlink_duplicates(&report.groups, cli.dry_run, cli.verbose && !quiet);
+        assert_eq!(cli.output, Some(PathBuf::from("m.json")));
 
-            if human {
-                if cli.dry_run {
-                    println!(
-                        "\n[dry-run] Would link {} files, saving {}",
-                        result.files_linked,
-                        util::format_bytes(result.bytes_saved)
-                    );
-                } else {
-                    println!(
-                        "\nLinked {} files, saved {}",
-                        result.files_linked,
-                        util::format_bytes(result.bytes_saved)
-                    );
-                }
+        assert!(Cli::try_parse_from(["dedup", "merge-reports"]).is_err());
+        let invalid = Cli::try_parse_from(["dedup", "--dry-run", "merge-reports", "a.json"])
+            .map_err(|e| e.to_string())
+            .and_then(|cli| cli.validate().map_err(|e| e.to_string()));
+        assert!(invalid.is_err());
+    }
 
-                if !result.errors.is_empty() {
-                    eprintln!("\nErrors ({}):", result.errors.len());
-                    for (path, err) in &result.errors {
-                        eprintln!("  {}: {}", path.display(), err);
-                    }
-                }
+    #[test]
+    fn test_diff_command() {
+        let cli = Cli::parse_from(["dedup", "diff", "old.json", "new.json", "--json"]);
+        match cli.command {
+            Some(Command::Diff { old, new, json }) => {
+                assert_eq!(old, PathBuf::from("old.json"));
+                assert_eq!(new, PathBuf::from("new.json"));
+                assert!(json);
             }
+            _ => panic!("expected diff"),
         }
+
+        assert!(Cli::try_parse_from(["dedup", "diff", "old.json"]).is_err());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use clap::CommandFactory;
+    #[test]
+    fn test_output_file() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.output, None);
+
+        let cli = Cli::parse_from(["dedup", "-f", "json", "-o", "report.json"]);
+        assert_eq!(cli.output, Some(PathBuf::from("report.json")));
+    }
 
     #[test]
-    fn verify_cli_config() {
-        Cli::command().debug_assert();
+    fn test_exec_per_duplicate() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.exec_per_duplicate, None);
+
+        let cli = Cli::parse_from(["dedup", "--exec-per-duplicate", "rm \"$DEDUP_DUPLICATE\""]);
+        assert_eq!(
+            cli.exec_per_duplicate.as_deref(),
+            Some("rm \"$DEDUP_DUPLICATE\"")
+        );
     }
 
     #[test]
-    fn test_default_values() {
+    fn test_expect_file_count() {
         let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.expect_min_files, None);
+        assert_eq!(cli.expect_max_files, None);
 
-        assert_eq!(cli.path, PathBuf::from("."));
-        assert!(matches!(cli.format, OutputFormat::Human));
-        assert!(matches!(cli.action, Action::None));
-        assert_eq!(cli.min_size, None);
-        assert!(!cli.dry_run);
-        assert!(!cli.verbose);
-        assert!(!cli.no_progress);
+        let cli = Cli::parse_from([
+            "dedup",
+            "--expect-min-files",
+            "100",
+            "--expect-max-files",
+            "5000",
+        ]);
+        assert_eq!(cli.expect_min_files, Some(100));
+        assert_eq!(cli.expect_max_files, Some(5000));
     }
 
     #[test]
-    fn test_report_exit_code_action() {
-        let cli = Cli::parse_from(["dedup", "--action", "report-exit-code"]);
-        assert!(matches!(cli.action, Action::ReportExitCode));
+    fn test_check_file_count() {
+        assert!(check_file_count(10, None, None).is_ok());
+        assert!(check_file_count(10, Some(10), Some(10)).is_ok());
+        assert!(check_file_count(9, Some(10), None).is_err());
+        assert!(check_file_count(11, None, Some(10)).is_err());
     }
 
     #[test]
-    fn test_verbose_flag() {
-        let cli = Cli::parse_from(["dedup", "--verbose"]);
-        assert!(cli.verbose);
+    fn test_include_empty_in_actions() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert!(!cli.include_empty_in_actions);
 
-        let cli = Cli::parse_from(["dedup", "-v"]);
-        assert!(cli.verbose);
+        let cli = Cli::parse_from(["dedup", "-a", "hardlink", "--include-empty-in-actions"]);
+        assert!(cli.include_empty_in_actions);
     }
 
     #[test]
-    fn test_no_progress_flag() {
-        let cli = Cli::parse_from(["dedup", "--no-progress"]);
-        assert!(cli.no_progress);
+    fn test_confirm_strategy() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.confirm, confirm::Strategy::FullHash);
+
+        let cli = Cli::parse_from(["dedup", "--confirm", "byte-compare"]);
+        assert_eq!(cli.confirm, confirm::Strategy::ByteCompare);
+
+        let cli = Cli::parse_from(["dedup", "--confirm", "normalized-hash"]);
+        assert_eq!(cli.confirm, confirm::Strategy::NormalizedHash);
     }
 
     #[test]
-    fn test_custom_path() {
-        let cli = Cli::parse_from(["dedup", "/some/path"]);
-        assert_eq!(cli.path, PathBuf::from("/some/path"));
+    fn test_io_mode() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.io_mode, iomode::IoMode::Cached);
+
+        let cli = Cli::parse_from(["dedup", "--io-mode", "dontneed"]);
+        assert_eq!(cli.io_mode, iomode::IoMode::Dontneed);
+
+        let cli = Cli::parse_from(["dedup", "--io-mode", "direct"]);
+        assert_eq!(cli.io_mode, iomode::IoMode::Direct);
     }
 
     #[test]
-    fn test_json_format() {
-        let cli = Cli::parse_from(["dedup", "--format", "json"]);
-        assert!(matches!(cli.format, OutputFormat::Json));
+    fn test_io_workers() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.io_workers, None);
+
+        let cli = Cli::parse_from(["dedup", "--io-workers", "2", "-j", "16"]);
+        assert_eq!(cli.io_workers, Some(2));
+
+        assert!(Cli::try_parse_from(["dedup", "--io-workers", "0"]).is_err());
+        assert!(Cli::try_parse_from(["dedup", "--io-workers", "1", "--mmap"]).is_err());
     }
 
     #[test]
-    fn test_quiet_format() {
-        let cli = Cli::parse_from(["dedup", "--format", "quiet"]);
-        assert!(matches!(cli.format, OutputFormat::Quiet));
+    fn test_parse_bandwidth() {
+        assert_eq!(parse_bandwidth("50MB/s"), Ok(50 * 1024 * 1024));
+        assert_eq!(parse_bandwidth("50mb/s"), Ok(50 * 1024 * 1024));
+        assert_eq!(parse_bandwidth("512K"), Ok(512 * 1024));
+        assert_eq!(parse_bandwidth("1.5GiB"), Ok(3 * 512 * 1024 * 1024));
+        assert_eq!(parse_bandwidth("4096"), Ok(4096));
+        assert_eq!(parse_bandwidth("100B/s"), Ok(100));
+        for invalid in ["", "MB/s", "0", "-5M", "fast", "5T"] {
+            assert!(parse_bandwidth(invalid).is_err(), "{}", invalid);
+        }
+
+        let cli = Cli::parse_from(["dedup", "--max-bandwidth", "50MB/s"]);
+        assert_eq!(cli.max_bandwidth, Some(50 * 1024 * 1024));
     }
 
     #[test]
-    fn test_short_format_flag() {
-        let cli = Cli::parse_from(["dedup", "-f", "json"]);
-        assert!(matches!(cli.format, OutputFormat::Json));
+    fn test_max_memory_flag() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.max_memory, None);
+
+        let cli = Cli::parse_from(["dedup", "--max-memory", "2G"]);
+        assert_eq!(cli.max_memory, Some(2 << 30));
+
+        for invalid in ["512K", "lots", "0"] {
+            assert!(Cli::try_parse_from(["dedup", "--max-memory", invalid]).is_err());
+        }
+        for conflicting in ["--isolate", "--scan-archives", "--estimate"] {
+            assert!(
+                Cli::try_parse_from(["dedup", "--max-memory", "1G", conflicting]).is_err(),
+                "{}",
+                conflicting
+            );
+        }
     }
 
     #[test]
-    fn test_hardlink_action() {
-        let cli = Cli::parse_from(["dedup", "--action", "hardlink"]);
-        assert!(matches!(cli.action, Action::Hardlink));
+    fn test_find_contained_flag() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert!(!cli.find_contained);
+
+        let cli = Cli::parse_from(["dedup", "--find-contained"]);
+        assert!(cli.find_contained);
+
+        assert!(Cli::try_parse_from(["dedup", "--find-contained", "--max-memory", "1G"]).is_err());
     }
 
     #[test]
-    fn test_min_size() {
-        let cli = Cli::parse_from(["dedup", "--min-size", "1024"]);
-        assert_eq!(cli.min_size, Some(1024));
+    fn test_mmap_flag() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert!(!cli.mmap);
+
+        let cli = Cli::parse_from(["dedup", "--mmap"]);
+        assert!(cli.mmap);
     }
 
     #[test]
-    fn test_short_min_size() {
-        let cli = Cli::parse_from(["dedup", "-s", "4096"]);
-        assert_eq!(cli.min_size, Some(4096));
+    fn test_paranoid_flag() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert!(!cli.paranoid);
+
+        let cli = Cli::parse_from(["dedup", "--paranoid"]);
+        assert!(cli.paranoid);
+
+        assert!(Cli::try_parse_from(["dedup", "--paranoid", "--scan-archives"]).is_err());
     }
 
     #[test]
-    fn test_max_size() {
-        let cli = Cli::parse_from(["dedup", "--max-size", "1048576"]);
-        assert_eq!(cli.max_size, Some(1048576));
+    fn test_match_mode() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.match_mode, grouping::MatchMode::Content);
+
+        let cli = Cli::parse_from(["dedup", "--match", "name", "--ignore-case"]);
+        assert_eq!(cli.match_mode, grouping::MatchMode::Name);
+        assert!(cli.ignore_case);
+
+        let result =
+            Cli::try_parse_from(["dedup", "--match", "name", "--same-relative-path", "/m"]);
+        assert!(result.is_err());
     }
 
     #[test]
-    fn test_short_max_size() {
-        let cli = Cli::parse_from(["dedup", "-S", "2048"]);
-        assert_eq!(cli.max_size, Some(2048));
+    fn test_skip_header_bytes() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.skip_header_bytes, 0);
+
+        let cli = Cli::parse_from(["dedup", "--skip-header-bytes", "512"]);
+        assert_eq!(cli.skip_header_bytes, 512);
     }
 
     #[test]
@@ -371,7 +2945,7 @@ mod tests {
             "--dry-run",
         ]);
 
-        assert_eq!(cli.path, PathBuf::from("/home/user/photos"));
+        assert_eq!(cli.paths, [PathBuf::from("/home/user/photos")]);
         assert!(matches!(cli.format, OutputFormat::Json));
         assert!(matches!(cli.action, Action::Hardlink));
         assert_eq!(cli.min_size, Some(100));
@@ -385,6 +2959,29 @@ mod tests {
 
         let cli = Cli::parse_from(["dedup", "-j", "2"]);
         assert_eq!(cli.jobs, Some(2));
+
+        let cli = Cli::parse_from(["dedup", "--threads", "3"]);
+        assert_eq!(cli.jobs, Some(3));
+    }
+
+    #[test]
+    fn test_nice_flag() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert!(!cli.nice);
+
+        let cli = Cli::parse_from(["dedup", "--nice"]);
+        assert!(cli.nice);
+        assert!(!cli.background);
+    }
+
+    #[test]
+    fn test_background_flag() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert!(!cli.background);
+
+        let cli = Cli::parse_from(["dedup", "--background", "-j", "2"]);
+        assert!(cli.background);
+        assert_eq!(cli.jobs, Some(2));
     }
 
     #[test]
@@ -402,6 +2999,31 @@ mod tests {
         assert_eq!(cli.exclude_file, Some(PathBuf::from(".gitignore")));
     }
 
+    #[test]
+    fn test_ignore_case_flag() {
+        assert!(!Cli::parse_from(["dedup"]).ignore_case);
+        assert!(Cli::parse_from(["dedup", "--ignore-case"]).ignore_case);
+    }
+
+    #[test]
+    fn test_no_dedupignore_flag() {
+        assert!(!Cli::parse_from(["dedup"]).no_dedupignore);
+        assert!(Cli::parse_from(["dedup", "--no-dedupignore"]).no_dedupignore);
+    }
+
+    #[test]
+    fn test_scan_archives_flag() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert!(!cli.scan_archives);
+
+        let cli = Cli::parse_from(["dedup", "--scan-archives"]);
+        assert!(cli.scan_archives);
+
+        let result =
+            Cli::try_parse_from(["dedup", "--scan-archives", "--same-relative-path", "/m"]);
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_parse_glob_file() {
         use std::io::Write;
@@ -434,9 +3056,170 @@ mod tests {
         assert_eq!(cli.include, vec!["*.txt", "*.rs"]);
     }
 
+    #[test]
+    fn test_ext_flag() {
+        let cli = Cli::parse_from(["dedup", "--ext", "jpg,png", "--ext", "mp4"]);
+        assert_eq!(cli.extensions, ["jpg", "png", "mp4"]);
+    }
+
+    #[test]
+    fn test_extension_globs() {
+        let globs = extension_globs(&["jpg".to_string(), ".Tar.gz".to_string(), "".to_string()]);
+        assert_eq!(globs, ["*.[jJ][pP][gG]", "*.[tT][aA][rR].[gG][zZ]"]);
+
+        let set = globset::Glob::new(&extension_globs(&["mp4".to_string()])[0])
+            .unwrap()
+            .compile_matcher();
+        assert!(set.is_match("clip.MP4"));
+        assert!(set.is_match("clip.mp4"));
+        assert!(!set.is_match("clip.mp3"));
+    }
+
     #[test]
     fn test_include_file_flag() {
         let cli = Cli::parse_from(["dedup", "--include-file", "include.txt"]);
         assert_eq!(cli.include_file, Some(PathBuf::from("include.txt")));
     }
+
+    #[test]
+    fn test_files_from_flag() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.files_from, None);
+        assert!(!cli.null);
+
+        let cli = Cli::parse_from(["dedup", "--files-from", "-", "-0"]);
+        assert_eq!(cli.files_from, Some(PathBuf::from("-")));
+        assert!(cli.null);
+
+        assert!(Cli::try_parse_from(["dedup", "-0"]).is_err());
+        assert!(Cli::try_parse_from(["dedup", "/data", "--files-from", "list"]).is_err());
+    }
+
+    #[test]
+    fn test_skip_hidden_flag() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert!(!cli.skip_hidden);
+
+        let cli = Cli::parse_from(["dedup", "--skip-hidden"]);
+        assert!(cli.skip_hidden);
+    }
+
+    #[test]
+    fn test_error_log_flag() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.error_log, None);
+
+        let cli = Cli::parse_from(["dedup", "--error-log", "errors.jsonl"]);
+        assert_eq!(cli.error_log, Some(PathBuf::from("errors.jsonl")));
+    }
+
+    #[test]
+    fn test_skip_hardlinked_flag() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert!(!cli.skip_hardlinked);
+
+        let cli = Cli::parse_from(["dedup", "--skip-hardlinked"]);
+        assert!(cli.skip_hardlinked);
+    }
+
+    #[test]
+    fn test_owner_and_group_filters() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.owner, None);
+        assert_eq!(cli.group, None);
+
+        let cli = Cli::parse_from(["dedup", "--owner", "alice", "--group", "100"]);
+        assert_eq!(cli.owner.as_deref(), Some("alice"));
+        assert_eq!(cli.group.as_deref(), Some("100"));
+    }
+
+    #[test]
+    fn test_modification_time_filters() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.newer_than, None);
+        assert_eq!(cli.older_than, None);
+
+        let cli = Cli::parse_from(["dedup", "--newer-than", "30d", "--older-than", "2h"]);
+        assert_eq!(
+            cli.newer_than,
+            Some(timespec::TimeSpec::Ago(Duration::from_secs(30 * 86400)))
+        );
+        assert_eq!(
+            cli.older_than,
+            Some(timespec::TimeSpec::Ago(Duration::from_secs(2 * 3600)))
+        );
+
+        assert!(Cli::try_parse_from(["dedup", "--older-than", "soon"]).is_err());
+    }
+
+    #[test]
+    fn test_cross_dir_only_flag() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert!(!cli.cross_dir_only);
+
+        let cli = Cli::parse_from(["dedup", "--cross-dir-only"]);
+        assert!(cli.cross_dir_only);
+    }
+
+    #[test]
+    fn test_hash_algorithm() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert_eq!(cli.hash_algorithm, hasher::Algorithm::Blake3);
+
+        let cli = Cli::parse_from(["dedup", "--hash", "xxh3"]);
+        assert_eq!(cli.hash_algorithm, hasher::Algorithm::Xxh3);
+
+        let cli = Cli::parse_from(["dedup", "--hash", "sha256"]);
+        assert_eq!(cli.hash_algorithm, hasher::Algorithm::Sha256);
+
+        assert!(Cli::try_parse_from(["dedup", "--hash", "md5"]).is_err());
+    }
+
+    #[test]
+    fn test_isolate_flag() {
+        let cli = Cli::parse_from(["dedup", "--isolate", "/a", "/b"]);
+        assert!(cli.isolate);
+
+        let result = Cli::try_parse_from(["dedup", "--isolate", "--compare-to", "/ref", "/a"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_compare_to_flag() {
+        let cli = Cli::parse_from(["dedup", "--compare-to", "/archive", "/media/card"]);
+        assert_eq!(cli.compare_to, Some(PathBuf::from("/archive")));
+        assert_eq!(cli.paths, vec![PathBuf::from("/media/card")]);
+
+        let result = Cli::try_parse_from([
+            "dedup",
+            "--compare-to",
+            "/archive",
+            "--same-relative-path",
+            "/mirror",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_same_dir_only_flag() {
+        let cli = Cli::parse_from(["dedup", "--same-dir-only"]);
+        assert!(cli.same_dir_only);
+
+        let result = Cli::try_parse_from(["dedup", "--same-dir-only", "--cross-dir-only"]);
+        assert!(result.is_err());
+
+        // Splitting by directory would separate the reference files from the copies
+        let result =
+            Cli::try_parse_from(["dedup", "--same-dir-only", "--compare-to", "/ref", "/a"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_skip_common_boilerplate_flag() {
+        let cli = Cli::parse_from(["dedup"]);
+        assert!(!cli.skip_common_boilerplate);
+
+        let cli = Cli::parse_from(["dedup", "--skip-common-boilerplate"]);
+        assert!(cli.skip_common_boilerplate);
+    }
 }
@@ -0,0 +1,340 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::hasher::{Algorithm, Digest};
+use crate::output::{DuplicateGroup, DuplicateReport, group_id};
+use crate::scanner::{FileEntry, ScanError, TreeStats};
+use crate::schema::SCHEMA_VERSION;
+
+/// JSON report as written by `--format json`, only the fields needed to merge or diff it
+#[derive(Debug, Deserialize)]
+pub(crate) struct ReportFile {
+    /// Missing in reports written before the field was introduced
+    #[serde(default)]
+    schema_version: Option<u32>,
+    /// Missing in reports written before hashes could be computed with other algorithms
+    #[serde(default)]
+    pub(crate) hash_algorithm: Algorithm,
+    stats: StatsFile,
+    #[serde(default)]
+    partial: bool,
+    #[serde(default)]
+    page: Option<serde_json::Value>,
+    #[serde(default)]
+    estimate: Option<serde_json::Value>,
+    #[serde(default)]
+    errors: Vec<ScanError>,
+    pub(crate) groups: Vec<GroupFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatsFile {
+    total_files: usize,
+    #[serde(default)]
+    already_deduplicated: usize,
+    #[serde(default)]
+    already_linked_bytes: u64,
+    #[serde(default)]
+    vanished_files: usize,
+    #[serde(default, flatten)]
+    tree: TreeStats,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct GroupFile {
+    pub(crate) hash: String,
+    pub(crate) size: u64,
+    pub(crate) files: Vec<PathBuf>,
+    #[serde(default)]
+    devices: Vec<u64>,
+    #[serde(default)]
+    omitted_files: usize,
+}
+
+/// Files and devices of one content hash, collected across reports
+#[derive(Debug, Default)]
+struct MergedGroup {
+    size: u64,
+    files: BTreeSet<PathBuf>,
+    devices: BTreeSet<u64>,
+    omitted_files: usize,
+}
+
+/// Read JSON reports and merge them into one.
+///
+/// Groups with the same content hash are combined, paths listed in more than one report
+/// are only counted once. The total number of scanned files is the sum over all reports.
+pub fn merge_report_files(paths: &[PathBuf]) -> Result<DuplicateReport, String> {
+    let reports = paths
+        .iter()
+        .map(|path| read_report(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    merge_reports(reports)
+}
+
+pub(crate) fn read_report(path: &Path) -> Result<ReportFile, String> {
+    let contents =
+        fs::read(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let report: ReportFile = serde_json::from_slice(&contents)
+        .map_err(|e| format!("{} is not a JSON report: {}", path.display(), e))?;
+    if let Some(version) = report.schema_version
+        && version > SCHEMA_VERSION
+    {
+        return Err(format!(
+            "{} has schema version {}, this version of dedup only reads up to {}",
+            path.display(),
+            version,
+            SCHEMA_VERSION
+        ));
+    }
+    if report.page.is_some() {
+        return Err(format!(
+            "{} only contains a page of a report, use reports written without --page-size",
+            path.display()
+        ));
+    }
+    if report.estimate.is_some() {
+        return Err(format!(
+            "{} only covers a sample of the candidates, use reports written without --estimate",
+            path.display()
+        ));
+    }
+    Ok(report)
+}
+
+fn merge_reports(reports: Vec<ReportFile>) -> Result<DuplicateReport, String> {
+    let mut total_files = 0;
+    let mut already_deduplicated = 0;
+    let mut already_linked_bytes = 0;
+    let mut vanished_files = 0;
+    let mut tree = TreeStats::default();
+    let mut errors = Vec::new();
+    let mut partial = false;
+    let mut merged: HashMap<Digest, MergedGroup> = HashMap::new();
+    let hash_algorithm = reports
+        .first()
+        .map_or_else(Default::default, |report| report.hash_algorithm);
+
+    for report in reports {
+        if report.hash_algorithm != hash_algorithm {
+            return Err(
+                "the reports were hashed with different algorithms (--hash), their groups can't be matched"
+                    .to_string(),
+            );
+        }
+        total_files += report.stats.total_files;
+        already_deduplicated += report.stats.already_deduplicated;
+        already_linked_bytes += report.stats.already_linked_bytes;
+        vanished_files += report.stats.vanished_files;
+        tree.symlinks += report.stats.tree.symlinks;
+        tree.unique_inodes += report.stats.tree.unique_inodes;
+        tree.hardlinked_inodes += report.stats.tree.hardlinked_inodes;
+        errors.extend(report.errors);
+        partial |= report.partial;
+
+        for group in report.groups {
+            let hash = Digest::from_hex(&group.hash)
+                .ok_or_else(|| format!("invalid hash {}", group.hash))?;
+
+            let entry = merged.entry(hash).or_insert_with(|| MergedGroup {
+                size: group.size,
+                ..Default::default()
+            });
+            if entry.size != group.size {
+                return Err(format!(
+                    "groups with hash {} have different sizes ({} and {} bytes)",
+                    group.hash, entry.size, group.size
+                ));
+            }
+            entry.files.extend(group.files);
+            entry.devices.extend(group.devices);
+            entry.omitted_files += group.omitted_files;
+        }
+    }
+
+    let groups = merged
+        .into_iter()
+        .map(|(hash, group)| {
+            let files = group
+                .files
+                .into_iter()
+                .map(|path| FileEntry {
+                    path,
+                    size: group.size,
+                    ..Default::default()
+                })
+                .collect();
+            DuplicateGroup {
+                id: group_id(&hash),
+                hash,
+                size: group.size,
+                files,
+                cross_filesystem: group.devices.len() > 1,
+                devices: group.devices.into_iter().collect(),
+                omitted_files: group.omitted_files,
+                // Allocated sizes are not in reports
+                allocated_wasted_bytes: None,
+            }
+        })
+        .collect();
+
+    let mut report = DuplicateReport::from_duplicate_groups(groups, total_files);
+    report.hash_algorithm = hash_algorithm;
+    report.stats.already_deduplicated = already_deduplicated;
+    report.stats.already_linked_bytes = already_linked_bytes;
+    report.stats.vanished_files = vanished_files;
+    report.stats.tree = tree;
+    report.errors = errors;
+    report.partial = partial;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::hash_bytes;
+
+    fn report(json: &str) -> ReportFile {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn group_json(hash: &[u8], size: u64, files: &[&str], devices: &[u64]) -> String {
+        serde_json::json!({
+            "hash": hash_bytes(hash).to_hex().as_str(),
+            "size": size,
+            "files": files,
+            "devices": devices,
+        })
+        .to_string()
+    }
+
+    fn report_json(total_files: usize, groups: &[String]) -> String {
+        format!(
+            r#"{{"stats": {{"total_files": {}, "duplicate_files": 0, "wasted_bytes": 0}}, "groups": [{}]}}"#,
+            total_files,
+            groups.join(",")
+        )
+    }
+
+    #[test]
+    fn test_merge_combines_groups_by_hash() {
+        let a = report(&report_json(
+            10,
+            &[
+                group_json(b"a", 5, &["/x/a1", "/x/a2"], &[1]),
+                group_json(b"b", 7, &["/x/b1", "/x/b2"], &[1]),
+            ],
+        ));
+        let b = report(&report_json(
+            20,
+            &[group_json(b"a", 5, &["/y/a1", "/y/a2"], &[2])],
+        ));
+
+        let merged = merge_reports(vec![a, b]).unwrap();
+
+        assert_eq!(merged.stats.total_files, 30);
+        assert_eq!(merged.stats.duplicate_files, 6);
+        assert_eq!(merged.stats.wasted_bytes, 5 * 3 + 7);
+        assert_eq!(merged.groups.len(), 2);
+
+        let group = &merged.groups[0];
+        assert_eq!(group.hash, hash_bytes(b"a"));
+        let paths: Vec<_> = group.files.iter().map(|f| f.path.clone()).collect();
+        assert_eq!(
+            paths,
+            ["/x/a1", "/x/a2", "/y/a1", "/y/a2"].map(PathBuf::from)
+        );
+        assert_eq!(group.devices, vec![1, 2]);
+        assert!(group.cross_filesystem);
+    }
+
+    #[test]
+    fn test_merge_counts_overlapping_paths_once() {
+        let a = report(&report_json(
+            2,
+            &[group_json(b"a", 5, &["/a1", "/a2"], &[])],
+        ));
+        let b = report(&report_json(
+            2,
+            &[group_json(b"a", 5, &["/a2", "/a3"], &[])],
+        ));
+
+        let merged = merge_reports(vec![a, b]).unwrap();
+
+        assert_eq!(merged.groups.len(), 1);
+        assert_eq!(merged.groups[0].files.len(), 3);
+        assert_eq!(merged.stats.duplicate_files, 3);
+    }
+
+    #[test]
+    fn test_merge_keeps_partial_flag() {
+        let complete = report(&report_json(1, &[]));
+        let partial = report(r#"{"stats": {"total_files": 1}, "partial": true, "groups": []}"#);
+
+        assert!(
+            !merge_reports(vec![report(&report_json(1, &[]))])
+                .unwrap()
+                .partial
+        );
+        assert!(merge_reports(vec![complete, partial]).unwrap().partial);
+    }
+
+    #[test]
+    fn test_merge_rejects_size_mismatch() {
+        let a = report(&report_json(
+            2,
+            &[group_json(b"a", 5, &["/a1", "/a2"], &[])],
+        ));
+        let b = report(&report_json(
+            2,
+            &[group_json(b"a", 6, &["/b1", "/b2"], &[])],
+        ));
+
+        assert!(merge_reports(vec![a, b]).is_err());
+    }
+
+    #[test]
+    fn test_merge_rejects_mixed_hash_algorithms() {
+        let a = report(&report_json(2, &[]));
+        let mut b = report(&report_json(2, &[]));
+        b.hash_algorithm = Algorithm::Sha256;
+
+        assert!(merge_reports(vec![a, b]).is_err());
+    }
+
+    #[test]
+    fn test_read_report_rejects_newer_schema() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("new.json");
+        fs::write(
+            &path,
+            format!(
+                r#"{{"schema_version": {}, "stats": {{"total_files": 0}}, "groups": []}}"#,
+                SCHEMA_VERSION + 1
+            ),
+        )
+        .unwrap();
+
+        let error = read_report(&path).unwrap_err();
+        assert!(error.contains("schema version"));
+    }
+
+    #[test]
+    fn test_read_report_rejects_pages() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("page.json");
+        fs::write(
+            &path,
+            r#"{"stats": {"total_files": 0, "duplicate_files": 0, "wasted_bytes": 0},
+                "page": {"number": 1, "size": 1, "total_pages": 1, "total_groups": 0},
+                "groups": []}"#,
+        )
+        .unwrap();
+
+        let error = read_report(&path).unwrap_err();
+        assert!(error.contains("--page-size"));
+    }
+}
@@ -1,10 +1,23 @@
-use std::fs;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
 
+use clap::ValueEnum;
 use colored::Colorize;
-use serde::Serialize;
+use serde::{Serialize, Serializer};
 
-use crate::hasher::HashGroup;
+use crate::actions::select_original;
+use crate::contained::ContainedFile;
+use crate::estimate::Estimate;
+use crate::hasher::{self, Algorithm, Digest, HashGroup};
+use crate::i18n::{Messages, fill};
+use crate::profile::IoProfile;
+use crate::scanner::{FileEntry, ScanError, TreeStats};
+use crate::schema::SCHEMA_VERSION;
+use crate::template::Template;
 use crate::util::{format_bytes, format_number};
 
 /// Statistics about duplicate files found
@@ -16,91 +29,752 @@ pub struct DuplicateStats {
     pub duplicate_files: usize,
     /// Total wasted space in bytes (could be reclaimed)
     pub wasted_bytes: u64,
+    /// Disk space that could be reclaimed, less than `wasted_bytes` for sparse or hardlinked
+    /// files. Unknown in merged reports.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allocated_wasted_bytes: Option<u64>,
+    /// Groups left out of the report because all their files are already hardlinked together
+    pub already_deduplicated: usize,
+    /// Bytes of duplicates that are already hardlinks to another copy of their group, in
+    /// listed and left out groups. Not counted in `wasted_bytes`.
+    pub already_linked_bytes: u64,
+    /// Scanned files deleted before they could be hashed, not counted in `total_files`
+    pub vanished_files: usize,
+    /// Symlinks and inodes of the scanned files
+    #[serde(flatten)]
+    pub tree: TreeStats,
+    /// Time spent in each stage of the pipeline, unset for merged reports
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub performance: Option<PerformanceStats>,
+}
+
+/// Timings and throughput of a run, to help tune `--jobs` and spot slow disks
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PerformanceStats {
+    /// Seconds spent scanning the directories
+    pub scan_secs: f64,
+    /// Seconds spent grouping the files by size
+    pub size_group_secs: f64,
+    /// Seconds spent on partial and sample hashes, summed over all threads
+    pub partial_hash_secs: f64,
+    /// Seconds spent confirming duplicates (full hashes by default), summed over all threads
+    pub full_hash_secs: f64,
+    /// Seconds the hashing stage took from start to end
+    pub hash_secs: f64,
+    /// Bytes read to hash or compare files
+    pub bytes_hashed: u64,
+    /// Bytes hashed per second of the hashing stage, in MB/s
+    pub throughput_mb_per_sec: f64,
+}
+
+impl PerformanceStats {
+    /// Set the throughput from the bytes hashed and the duration of the hashing stage
+    pub fn compute_throughput(&mut self) {
+        self.throughput_mb_per_sec = if self.hash_secs > 0.0 {
+            self.bytes_hashed as f64 / (1024.0 * 1024.0) / self.hash_secs
+        } else {
+            0.0
+        };
+    }
 }
 
 /// A group of duplicate files for output
 #[derive(Debug, Clone, Serialize)]
 pub struct DuplicateGroup {
+    /// Identifier derived from the content hash, the same across runs and machines
+    pub id: String,
+    /// Content hash shared by all files in this group
+    #[serde(serialize_with = "serialize_hash")]
+    pub hash: Digest,
     /// Size of each file in this group
     pub size: u64,
-    /// Paths to all duplicate files
-    pub files: Vec<PathBuf>,
+    /// All duplicate files, serialized as their paths
+    #[serde(serialize_with = "serialize_paths")]
+    pub files: Vec<FileEntry>,
+    /// Distinct devices (filesystems) the files live on
+    pub devices: Vec<u64>,
+    /// Whether the files span more than one filesystem and so can't all be hardlinked together
+    pub cross_filesystem: bool,
+    /// Number of files left out of `files` because of `--max-group-size`
+    #[serde(skip_serializing_if = "is_zero")]
+    pub omitted_files: usize,
+    /// Disk space that could be reclaimed by keeping a single copy, see
+    /// [`DuplicateGroup::allocated_wasted_bytes`]. Unknown in merged reports.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allocated_wasted_bytes: Option<u64>,
+}
+
+/// Number of hex digits of the content hash used as group id (64 bits)
+const GROUP_ID_LEN: usize = 16;
+
+/// Stable identifier of the group of files with content `hash`, e.g. for diffing reports
+/// or suppression lists. Only depends on the content, so a group keeps its id when files
+/// are added, removed or moved.
+pub fn group_id(hash: &Digest) -> String {
+    hash.to_hex()[..GROUP_ID_LEN].to_string()
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+impl DuplicateGroup {
+    /// Build a group from files with the given content hash and size
+    pub fn new(hash: Digest, size: u64, files: Vec<FileEntry>) -> Self {
+        let mut devices: Vec<u64> = files.iter().map(|f| f.dev).collect();
+        devices.sort_unstable();
+        devices.dedup();
+
+        let allocated_wasted_bytes = Some(allocated_wasted_bytes(&files));
+        Self {
+            id: group_id(&hash),
+            hash,
+            size,
+            cross_filesystem: devices.len() > 1,
+            devices,
+            files,
+            omitted_files: 0,
+            allocated_wasted_bytes,
+        }
+    }
+
+    /// Number of files in the group, including omitted ones
+    pub fn total_files(&self) -> usize {
+        self.files.len() + self.omitted_files
+    }
+
+    /// Whether all files of the group are hardlinks to the same inode, e.g. after a previous
+    /// `--action hardlink` run, so there is nothing left to reclaim
+    pub fn is_fully_linked(&self) -> bool {
+        let first = &self.files[0];
+        self.omitted_files == 0
+            && first.ino != 0
+            && self
+                .files
+                .iter()
+                .all(|f| (f.dev, f.ino) == (first.dev, first.ino))
+    }
+
+    /// Number of listed files that are hardlinks to an inode another listed file of the group
+    /// already has, so they take no space of their own. Files with an unknown inode (0) count
+    /// as distinct.
+    pub fn linked_files(&self) -> usize {
+        let mut inodes = HashSet::new();
+        self.files
+            .iter()
+            .filter(|f| f.ino != 0 && !inodes.insert((f.dev, f.ino)))
+            .count()
+    }
+
+    /// Space that could be reclaimed by keeping a single copy of the group. Hardlinks to the
+    /// same inode share their space and count once.
+    pub fn wasted_bytes(&self) -> u64 {
+        self.size * (self.total_files().saturating_sub(1 + self.linked_files())) as u64
+    }
+}
+
+/// Disk space that keeping only the copy the actions would keep frees: the blocks allocated
+/// to every other inode of the group. Sparse files count with what they actually use and
+/// hardlinks to the same inode only once.
+fn allocated_wasted_bytes(files: &[FileEntry]) -> u64 {
+    if files.is_empty() {
+        return 0;
+    }
+    let keeper = select_original(files);
+    // Inodes are unknown (0) for files that weren't scanned, they count as distinct
+    let mut inodes = HashSet::from([(keeper.dev, keeper.ino)]);
+    files
+        .iter()
+        .filter(|f| f.path != keeper.path && (f.ino == 0 || inodes.insert((f.dev, f.ino))))
+        .map(|f| f.allocated)
+        .sum()
+}
+
+/// Serialize a hash as a hex string
+fn serialize_hash<S: Serializer>(hash: &Digest, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hash.to_hex())
+}
+
+/// Serialize file entries as a plain list of paths
+fn serialize_paths<S: Serializer>(files: &[FileEntry], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(files.iter().map(|f| &f.path))
+}
+
+/// Order of the groups in a report
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SortKey {
+    /// Most reclaimable space first
+    Wasted,
+    /// Largest files first
+    Size,
+    /// Most files first
+    Count,
+    /// By the first path of each group
+    #[default]
+    Path,
+}
+
+/// Duplicates of the report that live in one directory
+#[derive(Debug, Clone, Serialize)]
+pub struct DirectoryWaste {
+    pub path: PathBuf,
+    /// Duplicates directly in the directory, not counting the copies that are kept
+    pub duplicate_files: usize,
+    /// Space taken by these duplicates
+    pub wasted_bytes: u64,
+}
+
+/// Position of a page of groups within the full report
+#[derive(Debug, Clone, Serialize)]
+pub struct PageInfo {
+    /// 1-based page number
+    pub number: usize,
+    /// Maximum number of groups per page
+    pub size: usize,
+    /// Number of pages in the full report
+    pub total_pages: usize,
+    /// Number of groups in the full report
+    pub total_groups: usize,
 }
 
 /// Complete report of duplicate findings
 #[derive(Debug, Clone, Serialize)]
 pub struct DuplicateReport {
+    /// Version of the JSON format, see [`SCHEMA_VERSION`]
+    pub schema_version: u32,
+    /// Algorithm the group hashes were computed with
+    pub hash_algorithm: Algorithm,
     pub stats: DuplicateStats,
+    /// Set when the run was interrupted, only the groups confirmed until then are listed
+    #[serde(skip_serializing_if = "is_false")]
+    pub partial: bool,
+    /// Set when only a page of the groups is included
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<PageInfo>,
+    /// Set when only a sample of the candidates was hashed (`--estimate`), the groups and
+    /// stats then only cover the sample
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimate: Option<Estimate>,
+    /// Wasted space per directory, most first, set by [`DuplicateReport::roll_up_directories`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub directories: Option<Vec<DirectoryWaste>>,
+    /// The files and directories that took the longest to hash (`--profile-io`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub io_profile: Option<IoProfile>,
+    /// Files whose contents are the start of larger files (`--find-contained`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contained: Option<Vec<ContainedFile>>,
+    /// Files and directories that could not be scanned, duplicates in them may be missing
+    pub errors: Vec<ScanError>,
     pub groups: Vec<DuplicateGroup>,
 }
 
 impl DuplicateReport {
     /// Build a report from hash groups
     pub fn from_groups(hash_groups: Vec<HashGroup>, total_files: usize) -> Self {
-        let mut groups = Vec::with_capacity(hash_groups.len());
+        let groups = hash_groups
+            .into_iter()
+            .map(|hash_group| {
+                // Get size from first file (all files in group have same size)
+                let size = hash_group.files.first().map(|f| f.size).unwrap_or(0);
+                DuplicateGroup::new(hash_group.hash, size, hash_group.files)
+            })
+            .collect();
+
+        Self::from_duplicate_groups(groups, total_files)
+    }
+
+    /// Build a report from duplicate groups, sorting them and computing the stats.
+    /// Fully linked groups are only counted as already deduplicated.
+    pub fn from_duplicate_groups(mut groups: Vec<DuplicateGroup>, total_files: usize) -> Self {
         let mut wasted_bytes: u64 = 0;
+        let mut allocated_wasted_bytes = Some(0);
         let mut duplicate_files: usize = 0;
 
-        for hash_group in hash_groups {
-            // Get size from first file (all files in group have same size)
-            let size = hash_group
-                .first()
-                .and_then(|p| fs::metadata(p).ok())
-                .map(|m| m.len())
-                .unwrap_or(0);
+        let before = groups.len();
+        let mut already_linked_bytes = 0;
+        groups.retain(|group| {
+            already_linked_bytes += group.size * group.linked_files() as u64;
+            !group.is_fully_linked()
+        });
+        let already_deduplicated = before - groups.len();
 
-            let file_count = hash_group.len();
-            duplicate_files += file_count;
+        for group in &mut groups {
+            group.files.sort_by(|a, b| a.path.cmp(&b.path));
 
+            duplicate_files += group.total_files();
             // Wasted space = size * (count - 1), since we keep one copy
-            if file_count > 1 {
-                wasted_bytes += size * (file_count - 1) as u64;
-            }
-
-            groups.push(DuplicateGroup {
-                size,
-                files: hash_group,
-            });
+            wasted_bytes += group.wasted_bytes();
+            allocated_wasted_bytes = allocated_wasted_bytes
+                .zip(group.allocated_wasted_bytes)
+                .map(|(total, group)| total + group);
         }
 
+        // Hashing runs in parallel, sort so the output is the same across runs
+        groups.sort_by(|a, b| a.files[0].path.cmp(&b.files[0].path));
+
         let stats = DuplicateStats {
             total_files,
             duplicate_files,
             wasted_bytes,
+            allocated_wasted_bytes,
+            already_deduplicated,
+            already_linked_bytes,
+            vanished_files: 0,
+            tree: TreeStats::default(),
+            performance: None,
         };
 
-        Self { stats, groups }
+        Self {
+            schema_version: SCHEMA_VERSION,
+            hash_algorithm: hasher::algorithm(),
+            stats,
+            partial: false,
+            page: None,
+            estimate: None,
+            directories: None,
+            io_profile: None,
+            contained: None,
+            errors: Vec::new(),
+            groups,
+        }
+    }
+
+    /// Keep at most `max` files of each group, the number of left out files is recorded
+    /// in the group. Stats still cover all files.
+    pub fn limit_group_size(&mut self, max: usize) {
+        for group in &mut self.groups {
+            if group.files.len() > max {
+                group.omitted_files += group.files.len() - max;
+                group.files.truncate(max);
+            }
+        }
+    }
+
+    /// Keep only the `n` groups that waste the most space, ordered by wasted space.
+    /// Stats still cover all groups.
+    pub fn top(&mut self, n: usize) {
+        self.sort(SortKey::Wasted, false);
+        self.groups.truncate(n);
+    }
+
+    /// Sort the groups by `key`, in the opposite order if `reverse` is set.
+    /// Groups that are equal by `key` are ordered by their first path.
+    pub fn sort(&mut self, key: SortKey, reverse: bool) {
+        self.groups.sort_by(|a, b| {
+            let order = match key {
+                SortKey::Wasted => b.wasted_bytes().cmp(&a.wasted_bytes()),
+                SortKey::Size => b.size.cmp(&a.size),
+                SortKey::Count => b.total_files().cmp(&a.total_files()),
+                SortKey::Path => std::cmp::Ordering::Equal,
+            };
+            let order = order.then_with(|| a.files[0].path.cmp(&b.files[0].path));
+            if reverse { order.reverse() } else { order }
+        });
+    }
+
+    /// Copy of the report containing only the given 1-based page of groups.
+    /// Stats still describe the full report.
+    pub fn page(&self, number: usize, size: usize) -> Self {
+        let total_groups = self.groups.len();
+        let start = number
+            .saturating_sub(1)
+            .saturating_mul(size)
+            .min(total_groups);
+        let end = start.saturating_add(size).min(total_groups);
+
+        Self {
+            schema_version: self.schema_version,
+            hash_algorithm: self.hash_algorithm,
+            stats: self.stats.clone(),
+            partial: self.partial,
+            page: Some(PageInfo {
+                number,
+                size,
+                total_pages: total_groups.div_ceil(size),
+                total_groups,
+            }),
+            estimate: self.estimate.clone(),
+            directories: self.directories.clone(),
+            io_profile: self.io_profile.clone(),
+            contained: self.contained.clone(),
+            errors: self.errors.clone(),
+            groups: self.groups[start..end].to_vec(),
+        }
+    }
+
+    /// Aggregate the duplicates of the listed files per directory, so the directories that
+    /// waste the most space stand out. Every file of a group except the copy the actions
+    /// would keep counts towards its parent directory, files left out by `--max-group-size`
+    /// are not counted.
+    pub fn roll_up_directories(&mut self) {
+        let mut directories: HashMap<&Path, (usize, u64)> = HashMap::new();
+        for group in &self.groups {
+            let keeper = select_original(&group.files);
+            // Hardlinks of the kept copy or of a copy already counted free nothing
+            let mut inodes = HashSet::from([(keeper.dev, keeper.ino)]);
+            for file in group
+                .files
+                .iter()
+                .filter(|f| f.path != keeper.path && (f.ino == 0 || inodes.insert((f.dev, f.ino))))
+            {
+                let directory = file.path.parent().unwrap_or(Path::new(""));
+                let entry = directories.entry(directory).or_default();
+                entry.0 += 1;
+                entry.1 += group.size;
+            }
+        }
+
+        let mut directories: Vec<DirectoryWaste> = directories
+            .into_iter()
+            .map(|(path, (duplicate_files, wasted_bytes))| DirectoryWaste {
+                path: path.to_path_buf(),
+                duplicate_files,
+                wasted_bytes,
+            })
+            .collect();
+        directories.sort_by(|a, b| {
+            b.wasted_bytes
+                .cmp(&a.wasted_bytes)
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        self.directories = Some(directories);
+    }
+
+    /// Copy of the report with the paths made relative to the scan `root`, for reports that
+    /// are read on another machine or mount. Paths outside of `root` are kept as they are.
+    pub fn relative_to(&self, root: &Path) -> Self {
+        let relative = |path: &mut PathBuf| {
+            if let Ok(relative) = path.strip_prefix(root)
+                && !relative.as_os_str().is_empty()
+            {
+                *path = relative.to_path_buf();
+            }
+        };
+
+        let mut report = self.clone();
+        for file in report.groups.iter_mut().flat_map(|group| &mut group.files) {
+            relative(&mut file.path);
+        }
+        for error in &mut report.errors {
+            relative(&mut error.path);
+        }
+        for directory in report.directories.iter_mut().flatten() {
+            if directory.path == root {
+                directory.path = PathBuf::from(".");
+            } else {
+                relative(&mut directory.path);
+            }
+        }
+        for file in report.contained.iter_mut().flatten() {
+            relative(&mut file.path);
+            for container in &mut file.contained_in {
+                relative(&mut container.path);
+            }
+        }
+        if let Some(ref mut profile) = report.io_profile {
+            for file in &mut profile.files {
+                relative(&mut file.path);
+            }
+            for directory in &mut profile.directories {
+                if directory.path == root {
+                    directory.path = PathBuf::from(".");
+                } else {
+                    relative(&mut directory.path);
+                }
+            }
+        }
+        report
     }
 
     /// Output as human-readable colored text
-    pub fn print_human(&self, verbose: bool) {
-        println!("\n{}", "Duplicate Report".bold().underline());
-        println!(
-            "  Scanned: {} files",
-            format_number(self.stats.total_files).cyan()
-        );
-        println!(
-            "  Duplicate files: {}",
-            format_number(self.stats.duplicate_files).cyan()
-        );
-        println!(
-            "  Potential savings: {}",
-            format_bytes(self.stats.wasted_bytes).yellow()
-        );
-        println!(
+    pub fn write_human(
+        &self,
+        out: &mut impl Write,
+        verbose: bool,
+        messages: &Messages,
+    ) -> io::Result<()> {
+        writeln!(out, "\n{}", messages.report_title.bold().underline())?;
+        if self.partial {
+            writeln!(out, "  {}", messages.partial_report.yellow().bold())?;
+        }
+        if let Some(ref estimate) = self.estimate {
+            writeln!(
+                out,
+                "  {}",
+                fill(
+                    messages.estimate_title,
+                    &[
+                        &format!("{:.3}", estimate.sample_rate * 100.0)
+                            .trim_end_matches('0')
+                            .trim_end_matches('.'),
+                        &format_number(estimate.sampled_groups),
+                        &format_number(estimate.candidate_groups),
+                    ]
+                )
+                .bold()
+            )?;
+            let files = estimate.duplicate_files;
+            writeln!(
+                out,
+                "    {}",
+                fill(
+                    messages.estimate_files,
+                    &[
+                        &format_number(files.estimate as usize).cyan(),
+                        &format_number(files.low as usize),
+                        &format_number(files.high as usize),
+                    ]
+                )
+            )?;
+            let bytes = estimate.wasted_bytes;
+            writeln!(
+                out,
+                "    {}",
+                fill(
+                    messages.estimate_savings,
+                    &[
+                        &format_bytes(bytes.estimate).yellow(),
+                        &format_bytes(bytes.low),
+                        &format_bytes(bytes.high),
+                    ]
+                )
+            )?;
+        }
+        writeln!(
+            out,
+            "  {}",
+            fill(
+                messages.scanned_files,
+                &[&format_number(self.stats.total_files).cyan()]
+            )
+        )?;
+        writeln!(
+            out,
             "  {}",
-            "Real savings may vary depending on existing links between files."
+            fill(
+                messages.duplicate_files,
+                &[&format_number(self.stats.duplicate_files).cyan()]
+            )
+        )?;
+        writeln!(
+            out,
+            "  {}",
+            fill(
+                messages.potential_savings,
+                &[&format_bytes(self.stats.wasted_bytes).yellow()]
+            )
+        )?;
+        if let Some(allocated) = self.stats.allocated_wasted_bytes
+            && allocated != self.stats.wasted_bytes
+        {
+            writeln!(
+                out,
+                "  {}",
+                fill(
+                    messages.allocated_savings,
+                    &[&format_bytes(allocated).yellow()]
+                )
+            )?;
+        }
+        writeln!(
+            out,
+            "  {}",
+            messages.savings_disclaimer.bright_black().italic()
+        )?;
+
+        if self.stats.already_deduplicated > 0 {
+            writeln!(
+                out,
+                "  {}",
+                fill(
+                    messages.already_deduplicated,
+                    &[&format_number(self.stats.already_deduplicated).green()]
+                )
+            )?;
+        }
+
+        if self.stats.already_linked_bytes > 0 {
+            writeln!(
+                out,
+                "  {}",
+                fill(
+                    messages.already_linked,
+                    &[&format_bytes(self.stats.already_linked_bytes).green()]
+                )
+            )?;
+        }
+
+        if self.stats.vanished_files > 0 {
+            writeln!(
+                out,
+                "  {}",
+                fill(
+                    messages.vanished_files,
+                    &[&format_number(self.stats.vanished_files).yellow()]
+                )
+            )?;
+        }
+
+        writeln!(
+            out,
+            "  {}",
+            fill(
+                messages.tree_structure,
+                &[
+                    &format_number(self.stats.tree.unique_inodes),
+                    &format_number(self.stats.tree.hardlinked_inodes),
+                    &format_number(self.stats.tree.symlinks),
+                ]
+            )
+            .bright_black()
+        )?;
+
+        if !self.errors.is_empty() {
+            writeln!(
+                out,
+                "  {}",
+                fill(
+                    messages.scan_errors,
+                    &[&format_number(self.errors.len()).red()]
+                )
+            )?;
+            if verbose {
+                for error in &self.errors {
+                    writeln!(out, "    {}: {}", error.path.display(), error.error)?;
+                }
+            }
+        }
+
+        if verbose && let Some(ref performance) = self.stats.performance {
+            writeln!(
+                out,
+                "  {}",
+                fill(
+                    messages.performance,
+                    &[
+                        &format!("{:.2}", performance.scan_secs),
+                        &format!("{:.2}", performance.size_group_secs),
+                        &format!("{:.2}", performance.partial_hash_secs),
+                        &format!("{:.2}", performance.full_hash_secs),
+                        &format_bytes(performance.bytes_hashed),
+                        &format!("{:.2}", performance.hash_secs),
+                        &format!("{:.1}", performance.throughput_mb_per_sec),
+                    ]
+                )
                 .bright_black()
-                .italic()
-        );
+            )?;
+        }
+
+        let cross_filesystem = self.groups.iter().filter(|g| g.cross_filesystem).count();
+        if cross_filesystem > 0 {
+            writeln!(
+                out,
+                "  {}",
+                fill(
+                    messages.cross_filesystem_groups,
+                    &[&format_number(cross_filesystem).red()]
+                )
+            )?;
+        }
+
+        if let Some(ref directories) = self.directories
+            && !directories.is_empty()
+        {
+            writeln!(out, "\n{}", messages.directories_title.bold())?;
+            for directory in directories {
+                writeln!(
+                    out,
+                    "  {}",
+                    fill(
+                        messages.directory_waste,
+                        &[
+                            &format_bytes(directory.wasted_bytes).yellow(),
+                            &format_number(directory.duplicate_files),
+                            &directory.path.display()
+                        ]
+                    )
+                )?;
+            }
+        }
+
+        if let Some(ref profile) = self.io_profile {
+            if !profile.files.is_empty() {
+                writeln!(out, "\n{}", messages.slowest_files_title.bold())?;
+            }
+            for file in &profile.files {
+                writeln!(
+                    out,
+                    "  {}",
+                    fill(
+                        messages.file_hot_spot,
+                        &[
+                            &format!("{:.2}", file.secs).yellow(),
+                            &format_bytes(file.bytes),
+                            &format!("{:.1}", file.mb_per_sec),
+                            &file.path.display()
+                        ]
+                    )
+                )?;
+            }
+            if !profile.directories.is_empty() {
+                writeln!(out, "\n{}", messages.slowest_directories_title.bold())?;
+            }
+            for directory in &profile.directories {
+                writeln!(
+                    out,
+                    "  {}",
+                    fill(
+                        messages.directory_hot_spot,
+                        &[
+                            &format!("{:.2}", directory.secs).yellow(),
+                            &format_bytes(directory.bytes),
+                            &format_number(directory.files),
+                            &format!("{:.1}", directory.mb_per_sec),
+                            &directory.path.display()
+                        ]
+                    )
+                )?;
+            }
+        }
+
+        if let Some(ref contained) = self.contained
+            && !contained.is_empty()
+        {
+            writeln!(out, "\n{}", messages.contained_title.bold())?;
+            for file in contained {
+                writeln!(
+                    out,
+                    "  {}",
+                    fill(
+                        messages.contained_file,
+                        &[&file.path.display(), &format_bytes(file.size).yellow()]
+                    )
+                )?;
+                for container in &file.contained_in {
+                    writeln!(
+                        out,
+                        "    {}",
+                        fill(
+                            messages.contained_in,
+                            &[&container.path.display(), &format_bytes(container.size)]
+                        )
+                    )?;
+                }
+            }
+        }
 
         if self.groups.is_empty() {
-            println!("\n{}", "No duplicates found.".green());
-            return;
+            writeln!(out, "\n{}", messages.no_duplicates.green())?;
+            return out.flush();
         }
 
         if !verbose {
-            return;
+            return out.flush();
         }
 
         //
@@ -108,31 +782,144 @@ impl DuplicateReport {
         //
 
         for (i, group) in self.groups.iter().enumerate() {
-            println!(
-                "\n{} {} ({} each)",
-                format!("Group {}:", format_number(i + 1)).bold(),
-                format!("{} files", format_number(group.files.len())).cyan(),
-                format_bytes(group.size).yellow()
-            );
+            let cross_filesystem = if group.cross_filesystem {
+                format!(
+                    " {}",
+                    fill(messages.group_cross_filesystem, &[&group.devices.len()]).red()
+                )
+            } else {
+                String::new()
+            };
 
-            for path in &group.files {
-                println!("  {}", path.display());
+            writeln!(
+                out,
+                "\n{} {} {}{} {}",
+                fill(messages.group_header, &[&format_number(i + 1)]).bold(),
+                fill(messages.group_files, &[&format_number(group.total_files())]).cyan(),
+                fill(messages.group_each, &[&format_bytes(group.size).yellow()]),
+                cross_filesystem,
+                group.id.bright_black()
+            )?;
+
+            for file in &group.files {
+                writeln!(out, "  {}", file.path.display())?;
+            }
+            if group.omitted_files > 0 {
+                writeln!(
+                    out,
+                    "  {}",
+                    fill(
+                        messages.group_omitted,
+                        &[&format_number(group.omitted_files)]
+                    )
+                    .bright_black()
+                )?;
             }
         }
+        out.flush()
     }
 
     /// Output as JSON
-    pub fn print_json(&self) {
-        match serde_json::to_string_pretty(self) {
-            Ok(json) => println!("{}", json),
-            Err(e) => eprintln!("Error serializing to JSON: {}", e),
+    pub fn write_json(&self, out: &mut impl Write) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut *out, self)?;
+        writeln!(out)?;
+        out.flush()
+    }
+
+    /// Output a single line of `key=value` pairs without any group details, e.g. for cron
+    /// mails and monitoring scripts
+    pub fn write_summary(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "scanned={} duplicates={} groups={} wasted={} wasted_bytes={}",
+            self.stats.total_files,
+            self.stats.duplicate_files,
+            self.groups.len(),
+            // Without spaces, so each value is a single token
+            format_bytes(self.stats.wasted_bytes)
+                .replace(" bytes", "B")
+                .replace(' ', ""),
+            self.stats.wasted_bytes
+        )?;
+        out.flush()
+    }
+
+    /// Output in the format of `fdupes`: the paths of each group on separate lines,
+    /// each group followed by a blank line
+    pub fn write_fdupes(&self, out: &mut impl Write) -> io::Result<()> {
+        for group in &self.groups {
+            for file in &group.files {
+                out.write_all(file.path.as_os_str().as_bytes())?;
+                out.write_all(b"\n")?;
+            }
+            out.write_all(b"\n")?;
         }
+        out.flush()
+    }
+
+    /// Output the paths of all duplicates except the copy the actions would keep, each
+    /// followed by a NUL byte, for `xargs -0`
+    pub fn write_paths0(&self, out: &mut impl Write) -> io::Result<()> {
+        for group in &self.groups {
+            let keeper = select_original(&group.files);
+            for file in group.files.iter().filter(|f| f.path != keeper.path) {
+                out.write_all(file.path.as_os_str().as_bytes())?;
+                out.write_all(b"\0")?;
+            }
+        }
+        out.flush()
+    }
+
+    /// Output one line per file, formatted by `template`
+    pub fn write_template(&self, out: &mut impl Write, template: &Template) -> io::Result<()> {
+        for (i, group) in self.groups.iter().enumerate() {
+            let keeper = select_original(&group.files);
+            for file in &group.files {
+                template.write_line(out, i + 1, group, file, file.path == keeper.path)?;
+            }
+        }
+        out.flush()
     }
 }
 
+/// Write a file through a temporary file in the same directory that replaces it once
+/// `write` succeeded, so readers never see a partially written file.
+pub fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = File::create(&temp_path).and_then(|file| {
+        let mut out = BufWriter::new(file);
+        write(&mut out)?;
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&temp_path, path)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::hasher::hash_bytes;
+
+    fn file_entry(path: &str, size: u64) -> FileEntry {
+        FileEntry {
+            path: PathBuf::from(path),
+            size,
+            ..Default::default()
+        }
+    }
 
     #[test]
     fn test_empty_report() {
@@ -144,22 +931,527 @@ mod tests {
         assert!(report.groups.is_empty());
     }
 
+    #[test]
+    fn test_group_wasted_bytes() {
+        let group = DuplicateGroup::new(
+            hash_bytes(b"content"),
+            100,
+            vec![
+                file_entry("/a.txt", 100),
+                file_entry("/b.txt", 100),
+                file_entry("/c.txt", 100),
+            ],
+        );
+        assert_eq!(group.wasted_bytes(), 200);
+
+        let single =
+            DuplicateGroup::new(hash_bytes(b"content"), 100, vec![file_entry("/a.txt", 100)]);
+        assert_eq!(single.wasted_bytes(), 0);
+    }
+
+    #[test]
+    fn test_group_allocated_wasted_bytes() {
+        let stored = |path: &str, ino: u64, allocated: u64| FileEntry {
+            ino,
+            allocated,
+            ..file_entry(path, 100)
+        };
+
+        // The kept copy (shortest path) is dense, the sparse copy and the hardlinks of the
+        // second inode only free the blocks they use
+        let group = DuplicateGroup::new(
+            hash_bytes(b"content"),
+            100,
+            vec![
+                stored("/a", 1, 4096),
+                stored("/sparse", 2, 0),
+                stored("/copy1", 3, 4096),
+                stored("/copy2", 3, 4096),
+            ],
+        );
+        // The second hardlink shares its space with the first
+        assert_eq!(group.linked_files(), 1);
+        assert_eq!(group.wasted_bytes(), 200);
+        assert_eq!(group.allocated_wasted_bytes, Some(4096));
+
+        let report = DuplicateReport::from_duplicate_groups(vec![group], 4);
+        assert_eq!(report.stats.allocated_wasted_bytes, Some(4096));
+    }
+
+    #[test]
+    fn test_group_id_depends_on_content_only() {
+        let group = DuplicateGroup::new(
+            hash_bytes(b"content"),
+            7,
+            vec![file_entry("/a", 7), file_entry("/b", 7)],
+        );
+        let moved = DuplicateGroup::new(
+            hash_bytes(b"content"),
+            7,
+            vec![
+                file_entry("/c", 7),
+                file_entry("/d", 7),
+                file_entry("/e", 7),
+            ],
+        );
+        let other = DuplicateGroup::new(
+            hash_bytes(b"other"),
+            5,
+            vec![file_entry("/a", 5), file_entry("/b", 5)],
+        );
+
+        assert_eq!(group.id, moved.id);
+        assert_ne!(group.id, other.id);
+        assert_eq!(group.id.len(), GROUP_ID_LEN);
+        assert!(hash_bytes(b"content").to_hex().starts_with(&group.id));
+    }
+
+    #[test]
+    fn test_group_devices() {
+        let on_device = |path: &str, dev: u64| FileEntry {
+            dev,
+            ..file_entry(path, 10)
+        };
+
+        let group = DuplicateGroup::new(
+            hash_bytes(b"content"),
+            10,
+            vec![on_device("/a", 1), on_device("/b", 1)],
+        );
+        assert_eq!(group.devices, vec![1]);
+        assert!(!group.cross_filesystem);
+
+        let group = DuplicateGroup::new(
+            hash_bytes(b"content"),
+            10,
+            vec![on_device("/a", 2), on_device("/b", 1), on_device("/c", 2)],
+        );
+        assert_eq!(group.devices, vec![1, 2]);
+        assert!(group.cross_filesystem);
+
+        let json = serde_json::to_string(&group).unwrap();
+        assert!(json.contains(&format!("\"id\":\"{}\"", group.id)));
+        assert!(json.contains("\"devices\":[1,2]"));
+        assert!(json.contains("\"cross_filesystem\":true"));
+    }
+
+    #[test]
+    fn test_fully_linked_groups_counted_separately() {
+        let linked = |path: &str, ino: u64| FileEntry {
+            dev: 1,
+            ino,
+            ..file_entry(path, 10)
+        };
+        let hash_groups = vec![
+            HashGroup {
+                hash: hash_bytes(b"linked"),
+                files: vec![linked("/a1", 5), linked("/a2", 5)],
+            },
+            HashGroup {
+                hash: hash_bytes(b"partly linked"),
+                files: vec![linked("/b1", 6), linked("/b2", 6), linked("/b3", 7)],
+            },
+            HashGroup {
+                hash: hash_bytes(b"unknown inodes"),
+                files: vec![file_entry("/c1", 10), file_entry("/c2", 10)],
+            },
+        ];
+
+        let report = DuplicateReport::from_groups(hash_groups, 7);
+
+        assert_eq!(report.stats.already_deduplicated, 1);
+        assert_eq!(report.groups.len(), 2);
+        assert_eq!(report.stats.duplicate_files, 5);
+        assert_eq!(report.groups[0].files[0].path, PathBuf::from("/b1"));
+        // Only the copy with its own inode and the unknown one waste space
+        assert_eq!(report.groups[0].wasted_bytes(), 10);
+        assert_eq!(report.stats.wasted_bytes, 20);
+        assert_eq!(report.stats.already_linked_bytes, 20);
+    }
+
+    #[test]
+    fn test_report_sorted_by_path() {
+        let hash_groups = vec![
+            HashGroup {
+                hash: hash_bytes(b"b"),
+                files: vec![file_entry("/z/b.txt", 1), file_entry("/b.txt", 1)],
+            },
+            HashGroup {
+                hash: hash_bytes(b"a"),
+                files: vec![file_entry("/a.txt", 1), file_entry("/y/a.txt", 1)],
+            },
+        ];
+
+        let report = DuplicateReport::from_groups(hash_groups, 4);
+
+        let paths: Vec<Vec<&str>> = report
+            .groups
+            .iter()
+            .map(|g| g.files.iter().map(|f| f.path.to_str().unwrap()).collect())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![vec!["/a.txt", "/y/a.txt"], vec!["/b.txt", "/z/b.txt"]]
+        );
+    }
+
+    #[test]
+    fn test_limit_group_size() {
+        let hash_groups = vec![
+            HashGroup {
+                hash: hash_bytes(b"a"),
+                files: vec![
+                    file_entry("/a1", 10),
+                    file_entry("/a2", 10),
+                    file_entry("/a3", 10),
+                    file_entry("/a4", 10),
+                ],
+            },
+            HashGroup {
+                hash: hash_bytes(b"b"),
+                files: vec![file_entry("/b1", 5), file_entry("/b2", 5)],
+            },
+        ];
+        let mut report = DuplicateReport::from_groups(hash_groups, 6);
+
+        report.limit_group_size(2);
+
+        assert_eq!(report.groups[0].files.len(), 2);
+        assert_eq!(report.groups[0].omitted_files, 2);
+        assert_eq!(report.groups[0].wasted_bytes(), 30);
+        assert_eq!(report.groups[1].omitted_files, 0);
+        assert_eq!(report.stats.duplicate_files, 6);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["groups"][0]["omitted_files"], 2);
+        assert!(json["groups"][1].get("omitted_files").is_none());
+    }
+
+    #[test]
+    fn test_report_top() {
+        let hash_groups = vec![
+            HashGroup {
+                hash: hash_bytes(b"a"),
+                files: vec![file_entry("/a1", 1), file_entry("/a2", 1)],
+            },
+            HashGroup {
+                hash: hash_bytes(b"b"),
+                files: vec![file_entry("/b1", 9), file_entry("/b2", 9)],
+            },
+            HashGroup {
+                hash: hash_bytes(b"c"),
+                files: vec![file_entry("/c1", 5), file_entry("/c2", 5)],
+            },
+        ];
+        let mut report = DuplicateReport::from_groups(hash_groups, 6);
+
+        report.top(2);
+
+        let hashes: Vec<_> = report.groups.iter().map(|g| g.hash).collect();
+        assert_eq!(hashes, [hash_bytes(b"b"), hash_bytes(b"c")]);
+        assert_eq!(report.stats.duplicate_files, 6);
+        assert_eq!(report.stats.wasted_bytes, 15);
+    }
+
+    #[test]
+    fn test_report_sort() {
+        let hash_groups = vec![
+            HashGroup {
+                hash: hash_bytes(b"a"),
+                files: vec![file_entry("/a1", 10), file_entry("/a2", 10)],
+            },
+            HashGroup {
+                hash: hash_bytes(b"b"),
+                files: vec![
+                    file_entry("/b1", 4),
+                    file_entry("/b2", 4),
+                    file_entry("/b3", 4),
+                ],
+            },
+            HashGroup {
+                hash: hash_bytes(b"c"),
+                files: vec![file_entry("/c1", 7), file_entry("/c2", 7)],
+            },
+        ];
+        let mut report = DuplicateReport::from_groups(hash_groups, 7);
+        let first_paths = |report: &DuplicateReport| -> Vec<String> {
+            report
+                .groups
+                .iter()
+                .map(|g| g.files[0].path.display().to_string())
+                .collect()
+        };
+
+        report.sort(SortKey::Wasted, false);
+        assert_eq!(first_paths(&report), ["/a1", "/b1", "/c1"]);
+
+        report.sort(SortKey::Size, false);
+        assert_eq!(first_paths(&report), ["/a1", "/c1", "/b1"]);
+
+        report.sort(SortKey::Count, false);
+        assert_eq!(first_paths(&report), ["/b1", "/a1", "/c1"]);
+
+        report.sort(SortKey::Wasted, true);
+        assert_eq!(first_paths(&report), ["/c1", "/b1", "/a1"]);
+
+        report.sort(SortKey::Path, true);
+        assert_eq!(first_paths(&report), ["/c1", "/b1", "/a1"]);
+    }
+
+    #[test]
+    fn test_report_page() {
+        let hash_groups = (0..5)
+            .map(|i| HashGroup {
+                hash: hash_bytes(&[i]),
+                files: vec![
+                    file_entry(&format!("/{}a", i), 10),
+                    file_entry(&format!("/{}b", i), 10),
+                ],
+            })
+            .collect();
+        let report = DuplicateReport::from_groups(hash_groups, 10);
+
+        let page = report.page(2, 2);
+        assert_eq!(page.groups.len(), 2);
+        assert_eq!(page.groups[0].files[0].path, PathBuf::from("/2a"));
+        assert_eq!(page.stats.duplicate_files, 10);
+
+        let info = page.page.unwrap();
+        assert_eq!(info.total_pages, 3);
+        assert_eq!(info.total_groups, 5);
+
+        assert_eq!(report.page(3, 2).groups.len(), 1);
+        assert!(report.page(4, 2).groups.is_empty());
+        assert_eq!(report.page(0, 2).groups.len(), 2);
+    }
+
+    #[test]
+    fn test_relative_to_root() {
+        let report = DuplicateReport::from_groups(
+            vec![HashGroup {
+                hash: hash_bytes(b"a"),
+                files: vec![
+                    file_entry("/mnt/snapshot/a.txt", 1),
+                    file_entry("/mnt/snapshot/dir/a.txt", 1),
+                    file_entry("/other/a.txt", 1),
+                ],
+            }],
+            3,
+        );
+
+        let relative = report.relative_to(Path::new("/mnt/snapshot/"));
+
+        let paths: Vec<_> = relative.groups[0].files.iter().map(|f| &f.path).collect();
+        assert_eq!(
+            paths,
+            [
+                Path::new("a.txt"),
+                Path::new("dir/a.txt"),
+                Path::new("/other/a.txt")
+            ]
+        );
+        assert_eq!(
+            report.groups[0].files[0].path,
+            PathBuf::from("/mnt/snapshot/a.txt")
+        );
+    }
+
+    #[test]
+    fn test_summary_output() {
+        let report = DuplicateReport::from_groups(
+            vec![HashGroup {
+                hash: hash_bytes(b"a"),
+                files: vec![
+                    file_entry("/a", 1536),
+                    file_entry("/b", 1536),
+                    file_entry("/c", 1536),
+                ],
+            }],
+            10,
+        );
+
+        let mut out = Vec::new();
+        report.write_summary(&mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "scanned=10 duplicates=3 groups=1 wasted=3.00KB wasted_bytes=3072\n"
+        );
+    }
+
+    #[test]
+    fn test_roll_up_directories() {
+        let mut report = DuplicateReport::from_groups(
+            vec![
+                HashGroup {
+                    hash: hash_bytes(b"a"),
+                    files: vec![
+                        file_entry("/k/a", 100),
+                        file_entry("/downloads/a1", 100),
+                        file_entry("/downloads/a2", 100),
+                    ],
+                },
+                HashGroup {
+                    hash: hash_bytes(b"b"),
+                    files: vec![file_entry("/k/b", 500), file_entry("/backup/old/b", 500)],
+                },
+            ],
+            5,
+        );
+
+        report.roll_up_directories();
+
+        let directories: Vec<_> = report
+            .directories
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|d| (d.path.to_str().unwrap(), d.duplicate_files, d.wasted_bytes))
+            .collect();
+        assert_eq!(
+            directories,
+            vec![("/backup/old", 1, 500), ("/downloads", 2, 200)]
+        );
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["directories"][0]["path"], "/backup/old");
+        let relative = report.relative_to(Path::new("/downloads"));
+        assert_eq!(relative.directories.unwrap()[1].path, PathBuf::from("."));
+    }
+
+    #[test]
+    fn test_roll_up_directories_counts_hardlinks_once() {
+        let linked = |path: &str, ino: u64| FileEntry {
+            dev: 1,
+            ino,
+            ..file_entry(path, 100)
+        };
+        let mut report = DuplicateReport::from_groups(
+            vec![HashGroup {
+                hash: hash_bytes(b"a"),
+                files: vec![
+                    linked("/k/a", 1),
+                    linked("/links/a", 1),
+                    linked("/copies/a1", 2),
+                    linked("/copies/a2", 2),
+                ],
+            }],
+            4,
+        );
+
+        report.roll_up_directories();
+
+        let directories: Vec<_> = report
+            .directories
+            .unwrap()
+            .iter()
+            .map(|d| (d.path.to_str().unwrap().to_string(), d.wasted_bytes))
+            .collect();
+        assert_eq!(directories, vec![("/copies".to_string(), 100)]);
+    }
+
     #[test]
     fn test_report_json_serialization() {
         let report = DuplicateReport {
+            schema_version: SCHEMA_VERSION,
+            hash_algorithm: Algorithm::Blake3,
             stats: DuplicateStats {
                 total_files: 100,
                 duplicate_files: 2,
                 wasted_bytes: 1024,
+                allocated_wasted_bytes: None,
+                already_deduplicated: 0,
+                already_linked_bytes: 0,
+                vanished_files: 0,
+                tree: TreeStats::default(),
+                performance: None,
             },
-            groups: vec![DuplicateGroup {
-                size: 1024,
-                files: vec![PathBuf::from("/a.txt"), PathBuf::from("/b.txt")],
-            }],
+            partial: false,
+            page: None,
+            estimate: None,
+            directories: None,
+            io_profile: None,
+            contained: None,
+            errors: Vec::new(),
+            groups: vec![DuplicateGroup::new(
+                hash_bytes(b"content"),
+                1024,
+                vec![file_entry("/a.txt", 1024), file_entry("/b.txt", 1024)],
+            )],
         };
 
         let json = serde_json::to_string(&report).unwrap();
+        assert!(!json.contains("\"page\""));
+        assert!(!json.contains("\"partial\""));
+        assert!(json.contains(&format!("\"hash\":\"{}\"", hash_bytes(b"content").to_hex())));
         assert!(json.contains("\"total_files\":100"));
         assert!(json.contains("\"wasted_bytes\":1024"));
+        assert!(json.contains("\"files\":[\"/a.txt\",\"/b.txt\"]"));
+    }
+
+    #[test]
+    fn test_report_paths0_format() {
+        let report = DuplicateReport::from_groups(
+            vec![
+                HashGroup {
+                    hash: hash_bytes(b"a"),
+                    files: vec![file_entry("/a/long\nname", 1), file_entry("/a/x", 1)],
+                },
+                HashGroup {
+                    hash: hash_bytes(b"b"),
+                    files: vec![
+                        file_entry("/b/copy1", 1),
+                        file_entry("/b", 1),
+                        file_entry("/b/copy2", 1),
+                    ],
+                },
+            ],
+            5,
+        );
+
+        let mut out = Vec::new();
+        report.write_paths0(&mut out).unwrap();
+
+        assert_eq!(out, b"/a/long\nname\0/b/copy1\0/b/copy2\0");
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("report.txt");
+        fs::write(&path, "old").unwrap();
+
+        let result = write_atomically(&path, |out| {
+            out.write_all(b"partial")?;
+            Err(io::Error::other("failed"))
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+
+        write_atomically(&path, |out| out.write_all(b"new")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(temp.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_report_fdupes_format() {
+        let report = DuplicateReport::from_groups(
+            vec![
+                HashGroup {
+                    hash: hash_bytes(b"b"),
+                    files: vec![file_entry("/b2", 1), file_entry("/b1", 1)],
+                },
+                HashGroup {
+                    hash: hash_bytes(b"a"),
+                    files: vec![file_entry("/a1", 1), file_entry("/a2", 1)],
+                },
+            ],
+            4,
+        );
+
+        let mut out = Vec::new();
+        report.write_fdupes(&mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "/a1\n/a2\n\n/b1\n/b2\n\n");
     }
 }
@@ -0,0 +1,113 @@
+/// User id of `user`, given as a name or a number (e.g. for `--owner`)
+pub fn resolve_user(user: &str) -> Result<u32, String> {
+    user.parse()
+        .ok()
+        .or_else(|| names::user_id(user))
+        .ok_or_else(|| format!("unknown user '{}'", user))
+}
+
+/// Group id of `group`, given as a name or a number (e.g. for `--group`)
+pub fn resolve_group(group: &str) -> Result<u32, String> {
+    group
+        .parse()
+        .ok()
+        .or_else(|| names::group_id(group))
+        .ok_or_else(|| format!("unknown group '{}'", group))
+}
+
+/// Name lookups through the system user and group databases (`/etc/passwd`, NSS, ...).
+#[cfg(unix)]
+mod names {
+    use std::ffi::CString;
+
+    /// Run a `get*nam_r` lookup, growing the buffer for the strings of the entry as needed
+    fn lookup<T>(
+        name: &str,
+        get: impl Fn(*const libc::c_char, &mut T, &mut [libc::c_char], &mut *mut T) -> libc::c_int,
+    ) -> Option<T> {
+        let name = CString::new(name).ok()?;
+        let mut buffer = vec![0 as libc::c_char; 4096];
+        loop {
+            // SAFETY: the entry is plain data, filled in by the lookup before it is read
+            let mut entry: T = unsafe { std::mem::zeroed() };
+            let mut result = std::ptr::null_mut();
+            match get(name.as_ptr(), &mut entry, &mut buffer, &mut result) {
+                0 if !result.is_null() => return Some(entry),
+                libc::ERANGE if buffer.len() < 1 << 20 => buffer.resize(buffer.len() * 2, 0),
+                _ => return None,
+            }
+        }
+    }
+
+    pub fn user_id(name: &str) -> Option<u32> {
+        lookup(name, |name, entry: &mut libc::passwd, buffer, result| {
+            // SAFETY: all pointers are valid, the buffer length is passed along
+            unsafe { libc::getpwnam_r(name, entry, buffer.as_mut_ptr(), buffer.len(), result) }
+        })
+        .map(|entry| entry.pw_uid)
+    }
+
+    pub fn group_id(name: &str) -> Option<u32> {
+        lookup(name, |name, entry: &mut libc::group, buffer, result| {
+            // SAFETY: all pointers are valid, the buffer length is passed along
+            unsafe { libc::getgrnam_r(name, entry, buffer.as_mut_ptr(), buffer.len(), result) }
+        })
+        .map(|entry| entry.gr_gid)
+    }
+}
+
+/// Names can only be looked up on Unix, elsewhere users and groups are given by number.
+#[cfg(not(unix))]
+mod names {
+    pub fn user_id(_name: &str) -> Option<u32> {
+        None
+    }
+
+    pub fn group_id(_name: &str) -> Option<u32> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_numbers() {
+        assert_eq!(resolve_user("1000"), Ok(1000));
+        assert_eq!(resolve_group("0"), Ok(0));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_resolve_names() {
+        assert_eq!(resolve_user("root"), Ok(0));
+        // Group 0 is called wheel on the BSDs and macOS
+        let root_group = if cfg!(target_os = "linux") {
+            "root"
+        } else {
+            "wheel"
+        };
+        assert_eq!(resolve_group(root_group), Ok(0));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_resolve_current_user() {
+        let output = std::process::Command::new("id")
+            .arg("-un")
+            .output()
+            .unwrap();
+        let name = String::from_utf8(output.stdout).unwrap();
+
+        // SAFETY: getuid can't fail
+        let uid = unsafe { libc::getuid() };
+        assert_eq!(resolve_user(name.trim()), Ok(uid));
+    }
+
+    #[test]
+    fn test_resolve_unknown() {
+        assert!(resolve_user("no-such-user-for-dedup").is_err());
+        assert!(resolve_group("no-such-group-for-dedup").is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Set while hashing should pause, by SIGUSR1 or SIGTSTP
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Set by SIGTSTP, the process stops itself once no candidate group is being hashed
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Number of candidate groups being hashed right now
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// How often paused threads check whether they may continue
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Number of polls after SIGTSTP before the process stops even though groups are still
+/// being hashed, e.g. by a thread that waits inside another group (2s)
+const STOP_GRACE_POLLS: u32 = 20;
+
+/// Marks a candidate group as being hashed, files are only open while one exists
+#[must_use]
+pub struct Busy;
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        ACTIVE.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Wait while hashing is paused, then mark a candidate group as being hashed.
+///
+/// Called between candidate groups, where no files are open, so a paused run holds no
+/// file handles. Once all threads wait here after SIGTSTP, the process stops itself.
+pub fn wait() -> Busy {
+    let mut stop_polls = 0;
+    loop {
+        // Counted before checking, so a stop never happens while this group is hashed
+        ACTIVE.fetch_add(1, Ordering::SeqCst);
+        if !PAUSED.load(Ordering::SeqCst) {
+            return Busy;
+        }
+        ACTIVE.fetch_sub(1, Ordering::SeqCst);
+
+        if STOP_REQUESTED.load(Ordering::SeqCst) {
+            stop_polls += 1;
+            let idle = ACTIVE.load(Ordering::SeqCst) == 0;
+            if (idle || stop_polls >= STOP_GRACE_POLLS)
+                && STOP_REQUESTED.swap(false, Ordering::SeqCst)
+            {
+                sys::stop();
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Pause hashing on SIGUSR1 and resume on SIGUSR2, stop cleanly on SIGTSTP (Ctrl-Z) and
+/// resume on SIGCONT (`fg`). Only for the hashing stage, see [`uninstall`].
+pub fn install() {
+    sys::install();
+}
+
+/// Restore the default signal handling once hashing is done. A stop requested after the
+/// last group finished happens right away.
+pub fn uninstall() {
+    sys::uninstall();
+    PAUSED.store(false, Ordering::SeqCst);
+    if STOP_REQUESTED.swap(false, Ordering::SeqCst) {
+        sys::stop();
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use super::{PAUSED, STOP_REQUESTED};
+    use std::sync::atomic::Ordering;
+
+    /// Only touches atomics, which is async-signal-safe
+    extern "C" fn handle(signal: libc::c_int) {
+        match signal {
+            libc::SIGUSR1 => PAUSED.store(true, Ordering::SeqCst),
+            libc::SIGUSR2 | libc::SIGCONT => PAUSED.store(false, Ordering::SeqCst),
+            libc::SIGTSTP => {
+                STOP_REQUESTED.store(true, Ordering::SeqCst);
+                PAUSED.store(true, Ordering::SeqCst);
+            }
+            _ => {}
+        }
+    }
+
+    const SIGNALS: [libc::c_int; 4] = [libc::SIGUSR1, libc::SIGUSR2, libc::SIGCONT, libc::SIGTSTP];
+
+    pub fn install() {
+        for signal in SIGNALS {
+            // SAFETY: the handler only stores to atomics
+            unsafe { libc::signal(signal, handle as *const () as libc::sighandler_t) };
+        }
+    }
+
+    pub fn uninstall() {
+        for signal in SIGNALS {
+            // SAFETY: restores the default disposition
+            unsafe { libc::signal(signal, libc::SIG_DFL) };
+        }
+    }
+
+    /// Stop the process like the default SIGTSTP action would
+    pub fn stop() {
+        // SAFETY: raising a signal has no memory safety requirements
+        unsafe { libc::raise(libc::SIGSTOP) };
+    }
+}
+
+/// Signals are only handled on Unix.
+#[cfg(not(unix))]
+mod sys {
+    pub fn install() {}
+
+    pub fn uninstall() {}
+
+    pub fn stop() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_wait_blocks_while_paused() {
+        drop(wait());
+        assert_eq!(ACTIVE.load(Ordering::SeqCst), 0);
+
+        PAUSED.store(true, Ordering::SeqCst);
+        let (sender, receiver) = mpsc::channel();
+        let waiter = thread::spawn(move || {
+            let busy = wait();
+            sender.send(()).unwrap();
+            drop(busy);
+        });
+
+        assert!(receiver.recv_timeout(POLL_INTERVAL * 3).is_err());
+        PAUSED.store(false, Ordering::SeqCst);
+        assert!(receiver.recv_timeout(POLL_INTERVAL * 10).is_ok());
+        waiter.join().unwrap();
+        assert_eq!(ACTIVE.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+
+use rayon::prelude::*;
+
+use crate::hasher::{self, Digest};
+use crate::scanner::FileEntry;
+
+/// Number of files each thread hashes before the pool is handed back to the walk
+const FILES_PER_THREAD: usize = 4;
+
+/// Partial hashes computed while the tree was scanned, by path
+pub type PartialHashes = HashMap<PathBuf, Digest>;
+
+/// Compute the partial hashes of the files a running scan sends to `found`, as soon as a
+/// second file of the same size shows up, until the scan is done and `found` is closed.
+///
+/// On slow filesystems (e.g. network shares) the walk alone can take many minutes, hashing
+/// the candidates found so far meanwhile overlaps it with reading files. Files whose partial
+/// hash would cover all of their contents are left to the full hash, see
+/// [`hasher::partial_hash_covers`]. Hardlinks to an inode that is already hashed get its
+/// hash without being read again.
+pub fn hash_while_scanning(found: Receiver<FileEntry>, skip: u64) -> PartialHashes {
+    // The only file of each size seen so far, and the sizes seen more than once
+    let mut single: HashMap<u64, FileEntry> = HashMap::new();
+    let mut repeated: HashSet<u64> = HashSet::new();
+    let mut hashes = PartialHashes::new();
+    // The first path of each inode hashed, and the hardlinks to it found after
+    let mut first_links: HashMap<(u64, u64), PathBuf> = HashMap::new();
+    let mut links: Vec<(PathBuf, PathBuf)> = Vec::new();
+
+    // Wait for the next file, the others that arrived meanwhile are hashed in the same batch
+    while let Ok(next) = found.recv() {
+        let mut batch = Vec::new();
+        for file in std::iter::once(next).chain(found.try_iter()) {
+            if hasher::partial_hash_covers(file.size, skip) {
+                continue;
+            }
+            if repeated.contains(&file.size) {
+                batch.push(file);
+            } else if let Some(first) = single.remove(&file.size) {
+                repeated.insert(file.size);
+                batch.extend([first, file]);
+            } else {
+                single.insert(file.size, file);
+            }
+        }
+        batch.retain(|file| {
+            if file.ino == 0 {
+                return true;
+            }
+            match first_links.entry((file.dev, file.ino)) {
+                Entry::Occupied(first) => {
+                    links.push((file.path.clone(), first.get().clone()));
+                    false
+                }
+                Entry::Vacant(first) => {
+                    first.insert(file.path.clone());
+                    true
+                }
+            }
+        });
+
+        // A few files per thread at a time, so the walk's own jobs on the shared pool get their
+        // turn in between (a walk gives up if it can't start within a second)
+        for chunk in batch.chunks_mut(rayon::current_num_threads() * FILES_PER_THREAD) {
+            hashes.par_extend(chunk.par_iter_mut().filter_map(|file| {
+                let hash = hasher::partial_hash_entry(file, skip)?;
+                Some((file.path.clone(), hash))
+            }));
+        }
+    }
+
+    for (link, first) in links {
+        if let Some(&hash) = hashes.get(&first) {
+            hashes.insert(link, hash);
+        }
+    }
+    hashes
+}
+
+/// Attach the partial hashes computed while scanning to the scanned files
+pub fn attach(files: &mut [FileEntry], mut hashes: PartialHashes) {
+    for file in files {
+        file.partial = hashes.remove(&file.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use tempfile::TempDir;
+
+    fn file_entry(dir: &std::path::Path, name: &str, content: &[u8]) -> FileEntry {
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        FileEntry {
+            path,
+            size: content.len() as u64,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hashes_files_with_repeated_sizes() {
+        let temp = TempDir::new().unwrap();
+        let large = vec![1u8; 100_000];
+        let other = vec![2u8; 100_000];
+        let files = [
+            file_entry(temp.path(), "a", &large),
+            file_entry(temp.path(), "unique", &[3u8; 50_000]),
+            file_entry(temp.path(), "b", &other),
+            file_entry(temp.path(), "c", &large),
+            file_entry(temp.path(), "small1", b"small"),
+            file_entry(temp.path(), "small2", b"small"),
+        ];
+
+        let (found, received) = mpsc::channel();
+        for file in &files {
+            found.send(file.clone()).unwrap();
+        }
+        drop(found);
+        let hashes = hash_while_scanning(received, 0);
+
+        let mut hashed: Vec<_> = hashes.keys().map(|p| p.file_name().unwrap()).collect();
+        hashed.sort();
+        assert_eq!(hashed, ["a", "b", "c"]);
+        assert_eq!(hashes[&files[0].path], hashes[&files[3].path]);
+        assert_ne!(hashes[&files[0].path], hashes[&files[2].path]);
+
+        let mut scanned = files.to_vec();
+        attach(&mut scanned, hashes);
+        assert!(scanned[0].partial.is_some());
+        assert!(scanned[1].partial.is_none());
+        assert_eq!(
+            hasher::group_by_partial_hash(scanned[..4].to_vec(), 0).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_hardlinks_are_hashed_once() {
+        let temp = TempDir::new().unwrap();
+        let mut original = file_entry(temp.path(), "a", &[1u8; 100_000]);
+        (original.dev, original.ino) = (1, 42);
+        // A path that can't be read, it only gets a hash as a hardlink of the original
+        let link = FileEntry {
+            path: temp.path().join("missing"),
+            ..original.clone()
+        };
+
+        let (found, received) = mpsc::channel();
+        found.send(original.clone()).unwrap();
+        found.send(link.clone()).unwrap();
+        drop(found);
+        let hashes = hash_while_scanning(received, 0);
+
+        assert_eq!(hashes.len(), 2);
+        assert_eq!(hashes[&original.path], hashes[&link.path]);
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::hasher;
+
+/// Number of files and directories listed in each section of the I/O profile
+pub const HOT_SPOTS: usize = 10;
+
+/// Time spent hashing each file and bytes read from it, summed over all stages
+#[derive(Debug, Default)]
+pub struct Profiler {
+    files: Mutex<HashMap<PathBuf, (Duration, u64)>>,
+}
+
+/// A file or directory among the slowest to hash
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HotSpot {
+    pub path: PathBuf,
+    /// Files hashed, 1 for a file
+    pub files: usize,
+    /// Bytes read to hash them
+    pub bytes: u64,
+    /// Seconds spent hashing them, summed over all threads
+    pub secs: f64,
+    /// Bytes read per second, in MB/s. Low for a failing disk, high for files that are just
+    /// large.
+    pub mb_per_sec: f64,
+}
+
+/// The files and directories that took the longest to hash, slowest first
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IoProfile {
+    pub files: Vec<HotSpot>,
+    pub directories: Vec<HotSpot>,
+}
+
+impl HotSpot {
+    fn new(path: &Path, files: usize, elapsed: Duration, bytes: u64) -> Self {
+        let secs = elapsed.as_secs_f64();
+        HotSpot {
+            path: path.to_path_buf(),
+            files,
+            bytes,
+            secs,
+            mb_per_sec: if secs > 0.0 {
+                bytes as f64 / (1024.0 * 1024.0) / secs
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+impl Profiler {
+    /// Add the time and bytes of hashing `path` once more
+    pub fn record(&self, path: &Path, elapsed: Duration, bytes: u64) {
+        let mut files = self.files.lock().unwrap();
+        let entry = files.entry(path.to_path_buf()).or_default();
+        entry.0 += elapsed;
+        entry.1 += bytes;
+    }
+
+    /// The `n` files and directories that took the longest to hash. Files count towards
+    /// their parent directory only, not its ancestors.
+    pub fn hot_spots(&self, n: usize) -> IoProfile {
+        let files = self.files.lock().unwrap();
+        let mut directories: HashMap<&Path, (usize, Duration, u64)> = HashMap::new();
+        for (path, &(elapsed, bytes)) in files.iter() {
+            let entry = directories
+                .entry(path.parent().unwrap_or(Path::new("")))
+                .or_default();
+            entry.0 += 1;
+            entry.1 += elapsed;
+            entry.2 += bytes;
+        }
+
+        let slowest = |mut spots: Vec<HotSpot>| {
+            spots.sort_by(|a, b| b.secs.total_cmp(&a.secs).then_with(|| a.path.cmp(&b.path)));
+            spots.truncate(n);
+            spots
+        };
+        IoProfile {
+            files: slowest(
+                files
+                    .iter()
+                    .map(|(path, &(elapsed, bytes))| HotSpot::new(path, 1, elapsed, bytes))
+                    .collect(),
+            ),
+            directories: slowest(
+                directories
+                    .into_iter()
+                    .map(|(path, (count, elapsed, bytes))| {
+                        HotSpot::new(path, count, elapsed, bytes)
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Profiler of the current run, set once by [`enable`]
+static PROFILER: OnceLock<Profiler> = OnceLock::new();
+
+/// Record the hashing time and bytes of every file for the rest of the run (`--profile-io`)
+pub fn enable() {
+    let _ = PROFILER.set(Profiler::default());
+}
+
+/// Start timing a file about to be hashed on this thread, `None` unless profiling
+pub fn start() -> Option<(Instant, u64)> {
+    PROFILER
+        .get()
+        .map(|_| (Instant::now(), hasher::bytes_read_by_thread()))
+}
+
+/// Record the file timed since `started`, returned by [`start`] on the same thread
+pub fn finish(path: &Path, started: Option<(Instant, u64)>) {
+    if let (Some(profiler), Some((instant, bytes_before))) = (PROFILER.get(), started) {
+        let bytes = hasher::bytes_read_by_thread() - bytes_before;
+        profiler.record(path, instant.elapsed(), bytes);
+    }
+}
+
+/// The slowest files and directories of the run so far, `None` unless profiling
+pub fn hot_spots() -> Option<IoProfile> {
+    PROFILER.get().map(|profiler| profiler.hot_spots(HOT_SPOTS))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hot_spots_slowest_first() {
+        let profiler = Profiler::default();
+        let second = Duration::from_secs(1);
+        profiler.record(Path::new("/fast/a"), second, 100 * 1024 * 1024);
+        profiler.record(Path::new("/fast/b"), second, 100 * 1024 * 1024);
+        profiler.record(Path::new("/slow/c"), 3 * second, 3 * 1024 * 1024);
+        // Later stages add up
+        profiler.record(Path::new("/fast/a"), 2 * second, 100 * 1024 * 1024);
+
+        let profile = profiler.hot_spots(2);
+
+        let files: Vec<_> = profile
+            .files
+            .iter()
+            .map(|s| s.path.to_str().unwrap())
+            .collect();
+        assert_eq!(files, ["/fast/a", "/slow/c"]);
+        assert_eq!(profile.files[0].bytes, 200 * 1024 * 1024);
+        assert_eq!(profile.files[1].mb_per_sec, 1.0);
+        assert_eq!(
+            profile.directories[0],
+            HotSpot::new(Path::new("/fast"), 2, 4 * second, 300 * 1024 * 1024)
+        );
+        assert_eq!(profile.directories[0].mb_per_sec, 75.0);
+        assert_eq!(profile.directories[1].path, PathBuf::from("/slow"));
+    }
+}
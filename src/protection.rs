@@ -0,0 +1,281 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+/// Write protection that prevents a file from being replaced or removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protection {
+    /// The file has the immutable attribute
+    ImmutableFile,
+    /// The parent directory has the immutable attribute
+    ImmutableParent,
+    /// The parent directory is not writable
+    ReadOnlyParent,
+}
+
+impl Protection {
+    fn describe(self) -> &'static str {
+        match self {
+            Protection::ImmutableFile => "file is immutable",
+            Protection::ImmutableParent => "parent directory is immutable",
+            Protection::ReadOnlyParent => "parent directory is read-only",
+        }
+    }
+}
+
+/// Directory containing `path`, replacing or removing `path` needs write access to it.
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Find the write protection preventing `path` from being replaced or removed, if any.
+pub fn detect(path: &Path) -> Option<Protection> {
+    let parent = parent_dir(path);
+
+    if attr::immutable_flags(path).is_some() {
+        return Some(Protection::ImmutableFile);
+    }
+    if attr::immutable_flags(parent).is_some() {
+        return Some(Protection::ImmutableParent);
+    }
+    match fs::metadata(parent) {
+        Ok(m) if m.permissions().mode() & 0o200 == 0 => Some(Protection::ReadOnlyParent),
+        _ => None,
+    }
+}
+
+/// Check if `path` has the immutable attribute, which also prevents hardlinks to it.
+pub fn is_immutable(path: &Path) -> bool {
+    attr::immutable_flags(path).is_some()
+}
+
+/// Describe an error from replacing or removing `path`, naming the write protection
+/// that caused it if there is one.
+pub fn describe_error(path: &Path, error: &io::Error, force: bool) -> String {
+    if error.kind() == io::ErrorKind::PermissionDenied
+        && let Some(protection) = detect(path)
+    {
+        if force {
+            return format!("{} ({})", error, protection.describe());
+        }
+        return format!(
+            "{} ({}, use --force to override)",
+            error,
+            protection.describe()
+        );
+    }
+    error.to_string()
+}
+
+/// Write protection lifted from a file and its parent directory by [`unprotect`].
+#[derive(Debug, Default)]
+pub struct Unprotected {
+    /// Immutable file with its (device, inode) and original attributes
+    file_flags: Option<(PathBuf, (u64, u64), i32)>,
+    parent_flags: Option<(PathBuf, i32)>,
+    parent_permissions: Option<(PathBuf, fs::Permissions)>,
+}
+
+impl Unprotected {
+    /// Put the lifted protection back in place.
+    ///
+    /// The file's immutable attribute is only restored if the path still refers to the
+    /// same file, a replaced file keeps the attributes of its replacement.
+    pub fn restore(self) -> io::Result<()> {
+        let mut result = Ok(());
+
+        if let Some((path, id, flags)) = self.file_flags
+            && let Ok(m) = fs::symlink_metadata(&path)
+            && (m.dev(), m.ino()) == id
+        {
+            result = result.and(attr::set_flags(&path, flags));
+        }
+        if let Some((parent, permissions)) = self.parent_permissions {
+            result = result.and(fs::set_permissions(parent, permissions));
+        }
+        // Restored last, nothing can be changed in an immutable directory
+        if let Some((parent, flags)) = self.parent_flags {
+            result = result.and(attr::set_flags(&parent, flags));
+        }
+
+        result
+    }
+}
+
+/// Temporarily lift the write protection preventing `path` from being replaced or
+/// removed: the immutable attribute of the file and its parent directory, and a
+/// read-only mode of the parent directory.
+///
+/// Clearing the immutable attribute usually requires root.
+pub fn unprotect(path: &Path) -> io::Result<Unprotected> {
+    let mut unprotected = Unprotected::default();
+    if let Err(e) = lift(path, &mut unprotected) {
+        let _ = unprotected.restore();
+        return Err(e);
+    }
+    Ok(unprotected)
+}
+
+fn lift(path: &Path, unprotected: &mut Unprotected) -> io::Result<()> {
+    let parent = parent_dir(path);
+
+    if let Some(flags) = attr::immutable_flags(path) {
+        let m = fs::symlink_metadata(path)?;
+        attr::set_flags(path, flags & !attr::IMMUTABLE)?;
+        unprotected.file_flags = Some((path.to_path_buf(), (m.dev(), m.ino()), flags));
+    }
+
+    if let Some(flags) = attr::immutable_flags(parent) {
+        attr::set_flags(parent, flags & !attr::IMMUTABLE)?;
+        unprotected.parent_flags = Some((parent.to_path_buf(), flags));
+    }
+
+    let permissions = fs::metadata(parent)?.permissions();
+    if permissions.mode() & 0o200 == 0 {
+        let mut writable = permissions.clone();
+        writable.set_mode(permissions.mode() | 0o200);
+        fs::set_permissions(parent, writable)?;
+        unprotected.parent_permissions = Some((parent.to_path_buf(), permissions));
+    }
+
+    Ok(())
+}
+
+/// Access to the immutable attribute (`chattr +i`) through the `FS_IOC_*FLAGS` ioctls.
+#[cfg(target_os = "linux")]
+mod attr {
+    use std::fs::{File, OpenOptions};
+    use std::io;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::path::Path;
+
+    /// `FS_IMMUTABLE_FL` from `linux/fs.h`
+    pub const IMMUTABLE: i32 = 0x0000_0010;
+
+    fn open(path: &Path) -> io::Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_NOFOLLOW)
+            .open(path)
+    }
+
+    /// The attributes of `path` if it is immutable, `None` if it is not or they can't be read.
+    pub fn immutable_flags(path: &Path) -> Option<i32> {
+        let file = open(path).ok()?;
+        let mut flags: libc::c_int = 0;
+        // SAFETY: FS_IOC_GETFLAGS writes a single int to the given pointer
+        let ret = unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) };
+        (ret == 0 && flags & IMMUTABLE != 0).then_some(flags)
+    }
+
+    pub fn set_flags(path: &Path, flags: i32) -> io::Result<()> {
+        let file = open(path)?;
+        let flags: libc::c_int = flags;
+        // SAFETY: FS_IOC_SETFLAGS reads a single int from the given pointer
+        let ret = unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_SETFLAGS, &flags) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// The immutable attribute is only supported on Linux.
+#[cfg(not(target_os = "linux"))]
+mod attr {
+    use std::io;
+    use std::path::Path;
+
+    pub const IMMUTABLE: i32 = 0;
+
+    pub fn immutable_flags(_path: &Path) -> Option<i32> {
+        None
+    }
+
+    pub fn set_flags(_path: &Path, _flags: i32) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Create a file in a read-only directory, returns the file path
+    fn file_in_read_only_dir(temp: &TempDir) -> PathBuf {
+        let dir = temp.path().join("ro");
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("file.txt");
+        fs::write(&path, b"content").unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o555)).unwrap();
+        path
+    }
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn test_detect_unprotected() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("file.txt");
+        fs::write(&path, b"content").unwrap();
+
+        assert_eq!(detect(&path), None);
+    }
+
+    #[test]
+    fn test_detect_read_only_parent() {
+        let temp = TempDir::new().unwrap();
+        let path = file_in_read_only_dir(&temp);
+
+        assert_eq!(detect(&path), Some(Protection::ReadOnlyParent));
+
+        fs::set_permissions(path.parent().unwrap(), fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_unprotect_and_restore_read_only_parent() {
+        let temp = TempDir::new().unwrap();
+        let path = file_in_read_only_dir(&temp);
+        let dir = path.parent().unwrap();
+
+        let unprotected = unprotect(&path).unwrap();
+        assert_eq!(mode(dir), 0o755);
+        assert_eq!(detect(&path), None);
+
+        unprotected.restore().unwrap();
+        assert_eq!(mode(dir), 0o555);
+
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_describe_error() {
+        let temp = TempDir::new().unwrap();
+        let path = file_in_read_only_dir(&temp);
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+
+        let message = describe_error(&path, &denied, false);
+        assert!(message.contains("parent directory is read-only"));
+        assert!(message.contains("--force"));
+
+        let message = describe_error(&path, &denied, true);
+        assert!(message.contains("parent directory is read-only"));
+        assert!(!message.contains("--force"));
+
+        let not_found = io::Error::from(io::ErrorKind::NotFound);
+        assert_eq!(
+            describe_error(&path, &not_found, false),
+            not_found.to_string()
+        );
+
+        fs::set_permissions(path.parent().unwrap(), fs::Permissions::from_mode(0o755)).unwrap();
+    }
+}
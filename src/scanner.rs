@@ -1,27 +1,108 @@
 use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
-use jwalk::WalkDirGeneric;
+use jwalk::{Parallelism, WalkDirGeneric};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::io::{self, BufRead};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::time::SystemTime;
+
+use crate::archive;
+use crate::dedupignore::IgnoreStack;
+use crate::hasher::{ContentHashes, Digest};
+use crate::util;
 
 /// Information about a file found during scanning
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct FileEntry {
     pub path: PathBuf,
     pub size: u64,
+    /// Bytes allocated on disk, less than `size` for sparse files
+    pub allocated: u64,
+    /// Last modification time, used to detect files changing between hashing and acting
+    pub modified: Option<SystemTime>,
+    /// ID of the device (filesystem) the file lives on
+    pub dev: u64,
+    /// Inode number on the device, 0 if unknown
+    pub ino: u64,
+    /// Hashes of the contents of an archive member, which can't be opened by its path
+    /// (`archive!member`), see [`crate::archive`]
+    pub archived: Option<Arc<ContentHashes>>,
+    /// Partial hash computed while the tree was still being scanned, see [`crate::pipeline`]
+    pub partial: Option<Digest>,
+    /// Found under the reference directory of `--compare-to`, never changed by actions
+    pub reference: bool,
+}
+
+/// Files found by a scan
+#[derive(Debug, Default)]
+pub struct Scan {
+    /// Regular files that passed the filters
+    pub files: Vec<FileEntry>,
+    /// Symbolic links seen, they are never followed
+    pub symlinks: usize,
+    /// Files and directories that could not be read
+    pub errors: Vec<ScanError>,
+}
+
+/// A file or directory that could not be read while scanning, so duplicates in it may be missed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanError {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+/// Structure of the scanned tree, reported alongside the duplicates
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TreeStats {
+    /// Symbolic links seen, they are never followed
+    pub symlinks: usize,
+    /// Distinct inodes of the scanned files, less than the number of files if some are hardlinks
+    pub unique_inodes: usize,
+    /// Inodes reached through more than one scanned path
+    pub hardlinked_inodes: usize,
+}
+
+impl TreeStats {
+    /// Count the inodes of `files`, files with an unknown inode count as distinct
+    pub fn add_files(&mut self, files: &[FileEntry]) {
+        let mut links: HashMap<(u64, u64), usize> = HashMap::new();
+        for file in files {
+            if file.ino == 0 {
+                self.unique_inodes += 1;
+            } else {
+                *links.entry((file.dev, file.ino)).or_default() += 1;
+            }
+        }
+        self.unique_inodes += links.len();
+        self.hardlinked_inodes += links.values().filter(|&&n| n > 1).count();
+    }
 }
 
 /// Build a GlobSet from a list of glob patterns
-fn build_glob_set(patterns: &[String]) -> Option<GlobSet> {
+fn build_glob_set(patterns: &[String], ignore_case: bool) -> Option<GlobSet> {
     if patterns.is_empty() {
         return None;
     }
 
     let mut builder = GlobSetBuilder::new();
     for pattern in patterns {
-        match GlobBuilder::new(pattern).literal_separator(true).build() {
+        match GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .case_insensitive(ignore_case)
+            .build()
+        {
             Ok(g) => {
                 builder.add(g);
             }
-            Err(e) => eprintln!("Warning: invalid exclude pattern '{}': {}", pattern, e),
+            Err(e) => util::warn(format_args!("invalid exclude pattern '{}': {}", pattern, e)),
         }
     }
     builder.build().ok()
@@ -40,23 +121,133 @@ fn matches_glob(path: &Path, glob_set: &GlobSet) -> bool {
         .is_some_and(|name| glob_set.is_match(name))
 }
 
-/// Scan a directory and return all regular files with their sizes
-pub fn scan_directory(
+/// Which files a scan includes
+#[derive(Debug, Clone, Default)]
+pub struct ScanFilters {
+    /// Skip files smaller than this many bytes
+    pub min_size: Option<u64>,
+    /// Skip files larger than this many bytes
+    pub max_size: Option<u64>,
+    /// Skip files with fewer bytes allocated on disk, e.g. mostly empty sparse files
+    pub min_allocated: Option<u64>,
+    /// Glob patterns of files and directories to skip
+    pub exclude_patterns: Vec<String>,
+    /// Glob patterns files must match, directories are always traversed
+    pub include_patterns: Vec<String>,
+    /// Match the exclude and include patterns regardless of case
+    pub ignore_case: bool,
+    /// Keep empty files, they are skipped by default
+    pub include_empty: bool,
+    /// Skip hidden files and directories (names starting with a dot)
+    pub skip_hidden: bool,
+    /// Skip files with more than one hardlink, they are likely deduplicated already
+    pub skip_hardlinked: bool,
+    /// Only keep files last modified after this time
+    pub modified_after: Option<SystemTime>,
+    /// Only keep files last modified before this time
+    pub modified_before: Option<SystemTime>,
+    /// Only keep files owned by this user id
+    pub owner: Option<u32>,
+    /// Only keep files owned by this group id
+    pub group: Option<u32>,
+    /// Apply the patterns of `.dedupignore` files to the directories they are in
+    pub dedupignore: bool,
+    /// Also scan the members of zip and tar archives, see [`add_archive_members`]
+    pub scan_archives: bool,
+}
+
+impl ScanFilters {
+    /// Whether a regular file with this metadata passes the size, allocated size, link count,
+    /// owner and modification time filters
+    fn keeps(&self, metadata: &std::fs::Metadata) -> bool {
+        let size = metadata.len();
+        // Empty files are commonly used as placeholders, they are all "duplicates" but not interesting
+        if size == 0 && !self.include_empty {
+            return false;
+        }
+        if size < self.min_size.unwrap_or(0) || size > self.max_size.unwrap_or(u64::MAX) {
+            return false;
+        }
+        if self
+            .min_allocated
+            .is_some_and(|min| metadata.blocks() * 512 < min)
+        {
+            return false;
+        }
+        if self.skip_hardlinked && metadata.nlink() > 1 {
+            return false;
+        }
+        if self.owner.is_some_and(|uid| metadata.uid() != uid)
+            || self.group.is_some_and(|gid| metadata.gid() != gid)
+        {
+            return false;
+        }
+
+        if self.modified_after.is_none() && self.modified_before.is_none() {
+            return true;
+        }
+        // Files without a modification time can't be shown to be in range
+        let Ok(modified) = metadata.modified() else {
+            return false;
+        };
+        self.modified_after.is_none_or(|after| modified > after)
+            && self.modified_before.is_none_or(|before| modified < before)
+    }
+}
+
+/// State of a walk: the `.dedupignore` files of each directory, and per file its entry if
+/// it passes the filters or the error stat'ing it
+type WalkState = (IgnoreStack, Option<Result<FileEntry, String>>);
+
+/// Counters of a running scan, e.g. to show progress while scanning large trees
+#[derive(Debug, Default)]
+pub struct ScanProgress {
+    /// Files found so far, before filters are applied
+    pub files: AtomicUsize,
+    /// Directories read so far
+    pub directories: AtomicUsize,
+}
+
+/// Scan a directory and return all regular files passing the `filters` with their sizes,
+/// the number of symbolic links seen and the paths that could not be read.
+///
+/// Directories are read and their files are stat'ed on the global rayon pool, the same
+/// workers that hash files afterwards, so the number of threads set with `--jobs` applies
+/// to the whole run.
+pub fn scan_directory(root: &Path, filters: &ScanFilters) -> Scan {
+    walk_directory(root, filters, Arc::new(ScanProgress::default()), None, true)
+}
+
+/// [`scan_directory`], counting the files and directories seen in `progress` and sending
+/// every file to `found` as soon as it passed the filters. Sent files are only kept in the
+/// returned scan as well if `keep` is set.
+fn walk_directory(
     root: &Path,
-    min_size: Option<u64>,
-    max_size: Option<u64>,
-    exclude_patterns: &[String],
-    include_patterns: &[String],
-) -> Vec<FileEntry> {
-    let min = min_size.unwrap_or(0);
-    let max = max_size.unwrap_or(u64::MAX);
-    let exclude_set = build_glob_set(exclude_patterns);
-    let include_set = build_glob_set(include_patterns);
-
-    WalkDirGeneric::<((), ())>::new(root)
-        .skip_hidden(false)
+    filters: &ScanFilters,
+    progress: Arc<ScanProgress>,
+    found: Option<&Sender<FileEntry>>,
+    keep: bool,
+) -> Scan {
+    let exclude_set = build_glob_set(&filters.exclude_patterns, filters.ignore_case);
+    let include_set = build_glob_set(&filters.include_patterns, filters.ignore_case);
+    let filters = filters.clone();
+    let symlinks = Arc::new(AtomicUsize::new(0));
+    let symlinks_seen = Arc::clone(&symlinks);
+
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+    let walk = WalkDirGeneric::<WalkState>::new(root)
+        .skip_hidden(filters.skip_hidden)
         .follow_links(false)
-        .process_read_dir(move |_depth, _path, _state, children| {
+        .parallelism(Parallelism::RayonDefaultPool {
+            busy_timeout: std::time::Duration::from_secs(1),
+        })
+        .process_read_dir(move |depth, path, ignore, children| {
+            // The root itself is handed in without a depth, before it is read
+            if filters.dedupignore && depth.is_some() {
+                *ignore = ignore.enter(path);
+            }
+
             children.retain(|entry| {
                 let Ok(e) = entry.as_ref() else {
                     return true; // keep errors to handle later
@@ -71,43 +262,411 @@ pub fn scan_directory(
                 }
 
                 let is_file = e.file_type().is_file();
-                // Include patterns only apply to files, we still have to traverse directories
+                if ignore.is_ignored(&path, e.file_type().is_dir()) {
+                    return false;
+                }
+
+                // Include patterns only apply to files, we still have to traverse directories.
+                // Archives are kept for their members, they are checked again afterwards.
                 if let Some(ref glob_set) = include_set
                     && is_file
                     && !matches_glob(&path, glob_set)
+                    && !(filters.scan_archives && archive::Format::of(&path).is_some())
                 {
                     return false;
                 }
 
                 true
             });
-        })
-        .into_iter()
-        .filter_map(|entry| {
-            let entry = entry.ok()?;
-            let metadata = entry.metadata().ok()?;
 
-            if !metadata.is_file() {
-                return None;
+            if depth.is_some() {
+                progress.directories.fetch_add(1, Ordering::Relaxed);
+            }
+
+            // Stat files here rather than while collecting, so it runs in parallel per directory
+            for entry in children.iter_mut().flatten() {
+                if entry.file_type().is_file() {
+                    progress.files.fetch_add(1, Ordering::Relaxed);
+                    entry.client_state = file_entry(entry, &filters);
+                } else if entry.file_type().is_symlink() {
+                    symlinks_seen.fetch_add(1, Ordering::Relaxed);
+                }
             }
+        })
+        .into_iter();
+    for entry in walk {
+        match entry {
+            Ok(mut entry) => match entry.client_state.take() {
+                Some(Ok(file)) => match found {
+                    Some(found) if !keep => {
+                        let _ = found.send(file);
+                    }
+                    Some(found) => {
+                        let _ = found.send(file.clone());
+                        files.push(file);
+                    }
+                    None => files.push(file),
+                },
+                Some(Err(error)) => errors.push(ScanError {
+                    path: entry.path(),
+                    error,
+                }),
+                None => {}
+            },
+            Err(e) => errors.push(ScanError {
+                path: e.path().unwrap_or(root).to_path_buf(),
+                error: e
+                    .io_error()
+                    .map_or_else(|| e.to_string(), |io| io.to_string()),
+            }),
+        }
+    }
+
+    Scan {
+        files,
+        symlinks: symlinks.load(Ordering::Relaxed),
+        errors,
+    }
+}
+
+/// Scan several directories like [`scan_directory`] and merge their files into one scan,
+/// so duplicates are found across all of them.
+///
+/// A root inside another root (or given twice) is skipped with a warning, its files are
+/// already found through the outer root and would otherwise be counted twice.
+pub fn scan_directories(
+    roots: &[PathBuf],
+    filters: &ScanFilters,
+    progress: &Arc<ScanProgress>,
+) -> Scan {
+    stream_directories(roots, filters, progress, None)
+}
+
+/// [`scan_directories`], sending the files of the roots to `found` while they are walked, so
+/// they can be processed before the whole tree is scanned. Archive members are only added
+/// to the returned scan.
+pub fn stream_directories(
+    roots: &[PathBuf],
+    filters: &ScanFilters,
+    progress: &Arc<ScanProgress>,
+    found: Option<Sender<FileEntry>>,
+) -> Scan {
+    let mut merged = Scan::default();
+    for root in distinct_roots(roots) {
+        let scan = walk_directory(root, filters, Arc::clone(progress), found.as_ref(), true);
+        merged.files.extend(scan.files);
+        merged.symlinks += scan.symlinks;
+        merged.errors.extend(scan.errors);
+    }
+    if filters.scan_archives {
+        add_archive_members(&mut merged, filters);
+    }
+    merged
+}
+
+/// [`scan_directories`], handing every file over to `found` instead of keeping it in the
+/// returned scan, for trees with more files than fit in memory. Archives are not opened.
+pub fn send_directories(
+    roots: &[PathBuf],
+    filters: &ScanFilters,
+    progress: &Arc<ScanProgress>,
+    found: Sender<FileEntry>,
+) -> Scan {
+    let mut merged = Scan::default();
+    for root in distinct_roots(roots) {
+        let scan = walk_directory(root, filters, Arc::clone(progress), Some(&found), false);
+        merged.symlinks += scan.symlinks;
+        merged.errors.extend(scan.errors);
+    }
+    merged
+}
+
+/// Scan the reference directory of `--compare-to` like [`scan_directories`], marking its
+/// files as reference files
+pub fn scan_reference(root: &Path, filters: &ScanFilters, progress: &Arc<ScanProgress>) -> Scan {
+    let mut scan = scan_directories(std::slice::from_ref(&root.to_path_buf()), filters, progress);
+    for file in &mut scan.files {
+        file.reference = true;
+    }
+    scan
+}
+
+/// The first of `roots` that is inside `reference` or contains it. Roots that can't be
+/// resolved don't overlap anything.
+pub fn overlapping_root<'a>(roots: &'a [PathBuf], reference: &Path) -> Option<&'a Path> {
+    let reference = reference.canonicalize().ok()?;
+    roots
+        .iter()
+        .find(|root| {
+            root.canonicalize()
+                .is_ok_and(|root| root.starts_with(&reference) || reference.starts_with(&root))
+        })
+        .map(PathBuf::as_path)
+}
 
-            let size = metadata.len();
+/// Read the paths of a file list (`--files-from`), one per line or NUL-separated (e.g. from
+/// `find -print0`). Empty entries are skipped.
+pub fn read_file_list(reader: impl BufRead, nul_separated: bool) -> io::Result<Vec<PathBuf>> {
+    let separator = if nul_separated { b'\0' } else { b'\n' };
+    reader
+        .split(separator)
+        .filter(|entry| entry.as_ref().is_ok_and(|entry| !entry.is_empty()))
+        .map(|entry| Ok(PathBuf::from(OsStr::from_bytes(&entry?))))
+        .collect()
+}
 
-            // Empty files are commonly used as placeholders, they are all "duplicates" but not interesting
-            if size == 0 {
+/// Build a scan from a list of files instead of walking a directory, with the same filters
+/// as [`scan_directory`]. An exclude pattern matching a parent directory excludes the file,
+/// like it would exclude the directory from a walk. Files are only hidden by their own name,
+/// a list has no root below which hidden parent directories could be told apart.
+///
+/// Paths listed more than once, also under different spellings such as `./a` and `a`, are
+/// only included once. Paths that aren't regular files are
+/// skipped, symbolic links are counted and paths that can't be read are reported as errors.
+pub fn scan_file_list(
+    mut paths: Vec<PathBuf>,
+    filters: &ScanFilters,
+    progress: &ScanProgress,
+) -> Scan {
+    let exclude_set = build_glob_set(&filters.exclude_patterns, filters.ignore_case);
+    let include_set = build_glob_set(&filters.include_patterns, filters.ignore_case);
+
+    paths.sort();
+    paths.dedup();
+    paths.retain(|path| {
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.as_bytes().starts_with(b"."));
+        if filters.skip_hidden && hidden {
+            return false;
+        }
+        let excluded = exclude_set
+            .as_ref()
+            .is_some_and(|set| path.ancestors().any(|p| matches_glob(p, set)));
+        let included = include_set
+            .as_ref()
+            .is_none_or(|set| matches_glob(path, set))
+            || (filters.scan_archives && archive::Format::of(path).is_some());
+        !excluded && included
+    });
+
+    let symlinks = AtomicUsize::new(0);
+    let (files, errors): (Vec<_>, Vec<_>) = paths
+        .par_iter()
+        .filter_map(|path| {
+            progress.files.fetch_add(1, Ordering::Relaxed);
+            let metadata = match std::fs::symlink_metadata(path) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    return Some(Err(ScanError {
+                        path: path.clone(),
+                        error: e.to_string(),
+                    }));
+                }
+            };
+            if metadata.is_symlink() {
+                symlinks.fetch_add(1, Ordering::Relaxed);
+            }
+            if !metadata.is_file() || !filters.keeps(&metadata) {
                 return None;
             }
+            Some(Ok(FileEntry {
+                path: path.clone(),
+                size: metadata.len(),
+                allocated: metadata.blocks() * 512,
+                modified: metadata.modified().ok(),
+                dev: metadata.dev(),
+                ino: metadata.ino(),
+                archived: None,
+                partial: None,
+                reference: false,
+            }))
+        })
+        .partition_map(|result| match result {
+            Ok(file) => rayon::iter::Either::Left(file),
+            Err(error) => rayon::iter::Either::Right(error),
+        });
+
+    let mut scan = Scan {
+        files: drop_aliases(files),
+        symlinks: symlinks.into_inner(),
+        errors,
+    };
+    if filters.scan_archives {
+        add_archive_members(&mut scan, filters);
+    }
+    scan
+}
 
-            if size < min || size > max {
-                return None;
+/// Add the members of the zip and tar archives among the scanned files, named
+/// `<archive>!<member>`, so duplicates between loose files and archived copies are found.
+///
+/// Members are hashed while they are read, see [`archive::members`]. They pass the same
+/// size, include, exclude and hidden filters as files, archives not matching the include
+/// patterns are only read for their members. Archives that can't be read are reported as
+/// errors.
+fn add_archive_members(scan: &mut Scan, filters: &ScanFilters) {
+    let exclude_set = build_glob_set(&filters.exclude_patterns, filters.ignore_case);
+    let include_set = build_glob_set(&filters.include_patterns, filters.ignore_case);
+    let keep = |member: &FileEntry| {
+        let hidden = member
+            .path
+            .file_name()
+            .is_some_and(|name| name.as_bytes().starts_with(b"."));
+        (member.size > 0 || filters.include_empty)
+            && member.size >= filters.min_size.unwrap_or(0)
+            && member.size <= filters.max_size.unwrap_or(u64::MAX)
+            && !(filters.skip_hidden && hidden)
+            && exclude_set
+                .as_ref()
+                .is_none_or(|set| !matches_glob(&member.path, set))
+            && include_set
+                .as_ref()
+                .is_none_or(|set| matches_glob(&member.path, set))
+    };
+
+    let members: Vec<archive::Members> = scan
+        .files
+        .par_iter()
+        .filter_map(|file| {
+            Some(archive::members(
+                file,
+                archive::Format::of(&file.path)?,
+                keep,
+            ))
+        })
+        .collect();
+    scan.files.retain(|file| {
+        archive::Format::of(&file.path).is_none()
+            || include_set
+                .as_ref()
+                .is_none_or(|set| matches_glob(&file.path, set))
+    });
+    for members in members {
+        scan.files.extend(members.files);
+        scan.errors.extend(members.errors);
+    }
+}
+
+/// Drop files listed more than once under different spellings of their path (`./a` and
+/// `a`, or through a symlinked directory), which would otherwise form a group with
+/// themselves. Only paths of the same inode are resolved, hardlinks stay separate files.
+fn drop_aliases(files: Vec<FileEntry>) -> Vec<FileEntry> {
+    let mut by_inode: HashMap<(u64, u64), usize> = HashMap::new();
+    for file in &files {
+        *by_inode.entry((file.dev, file.ino)).or_default() += 1;
+    }
+
+    let mut seen = HashSet::new();
+    let before = files.len();
+    let distinct: Vec<FileEntry> = files
+        .into_iter()
+        .filter(|file| {
+            if file.ino == 0 || by_inode[&(file.dev, file.ino)] < 2 {
+                return true;
             }
+            let resolved = file
+                .path
+                .canonicalize()
+                .unwrap_or_else(|_| file.path.clone());
+            seen.insert(resolved)
+        })
+        .collect();
+
+    let aliases = before - distinct.len();
+    if aliases > 0 {
+        util::warn(format_args!(
+            "{} files were listed more than once under different paths, they are only scanned once",
+            aliases
+        ));
+    }
+    distinct
+}
 
-            Some(FileEntry {
-                path: entry.path(),
-                size,
+/// Roots that are not inside another root, in their original order. Roots that can't be
+/// resolved are kept, scanning them finds nothing.
+fn distinct_roots(roots: &[PathBuf]) -> Vec<&Path> {
+    let resolved: Vec<Option<PathBuf>> = roots.iter().map(|r| r.canonicalize().ok()).collect();
+
+    let mut distinct = Vec::new();
+    for (i, root) in roots.iter().enumerate() {
+        let outer = resolved[i].as_ref().and_then(|path| {
+            resolved.iter().enumerate().find_map(|(j, other)| {
+                let other = other.as_ref()?;
+                let contains = if other == path {
+                    j < i
+                } else {
+                    path.starts_with(other)
+                };
+                contains.then_some(&roots[j])
             })
-        })
-        .collect()
+        });
+
+        match outer {
+            Some(outer) => util::warn(format_args!(
+                "{} is inside {}, its files are only scanned once",
+                root.display(),
+                outer.display()
+            )),
+            None => distinct.push(root.as_path()),
+        }
+    }
+    distinct
+}
+
+/// Build the entry of a single file outside of a scan, `None` if it can't be read or is
+/// not a regular file
+pub fn stat_file(path: &Path) -> Option<FileEntry> {
+    let metadata = std::fs::symlink_metadata(path).ok()?;
+
+    if !metadata.is_file() {
+        return None;
+    }
+
+    Some(FileEntry {
+        path: path.to_path_buf(),
+        size: metadata.len(),
+        allocated: metadata.blocks() * 512,
+        modified: metadata.modified().ok(),
+        dev: metadata.dev(),
+        ino: metadata.ino(),
+        archived: None,
+        partial: None,
+        reference: false,
+    })
+}
+
+/// Build the entry of a scanned file, `None` if it is filtered out and the error if it
+/// can't be read
+fn file_entry(
+    entry: &jwalk::DirEntry<WalkState>,
+    filters: &ScanFilters,
+) -> Option<Result<FileEntry, String>> {
+    let metadata = match entry.metadata() {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            let error = e
+                .io_error()
+                .map_or_else(|| e.to_string(), |io| io.to_string());
+            return Some(Err(error));
+        }
+    };
+
+    if !metadata.is_file() || !filters.keeps(&metadata) {
+        return None;
+    }
+
+    Some(Ok(FileEntry {
+        path: entry.path(),
+        size: metadata.len(),
+        allocated: metadata.blocks() * 512,
+        modified: metadata.modified().ok(),
+        dev: metadata.dev(),
+        ino: metadata.ino(),
+        archived: None,
+        partial: None,
+        reference: false,
+    }))
 }
 
 #[cfg(test)]
@@ -125,13 +684,207 @@ mod tests {
         path
     }
 
+    #[test]
+    fn test_scan_directories_merges_roots() {
+        let first = TempDir::new().unwrap();
+        let second = TempDir::new().unwrap();
+        create_file(first.path(), "a.txt", b"content");
+        create_file(second.path(), "b.txt", b"content");
+        fs::create_dir(first.path().join("sub")).unwrap();
+        create_file(&first.path().join("sub"), "c.txt", b"content");
+
+        let roots = [
+            first.path().to_path_buf(),
+            second.path().to_path_buf(),
+            first.path().join("sub"),
+            second.path().to_path_buf(),
+        ];
+        let files = scan_directories(&roots, &ScanFilters::default(), &Default::default()).files;
+
+        let mut names: Vec<_> = files
+            .iter()
+            .map(|f| f.path.file_name().unwrap().to_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a.txt", "b.txt", "c.txt"]);
+    }
+
+    #[test]
+    fn test_read_file_list() {
+        let lines = read_file_list(&b"/a b.txt\n\n/c.txt\n"[..], false).unwrap();
+        assert_eq!(lines, [PathBuf::from("/a b.txt"), PathBuf::from("/c.txt")]);
+
+        let entries = read_file_list(&b"/line\nbreak\0/c.txt\0"[..], true).unwrap();
+        assert_eq!(
+            entries,
+            [PathBuf::from("/line\nbreak"), PathBuf::from("/c.txt")]
+        );
+    }
+
+    #[test]
+    fn test_scan_file_list_filters() {
+        let temp = TempDir::new().unwrap();
+        let kept = create_file(temp.path(), "kept.txt", b"content");
+        let small = create_file(temp.path(), "small.txt", b"x");
+        let empty = create_file(temp.path(), "empty.txt", b"");
+        fs::create_dir(temp.path().join("cache")).unwrap();
+        let cached = create_file(&temp.path().join("cache"), "cached.txt", b"content");
+        let link = temp.path().join("link.txt");
+        std::os::unix::fs::symlink(&kept, &link).unwrap();
+
+        let paths = vec![
+            kept.clone(),
+            small,
+            empty,
+            cached,
+            link,
+            temp.path().join("missing.txt"),
+            temp.path().to_path_buf(),
+            kept.clone(),
+        ];
+        let scan = scan_file_list(
+            paths,
+            &ScanFilters {
+                min_size: Some(2),
+                exclude_patterns: vec!["cache".to_string()],
+                ..Default::default()
+            },
+            &Default::default(),
+        );
+
+        let files: Vec<_> = scan.files.iter().map(|f| &f.path).collect();
+        assert_eq!(files, [&kept]);
+        assert_eq!(scan.symlinks, 1);
+    }
+
+    #[test]
+    fn test_scan_file_list_drops_aliases() {
+        let temp = TempDir::new().unwrap();
+        fs::create_dir(temp.path().join("sub")).unwrap();
+        let file = create_file(temp.path(), "a.txt", b"content");
+        let hardlink = temp.path().join("b.txt");
+        fs::hard_link(&file, &hardlink).unwrap();
+        let linked_dir = temp.path().join("linked");
+        std::os::unix::fs::symlink(temp.path(), &linked_dir).unwrap();
+
+        let paths = vec![
+            file.clone(),
+            temp.path().join("sub/../a.txt"),
+            linked_dir.join("a.txt"),
+            hardlink.clone(),
+        ];
+        let scan = scan_file_list(paths, &ScanFilters::default(), &Default::default());
+
+        // Hardlinks are separate files, other spellings of the same path are not
+        assert_eq!(scan.files.len(), 2);
+        assert!(scan.files.iter().any(|f| f.path == hardlink));
+    }
+
+    #[test]
+    fn test_scan_progress_counts_files_and_directories() {
+        let temp = TempDir::new().unwrap();
+        fs::create_dir(temp.path().join("sub")).unwrap();
+        create_file(temp.path(), "a.txt", b"content");
+        create_file(temp.path(), "empty.txt", b"");
+        create_file(&temp.path().join("sub"), "b.txt", b"content");
+
+        let progress = Arc::new(ScanProgress::default());
+        let scan = scan_directories(
+            &[temp.path().to_path_buf()],
+            &ScanFilters::default(),
+            &progress,
+        );
+
+        // Files are counted before filters, the empty file is skipped afterwards
+        assert_eq!(scan.files.len(), 2);
+        assert_eq!(progress.files.load(Ordering::Relaxed), 3);
+        assert_eq!(progress.directories.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_scan_errors_collected() {
+        let temp = TempDir::new().unwrap();
+        create_file(temp.path(), "a.txt", b"content");
+        let missing = temp.path().join("missing");
+
+        let scan = scan_directories(
+            &[temp.path().to_path_buf(), missing.clone()],
+            &ScanFilters::default(),
+            &Default::default(),
+        );
+        assert_eq!(scan.files.len(), 1);
+        assert_eq!(scan.errors.len(), 1);
+        assert_eq!(scan.errors[0].path, missing);
+
+        let scan = scan_file_list(
+            vec![temp.path().join("a.txt"), missing.clone()],
+            &ScanFilters::default(),
+            &Default::default(),
+        );
+        assert_eq!(scan.files.len(), 1);
+        assert_eq!(scan.errors.len(), 1);
+        assert_eq!(scan.errors[0].path, missing);
+    }
+
+    #[test]
+    fn test_distinct_roots() {
+        let temp = TempDir::new().unwrap();
+        fs::create_dir(temp.path().join("a")).unwrap();
+        fs::create_dir(temp.path().join("ab")).unwrap();
+        let a = temp.path().join("a");
+        let ab = temp.path().join("ab");
+        let missing = temp.path().join("missing");
+
+        // "ab" only shares a prefix with "a", it's not inside it
+        let roots = [a.join("."), ab.clone(), missing.clone(), a.clone()];
+        assert_eq!(
+            distinct_roots(&roots),
+            [a.join(".").as_path(), &ab, &missing]
+        );
+
+        let roots = [a.clone(), temp.path().to_path_buf()];
+        assert_eq!(distinct_roots(&roots), [temp.path()]);
+    }
+
+    #[test]
+    fn test_overlapping_root() {
+        let temp = TempDir::new().unwrap();
+        fs::create_dir(temp.path().join("archive")).unwrap();
+        fs::create_dir(temp.path().join("archive2")).unwrap();
+        let archive = temp.path().join("archive");
+        let card = temp.path().join("archive2");
+
+        assert_eq!(
+            overlapping_root(std::slice::from_ref(&card), &archive),
+            None
+        );
+        let roots = [card.clone(), archive.join(".")];
+        assert_eq!(
+            overlapping_root(&roots, &archive),
+            Some(archive.join(".").as_path())
+        );
+        let roots = [temp.path().to_path_buf()];
+        assert_eq!(overlapping_root(&roots, &archive), Some(temp.path()));
+    }
+
+    #[test]
+    fn test_scan_reference_marks_files() {
+        let temp = TempDir::new().unwrap();
+        create_file(temp.path(), "a.txt", b"content");
+
+        let scan = scan_reference(temp.path(), &ScanFilters::default(), &Default::default());
+        assert_eq!(scan.files.len(), 1);
+        assert!(scan.files[0].reference);
+        assert!(!scan_directory(temp.path(), &ScanFilters::default()).files[0].reference);
+    }
+
     #[test]
     fn test_finds_files() {
         let temp = TempDir::new().unwrap();
         create_file(temp.path(), "file1.txt", b"hello");
         create_file(temp.path(), "file2.txt", b"world");
 
-        let files = scan_directory(temp.path(), None, None, &[], &[]);
+        let files = scan_directory(temp.path(), &ScanFilters::default()).files;
 
         assert_eq!(files.len(), 2);
     }
@@ -142,7 +895,7 @@ mod tests {
         create_file(temp.path(), "small.txt", b"hi");
         create_file(temp.path(), "large.txt", b"hello world!");
 
-        let files = scan_directory(temp.path(), None, None, &[], &[]);
+        let files = scan_directory(temp.path(), &ScanFilters::default()).files;
 
         let small = files
             .iter()
@@ -166,7 +919,7 @@ mod tests {
         create_file(temp.path(), "root.txt", b"root");
         create_file(&subdir, "nested.txt", b"nested");
 
-        let files = scan_directory(temp.path(), None, None, &[], &[]);
+        let files = scan_directory(temp.path(), &ScanFilters::default()).files;
 
         assert_eq!(files.len(), 2);
         assert!(files.iter().any(|f| f.path.ends_with("root.txt")));
@@ -180,7 +933,7 @@ mod tests {
         fs::create_dir(&subdir).unwrap();
         create_file(temp.path(), "file.txt", b"content");
 
-        let files = scan_directory(temp.path(), None, None, &[], &[]);
+        let files = scan_directory(temp.path(), &ScanFilters::default()).files;
 
         assert_eq!(files.len(), 1);
         assert!(files[0].path.ends_with("file.txt"));
@@ -193,7 +946,14 @@ mod tests {
         create_file(temp.path(), "small.txt", b"hello"); // 5 bytes
         create_file(temp.path(), "large.txt", b"hello world!"); // 12 bytes
 
-        let files = scan_directory(temp.path(), Some(5), None, &[], &[]);
+        let files = scan_directory(
+            temp.path(),
+            &ScanFilters {
+                min_size: Some(5),
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert_eq!(files.len(), 2);
         assert!(!files.iter().any(|f| f.path.ends_with("tiny.txt")));
@@ -206,7 +966,14 @@ mod tests {
         create_file(temp.path(), "small.txt", b"hello"); // 5 bytes
         create_file(temp.path(), "large.txt", b"hello world!"); // 12 bytes
 
-        let files = scan_directory(temp.path(), None, Some(5), &[], &[]);
+        let files = scan_directory(
+            temp.path(),
+            &ScanFilters {
+                max_size: Some(5),
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert_eq!(files.len(), 2);
         assert!(!files.iter().any(|f| f.path.ends_with("large.txt")));
@@ -219,21 +986,116 @@ mod tests {
         create_file(temp.path(), "small.txt", b"hello"); // 5 bytes
         create_file(temp.path(), "large.txt", b"hello world!"); // 12 bytes
 
-        let files = scan_directory(temp.path(), Some(3), Some(10), &[], &[]);
+        let files = scan_directory(
+            temp.path(),
+            &ScanFilters {
+                min_size: Some(3),
+                max_size: Some(10),
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert_eq!(files.len(), 1);
         assert!(files.iter().any(|f| f.path.ends_with("small.txt")));
     }
 
+    #[test]
+    fn test_skip_hardlinked() {
+        let temp = TempDir::new().unwrap();
+        let linked = create_file(temp.path(), "linked.txt", b"content");
+        fs::hard_link(&linked, temp.path().join("link.txt")).unwrap();
+        create_file(temp.path(), "single.txt", b"content");
+
+        let filters = ScanFilters {
+            skip_hardlinked: true,
+            ..Default::default()
+        };
+        let files = scan_directory(temp.path(), &filters).files;
+
+        assert_eq!(files.len(), 1);
+        assert!(files[0].path.ends_with("single.txt"));
+    }
+
+    #[test]
+    fn test_owner_and_group_filter() {
+        let temp = TempDir::new().unwrap();
+        let path = create_file(temp.path(), "mine.txt", b"content");
+        let metadata = fs::metadata(path).unwrap();
+
+        let scan = |owner, group| {
+            let filters = ScanFilters {
+                owner,
+                group,
+                ..Default::default()
+            };
+            scan_directory(temp.path(), &filters).files.len()
+        };
+
+        assert_eq!(scan(Some(metadata.uid()), Some(metadata.gid())), 1);
+        assert_eq!(scan(Some(metadata.uid().wrapping_add(1)), None), 0);
+        assert_eq!(scan(None, Some(metadata.gid().wrapping_add(1))), 0);
+    }
+
+    #[test]
+    fn test_modification_time_filter() {
+        let temp = TempDir::new().unwrap();
+        let day = std::time::Duration::from_secs(24 * 60 * 60);
+        let now = SystemTime::now();
+        for (name, age) in [("new.txt", 0), ("month.txt", 30), ("year.txt", 365)] {
+            let path = create_file(temp.path(), name, b"content");
+            File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(now - day * age)
+                .unwrap();
+        }
+
+        let files = scan_directory(
+            temp.path(),
+            &ScanFilters {
+                modified_after: Some(now - day * 60),
+                modified_before: Some(now - day),
+                ..Default::default()
+            },
+        )
+        .files;
+
+        assert_eq!(files.len(), 1);
+        assert!(files[0].path.ends_with("month.txt"));
+    }
+
     #[test]
     fn test_empty_directory() {
         let temp = TempDir::new().unwrap();
 
-        let files = scan_directory(temp.path(), None, None, &[], &[]);
+        let files = scan_directory(temp.path(), &ScanFilters::default()).files;
 
         assert!(files.is_empty());
     }
 
+    #[test]
+    fn test_empty_files_skipped_unless_included() {
+        let temp = TempDir::new().unwrap();
+        create_file(temp.path(), "empty.txt", b"");
+        create_file(temp.path(), "file.txt", b"content");
+
+        let files = scan_directory(temp.path(), &ScanFilters::default()).files;
+        assert_eq!(files.len(), 1);
+        assert!(files[0].path.ends_with("file.txt"));
+
+        let files = scan_directory(
+            temp.path(),
+            &ScanFilters {
+                include_empty: true,
+                ..Default::default()
+            },
+        )
+        .files;
+        assert_eq!(files.len(), 2);
+    }
+
     #[test]
     fn test_handles_symlinks() {
         let temp = TempDir::new().unwrap();
@@ -245,10 +1107,38 @@ mod tests {
             std::os::unix::fs::symlink(&file_path, &link_path).unwrap();
         }
 
-        let files = scan_directory(temp.path(), None, None, &[], &[]);
+        let scan = scan_directory(temp.path(), &ScanFilters::default());
+
+        assert_eq!(scan.files.len(), 1);
+        assert!(scan.files[0].path.ends_with("real.txt"));
+        assert_eq!(scan.symlinks, 1);
+    }
+
+    #[test]
+    fn test_tree_stats_count_inodes() {
+        let temp = TempDir::new().unwrap();
+        let a = create_file(temp.path(), "a.txt", b"content");
+        fs::hard_link(&a, temp.path().join("a-link1.txt")).unwrap();
+        fs::hard_link(&a, temp.path().join("a-link2.txt")).unwrap();
+        create_file(temp.path(), "b.txt", b"content");
+
+        let mut stats = TreeStats::default();
+        stats.add_files(&scan_directory(temp.path(), &ScanFilters::default()).files);
+        stats.add_files(&[FileEntry::default()]);
+
+        assert_eq!(stats.unique_inodes, 3);
+        assert_eq!(stats.hardlinked_inodes, 1);
+    }
+
+    #[test]
+    fn test_records_device() {
+        let temp = TempDir::new().unwrap();
+        let path = create_file(temp.path(), "file.txt", b"content");
+
+        let files = scan_directory(temp.path(), &ScanFilters::default()).files;
 
         assert_eq!(files.len(), 1);
-        assert!(files[0].path.ends_with("real.txt"));
+        assert_eq!(files[0].dev, fs::metadata(path).unwrap().dev());
     }
 
     #[test]
@@ -258,7 +1148,7 @@ mod tests {
         fs::create_dir_all(&deep).unwrap();
         create_file(&deep, "deep.txt", b"deep content");
 
-        let files = scan_directory(temp.path(), None, None, &[], &[]);
+        let files = scan_directory(temp.path(), &ScanFilters::default()).files;
 
         assert_eq!(files.len(), 1);
         assert!(files[0].path.ends_with("deep.txt"));
@@ -270,7 +1160,7 @@ mod tests {
         create_file(temp.path(), "empty.txt", b"");
         create_file(temp.path(), "nonempty.txt", b"content");
 
-        let files = scan_directory(temp.path(), None, None, &[], &[]);
+        let files = scan_directory(temp.path(), &ScanFilters::default()).files;
 
         assert_eq!(files.len(), 1);
         assert!(files[0].path.ends_with("nonempty.txt"));
@@ -282,12 +1172,43 @@ mod tests {
         create_file(temp.path(), "keep.txt", b"keep");
         create_file(temp.path(), "skip.log", b"skip");
 
-        let files = scan_directory(temp.path(), None, None, &["*.log".to_string()], &[]);
+        let files = scan_directory(
+            temp.path(),
+            &ScanFilters {
+                exclude_patterns: vec!["*.log".to_string()],
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert_eq!(files.len(), 1);
         assert!(files[0].path.ends_with("keep.txt"));
     }
 
+    #[test]
+    fn test_exclude_ignore_case() {
+        let temp = TempDir::new().unwrap();
+        create_file(temp.path(), "keep.txt", b"keep");
+        create_file(temp.path(), "upper.JPG", b"skip");
+        create_file(temp.path(), "lower.jpg", b"skip");
+
+        let scan = |ignore_case| {
+            scan_directory(
+                temp.path(),
+                &ScanFilters {
+                    exclude_patterns: vec!["*.jpg".to_string()],
+                    ignore_case,
+                    ..Default::default()
+                },
+            )
+            .files
+            .len()
+        };
+
+        assert_eq!(scan(false), 2);
+        assert_eq!(scan(true), 1);
+    }
+
     #[test]
     fn test_exclude_directory() {
         let temp = TempDir::new().unwrap();
@@ -299,11 +1220,12 @@ mod tests {
 
         let files = scan_directory(
             temp.path(),
-            None,
-            None,
-            &["**/node_modules".to_string()],
-            &[],
-        );
+            &ScanFilters {
+                exclude_patterns: vec!["**/node_modules".to_string()],
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert_eq!(files.len(), 1);
         assert!(files[0].path.ends_with("root.txt"));
@@ -318,11 +1240,12 @@ mod tests {
 
         let files = scan_directory(
             temp.path(),
-            None,
-            None,
-            &["*.log".to_string(), "*.tmp".to_string()],
-            &[],
-        );
+            &ScanFilters {
+                exclude_patterns: vec!["*.log".to_string(), "*.tmp".to_string()],
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert_eq!(files.len(), 1);
         assert!(files[0].path.ends_with("keep.txt"));
@@ -338,7 +1261,14 @@ mod tests {
         create_file(&subdir, "nested.log", b"nested");
         create_file(&subdir, "keep.txt", b"keep");
 
-        let files = scan_directory(temp.path(), None, None, &["**/*.log".to_string()], &[]);
+        let files = scan_directory(
+            temp.path(),
+            &ScanFilters {
+                exclude_patterns: vec!["**/*.log".to_string()],
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert_eq!(files.len(), 1);
         assert!(files[0].path.ends_with("keep.txt"));
@@ -350,7 +1280,14 @@ mod tests {
         create_file(temp.path(), "keep.txt", b"keep");
         create_file(temp.path(), "secret.env", b"skip");
 
-        let files = scan_directory(temp.path(), None, None, &["secret.env".to_string()], &[]);
+        let files = scan_directory(
+            temp.path(),
+            &ScanFilters {
+                exclude_patterns: vec!["secret.env".to_string()],
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert_eq!(files.len(), 1);
         assert!(files[0].path.ends_with("keep.txt"));
@@ -365,7 +1302,14 @@ mod tests {
         create_file(temp.path(), "root.txt", b"root");
         create_file(&deep_build, "output.js", b"built");
 
-        let files = scan_directory(temp.path(), None, None, &["**/build".to_string()], &[]);
+        let files = scan_directory(
+            temp.path(),
+            &ScanFilters {
+                exclude_patterns: vec!["**/build".to_string()],
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert_eq!(files.len(), 1);
         assert!(files[0].path.ends_with("root.txt"));
@@ -381,19 +1325,63 @@ mod tests {
         create_file(&cache_dir, "cached.txt", b"cached");
 
         // Using just the directory name without **/ prefix
-        let files = scan_directory(temp.path(), None, None, &[".cache".to_string()], &[]);
+        let files = scan_directory(
+            temp.path(),
+            &ScanFilters {
+                exclude_patterns: vec![".cache".to_string()],
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert_eq!(files.len(), 1);
         assert!(files[0].path.ends_with("keep.txt"));
     }
 
+    #[test]
+    fn test_skip_hidden() {
+        let temp = TempDir::new().unwrap();
+        let cache_dir = temp.path().join(".cache");
+        fs::create_dir(&cache_dir).unwrap();
+        create_file(temp.path(), "keep.txt", b"keep");
+        create_file(temp.path(), ".hidden.txt", b"hidden");
+        create_file(&cache_dir, "cached.txt", b"cached");
+
+        // Hidden files are scanned by default
+        let files = scan_directory(temp.path(), &ScanFilters::default()).files;
+        assert_eq!(files.len(), 3);
+
+        let filters = ScanFilters {
+            skip_hidden: true,
+            ..Default::default()
+        };
+        let files = scan_directory(temp.path(), &filters).files;
+        assert_eq!(files.len(), 1);
+        assert!(files[0].path.ends_with("keep.txt"));
+
+        let listed = vec![
+            temp.path().join("keep.txt"),
+            temp.path().join(".hidden.txt"),
+        ];
+        let files = scan_file_list(listed, &filters, &Default::default()).files;
+        assert_eq!(files.len(), 1);
+        assert!(files[0].path.ends_with("keep.txt"));
+    }
+
     #[test]
     fn test_exclude_with_no_matches() {
         let temp = TempDir::new().unwrap();
         create_file(temp.path(), "file1.txt", b"one");
         create_file(temp.path(), "file2.txt", b"two");
 
-        let files = scan_directory(temp.path(), None, None, &["*.log".to_string()], &[]);
+        let files = scan_directory(
+            temp.path(),
+            &ScanFilters {
+                exclude_patterns: vec!["*.log".to_string()],
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert_eq!(files.len(), 2);
     }
@@ -404,7 +1392,14 @@ mod tests {
         create_file(temp.path(), "file1.log", b"one");
         create_file(temp.path(), "file2.log", b"two");
 
-        let files = scan_directory(temp.path(), None, None, &["*.log".to_string()], &[]);
+        let files = scan_directory(
+            temp.path(),
+            &ScanFilters {
+                exclude_patterns: vec!["*.log".to_string()],
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert!(files.is_empty());
     }
@@ -415,7 +1410,14 @@ mod tests {
         create_file(temp.path(), "123.txt", b"one");
         create_file(temp.path(), "321.bin", b"two");
 
-        let files = scan_directory(temp.path(), None, None, &["*".to_string()], &[]);
+        let files = scan_directory(
+            temp.path(),
+            &ScanFilters {
+                exclude_patterns: vec!["*".to_string()],
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert!(files.is_empty());
     }
@@ -431,7 +1433,14 @@ mod tests {
         create_file(&keep_dir, "app.js", b"app");
         create_file(&skip_dir, "lib.js", b"lib");
 
-        let files = scan_directory(temp.path(), None, None, &["node_modules".to_string()], &[]);
+        let files = scan_directory(
+            temp.path(),
+            &ScanFilters {
+                exclude_patterns: vec!["node_modules".to_string()],
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert_eq!(files.len(), 1);
         assert!(files[0].path.ends_with("app.js"));
@@ -443,7 +1452,14 @@ mod tests {
         create_file(temp.path(), "keep.txt", b"keep");
         create_file(temp.path(), "skip.log", b"skip");
 
-        let files = scan_directory(temp.path(), None, None, &[], &["*.txt".to_string()]);
+        let files = scan_directory(
+            temp.path(),
+            &ScanFilters {
+                include_patterns: vec!["*.txt".to_string()],
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert_eq!(files.len(), 1);
         assert!(files[0].path.ends_with("keep.txt"));
@@ -458,11 +1474,12 @@ mod tests {
 
         let files = scan_directory(
             temp.path(),
-            None,
-            None,
-            &[],
-            &["*.txt".to_string(), "*.rs".to_string()],
-        );
+            &ScanFilters {
+                include_patterns: vec!["*.txt".to_string(), "*.rs".to_string()],
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert_eq!(files.len(), 2);
         assert!(files.iter().any(|f| f.path.ends_with("file.txt")));
@@ -479,7 +1496,14 @@ mod tests {
         create_file(&subdir, "nested.txt", b"nested");
         create_file(&subdir, "other.log", b"other");
 
-        let files = scan_directory(temp.path(), None, None, &[], &["*.txt".to_string()]);
+        let files = scan_directory(
+            temp.path(),
+            &ScanFilters {
+                include_patterns: vec!["*.txt".to_string()],
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert_eq!(files.len(), 2);
         assert!(files.iter().any(|f| f.path.ends_with("root.txt")));
@@ -496,7 +1520,14 @@ mod tests {
         create_file(temp.path(), "lib.rs", b"lib");
         create_file(temp.path(), "README.md", b"readme");
 
-        let files = scan_directory(temp.path(), None, None, &[], &["**/*.rs".to_string()]);
+        let files = scan_directory(
+            temp.path(),
+            &ScanFilters {
+                include_patterns: vec!["**/*.rs".to_string()],
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert_eq!(files.len(), 2);
         assert!(files.iter().any(|f| f.path.ends_with("main.rs")));
@@ -513,11 +1544,13 @@ mod tests {
         // Include *.txt but exclude skip.txt
         let files = scan_directory(
             temp.path(),
-            None,
-            None,
-            &["skip.txt".to_string()],
-            &["*.txt".to_string()],
-        );
+            &ScanFilters {
+                exclude_patterns: vec!["skip.txt".to_string()],
+                include_patterns: vec!["*.txt".to_string()],
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert_eq!(files.len(), 1);
         assert!(files[0].path.ends_with("keep.txt"));
@@ -538,11 +1571,13 @@ mod tests {
         // Include *.rs but exclude vendor directory
         let files = scan_directory(
             temp.path(),
-            None,
-            None,
-            &["vendor".to_string()],
-            &["*.rs".to_string()],
-        );
+            &ScanFilters {
+                exclude_patterns: vec!["vendor".to_string()],
+                include_patterns: vec!["*.rs".to_string()],
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert_eq!(files.len(), 2);
         assert!(files.iter().any(|f| f.path.ends_with("main.rs")));
@@ -569,11 +1604,13 @@ mod tests {
         // Include *.rs and *.txt, but exclude test.rs and build directory
         let files = scan_directory(
             temp.path(),
-            None,
-            None,
-            &["test.rs".to_string(), "build".to_string()],
-            &["*.rs".to_string(), "*.txt".to_string()],
-        );
+            &ScanFilters {
+                exclude_patterns: vec!["test.rs".to_string(), "build".to_string()],
+                include_patterns: vec!["*.rs".to_string(), "*.txt".to_string()],
+                ..Default::default()
+            },
+        )
+        .files;
 
         assert_eq!(files.len(), 3);
         assert!(files.iter().any(|f| f.path.ends_with("app.rs")));
@@ -587,7 +1624,7 @@ mod tests {
         create_file(temp.path(), "file.txt", b"txt");
         create_file(temp.path(), "file.rs", b"rs");
 
-        let files = scan_directory(temp.path(), None, None, &[], &[]);
+        let files = scan_directory(temp.path(), &ScanFilters::default()).files;
 
         assert_eq!(files.len(), 2);
     }
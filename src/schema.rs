@@ -0,0 +1,395 @@
+use serde_json::{Value, json};
+
+/// Version of the JSON report format, written to every report as `schema_version`.
+///
+/// Compatibility policy: the version is incremented whenever a field is removed or renamed,
+/// changes its type or meaning, or an optional field becomes required. Adding new fields is
+/// not a breaking change and keeps the version, so parsers should ignore fields they don't
+/// know. Reports with a newer version than the running binary are rejected when read back
+/// (e.g. by `merge-reports`).
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// JSON Schema (draft 2020-12) of the report written by `--format json`
+pub fn report_schema() -> Value {
+    let bounds = json!({
+        "description": "Extrapolated value with its 95% confidence interval",
+        "type": "object",
+        "required": ["estimate", "low", "high"],
+        "properties": {
+            "estimate": { "type": "integer", "minimum": 0 },
+            "low": { "type": "integer", "minimum": 0 },
+            "high": { "type": "integer", "minimum": 0 }
+        }
+    });
+
+    let hot_spots = json!({
+        "type": "array",
+        "items": {
+            "type": "object",
+            "required": ["path", "files", "bytes", "secs", "mb_per_sec"],
+            "properties": {
+                "path": { "type": "string" },
+                "files": { "type": "integer", "minimum": 1 },
+                "bytes": { "type": "integer", "minimum": 0 },
+                "secs": {
+                    "description": "Summed over all threads and hashing stages",
+                    "type": "number",
+                    "minimum": 0
+                },
+                "mb_per_sec": { "type": "number", "minimum": 0 }
+            }
+        }
+    });
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "dedup duplicate report",
+        "type": "object",
+        "required": ["schema_version", "hash_algorithm", "stats", "errors", "groups"],
+        "properties": {
+            "schema_version": {
+                "description": "Version of this format, incremented on breaking changes",
+                "const": SCHEMA_VERSION
+            },
+            "hash_algorithm": {
+                "description": "Algorithm the group hashes were computed with (--hash)",
+                "enum": ["blake3", "xxh3", "sha256"]
+            },
+            "stats": {
+                "type": "object",
+                "required": [
+                    "total_files",
+                    "duplicate_files",
+                    "wasted_bytes",
+                    "already_deduplicated",
+                    "already_linked_bytes",
+                    "vanished_files",
+                    "symlinks",
+                    "unique_inodes",
+                    "hardlinked_inodes"
+                ],
+                "properties": {
+                    "total_files": {
+                        "description": "Number of files scanned",
+                        "type": "integer",
+                        "minimum": 0
+                    },
+                    "duplicate_files": {
+                        "description": "Number of files in duplicate groups, including omitted ones",
+                        "type": "integer",
+                        "minimum": 0
+                    },
+                    "wasted_bytes": {
+                        "description": "Bytes that could be reclaimed by keeping one copy of each group",
+                        "type": "integer",
+                        "minimum": 0
+                    },
+                    "allocated_wasted_bytes": {
+                        "description": "Disk space that could be reclaimed, counting sparse files and hardlinks with the blocks they use; missing in merged reports",
+                        "type": "integer",
+                        "minimum": 0
+                    },
+                    "already_deduplicated": {
+                        "description": "Groups left out because all their files are hardlinked together",
+                        "type": "integer",
+                        "minimum": 0
+                    },
+                    "already_linked_bytes": {
+                        "description": "Bytes of duplicates that are already hardlinks to another copy of their group, not counted in wasted_bytes",
+                        "type": "integer",
+                        "minimum": 0
+                    },
+                    "vanished_files": {
+                        "description": "Scanned files deleted before they could be hashed, not counted in total_files",
+                        "type": "integer",
+                        "minimum": 0
+                    },
+                    "symlinks": {
+                        "description": "Symbolic links seen while scanning, they are never followed",
+                        "type": "integer",
+                        "minimum": 0
+                    },
+                    "unique_inodes": {
+                        "description": "Distinct inodes of the scanned files",
+                        "type": "integer",
+                        "minimum": 0
+                    },
+                    "hardlinked_inodes": {
+                        "description": "Inodes reached through more than one scanned path",
+                        "type": "integer",
+                        "minimum": 0
+                    },
+                    "performance": {
+                        "description": "Timings of the run, missing in merged reports",
+                        "type": "object",
+                        "required": [
+                            "scan_secs",
+                            "size_group_secs",
+                            "partial_hash_secs",
+                            "full_hash_secs",
+                            "hash_secs",
+                            "bytes_hashed",
+                            "throughput_mb_per_sec"
+                        ],
+                        "properties": {
+                            "scan_secs": { "type": "number", "minimum": 0 },
+                            "size_group_secs": { "type": "number", "minimum": 0 },
+                            "partial_hash_secs": {
+                                "description": "Summed over all threads, can exceed the wall time",
+                                "type": "number",
+                                "minimum": 0
+                            },
+                            "full_hash_secs": {
+                                "description": "Summed over all threads, can exceed the wall time",
+                                "type": "number",
+                                "minimum": 0
+                            },
+                            "hash_secs": {
+                                "description": "Wall time of the hashing stage",
+                                "type": "number",
+                                "minimum": 0
+                            },
+                            "bytes_hashed": {
+                                "description": "Bytes read to hash or compare files",
+                                "type": "integer",
+                                "minimum": 0
+                            },
+                            "throughput_mb_per_sec": {
+                                "description": "bytes_hashed per second of hash_secs, in MiB/s",
+                                "type": "number",
+                                "minimum": 0
+                            }
+                        }
+                    }
+                }
+            },
+            "partial": {
+                "description": "Present and true when the run was interrupted, only the groups confirmed until then are listed",
+                "type": "boolean"
+            },
+            "page": {
+                "description": "Present when only a page of the groups is included (--page-size)",
+                "type": "object",
+                "required": ["number", "size", "total_pages", "total_groups"],
+                "properties": {
+                    "number": { "type": "integer", "minimum": 1 },
+                    "size": { "type": "integer", "minimum": 1 },
+                    "total_pages": { "type": "integer", "minimum": 0 },
+                    "total_groups": { "type": "integer", "minimum": 0 }
+                }
+            },
+            "estimate": {
+                "description": "Present when only a sample of the candidates was hashed (--estimate), stats and groups then only cover the sample",
+                "type": "object",
+                "required": [
+                    "sample_rate",
+                    "candidate_groups",
+                    "sampled_groups",
+                    "duplicate_files",
+                    "wasted_bytes"
+                ],
+                "properties": {
+                    "sample_rate": { "type": "number", "exclusiveMinimum": 0, "maximum": 1 },
+                    "candidate_groups": { "type": "integer", "minimum": 0 },
+                    "sampled_groups": { "type": "integer", "minimum": 0 },
+                    "duplicate_files": bounds,
+                    "wasted_bytes": bounds
+                }
+            },
+            "directories": {
+                "description": "Wasted space per directory, most first (--by-directory)",
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["path", "duplicate_files", "wasted_bytes"],
+                    "properties": {
+                        "path": { "type": "string" },
+                        "duplicate_files": {
+                            "description": "Duplicates in the directory, not counting kept copies",
+                            "type": "integer",
+                            "minimum": 1
+                        },
+                        "wasted_bytes": { "type": "integer", "minimum": 0 }
+                    }
+                }
+            },
+            "io_profile": {
+                "description": "The files and directories that took the longest to hash, slowest first (--profile-io)",
+                "type": "object",
+                "required": ["files", "directories"],
+                "properties": {
+                    "files": hot_spots,
+                    "directories": hot_spots
+                }
+            },
+            "contained": {
+                "description": "Files whose contents are the start of larger files (--find-contained)",
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["path", "size", "contained_in"],
+                    "properties": {
+                        "path": { "type": "string" },
+                        "size": { "type": "integer" },
+                        "contained_in": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "required": ["path", "size"],
+                                "properties": {
+                                    "path": { "type": "string" },
+                                    "size": { "type": "integer" }
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            "errors": {
+                "description": "Files and directories that could not be scanned, duplicates in them may be missing",
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["path", "error"],
+                    "properties": {
+                        "path": { "type": "string" },
+                        "error": { "type": "string" }
+                    }
+                }
+            },
+            "groups": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["id", "hash", "size", "files", "devices", "cross_filesystem"],
+                    "properties": {
+                        "id": {
+                            "description": "Identifier derived from the content hash, stable across runs",
+                            "type": "string",
+                            "pattern": "^[0-9a-f]{16}$"
+                        },
+                        "hash": {
+                            "description": "Hash of the content in hex (see hash_algorithm), of the file name with --match name",
+                            "type": "string",
+                            "pattern": "^[0-9a-f]{32}([0-9a-f]{32})?$"
+                        },
+                        "size": {
+                            "description": "Size of each file in bytes",
+                            "type": "integer",
+                            "minimum": 0
+                        },
+                        "files": {
+                            "description": "Paths of the files, sorted",
+                            "type": "array",
+                            "items": { "type": "string" }
+                        },
+                        "devices": {
+                            "description": "Distinct devices (filesystems) the files live on",
+                            "type": "array",
+                            "items": { "type": "integer", "minimum": 0 }
+                        },
+                        "cross_filesystem": {
+                            "description": "Whether the files span more than one filesystem",
+                            "type": "boolean"
+                        },
+                        "allocated_wasted_bytes": {
+                            "description": "Disk space that could be reclaimed in this group; missing in merged reports",
+                            "type": "integer",
+                            "minimum": 0
+                        },
+                        "omitted_files": {
+                            "description": "Files left out of `files` because of --max-group-size",
+                            "type": "integer",
+                            "minimum": 1
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contained::{ContainedFile, Container};
+    use crate::estimate;
+    use crate::hasher::{HashGroup, hash_bytes};
+    use crate::output::{DuplicateReport, PerformanceStats};
+    use crate::profile::Profiler;
+    use crate::scanner::{FileEntry, ScanError};
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    /// Check that every key of `value` is declared in `schema` and every required key is present
+    fn check(value: &Value, schema: &Value, location: &str) {
+        match value {
+            Value::Object(object) => {
+                let properties = schema["properties"].as_object().unwrap();
+                for (key, child) in object {
+                    let child_schema = properties
+                        .get(key)
+                        .unwrap_or_else(|| panic!("{}.{} is not in the schema", location, key));
+                    check(child, child_schema, &format!("{}.{}", location, key));
+                }
+                for key in schema["required"].as_array().into_iter().flatten() {
+                    let key = key.as_str().unwrap();
+                    assert!(object.contains_key(key), "{}.{} is missing", location, key);
+                }
+            }
+            Value::Array(items) if schema.get("items").is_some() => {
+                for item in items {
+                    check(item, &schema["items"], location);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_schema_matches_report() {
+        let files = ["/a", "/b", "/c"]
+            .iter()
+            .map(|path| FileEntry {
+                path: PathBuf::from(path),
+                size: 1,
+                ..Default::default()
+            })
+            .collect();
+        let mut report = DuplicateReport::from_groups(
+            vec![HashGroup {
+                hash: hash_bytes(b"a"),
+                files,
+            }],
+            3,
+        );
+        report.stats.performance = Some(PerformanceStats::default());
+        report.partial = true;
+        let (sampled, sample) = estimate::sample(vec![report.groups[0].files.clone()], 1.0);
+        assert_eq!(sampled.len(), 1);
+        report.estimate = Some(sample.extrapolate(&report.groups));
+        report.errors.push(ScanError {
+            path: PathBuf::from("/unreadable"),
+            error: "Permission denied (os error 13)".to_string(),
+        });
+        report.limit_group_size(2);
+        report.roll_up_directories();
+        let profiler = Profiler::default();
+        profiler.record(Path::new("/a"), Duration::from_secs(1), 1);
+        report.io_profile = Some(profiler.hot_spots(10));
+        report.contained = Some(vec![ContainedFile {
+            path: PathBuf::from("/a.part"),
+            size: 1,
+            contained_in: vec![Container {
+                path: PathBuf::from("/a"),
+                size: 2,
+            }],
+        }]);
+        let report = report.page(1, 10);
+
+        let value = serde_json::to_value(&report).unwrap();
+
+        assert_eq!(value["schema_version"], SCHEMA_VERSION);
+        check(&value, &report_schema(), "report");
+    }
+}
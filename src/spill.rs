@@ -0,0 +1,389 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::scanner::{FileEntry, TreeStats};
+use crate::util;
+
+/// Smallest memory budget accepted for `--max-memory`, smaller ones would write a run file
+/// for every few files
+pub const MIN_MEMORY: u64 = 1 << 20;
+
+/// Memory taken by a buffered file, its path included
+fn entry_bytes(file: &FileEntry) -> usize {
+    std::mem::size_of::<FileEntry>() + file.path.as_os_str().len()
+}
+
+fn write_u64(out: &mut impl Write, value: u64) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Read the u64 a record starts with, `None` at the end of the file
+fn read_start(input: &mut impl Read) -> io::Result<Option<u64>> {
+    match read_u64(input) {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn write_entry(out: &mut impl Write, file: &FileEntry) -> io::Result<()> {
+    write_u64(out, file.size)?;
+    write_u64(out, file.allocated)?;
+    write_u64(out, file.dev)?;
+    write_u64(out, file.ino)?;
+    let (flag, offset) = match file.modified.map(|time| time.duration_since(UNIX_EPOCH)) {
+        None => (0, Duration::ZERO),
+        Some(Ok(after)) => (1, after),
+        Some(Err(before)) => (2, before.duration()),
+    };
+    out.write_all(&[flag])?;
+    write_u64(out, offset.as_secs())?;
+    out.write_all(&offset.subsec_nanos().to_le_bytes())?;
+    let path = file.path.as_os_str().as_bytes();
+    write_u64(out, path.len() as u64)?;
+    out.write_all(path)
+}
+
+/// Read the next file written by [`write_entry`], `None` at the end of the file
+fn read_entry(input: &mut impl Read) -> io::Result<Option<FileEntry>> {
+    let Some(size) = read_start(input)? else {
+        return Ok(None);
+    };
+    let allocated = read_u64(input)?;
+    let dev = read_u64(input)?;
+    let ino = read_u64(input)?;
+    let mut flag = [0; 1];
+    input.read_exact(&mut flag)?;
+    let secs = read_u64(input)?;
+    let mut nanos = [0; 4];
+    input.read_exact(&mut nanos)?;
+    let offset = Duration::new(secs, u32::from_le_bytes(nanos));
+    let modified = match flag[0] {
+        0 => None,
+        1 => Some(UNIX_EPOCH + offset),
+        _ => Some(UNIX_EPOCH - offset),
+    };
+    let mut path = vec![0; read_u64(input)? as usize];
+    input.read_exact(&mut path)?;
+
+    Ok(Some(FileEntry {
+        path: PathBuf::from(OsString::from_vec(path)),
+        size,
+        allocated,
+        modified,
+        dev,
+        ino,
+        archived: None,
+        partial: None,
+        reference: false,
+    }))
+}
+
+/// Temporary directory holding the files of a spill, removed with it
+#[derive(Debug)]
+struct SpillDir(PathBuf);
+
+impl SpillDir {
+    fn create(parent: &Path) -> io::Result<Self> {
+        static CREATED: AtomicUsize = AtomicUsize::new(0);
+        loop {
+            let n = CREATED.fetch_add(1, Ordering::Relaxed);
+            let path = parent.join(format!("dedup-spill-{}-{}", std::process::id(), n));
+            match fs::create_dir(&path) {
+                Ok(()) => return Ok(SpillDir(path)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for SpillDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Files of a scan too large to keep in memory, grouped by size on disk (`--max-memory`).
+///
+/// Files are buffered until they take up the memory budget, then sorted by size and
+/// written to a run file in a temporary directory. [`SizeSpill::group`] merges the sorted
+/// runs into the candidate groups, holding the files of a single size at a time.
+#[derive(Debug)]
+pub struct SizeSpill {
+    dir: SpillDir,
+    max_memory: usize,
+    buffered: Vec<FileEntry>,
+    buffered_bytes: usize,
+    runs: Vec<PathBuf>,
+    /// Number of files pushed
+    files: usize,
+}
+
+impl SizeSpill {
+    /// Spill to a new directory under `parent`, buffering up to about `max_memory` bytes
+    pub fn new(parent: &Path, max_memory: u64) -> io::Result<Self> {
+        Ok(SizeSpill {
+            dir: SpillDir::create(parent)?,
+            max_memory: usize::try_from(max_memory).unwrap_or(usize::MAX),
+            buffered: Vec::new(),
+            buffered_bytes: 0,
+            runs: Vec::new(),
+            files: 0,
+        })
+    }
+
+    /// Spill all files received from a scan, until its sender is dropped
+    pub fn collect(mut self, received: Receiver<FileEntry>) -> io::Result<Self> {
+        for file in received {
+            self.push(file)?;
+        }
+        Ok(self)
+    }
+
+    pub fn push(&mut self, file: FileEntry) -> io::Result<()> {
+        self.files += 1;
+        self.buffered_bytes += entry_bytes(&file);
+        self.buffered.push(file);
+        if self.buffered_bytes >= self.max_memory {
+            self.write_run()?;
+        }
+        Ok(())
+    }
+
+    /// Number of files pushed so far
+    pub fn len(&self) -> usize {
+        self.files
+    }
+
+    /// Sort the buffered files by size and write them to a new run file
+    fn write_run(&mut self) -> io::Result<()> {
+        self.buffered.sort_unstable_by_key(|file| file.size);
+        let path = self.dir.0.join(format!("run-{}", self.runs.len()));
+        let mut out = BufWriter::new(File::create(&path)?);
+        for file in self.buffered.drain(..) {
+            write_entry(&mut out, &file)?;
+        }
+        out.flush()?;
+        self.runs.push(path);
+        self.buffered_bytes = 0;
+        Ok(())
+    }
+
+    /// Merge the runs into groups of files with the same size, keeping the groups of two or
+    /// more files. The inodes of all files are counted in `tree` along the way.
+    pub fn group(mut self, tree: &mut TreeStats) -> io::Result<SpilledGroups> {
+        if !self.buffered.is_empty() {
+            self.write_run()?;
+        }
+        let mut runs = self
+            .runs
+            .iter()
+            .map(|path| File::open(path).map(BufReader::new))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        // The smallest unmerged file of each run
+        let mut heads = Vec::with_capacity(runs.len());
+        let mut heap = BinaryHeap::new();
+        for (index, run) in runs.iter_mut().enumerate() {
+            let head = read_entry(run)?;
+            if let Some(ref file) = head {
+                heap.push(Reverse((file.size, index)));
+            }
+            heads.push(head);
+        }
+
+        let path = self.dir.0.join("candidates");
+        let mut out = BufWriter::new(File::create(&path)?);
+        let mut files = 0;
+        let mut written = |out: &mut BufWriter<File>, same_size: &[FileEntry]| {
+            if write_group(out, same_size, tree)? {
+                files += same_size.len();
+            }
+            io::Result::Ok(())
+        };
+        let mut same_size: Vec<FileEntry> = Vec::new();
+        while let Some(Reverse((size, index))) = heap.pop() {
+            if same_size.first().is_some_and(|file| file.size != size) {
+                written(&mut out, &same_size)?;
+                same_size.clear();
+            }
+            same_size.extend(heads[index].take());
+            heads[index] = read_entry(&mut runs[index])?;
+            if let Some(ref file) = heads[index] {
+                heap.push(Reverse((file.size, index)));
+            }
+        }
+        written(&mut out, &same_size)?;
+        out.flush()?;
+
+        drop(runs);
+        for run in &self.runs {
+            fs::remove_file(run)?;
+        }
+        Ok(SpilledGroups {
+            input: Some(BufReader::new(File::open(&path)?)),
+            files,
+            dir: self.dir,
+        })
+    }
+}
+
+/// Candidate groups merged by [`SizeSpill::group`], read back from disk one group at a time
+#[derive(Debug)]
+pub struct SpilledGroups {
+    input: Option<BufReader<File>>,
+    /// Number of files in the candidate groups
+    pub files: usize,
+    /// Kept until the groups were read
+    dir: SpillDir,
+}
+
+/// Count the inodes of files of the same size and write them as a group if there are at
+/// least two, returning whether they were. Hardlinks always have the same size, so each
+/// inode is counted once.
+fn write_group(
+    out: &mut impl Write,
+    same_size: &[FileEntry],
+    tree: &mut TreeStats,
+) -> io::Result<bool> {
+    tree.add_files(same_size);
+    if same_size.len() < 2 {
+        return Ok(false);
+    }
+    write_u64(out, same_size.len() as u64)?;
+    for file in same_size {
+        write_entry(out, file)?;
+    }
+    Ok(true)
+}
+
+impl SpilledGroups {
+    fn read_group(&mut self) -> io::Result<Option<Vec<FileEntry>>> {
+        let Some(input) = self.input.as_mut() else {
+            return Ok(None);
+        };
+        let Some(len) = read_start(input)? else {
+            return Ok(None);
+        };
+        let mut group = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            let file = read_entry(input)?.ok_or(io::ErrorKind::UnexpectedEof)?;
+            group.push(file);
+        }
+        Ok(Some(group))
+    }
+}
+
+impl Iterator for SpilledGroups {
+    type Item = Vec<FileEntry>;
+
+    fn next(&mut self) -> Option<Vec<FileEntry>> {
+        match self.read_group() {
+            Ok(group) => group,
+            Err(e) => {
+                util::warn(format_args!(
+                    "could not read the candidates spilled to {}: {}",
+                    self.dir.0.display(),
+                    e
+                ));
+                self.input = None;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grouping;
+    use tempfile::TempDir;
+
+    fn file(path: &str, size: u64, ino: u64) -> FileEntry {
+        FileEntry {
+            path: PathBuf::from(path),
+            size,
+            allocated: size,
+            modified: Some(UNIX_EPOCH + Duration::new(1_700_000_000, 123)),
+            dev: 1,
+            ino,
+            archived: None,
+            partial: None,
+            reference: false,
+        }
+    }
+
+    fn paths(groups: Vec<Vec<FileEntry>>) -> Vec<Vec<PathBuf>> {
+        let mut paths: Vec<Vec<PathBuf>> = groups
+            .into_iter()
+            .map(|group| {
+                let mut paths: Vec<_> = group.into_iter().map(|file| file.path).collect();
+                paths.sort();
+                paths
+            })
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn test_spilled_groups_match_group_by_size() {
+        let temp = TempDir::new().unwrap();
+        let files: Vec<FileEntry> = (0..200)
+            .map(|i| file(&format!("/data/{}", i), i % 37 * 100 + i % 2, i + 1))
+            .collect();
+        // A budget this small writes a run for every few files
+        let mut spill = SizeSpill::new(temp.path(), 500).unwrap();
+        for file in files.clone() {
+            spill.push(file).unwrap();
+        }
+        assert_eq!(spill.len(), 200);
+        assert!(spill.runs.len() > 10);
+
+        let mut tree = TreeStats::default();
+        let spilled = spill.group(&mut tree).unwrap();
+        let expected = grouping::group_by_size(files);
+        assert_eq!(spilled.files, 200);
+        assert_eq!(tree.unique_inodes, 200);
+
+        let groups: Vec<_> = spilled.collect();
+        assert_eq!(groups.len(), expected.len());
+        assert_eq!(groups[0][0].modified, expected[0][0].modified);
+        assert_eq!(paths(groups), paths(expected));
+        // The temporary files are removed with the groups
+        assert_eq!(fs::read_dir(temp.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_hardlinks_are_counted_once() {
+        let temp = TempDir::new().unwrap();
+        let mut spill = SizeSpill::new(temp.path(), MIN_MEMORY).unwrap();
+        spill.push(file("/a", 10, 5)).unwrap();
+        spill.push(file("/b", 10, 5)).unwrap();
+        spill.push(file("/c", 20, 6)).unwrap();
+
+        let mut tree = TreeStats::default();
+        let spilled = spill.group(&mut tree).unwrap();
+        assert_eq!(tree.unique_inodes, 2);
+        assert_eq!(tree.hardlinked_inodes, 1);
+        assert_eq!(
+            paths(spilled.collect()),
+            [[PathBuf::from("/a"), PathBuf::from("/b")]]
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// Files read at the same time from a spinning disk, more make its heads seek back and
+/// forth between them
+pub const ROTATIONAL_IO_WORKERS: usize = 1;
+
+/// Files read at the same time from a network filesystem, enough to hide the latency of
+/// each request without flooding the server
+pub const NETWORK_IO_WORKERS: usize = 4;
+
+/// What a scanned directory is stored on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageKind {
+    /// A spinning disk
+    Rotational,
+    /// An SSD, NVMe drive or anything else without seek times
+    SolidState,
+    /// A network filesystem (NFS, SMB, ...)
+    Network,
+    /// Couldn't be detected, e.g. on other systems than Linux or on filesystems spanning
+    /// several devices
+    Unknown,
+}
+
+impl StorageKind {
+    /// Number of files read at the same time from storage of this kind unless
+    /// `--io-workers` is set, `None` to read as many as there are threads
+    pub fn default_io_workers(self) -> Option<usize> {
+        match self {
+            StorageKind::Rotational => Some(ROTATIONAL_IO_WORKERS),
+            StorageKind::Network => Some(NETWORK_IO_WORKERS),
+            StorageKind::SolidState | StorageKind::Unknown => None,
+        }
+    }
+}
+
+/// `f_type` of network filesystems reported by `statfs`, from `linux/magic.h`
+const NETWORK_FILESYSTEMS: [u64; 9] = [
+    0x6969,     // NFS
+    0x517B,     // SMB
+    0xFF534D42, // CIFS
+    0xFE534D42, // SMB2
+    0x5346414F, // AFS
+    0x00C36400, // Ceph
+    0x01021997, // 9p
+    0x73757245, // Coda
+    0x0BD00BD0, // Lustre
+];
+
+/// Whether a filesystem of type `f_type` is a network filesystem
+fn is_network_filesystem(f_type: u64) -> bool {
+    NETWORK_FILESYSTEMS.contains(&(f_type & 0xFFFF_FFFF))
+}
+
+/// Major and minor number of the device with ID `dev`, as glibc's `major()` and `minor()`
+fn major_minor(dev: u64) -> (u64, u64) {
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+    let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+    (major, minor)
+}
+
+/// Whether the block device at `device_dir` in sysfs (`/sys/dev/block/MAJOR:MINOR`) is a
+/// spinning disk. Partitions have no queue of their own, the disk holding them is checked.
+fn is_rotational(device_dir: &Path) -> Option<bool> {
+    let device_dir = fs::canonicalize(device_dir).ok()?;
+    let flag = fs::read_to_string(device_dir.join("queue/rotational"))
+        .ok()
+        .or_else(|| fs::read_to_string(device_dir.parent()?.join("queue/rotational")).ok())?;
+    match flag.trim() {
+        "1" => Some(true),
+        "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Type of the filesystem `path` is on, as reported by `statfs`
+#[cfg(target_os = "linux")]
+fn filesystem_type(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    // SAFETY: a valid C string and a statfs struct the kernel writes to
+    let ret = unsafe { libc::statfs(path.as_ptr(), &mut stat) };
+    (ret == 0).then_some(stat.f_type as u64)
+}
+
+/// Detect what `path` is stored on, from its filesystem type and the rotational flag of its
+/// block device in sysfs
+#[cfg(target_os = "linux")]
+pub fn detect(path: &Path) -> StorageKind {
+    if filesystem_type(path).is_some_and(is_network_filesystem) {
+        return StorageKind::Network;
+    }
+    let Ok(metadata) = fs::metadata(path) else {
+        return StorageKind::Unknown;
+    };
+    let (major, minor) = major_minor(metadata.dev());
+    let device_dir = PathBuf::from(format!("/sys/dev/block/{}:{}", major, minor));
+    match is_rotational(&device_dir) {
+        Some(true) => StorageKind::Rotational,
+        Some(false) => StorageKind::SolidState,
+        None => StorageKind::Unknown,
+    }
+}
+
+/// Storage is only detected on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn detect(_path: &Path) -> StorageKind {
+    StorageKind::Unknown
+}
+
+/// Number of files to read at the same time from the devices of the scanned `roots`, for
+/// those whose storage needs fewer readers than there are threads. Several roots on the same
+/// device share its limit.
+pub fn device_io_workers(roots: &[PathBuf]) -> HashMap<u64, usize> {
+    let mut workers = HashMap::new();
+    for root in roots {
+        let Ok(metadata) = fs::metadata(root) else {
+            continue;
+        };
+        if let Some(limit) = detect(root).default_io_workers() {
+            workers
+                .entry(metadata.dev())
+                .and_modify(|workers: &mut usize| *workers = (*workers).min(limit))
+                .or_insert(limit);
+        }
+    }
+    workers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_major_minor() {
+        // makedev(8, 1), /dev/sda1
+        assert_eq!(major_minor(0x801), (8, 1));
+        // makedev(259, 65536), an NVMe partition with a large minor number
+        assert_eq!(major_minor(0x1001_0300), (259, 65536));
+    }
+
+    #[test]
+    fn test_network_filesystems() {
+        assert!(is_network_filesystem(0x6969));
+        assert!(is_network_filesystem(0xFF534D42));
+        // ext4 and tmpfs
+        assert!(!is_network_filesystem(0xEF53));
+        assert!(!is_network_filesystem(0x01021994));
+    }
+
+    #[test]
+    fn test_rotational_flag_of_disks_and_partitions() {
+        let temp = TempDir::new().unwrap();
+        let disk = temp.path().join("sda");
+        fs::create_dir_all(disk.join("queue")).unwrap();
+        fs::create_dir_all(disk.join("sda1")).unwrap();
+        fs::write(disk.join("queue/rotational"), "1\n").unwrap();
+
+        assert_eq!(is_rotational(&disk), Some(true));
+        assert_eq!(is_rotational(&disk.join("sda1")), Some(true));
+        fs::write(disk.join("queue/rotational"), "0\n").unwrap();
+        assert_eq!(is_rotational(&disk.join("sda1")), Some(false));
+        assert_eq!(is_rotational(&temp.path().join("missing")), None);
+    }
+
+    #[test]
+    fn test_default_io_workers() {
+        assert_eq!(
+            StorageKind::Rotational.default_io_workers(),
+            Some(ROTATIONAL_IO_WORKERS)
+        );
+        assert_eq!(
+            StorageKind::Network.default_io_workers(),
+            Some(NETWORK_IO_WORKERS)
+        );
+        assert_eq!(StorageKind::SolidState.default_io_workers(), None);
+        assert_eq!(StorageKind::Unknown.default_io_workers(), None);
+    }
+}
@@ -0,0 +1,174 @@
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::str::FromStr;
+
+use crate::output::DuplicateGroup;
+use crate::scanner::FileEntry;
+
+/// Part of a parsed `--format-template`
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(Vec<u8>),
+    /// `{group}`: 1-based number of the group in the report
+    Group,
+    /// `{id}`: stable identifier of the group
+    Id,
+    /// `{hash}`: hash of the full contents (algorithm from `--hash`)
+    Hash,
+    /// `{size}`: size of the file in bytes
+    Size,
+    /// `{path}`: path of the file
+    Path,
+    /// `{is_original}`: `true` for the file the actions keep, `false` for the others
+    IsOriginal,
+}
+
+/// Template of a line printed for each file of a report, e.g. `{hash}\t{size}\t{path}`.
+///
+/// Supports the escapes `\t`, `\n`, `\0` and `\\`, a newline is added after each line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        let mut literal = Vec::new();
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('t') => literal.push(b'\t'),
+                    Some('n') => literal.push(b'\n'),
+                    Some('0') => literal.push(b'\0'),
+                    Some('\\') => literal.push(b'\\'),
+                    other => {
+                        return Err(format!(
+                            "unknown escape \\{}, use \\t, \\n, \\0 or \\\\",
+                            other.map(String::from).unwrap_or_default()
+                        ));
+                    }
+                },
+                '{' => {
+                    let rest = chars.as_str();
+                    let Some(end) = rest.find('}') else {
+                        return Err(format!("unclosed placeholder {{{}", rest));
+                    };
+                    let name = &rest[..end];
+                    chars = rest[end + 1..].chars();
+                    let segment = match name {
+                        "group" => Segment::Group,
+                        "id" => Segment::Id,
+                        "hash" => Segment::Hash,
+                        "size" => Segment::Size,
+                        "path" => Segment::Path,
+                        "is_original" => Segment::IsOriginal,
+                        _ => {
+                            return Err(format!(
+                                "unknown placeholder {{{}}}, use {{group}}, {{id}}, {{hash}}, {{size}}, {{path}} or {{is_original}}",
+                                name
+                            ));
+                        }
+                    };
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(segment);
+                }
+                c => {
+                    let mut buffer = [0u8; 4];
+                    literal.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                }
+            }
+        }
+
+        literal.push(b'\n');
+        segments.push(Segment::Literal(literal));
+        Ok(Self { segments })
+    }
+}
+
+impl Template {
+    /// Write the line for `file`, the `number`th group of the report
+    pub fn write_line(
+        &self,
+        out: &mut impl Write,
+        number: usize,
+        group: &DuplicateGroup,
+        file: &FileEntry,
+        is_original: bool,
+    ) -> io::Result<()> {
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(bytes) => out.write_all(bytes)?,
+                Segment::Group => write!(out, "{}", number)?,
+                Segment::Id => out.write_all(group.id.as_bytes())?,
+                Segment::Hash => out.write_all(group.hash.to_hex().as_bytes())?,
+                Segment::Size => write!(out, "{}", group.size)?,
+                Segment::Path => out.write_all(file.path.as_os_str().as_bytes())?,
+                Segment::IsOriginal => write!(out, "{}", is_original)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::hash_bytes;
+    use std::path::PathBuf;
+
+    fn group() -> DuplicateGroup {
+        let files = ["/a/copy", "/a"]
+            .iter()
+            .map(|p| FileEntry {
+                path: PathBuf::from(p),
+                size: 3,
+                ..Default::default()
+            })
+            .collect();
+        DuplicateGroup::new(hash_bytes(b"abc"), 3, files)
+    }
+
+    fn line(template: &str, is_original: bool) -> String {
+        let template: Template = template.parse().unwrap();
+        let group = group();
+        let mut out = Vec::new();
+        template
+            .write_line(&mut out, 2, &group, &group.files[0], is_original)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_placeholders() {
+        assert_eq!(
+            line("{group}\\t{size}\\t{path}\\t{is_original}", false),
+            "2\t3\t/a/copy\tfalse\n"
+        );
+        assert_eq!(
+            line("{hash}", true),
+            format!("{}\n", hash_bytes(b"abc").to_hex())
+        );
+        assert_eq!(line("original={is_original}", true), "original=true\n");
+        assert_eq!(line("{id}", true), format!("{}\n", group().id));
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert_eq!(line("a\\\\b\\0", false), "a\\b\0\n");
+        assert_eq!(line("ü {size}", false), "ü 3\n");
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        assert!("{nope}".parse::<Template>().is_err());
+        assert!("\\x".parse::<Template>().is_err());
+        assert!("{path".parse::<Template>().is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Point in time given on the command line, e.g. for `--newer-than` and `--older-than`.
+///
+/// Either an age relative to the start of the run (`30d`, `12h`) or a timestamp in UTC
+/// (`2024-01-31`, `2024-01-31T08:30:00` or `@1706689800` in seconds since the epoch).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeSpec {
+    /// Time before now
+    Ago(Duration),
+    /// Fixed point in time
+    At(SystemTime),
+}
+
+impl TimeSpec {
+    /// The point in time, with ages counted back from `now`. Ages beyond what can be
+    /// represented resolve to the epoch.
+    pub fn resolve(self, now: SystemTime) -> SystemTime {
+        match self {
+            TimeSpec::Ago(age) => now.checked_sub(age).unwrap_or(UNIX_EPOCH),
+            TimeSpec::At(time) => time,
+        }
+    }
+}
+
+impl FromStr for TimeSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid time '{}', use an age like 30d, 12h, 45m, 2w or a UTC timestamp like 2024-01-31 or 2024-01-31T08:30:00",
+                spec
+            )
+        };
+
+        if let Some(seconds) = spec.strip_prefix('@') {
+            let seconds: u64 = seconds.parse().map_err(|_| invalid())?;
+            return Ok(TimeSpec::At(UNIX_EPOCH + Duration::from_secs(seconds)));
+        }
+
+        if let Some(unit) = spec.chars().last()
+            && let Some(unit_secs) = unit_seconds(unit)
+            && let Ok(amount) = spec[..spec.len() - 1].parse::<u64>()
+        {
+            let secs = amount.checked_mul(unit_secs).ok_or_else(invalid)?;
+            return Ok(TimeSpec::Ago(Duration::from_secs(secs)));
+        }
+
+        parse_timestamp(spec)
+            .map(|secs| TimeSpec::At(UNIX_EPOCH + Duration::from_secs(secs)))
+            .ok_or_else(invalid)
+    }
+}
+
+/// Seconds in one of the units of an age
+fn unit_seconds(unit: char) -> Option<u64> {
+    match unit {
+        's' => Some(1),
+        'm' => Some(60),
+        'h' => Some(60 * 60),
+        'd' => Some(24 * 60 * 60),
+        'w' => Some(7 * 24 * 60 * 60),
+        _ => None,
+    }
+}
+
+/// Seconds since the epoch of a UTC timestamp `YYYY-MM-DD[(T| )HH:MM[:SS]]`, `None` if it is
+/// malformed or before 1970
+fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let (date, time) = match timestamp.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (timestamp, None),
+    };
+
+    let mut date_parts = date.splitn(3, '-');
+    let year: i64 = date_parts.next()?.parse().ok()?;
+    let month: u32 = date_parts.next()?.parse().ok()?;
+    let day: u32 = date_parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    let mut seconds = 0;
+    if let Some(time) = time {
+        let mut time_parts = time.splitn(3, ':');
+        let hour: u64 = time_parts.next()?.parse().ok()?;
+        let minute: u64 = time_parts.next()?.parse().ok()?;
+        let second: u64 = time_parts.next().map_or(Some(0), |s| s.parse().ok())?;
+        if hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+        seconds = hour * 3600 + minute * 60 + second;
+    }
+
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    Some(days * 24 * 60 * 60 + seconds)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days between 1970-01-01 and the given date of the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // Count years from March, so the leap day is the last day of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_from_march = (i64::from(month) + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> TimeSpec {
+        TimeSpec::At(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn test_parse_ages() {
+        assert_eq!(
+            "30d".parse(),
+            Ok(TimeSpec::Ago(Duration::from_secs(30 * 86400)))
+        );
+        assert_eq!(
+            "12h".parse(),
+            Ok(TimeSpec::Ago(Duration::from_secs(12 * 3600)))
+        );
+        assert_eq!(
+            "45m".parse(),
+            Ok(TimeSpec::Ago(Duration::from_secs(45 * 60)))
+        );
+        assert_eq!(
+            "2w".parse(),
+            Ok(TimeSpec::Ago(Duration::from_secs(14 * 86400)))
+        );
+        assert_eq!("10s".parse(), Ok(TimeSpec::Ago(Duration::from_secs(10))));
+    }
+
+    #[test]
+    fn test_parse_timestamps() {
+        assert_eq!("1970-01-01".parse(), Ok(at(0)));
+        assert_eq!("2024-01-31".parse(), Ok(at(1_706_659_200)));
+        assert_eq!("2024-01-31T08:30:00".parse(), Ok(at(1_706_689_800)));
+        assert_eq!("2024-01-31 08:30".parse(), Ok(at(1_706_689_800)));
+        assert_eq!("2024-02-29".parse(), Ok(at(1_709_164_800)));
+        assert_eq!("@1706689800".parse(), Ok(at(1_706_689_800)));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for spec in [
+            "",
+            "d",
+            "30",
+            "30y",
+            "-1d",
+            "2023-02-29",
+            "2024-13-01",
+            "1969-12-31",
+            "2024-01-31T25:00",
+        ] {
+            assert!(spec.parse::<TimeSpec>().is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        assert_eq!(
+            TimeSpec::Ago(Duration::from_secs(100)).resolve(now),
+            UNIX_EPOCH + Duration::from_secs(900)
+        );
+        // Ages reaching further back than the system can represent
+        assert_eq!(TimeSpec::Ago(Duration::MAX).resolve(now), UNIX_EPOCH);
+        assert_eq!(at(5).resolve(now), UNIX_EPOCH + Duration::from_secs(5));
+    }
+}
@@ -1,3 +1,21 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether warnings are suppressed (`--format quiet`)
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Suppress warnings for the rest of the run
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Print a warning to stderr, unless warnings are suppressed
+pub fn warn(message: impl Display) {
+    if !QUIET.load(Ordering::Relaxed) {
+        eprintln!("Warning: {}", message);
+    }
+}
+
 /// Format a number with separators (spaces)
 pub fn format_number(n: usize) -> String {
     let s = n.to_string();
@@ -0,0 +1,119 @@
+mod common;
+
+use common::{create_file, dedup, get_all_filenames};
+use predicates::prelude::*;
+use tempfile::TempDir;
+
+#[test]
+fn test_timeout_defers_and_resume_finishes() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a1.txt", b"content a");
+    create_file(dir.path(), "a2.txt", b"content a");
+    create_file(dir.path(), "b1.txt", b"content bb");
+    create_file(dir.path(), "b2.txt", b"content bb");
+    let state = TempDir::new().unwrap();
+    let deferred = state.path().join("deferred.json");
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("json")
+        .arg("--timeout")
+        .arg("0")
+        .arg("--deferred-file")
+        .arg(&deferred)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("4 candidate files in 2 groups"))
+        .get_output()
+        .stdout
+        .clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert!(json["groups"].as_array().unwrap().is_empty());
+
+    let output = dedup()
+        .arg("--format")
+        .arg("json")
+        .arg("--resume-deferred")
+        .arg(&deferred)
+        .arg("--deferred-file")
+        .arg(&deferred)
+        .arg("--timeout")
+        .arg("3600")
+        .assert()
+        .success()
+        .stderr("")
+        .get_output()
+        .stdout
+        .clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let mut names = get_all_filenames(&json);
+    names.sort();
+    assert_eq!(names, vec!["a1.txt", "a2.txt", "b1.txt", "b2.txt"]);
+
+    // Nothing is left for the next run
+    let remaining: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&deferred).unwrap()).unwrap();
+    assert!(remaining["groups"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn test_resume_deferred_missing_file() {
+    let state = TempDir::new().unwrap();
+
+    dedup()
+        .arg("--resume-deferred")
+        .arg(state.path().join("missing.json"))
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("could not read"));
+}
+
+#[test]
+fn test_checkpoint_resumes_stopped_run() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a1.txt", b"content a");
+    create_file(dir.path(), "a2.txt", b"content a");
+    create_file(dir.path(), "b1.txt", b"content bb");
+    create_file(dir.path(), "b2.txt", b"content bb");
+    let state = TempDir::new().unwrap();
+    let checkpoint = state.path().join("checkpoint.json");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--timeout")
+        .arg("0")
+        .arg("--checkpoint")
+        .arg(&checkpoint)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("for --resume"));
+    assert!(checkpoint.exists());
+
+    let output = dedup()
+        .arg("--format")
+        .arg("json")
+        .arg("--checkpoint")
+        .arg(&checkpoint)
+        .arg("--resume")
+        .assert()
+        .success()
+        .stderr("")
+        .get_output()
+        .stdout
+        .clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let mut names = get_all_filenames(&json);
+    names.sort();
+    assert_eq!(names, vec!["a1.txt", "a2.txt", "b1.txt", "b2.txt"]);
+
+    // A finished run leaves nothing to resume
+    assert!(!checkpoint.exists());
+    dedup()
+        .arg("--checkpoint")
+        .arg(&checkpoint)
+        .arg("--resume")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("could not read"));
+}
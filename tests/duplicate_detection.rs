@@ -1,6 +1,7 @@
 mod common;
 
-use common::{create_file, dedup};
+use common::{create_file, dedup, get_all_filenames};
+use predicates::prelude::*;
 use tempfile::TempDir;
 
 #[test]
@@ -26,6 +27,35 @@ fn test_finds_identical_files() {
     assert_eq!(json["stats"]["wasted_bytes"], content.len() as u64);
 }
 
+#[test]
+fn test_finds_duplicates_across_roots() {
+    let data = TempDir::new().unwrap();
+    let backup = TempDir::new().unwrap();
+    create_file(data.path(), "photo.jpg", b"same photo");
+    create_file(backup.path(), "copy.jpg", b"same photo");
+    create_file(backup.path(), "other.jpg", b"other photo");
+
+    let output = dedup()
+        .arg(data.path())
+        .arg(backup.path())
+        // A root given twice is only scanned once
+        .arg(data.path())
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["stats"]["total_files"], 3);
+    assert_eq!(json["groups"].as_array().unwrap().len(), 1);
+    let mut names = get_all_filenames(&json);
+    names.sort();
+    assert_eq!(names, ["copy.jpg", "photo.jpg"]);
+}
+
 #[test]
 fn test_handles_different_files() {
     let dir = TempDir::new().unwrap();
@@ -132,3 +162,486 @@ fn test_three_way_duplicates() {
     let wasted = json["stats"]["wasted_bytes"].as_u64().unwrap();
     assert_eq!(wasted, 2 * b"triple duplicate".len() as u64);
 }
+
+#[test]
+fn test_skip_common_boilerplate() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "pkg_a/__init__.py", b"\n");
+    create_file(dir.path(), "pkg_b/__init__.py", b"\n");
+    create_file(dir.path(), "a.txt", b"real duplicate");
+    create_file(dir.path(), "b.txt", b"real duplicate");
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["groups"].as_array().unwrap().len(), 2);
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--skip-common-boilerplate")
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["groups"].as_array().unwrap().len(), 1);
+    assert_eq!(json["stats"]["duplicate_files"], 2);
+}
+
+#[test]
+fn test_same_relative_path_compares_mirrored_files_only() {
+    let data = TempDir::new().unwrap();
+    let backup = TempDir::new().unwrap();
+
+    create_file(data.path(), "photos/a.jpg", b"photo a");
+    create_file(backup.path(), "photos/a.jpg", b"photo a");
+
+    // Same content but at a different relative path
+    create_file(data.path(), "b.txt", b"moved file");
+    create_file(backup.path(), "old/b.txt", b"moved file");
+
+    // Same relative path but different content
+    create_file(data.path(), "c.txt", b"version 1");
+    create_file(backup.path(), "c.txt", b"version 2");
+
+    let output = dedup()
+        .arg(data.path())
+        .arg("--same-relative-path")
+        .arg(backup.path())
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["stats"]["total_files"], 6);
+    assert_eq!(json["groups"].as_array().unwrap().len(), 1);
+
+    let files = json["groups"][0]["files"].as_array().unwrap();
+    assert_eq!(files.len(), 2);
+    assert!(
+        files
+            .iter()
+            .all(|f| f.as_str().unwrap().ends_with("photos/a.jpg"))
+    );
+}
+
+#[test]
+fn test_confirm_byte_compare() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"duplicate content");
+    create_file(dir.path(), "b.txt", b"duplicate content");
+    create_file(dir.path(), "c.txt", b"different content");
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--confirm")
+        .arg("byte-compare")
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["groups"].as_array().unwrap().len(), 1);
+    assert_eq!(
+        json["groups"][0]["hash"],
+        blake3::hash(b"duplicate content").to_hex().as_str()
+    );
+}
+
+#[test]
+fn test_paranoid_compares_groups() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"duplicate content");
+    create_file(dir.path(), "b.txt", b"duplicate content");
+    create_file(dir.path(), "c.txt", b"different content");
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--paranoid")
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["groups"].as_array().unwrap().len(), 1);
+    assert_eq!(json["groups"][0]["files"].as_array().unwrap().len(), 2);
+
+    dedup()
+        .arg(dir.path())
+        .arg("--paranoid")
+        .arg("--confirm")
+        .arg("normalized-hash")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("--paranoid"));
+}
+
+#[test]
+fn test_confirm_normalized_hash_matches_line_endings() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "unix.txt", b"line 1\nline 2\n");
+    create_file(dir.path(), "windows.txt", b"line 1\r\nline 2\r\n");
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--confirm")
+        .arg("normalized-hash")
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["groups"].as_array().unwrap().len(), 1);
+    assert_eq!(json["groups"][0]["files"].as_array().unwrap().len(), 2);
+
+    // Normalized matches are not identical, they must not be acted on
+    dedup()
+        .arg(dir.path())
+        .arg("--confirm")
+        .arg("normalized-hash")
+        .arg("--action")
+        .arg("hardlink")
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_skip_header_bytes_matches_payloads() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.bin", b"2024-01-01 payload");
+    create_file(dir.path(), "b.bin", b"2025-06-30 payload");
+    create_file(dir.path(), "c.bin", b"2025-06-30 another");
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert!(json["groups"].as_array().unwrap().is_empty());
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--skip-header-bytes")
+        .arg("10")
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let mut names = get_all_filenames(&json);
+    names.sort();
+    assert_eq!(names, vec!["a.bin", "b.bin"]);
+
+    // Files with different headers are not identical, they must not be acted on
+    dedup()
+        .arg(dir.path())
+        .arg("--skip-header-bytes")
+        .arg("10")
+        .arg("--action")
+        .arg("hardlink")
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_match_by_name() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "card/IMG_0001.JPG", b"original photo");
+    create_file(dir.path(), "archive/IMG_0001.JPG", b"edited photo, larger");
+    create_file(dir.path(), "archive/2019/img_0001.jpg", b"another photo");
+    create_file(dir.path(), "archive/IMG_0002.JPG", b"original photo");
+
+    let output = dedup()
+        .arg(dir.path())
+        .args(["--match", "name", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(
+        get_all_filenames(&json),
+        vec!["IMG_0001.JPG", "IMG_0001.JPG"]
+    );
+
+    let output = dedup()
+        .arg(dir.path())
+        .args(["--match", "name", "--ignore-case", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["groups"].as_array().unwrap().len(), 1);
+    assert_eq!(json["stats"]["duplicate_files"], 3);
+
+    // Files with the same name may differ, they must not be acted on
+    dedup()
+        .arg(dir.path())
+        .args(["--match", "name", "--action", "hardlink"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_cross_dir_only() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "docs/report.pdf", b"report");
+    create_file(dir.path(), "docs/report (1).pdf", b"report");
+    create_file(dir.path(), "docs/notes.txt", b"notes");
+    create_file(dir.path(), "backup/notes.txt", b"notes");
+
+    let output = dedup()
+        .arg(dir.path())
+        .args(["--cross-dir-only", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(get_all_filenames(&json), vec!["notes.txt", "notes.txt"]);
+}
+
+#[test]
+fn test_same_dir_only() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "docs/report.pdf", b"report");
+    create_file(dir.path(), "docs/report (1).pdf", b"report");
+    create_file(dir.path(), "backup/report.pdf", b"report");
+    create_file(dir.path(), "docs/notes.txt", b"notes");
+    create_file(dir.path(), "backup/notes.txt", b"notes");
+
+    let output = dedup()
+        .arg(dir.path())
+        .args(["--same-dir-only", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(
+        get_all_filenames(&json),
+        vec!["report (1).pdf", "report.pdf"]
+    );
+    assert_eq!(json["stats"]["duplicate_files"], 2);
+}
+
+#[test]
+fn test_compare_to_reports_files_already_in_reference() {
+    let dir = TempDir::new().unwrap();
+    let archive = dir.path().join("archive");
+    let card = dir.path().join("card");
+    create_file(&archive, "2024/a.jpg", b"photo a");
+    create_file(&archive, "2024/b.jpg", b"photo b");
+    create_file(&archive, "old/b.jpg", b"photo b");
+    create_file(&card, "DCIM/a.jpg", b"photo a");
+    create_file(&card, "DCIM/new.jpg", b"new photo");
+    create_file(&card, "DCIM/new (1).jpg", b"new photo");
+
+    let output = dedup()
+        .arg("--compare-to")
+        .arg(&archive)
+        .arg(&card)
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["groups"].as_array().unwrap().len(), 1);
+    assert_eq!(get_all_filenames(&json), vec!["a.jpg", "a.jpg"]);
+    assert_eq!(json["stats"]["wasted_bytes"], 7);
+
+    dedup()
+        .arg("--compare-to")
+        .arg(&archive)
+        .arg(dir.path())
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains(
+            "overlaps the --compare-to directory",
+        ));
+}
+
+#[test]
+fn test_isolate_only_groups_within_each_root() {
+    let dir = TempDir::new().unwrap();
+    let first = dir.path().join("first");
+    let second = dir.path().join("second");
+    create_file(&first, "a.txt", b"shared");
+    create_file(&second, "a.txt", b"shared");
+    create_file(&second, "sub/b.txt", b"shared");
+    create_file(&first, "c.txt", b"only across");
+    create_file(&second, "c.txt", b"only across");
+
+    let output = dedup()
+        .arg(&first)
+        .arg(&second)
+        .args(["--isolate", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let groups = json["groups"].as_array().unwrap();
+    assert_eq!(groups.len(), 1);
+    let files = groups[0]["files"].as_array().unwrap();
+    assert_eq!(files.len(), 2);
+    assert!(
+        files
+            .iter()
+            .all(|f| f.as_str().unwrap().starts_with(second.to_str().unwrap()))
+    );
+}
+
+#[test]
+fn test_mmap_hashes_large_files() {
+    let dir = TempDir::new().unwrap();
+    let content = vec![7u8; 17 * 1024 * 1024];
+    let mut different = content.clone();
+    *different.last_mut().unwrap() = 8;
+    create_file(dir.path(), "a.bin", &content);
+    create_file(dir.path(), "b.bin", &content);
+    create_file(dir.path(), "c.bin", &different);
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--mmap")
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["groups"].as_array().unwrap().len(), 1);
+    assert_eq!(
+        json["groups"][0]["hash"],
+        blake3::hash(&content).to_hex().as_str()
+    );
+}
+
+#[test]
+fn test_io_workers_limit_reads() {
+    let dir = TempDir::new().unwrap();
+    for i in 0..20 {
+        create_file(dir.path(), &format!("a{}.txt", i), b"duplicate content");
+        create_file(
+            dir.path(),
+            &format!("b{}.txt", i),
+            format!("unique {}", i).as_bytes(),
+        );
+    }
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--io-workers")
+        .arg("1")
+        .arg("--jobs")
+        .arg("4")
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["groups"].as_array().unwrap().len(), 1);
+    assert_eq!(json["groups"][0]["files"].as_array().unwrap().len(), 20);
+}
+
+#[test]
+fn test_max_bandwidth_limits_reads() {
+    let dir = TempDir::new().unwrap();
+    let content = vec![1u8; 150 * 1024];
+    create_file(dir.path(), "a.bin", &content);
+    create_file(dir.path(), "b.bin", &content);
+
+    // About 350KB are read, the first 100KB right away
+    let started = std::time::Instant::now();
+    dedup()
+        .arg(dir.path())
+        .arg("--max-bandwidth")
+        .arg("100K/s")
+        .arg("--action")
+        .arg("report-exit-code")
+        .assert()
+        .code(1);
+    assert!(started.elapsed() >= std::time::Duration::from_secs(2));
+}
+
+#[test]
+fn test_candidates_hashed_while_scanning() {
+    let dir = TempDir::new().unwrap();
+    // Same size, same start and end, only the middle chunk of the partial hash differs
+    for i in 0..30u8 {
+        let mut content = vec![0u8; 100_000];
+        content[50_000] = i % 10;
+        create_file(dir.path(), &format!("dir{}/file.bin", i), &content);
+    }
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let groups = json["groups"].as_array().unwrap();
+    assert_eq!(groups.len(), 10);
+    assert!(
+        groups
+            .iter()
+            .all(|g| g["files"].as_array().unwrap().len() == 3)
+    );
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use common::{create_file, dedup};
+use tempfile::TempDir;
+
+#[test]
+fn test_exec_per_group_runs_once_per_group() {
+    let dir = TempDir::new().unwrap();
+    let log_dir = TempDir::new().unwrap();
+    let log = log_dir.path().join("groups.log");
+
+    create_file(dir.path(), "a.txt", b"group one");
+    create_file(dir.path(), "sub/a.txt", b"group one");
+    create_file(dir.path(), "b.txt", b"group two");
+    create_file(dir.path(), "sub/b.txt", b"group two");
+    create_file(dir.path(), "unique.txt", b"no duplicate");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("quiet")
+        .arg("--exec-per-group")
+        .arg(format!(
+            "echo {{keeper}} {{duplicates}} >> '{}'",
+            log.display()
+        ))
+        .assert()
+        .success();
+
+    let content = std::fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.len(), 2);
+    for line in lines {
+        let paths: Vec<&str> = line.split(' ').collect();
+        assert_eq!(paths.len(), 2);
+        assert!(!paths[0].contains("/sub/"));
+        assert!(paths[1].contains("/sub/"));
+    }
+}
+
+#[test]
+fn test_exec_per_group_dry_run_does_not_run() {
+    let dir = TempDir::new().unwrap();
+    let log_dir = TempDir::new().unwrap();
+    let log = log_dir.path().join("groups.log");
+
+    create_file(dir.path(), "a.txt", b"duplicate");
+    create_file(dir.path(), "b.txt", b"duplicate");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--dry-run")
+        .arg("--exec-per-group")
+        .arg(format!("touch '{}'", log.display()))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("[dry-run] touch"));
+
+    assert!(!log.exists());
+}
+
+#[test]
+fn test_exec_per_duplicate_reports_exit_codes() {
+    let dir = TempDir::new().unwrap();
+
+    create_file(dir.path(), "a.txt", b"duplicate");
+    create_file(dir.path(), "b.txt", b"duplicate");
+    create_file(dir.path(), "c.txt", b"duplicate");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--no-progress")
+        .arg("--exec-per-duplicate")
+        .arg("test \"$DEDUP_DUPLICATE\" != \"$DEDUP_KEEPER\" && exit 4")
+        .assert()
+        .code(4)
+        .stdout(predicates::str::contains("Ran 2 commands, 2 failed"))
+        .stdout(predicates::str::contains("exit code 4: 2 commands"));
+}
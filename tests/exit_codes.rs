@@ -58,3 +58,182 @@ fn test_report_exit_code_one_with_dups() {
         .assert()
         .code(1);
 }
+
+#[test]
+fn test_expect_min_files_aborts_before_action() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"duplicate content");
+    create_file(dir.path(), "b.txt", b"duplicate content");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--action")
+        .arg("hardlink")
+        .arg("--expect-min-files")
+        .arg("3")
+        .arg("--no-progress")
+        .assert()
+        .code(2)
+        .stderr(predicates::str::contains("expected at least 3"));
+
+    assert_ne!(
+        common::file_inode(&dir.path().join("a.txt")),
+        common::file_inode(&dir.path().join("b.txt"))
+    );
+}
+
+#[test]
+fn test_expect_max_files_aborts() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"unique content a");
+    create_file(dir.path(), "b.txt", b"unique content b");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--expect-max-files")
+        .arg("1")
+        .arg("--no-progress")
+        .assert()
+        .code(2);
+
+    dedup()
+        .arg(dir.path())
+        .arg("--expect-min-files")
+        .arg("2")
+        .arg("--expect-max-files")
+        .arg("2")
+        .arg("--no-progress")
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_quiet_report_exit_code_prints_nothing() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"duplicate content");
+    create_file(dir.path(), "b.txt", b"duplicate content");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("quiet")
+        .arg("--action")
+        .arg("report-exit-code")
+        .arg("--exclude")
+        .arg("[invalid")
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr("");
+}
+
+#[test]
+fn test_exit_four_when_commands_fail() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"duplicate content");
+    create_file(dir.path(), "b.txt", b"duplicate content");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("quiet")
+        .arg("--action")
+        .arg("report-exit-code")
+        .arg("--exec-per-duplicate")
+        .arg("exit 1")
+        .assert()
+        .code(4)
+        .stdout("")
+        .stderr("");
+}
+
+#[test]
+fn test_quiet_rejects_verbose() {
+    let dir = TempDir::new().unwrap();
+
+    dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("quiet")
+        .arg("--verbose")
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_scan_errors_reported_and_fail_run() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"content");
+    let missing = dir.path().join("missing");
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg(&missing)
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .code(3)
+        .get_output()
+        .stdout
+        .clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let errors = json["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0]["path"], missing.to_str().unwrap());
+
+    dedup()
+        .arg(dir.path())
+        .arg("--no-progress")
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_error_log_records_skipped_and_failed_paths() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"duplicate content");
+    create_file(dir.path(), "b.txt", b"duplicate content");
+    let missing = dir.path().join("missing");
+    let log = dir.path().join("errors.jsonl");
+
+    dedup()
+        .arg(dir.path())
+        .arg(&missing)
+        .arg("--no-progress")
+        .arg("--exclude")
+        .arg("*.jsonl")
+        .arg("--exec-per-duplicate")
+        .arg("exit 1")
+        .arg("--error-log")
+        .arg(&log)
+        .assert()
+        .code(4);
+
+    let entries: Vec<serde_json::Value> = std::fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["kind"], "scan");
+    assert_eq!(entries[0]["path"], missing.to_str().unwrap());
+    assert_eq!(entries[1]["kind"], "command");
+    assert!(
+        entries[1]["error"]
+            .as_str()
+            .unwrap()
+            .contains("exit status: 1")
+    );
+
+    // A clean run leaves an empty log
+    dedup()
+        .arg(dir.path())
+        .arg("--no-progress")
+        .arg("--exclude")
+        .arg("*.jsonl")
+        .arg("--error-log")
+        .arg(&log)
+        .assert()
+        .success();
+    assert_eq!(std::fs::read_to_string(&log).unwrap(), "");
+}
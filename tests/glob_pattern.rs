@@ -472,3 +472,83 @@ fn test_include_file_and_exclude_file_combined() {
     assert!(filenames.contains(&"lib.rs".to_string()));
     // readme.txt is included but not a duplicate so not in groups
 }
+
+#[test]
+fn test_ext_filter_ignores_case() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.jpg", b"same content");
+    create_file(dir.path(), "b.JPG", b"same content");
+    create_file(dir.path(), "c.txt", b"same content");
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--ext")
+        .arg("jpg,png")
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let mut names = get_all_filenames(&json);
+    names.sort();
+    assert_eq!(names, ["a.jpg", "b.JPG"]);
+}
+
+#[test]
+fn test_dedupignore_applies_to_its_subtree() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.log", b"same content");
+    create_file(dir.path(), "project/b.log", b"same content");
+    create_file(dir.path(), "project/keep.log", b"same content");
+    create_file(dir.path(), "project/.dedupignore", b"*.log\n!keep.log\n");
+
+    let scan = |extra: &[&str]| {
+        let output = dedup()
+            .arg(dir.path())
+            .args(extra)
+            .arg("--format")
+            .arg("json")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        let mut names = get_all_filenames(&json);
+        names.sort();
+        names
+    };
+
+    assert_eq!(scan(&[]), ["a.log", "keep.log"]);
+    assert_eq!(scan(&["--no-dedupignore"]), ["a.log", "b.log", "keep.log"]);
+}
+
+#[test]
+fn test_include_ignore_case() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.jpg", b"same content");
+    create_file(dir.path(), "b.JPG", b"same content");
+    create_file(dir.path(), "c.txt", b"same content");
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--include")
+        .arg("*.jpg")
+        .arg("--ignore-case")
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let mut names = get_all_filenames(&json);
+    names.sort();
+    assert_eq!(names, ["a.jpg", "b.JPG"]);
+}
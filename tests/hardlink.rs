@@ -84,6 +84,85 @@ fn test_hardlink_skips_already_linked() {
     assert_eq!(inode_a_before, inode_a_after);
 }
 
+#[test]
+fn test_rerun_after_hardlink_reports_groups_as_deduplicated() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"duplicate content");
+    create_file(dir.path(), "b.txt", b"duplicate content");
+    create_file(dir.path(), "c.txt", b"duplicate content");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--action")
+        .arg("hardlink")
+        .arg("--no-progress")
+        .assert()
+        .success();
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("json")
+        .arg("--no-progress")
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    assert!(json["groups"].as_array().unwrap().is_empty());
+    assert_eq!(json["stats"]["already_deduplicated"], 1);
+    assert_eq!(json["stats"]["wasted_bytes"], 0);
+}
+
+#[test]
+fn test_partly_linked_groups_only_count_distinct_inodes() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"duplicate content");
+    fs::hard_link(dir.path().join("a.txt"), dir.path().join("b.txt")).unwrap();
+    create_file(dir.path(), "c.txt", b"duplicate content");
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("json")
+        .arg("--no-progress")
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    assert_eq!(json["groups"].as_array().unwrap().len(), 1);
+    // Only the copy with its own inode can be reclaimed
+    assert_eq!(json["stats"]["wasted_bytes"], 17);
+    assert_eq!(json["stats"]["already_linked_bytes"], 17);
+
+    dedup()
+        .arg(dir.path())
+        .arg("--no-progress")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Already hardlinked: 17 bytes"));
+}
+
+#[test]
+fn test_skip_hardlinked_ignores_linked_files() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"duplicate content");
+    fs::hard_link(dir.path().join("a.txt"), dir.path().join("b.txt")).unwrap();
+    create_file(dir.path(), "c.txt", b"duplicate content");
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--skip-hardlinked")
+        .arg("--format")
+        .arg("json")
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    // c.txt has no duplicate left once the linked files are skipped
+    assert_eq!(json["stats"]["total_files"], 1);
+    assert!(json["groups"].as_array().unwrap().is_empty());
+}
+
 #[test]
 fn test_hardlink_reports_savings() {
     let dir = TempDir::new().unwrap();
@@ -137,3 +216,246 @@ fn test_hardlink_multiple_groups() {
         file_inode(&dir.path().join("g2_a.txt"))
     );
 }
+
+#[test]
+fn test_hardlink_min_saving() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "small_a.txt", b"small");
+    create_file(dir.path(), "small_b.txt", b"small");
+    create_file(dir.path(), "large_a.txt", b"larger duplicate content");
+    create_file(dir.path(), "large_b.txt", b"larger duplicate content");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--action")
+        .arg("hardlink")
+        .arg("--min-saving")
+        .arg("10")
+        .arg("--no-progress")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Linked 1 files"))
+        .stdout(predicate::str::contains("Left 1 groups untouched"));
+
+    assert_ne!(
+        file_inode(&dir.path().join("small_a.txt")),
+        file_inode(&dir.path().join("small_b.txt"))
+    );
+    assert_eq!(
+        file_inode(&dir.path().join("large_a.txt")),
+        file_inode(&dir.path().join("large_b.txt"))
+    );
+}
+
+#[test]
+fn test_cas_action_links_to_store() {
+    let dir = TempDir::new().unwrap();
+    let content = b"duplicate content";
+    create_file(dir.path(), "data/a.txt", content);
+    create_file(dir.path(), "data/b.txt", content);
+    let store = dir.path().join("store");
+
+    dedup()
+        .arg(dir.path().join("data"))
+        .arg("--action")
+        .arg("cas")
+        .arg("--store")
+        .arg(&store)
+        .arg("--no-progress")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Linked 1 files"));
+
+    let hash = blake3::hash(content).to_hex();
+    let object = store.join(&hash[..2]).join(hash.as_str());
+    assert_eq!(fs::read(&object).unwrap(), content);
+    assert_eq!(
+        file_inode(&object),
+        file_inode(&dir.path().join("data/a.txt"))
+    );
+    assert_eq!(
+        file_inode(&object),
+        file_inode(&dir.path().join("data/b.txt"))
+    );
+}
+
+#[test]
+fn test_cas_action_requires_blake3() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "data/a.txt", b"duplicate content");
+    create_file(dir.path(), "data/b.txt", b"duplicate content");
+    let store = dir.path().join("store");
+
+    dedup()
+        .arg(dir.path().join("data"))
+        .arg("--action")
+        .arg("cas")
+        .arg("--store")
+        .arg(&store)
+        .arg("--hash")
+        .arg("xxh3")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("--hash blake3"));
+
+    assert!(!store.exists());
+    assert_ne!(
+        file_inode(&dir.path().join("data/a.txt")),
+        file_inode(&dir.path().join("data/b.txt"))
+    );
+}
+
+#[test]
+fn test_stub_action_replaces_duplicates() {
+    let dir = TempDir::new().unwrap();
+    let content = b"duplicate content";
+    create_file(dir.path(), "a.txt", content);
+    create_file(dir.path(), "sub/a.txt", content);
+
+    dedup()
+        .arg(dir.path())
+        .arg("--action")
+        .arg("stub")
+        .arg("--no-progress")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Replaced 1 files with stubs"));
+
+    assert_eq!(fs::read(dir.path().join("a.txt")).unwrap(), content);
+    assert!(!dir.path().join("sub/a.txt").exists());
+
+    let stub = fs::read_to_string(dir.path().join("sub/a.txt.dedup-stub")).unwrap();
+    assert!(stub.starts_with("dedup-stub 1\n"));
+    assert!(stub.contains(&format!("hash: {}", blake3::hash(content).to_hex())));
+    assert!(stub.contains("a.txt\n"));
+}
+
+#[test]
+fn test_hardlink_skips_empty_files_by_default() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a", b"");
+    create_file(dir.path(), "b", b"");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--action")
+        .arg("hardlink")
+        .arg("--no-progress")
+        .assert()
+        .success();
+
+    assert_ne!(
+        file_inode(&dir.path().join("a")),
+        file_inode(&dir.path().join("b"))
+    );
+
+    dedup()
+        .arg(dir.path())
+        .arg("--action")
+        .arg("hardlink")
+        .arg("--include-empty-in-actions")
+        .arg("--no-progress")
+        .assert()
+        .success();
+
+    assert_eq!(
+        file_inode(&dir.path().join("a")),
+        file_inode(&dir.path().join("b"))
+    );
+}
+
+/// A tar archive with a single file
+fn tar_with(name: &str, content: &[u8]) -> Vec<u8> {
+    let mut header = vec![0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..107].copy_from_slice(b"0000644");
+    header[124..135].copy_from_slice(format!("{:011o}", content.len()).as_bytes());
+    header[136..147].copy_from_slice(b"00000000000");
+    header[156] = b'0';
+    header[148..156].fill(b' ');
+    let sum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+
+    let mut archive = header;
+    archive.extend_from_slice(content);
+    archive.resize(archive.len().div_ceil(512) * 512 + 1024, 0);
+    archive
+}
+
+#[test]
+fn test_hardlink_leaves_archive_members_untouched() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"duplicate content");
+    create_file(dir.path(), "b.txt", b"duplicate content");
+    create_file(
+        dir.path(),
+        "backup.tar",
+        &tar_with("docs/a.txt", b"duplicate content"),
+    );
+    let archive_before = fs::read(dir.path().join("backup.tar")).unwrap();
+
+    let output = dedup()
+        .arg(dir.path())
+        .args(["--scan-archives", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let files = json["groups"][0]["files"].as_array().unwrap();
+    assert_eq!(files.len(), 3);
+    let member = format!("{}!docs/a.txt", dir.path().join("backup.tar").display());
+    assert!(files.iter().any(|f| f.as_str() == Some(member.as_str())));
+
+    dedup()
+        .arg(dir.path())
+        .args(["--scan-archives", "--action", "hardlink", "--no-progress"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Linked 1 files"))
+        .stdout(predicate::str::contains("Left 1 archive members untouched"));
+
+    assert_eq!(
+        file_inode(&dir.path().join("a.txt")),
+        file_inode(&dir.path().join("b.txt"))
+    );
+    assert_eq!(
+        fs::read(dir.path().join("backup.tar")).unwrap(),
+        archive_before
+    );
+}
+
+#[test]
+fn test_compare_to_never_modifies_reference() {
+    let dir = TempDir::new().unwrap();
+    let archive = dir.path().join("archive");
+    let card = dir.path().join("card");
+    create_file(&archive, "2024/a.jpg", b"photo a");
+    create_file(&archive, "copy/a.jpg", b"photo a");
+    create_file(&card, "DCIM/a.jpg", b"photo a");
+    create_file(&card, "DCIM/new.jpg", b"new photo");
+    create_file(&card, "DCIM/new (1).jpg", b"new photo");
+
+    let archive_inodes = [
+        file_inode(&archive.join("2024/a.jpg")),
+        file_inode(&archive.join("copy/a.jpg")),
+    ];
+    let new_inode = file_inode(&card.join("DCIM/new.jpg"));
+
+    dedup()
+        .arg("--compare-to")
+        .arg(&archive)
+        .arg(&card)
+        .args(["--action", "hardlink", "--no-progress"])
+        .assert()
+        .success();
+
+    // The card copy is linked to one of the reference copies, which stay as they were
+    let card_inode = file_inode(&card.join("DCIM/a.jpg"));
+    assert!(archive_inodes.contains(&card_inode));
+    assert_eq!(file_inode(&archive.join("2024/a.jpg")), archive_inodes[0]);
+    assert_eq!(file_inode(&archive.join("copy/a.jpg")), archive_inodes[1]);
+    // Duplicates only on the card are not in the archive and left alone
+    assert_eq!(file_inode(&card.join("DCIM/new.jpg")), new_inode);
+}
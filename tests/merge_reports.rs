@@ -0,0 +1,158 @@
+mod common;
+
+use common::{create_file, dedup, get_all_filenames};
+use predicates::prelude::*;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Scan a directory and write its JSON report to `report`
+fn write_report(dir: &Path, report: &Path) {
+    dedup()
+        .arg(dir)
+        .arg("--format")
+        .arg("json")
+        .arg("--output")
+        .arg(report)
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_merge_reports_of_shards() {
+    let shard_a = TempDir::new().unwrap();
+    create_file(shard_a.path(), "a1.txt", b"shared content");
+    create_file(shard_a.path(), "a2.txt", b"shared content");
+    create_file(shard_a.path(), "only_a1.txt", b"content of a");
+    create_file(shard_a.path(), "only_a2.txt", b"content of a");
+    let shard_b = TempDir::new().unwrap();
+    create_file(shard_b.path(), "b1.txt", b"shared content");
+    create_file(shard_b.path(), "b2.txt", b"shared content");
+    create_file(shard_b.path(), "unique.txt", b"unique");
+
+    let reports = TempDir::new().unwrap();
+    let report_a = reports.path().join("a.json");
+    let report_b = reports.path().join("b.json");
+    let merged = reports.path().join("merged.json");
+    write_report(shard_a.path(), &report_a);
+    write_report(shard_b.path(), &report_b);
+
+    dedup()
+        .arg("merge-reports")
+        .arg(&report_a)
+        .arg(&report_b)
+        .arg("--output")
+        .arg(&merged)
+        .assert()
+        .success()
+        .stdout("");
+
+    let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&merged).unwrap()).unwrap();
+    assert_eq!(json["stats"]["total_files"], 7);
+    assert_eq!(json["stats"]["duplicate_files"], 6);
+    assert_eq!(json["groups"].as_array().unwrap().len(), 2);
+
+    let mut names = get_all_filenames(&json);
+    names.sort();
+    assert_eq!(
+        names,
+        vec![
+            "a1.txt",
+            "a2.txt",
+            "b1.txt",
+            "b2.txt",
+            "only_a1.txt",
+            "only_a2.txt"
+        ]
+    );
+}
+
+#[test]
+fn test_merge_reports_to_stdout() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"duplicate content");
+    create_file(dir.path(), "b.txt", b"duplicate content");
+    let reports = TempDir::new().unwrap();
+    let report = reports.path().join("report.json");
+    write_report(dir.path(), &report);
+
+    let output = dedup()
+        .arg("merge-reports")
+        .arg(&report)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["stats"]["duplicate_files"], 2);
+}
+
+#[test]
+fn test_merge_reports_invalid_report() {
+    let reports = TempDir::new().unwrap();
+    create_file(reports.path(), "report.json", b"not json");
+
+    dedup()
+        .arg("merge-reports")
+        .arg(reports.path().join("report.json"))
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("is not a JSON report"));
+}
+
+#[test]
+fn test_diff_of_two_runs() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a1.txt", b"content a");
+    create_file(dir.path(), "a2.txt", b"content a");
+    create_file(dir.path(), "b1.txt", b"content b");
+    create_file(dir.path(), "b2.txt", b"content b");
+    let reports = TempDir::new().unwrap();
+    let before = reports.path().join("before.json");
+    let after = reports.path().join("after.json");
+    write_report(dir.path(), &before);
+
+    std::fs::remove_file(dir.path().join("b2.txt")).unwrap();
+    create_file(dir.path(), "a3.txt", b"content a");
+    create_file(dir.path(), "c1.txt", b"content c");
+    create_file(dir.path(), "c2.txt", b"content c");
+    write_report(dir.path(), &after);
+
+    dedup()
+        .arg("diff")
+        .arg(&before)
+        .arg(&after)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("New duplicate groups (1):"))
+        .stdout(predicate::str::contains("Resolved duplicate groups (1):"))
+        .stdout(predicate::str::contains("Changed duplicate groups (1):"))
+        .stdout(predicate::str::contains("+ ").and(predicate::str::contains("a3.txt")))
+        .stdout(predicate::str::contains("0 groups unchanged"));
+
+    let output = dedup()
+        .arg("diff")
+        .arg(&before)
+        .arg(&after)
+        .arg("--json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["new"].as_array().unwrap().len(), 1);
+    assert_eq!(json["resolved"].as_array().unwrap().len(), 1);
+    assert_eq!(json["changed"][0]["files_after"], 3);
+    assert_eq!(json["wasted_bytes_before"], 18);
+    assert_eq!(json["wasted_bytes_after"], 27);
+
+    dedup()
+        .arg("diff")
+        .arg(&before)
+        .arg(reports.path().join("missing.json"))
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("could not read"));
+}
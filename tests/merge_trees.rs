@@ -0,0 +1,85 @@
+mod common;
+
+use common::{create_file, dedup, file_inode};
+use predicates::prelude::*;
+use std::fs;
+use tempfile::TempDir;
+
+#[test]
+fn test_merge_moves_unique_files_and_removes_copies() {
+    let dir = TempDir::new().unwrap();
+    let src = dir.path().join("old-drive");
+    let dst = dir.path().join("archive");
+    create_file(&src, "photos/a.jpg", b"photo a");
+    create_file(&src, "photos/b.jpg", b"photo b");
+    create_file(&src, "docs/taken.txt", b"new notes");
+    create_file(&dst, "2024/a.jpg", b"photo a");
+    create_file(&dst, "docs/taken.txt", b"old notes");
+
+    dedup()
+        .arg("merge")
+        .arg(&src)
+        .arg(&dst)
+        .assert()
+        .code(4)
+        .stdout(predicate::str::contains("Moved 1 files"))
+        .stdout(predicate::str::contains("Removed 1 files"))
+        .stderr(predicate::str::contains(
+            "already exists with different contents",
+        ));
+
+    assert!(!src.join("photos/a.jpg").exists());
+    assert!(!src.join("photos/b.jpg").exists());
+    assert_eq!(fs::read(dst.join("photos/b.jpg")).unwrap(), b"photo b");
+    assert!(!dst.join("photos/a.jpg").exists());
+    // A different file at the same path is never overwritten
+    assert_eq!(fs::read(src.join("docs/taken.txt")).unwrap(), b"new notes");
+    assert_eq!(fs::read(dst.join("docs/taken.txt")).unwrap(), b"old notes");
+}
+
+#[test]
+fn test_merge_dry_run_and_link() {
+    let dir = TempDir::new().unwrap();
+    let src = dir.path().join("old-drive");
+    let dst = dir.path().join("archive");
+    create_file(&src, "a.jpg", b"photo a");
+    create_file(&src, "b.jpg", b"photo b");
+    create_file(&dst, "2024/a.jpg", b"photo a");
+
+    dedup()
+        .args(["merge", "--dry-run"])
+        .arg(&src)
+        .arg(&dst)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("[dry-run] Would move 1 files"));
+    assert!(src.join("a.jpg").exists());
+    assert!(src.join("b.jpg").exists());
+    assert!(!dst.join("b.jpg").exists());
+
+    dedup()
+        .args(["merge", "--link"])
+        .arg(&src)
+        .arg(&dst)
+        .assert()
+        .success();
+    assert_eq!(
+        file_inode(&src.join("a.jpg")),
+        file_inode(&dst.join("2024/a.jpg"))
+    );
+    assert!(dst.join("b.jpg").exists());
+}
+
+#[test]
+fn test_merge_rejects_nested_directories() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "inner/a.txt", b"a");
+
+    dedup()
+        .arg("merge")
+        .arg(dir.path().join("inner"))
+        .arg(dir.path())
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("inside each other"));
+}
@@ -63,3 +63,380 @@ fn test_single_file() {
     assert_eq!(json["groups"].as_array().unwrap().len(), 0);
     assert_eq!(json["stats"]["duplicate_files"], 0);
 }
+
+#[test]
+fn test_files_from_stdin() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"same content");
+    create_file(dir.path(), "b.txt", b"same content");
+    let (a, b) = (dir.path().join("a.txt"), dir.path().join("b.txt"));
+    // Not listed, so not compared even though it's a duplicate
+    create_file(dir.path(), "c.txt", b"same content");
+
+    let output = dedup()
+        .arg("--files-from")
+        .arg("-")
+        .arg("--format")
+        .arg("json")
+        .write_stdin(format!("{}\n{}\n", a.display(), b.display()))
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["stats"]["total_files"], 2);
+    assert_eq!(json["stats"]["duplicate_files"], 2);
+}
+
+#[test]
+fn test_files_from_nul_separated_file() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "line\nbreak.txt", b"same content");
+    create_file(dir.path(), "b.txt", b"same content");
+    let (a, b) = (dir.path().join("line\nbreak.txt"), dir.path().join("b.txt"));
+    let list = dir.path().join("list");
+    std::fs::write(&list, format!("{}\0{}\0", a.display(), b.display())).unwrap();
+
+    let output = dedup()
+        .arg("--files-from")
+        .arg(&list)
+        .arg("-0")
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["stats"]["duplicate_files"], 2);
+}
+
+#[test]
+fn test_files_from_missing_list_fails() {
+    dedup()
+        .arg("--files-from")
+        .arg("/nonexistent/list")
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_nice_finds_duplicates() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"duplicate content");
+    create_file(dir.path(), "b.txt", b"duplicate content");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--nice")
+        .arg("--action")
+        .arg("report-exit-code")
+        .assert()
+        .code(1);
+}
+
+#[test]
+fn test_cache_skips_unchanged_files() {
+    let dir = TempDir::new().unwrap();
+    let cache_dir = TempDir::new().unwrap();
+    let cache = cache_dir.path().join("hashes.sqlite");
+    let content = vec![1u8; 100_000];
+    let mut other = content.clone();
+    other[99_999] = 2;
+    create_file(dir.path(), "a.bin", &content);
+    create_file(dir.path(), "b.bin", &content);
+    create_file(dir.path(), "c.bin", &other);
+
+    let run = || {
+        let output = dedup()
+            .arg(dir.path())
+            .arg("--cache")
+            .arg(&cache)
+            .arg("--format")
+            .arg("json")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        (
+            json["groups"][0]["files"].as_array().unwrap().len(),
+            json["stats"]["performance"]["bytes_hashed"]
+                .as_u64()
+                .unwrap(),
+        )
+    };
+
+    let (files, bytes_hashed) = run();
+    assert_eq!(files, 2);
+    assert!(bytes_hashed > 0);
+    assert_eq!(run(), (2, 0));
+
+    // The changed file is hashed again, the others still come from the cache
+    create_file(dir.path(), "c.bin", &content);
+    let (files, bytes_hashed) = run();
+    assert_eq!(files, 3);
+    assert!(bytes_hashed > 0 && bytes_hashed <= 100_000);
+}
+
+#[test]
+fn test_incremental_updates_the_last_report() {
+    let dir = TempDir::new().unwrap();
+    let cache_dir = TempDir::new().unwrap();
+    let cache = cache_dir.path().join("hashes.sqlite");
+    let content = vec![1u8; 100_000];
+    let mut other = content.clone();
+    other[99_999] = 2;
+    create_file(dir.path(), "a.bin", &content);
+    create_file(dir.path(), "b.bin", &content);
+    create_file(dir.path(), "c.bin", &other);
+
+    let run = || {
+        let output = dedup()
+            .arg(dir.path())
+            .arg("--cache")
+            .arg(&cache)
+            .arg("--incremental")
+            .arg("--format")
+            .arg("json")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        let mut groups: Vec<Vec<String>> = json["groups"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|group| {
+                let mut names: Vec<String> = group["files"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|file| {
+                        file.as_str()
+                            .unwrap()
+                            .rsplit('/')
+                            .next()
+                            .unwrap()
+                            .to_string()
+                    })
+                    .collect();
+                names.sort();
+                names
+            })
+            .collect();
+        groups.sort();
+        groups
+    };
+
+    assert_eq!(run(), [["a.bin", "b.bin"]]);
+    assert_eq!(run(), [["a.bin", "b.bin"]]);
+
+    // New files join the groups, changed ones leave them
+    create_file(dir.path(), "d.bin", &other);
+    create_file(dir.path(), "e.bin", &content);
+    assert_eq!(
+        run(),
+        [vec!["a.bin", "b.bin", "e.bin"], vec!["c.bin", "d.bin"]]
+    );
+    create_file(dir.path(), "a.bin", &other);
+    std::fs::remove_file(dir.path().join("e.bin")).unwrap();
+    assert_eq!(run(), [["a.bin", "c.bin", "d.bin"]]);
+}
+
+#[test]
+fn test_cache_command() {
+    let dir = TempDir::new().unwrap();
+    let cache_dir = TempDir::new().unwrap();
+    let cache = cache_dir.path().join("hashes.sqlite");
+    create_file(dir.path(), "a.txt", b"content");
+    create_file(dir.path(), "b.txt", b"content");
+    create_file(dir.path(), "c.txt", b"content");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--cache")
+        .arg(&cache)
+        .arg("--format")
+        .arg("quiet")
+        .assert()
+        .success();
+
+    dedup()
+        .args(["cache", "stats"])
+        .arg(&cache)
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "3 cached hashes of 3 files (0 partial, 3 full)",
+        ));
+
+    dedup()
+        .args(["cache", "verify"])
+        .arg(&cache)
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "Checked 3 hashes, 0 were out of date",
+        ));
+
+    std::fs::remove_file(dir.path().join("c.txt")).unwrap();
+    dedup()
+        .args(["cache", "prune"])
+        .arg(&cache)
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "Removed 1 hashes of missing or changed files and 0 over the limit, 2 left",
+        ));
+
+    dedup()
+        .args(["cache", "clear"])
+        .arg(&cache)
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Removed 2 hashes"));
+
+    dedup()
+        .args(["cache", "stats"])
+        .arg(cache_dir.path().join("missing.sqlite"))
+        .assert()
+        .code(2);
+    assert!(!cache_dir.path().join("missing.sqlite").exists());
+}
+
+#[test]
+fn test_imported_hashes_are_not_read_again() {
+    let dir = TempDir::new().unwrap();
+    let nas = dir.path().join("nas");
+    let content = vec![1u8; 100_000];
+    create_file(&nas, "a.bin", &content);
+    create_file(&nas, "b.bin", &content);
+    let list = dir.path().join("nas.json");
+
+    dedup()
+        .arg("export-hashes")
+        .arg(&nas)
+        .arg(&list)
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Exported the hashes of 2 files"));
+
+    let output = dedup()
+        .arg(&nas)
+        .arg("--import-hashes")
+        .arg(&list)
+        .arg("--format")
+        .arg("json")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["groups"][0]["files"].as_array().unwrap().len(), 2);
+    let bytes_hashed = json["stats"]["performance"]["bytes_hashed"]
+        .as_u64()
+        .unwrap();
+    assert!(bytes_hashed < 100_000);
+
+    dedup()
+        .arg(&nas)
+        .arg("--import-hashes")
+        .arg(&list)
+        .arg("--hash")
+        .arg("sha256")
+        .assert()
+        .code(2)
+        .stderr(predicates::str::contains("--hash blake3"));
+}
+
+#[test]
+fn test_max_memory_finds_the_same_duplicates() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"same content");
+    create_file(dir.path(), "sub/b.txt", b"same content");
+    create_file(dir.path(), "sub/c.txt", b"same size!!!");
+    create_file(dir.path(), "d.txt", b"unique");
+    std::fs::hard_link(dir.path().join("d.txt"), dir.path().join("e.txt")).unwrap();
+
+    let report = |args: &[&str]| {
+        let output = dedup()
+            .arg(dir.path())
+            .args(args)
+            .arg("--format")
+            .arg("json")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        (json["groups"].clone(), json["stats"].clone())
+    };
+
+    let (groups, stats) = report(&["--max-memory", "1M"]);
+    // The hardlinks already share their contents
+    assert_eq!(groups.as_array().unwrap().len(), 1);
+    assert_eq!(stats["total_files"], 5);
+    assert_eq!(stats["unique_inodes"], 4);
+    assert_eq!(stats["hardlinked_inodes"], 1);
+    let (expected_groups, expected_stats) = report(&[]);
+    assert_eq!(groups, expected_groups);
+    assert_eq!(stats["unique_inodes"], expected_stats["unique_inodes"]);
+
+    dedup()
+        .arg(dir.path())
+        .arg("--max-memory")
+        .arg("1M")
+        .arg("--match")
+        .arg("name")
+        .assert()
+        .code(2)
+        .stderr(predicates::str::contains("--max-memory"));
+}
+
+#[test]
+fn test_find_contained_reports_truncated_copies() {
+    let dir = TempDir::new().unwrap();
+    let content: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+    create_file(dir.path(), "video.mp4", &content);
+    create_file(dir.path(), "video.mp4.part", &content[..60_000]);
+    create_file(dir.path(), "other.bin", &content[1..50_001]);
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--find-contained")
+        .arg("--format")
+        .arg("json")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let contained = json["contained"].as_array().unwrap();
+    assert_eq!(contained.len(), 1);
+    assert!(
+        contained[0]["path"]
+            .as_str()
+            .unwrap()
+            .ends_with("video.mp4.part")
+    );
+    assert_eq!(contained[0]["size"], 60_000);
+    assert_eq!(contained[0]["contained_in"][0]["size"], 100_000);
+
+    dedup()
+        .arg(dir.path())
+        .arg("--find-contained")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("video.mp4.part"));
+
+    // Only reported when asked for
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("json")
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(json.get("contained").is_none());
+}
@@ -142,3 +142,601 @@ fn test_verbose_shows_file_paths() {
         .success()
         .stdout(predicate::str::contains("a.txt").or(predicate::str::contains("b.txt")));
 }
+
+#[test]
+fn test_human_output_localized() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"duplicate content");
+    create_file(dir.path(), "b.txt", b"duplicate content");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--no-progress")
+        .arg("--lang")
+        .arg("tr")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Kopya Dosya Raporu"))
+        .stdout(predicate::str::contains("Duplicate Report").not());
+}
+
+#[test]
+fn test_json_output_not_localized() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"duplicate content");
+    create_file(dir.path(), "b.txt", b"duplicate content");
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("json")
+        .arg("--lang")
+        .arg("tr")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["stats"]["duplicate_files"], 2);
+}
+
+#[test]
+fn test_json_output_reports_devices() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"duplicate content");
+    create_file(dir.path(), "b.txt", b"duplicate content");
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let group = &json["groups"][0];
+    assert_eq!(group["devices"].as_array().unwrap().len(), 1);
+    assert_eq!(group["cross_filesystem"], false);
+}
+
+#[test]
+fn test_json_output_paginated() {
+    let dir = TempDir::new().unwrap();
+    for i in 0..3 {
+        let content = format!("group {} content", i);
+        create_file(dir.path(), &format!("{}_a.txt", i), content.as_bytes());
+        create_file(dir.path(), &format!("{}_b.txt", i), content.as_bytes());
+    }
+
+    let page = |number: &str| {
+        let output = dedup()
+            .arg(dir.path())
+            .arg("--format")
+            .arg("json")
+            .arg("--page-size")
+            .arg("2")
+            .arg("--page")
+            .arg(number)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        serde_json::from_slice::<serde_json::Value>(&output).unwrap()
+    };
+
+    let first = page("1");
+    assert_eq!(first["groups"].as_array().unwrap().len(), 2);
+    assert_eq!(first["page"]["total_pages"], 2);
+    assert_eq!(first["page"]["total_groups"], 3);
+    assert_eq!(first["stats"]["duplicate_files"], 6);
+
+    let second = page("2");
+    assert_eq!(second["groups"].as_array().unwrap().len(), 1);
+    assert!(
+        second["groups"][0]["files"][0]
+            .as_str()
+            .unwrap()
+            .ends_with("2_a.txt")
+    );
+}
+
+#[test]
+fn test_fdupes_output() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a1.txt", b"content a");
+    create_file(dir.path(), "a2.txt", b"content a");
+    create_file(dir.path(), "b1.txt", b"content b");
+    create_file(dir.path(), "b2.txt", b"content b");
+    create_file(dir.path(), "unique.txt", b"unique");
+
+    let root = dir.path().display();
+    dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("fdupes")
+        .assert()
+        .success()
+        .stdout(format!(
+            "{root}/a1.txt\n{root}/a2.txt\n\n{root}/b1.txt\n{root}/b2.txt\n\n"
+        ));
+}
+
+#[test]
+fn test_output_file_keeps_report_separate() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"duplicate content");
+    create_file(dir.path(), "b.txt", b"duplicate content");
+    let out_dir = TempDir::new().unwrap();
+    let report = out_dir.path().join("report.json");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("json")
+        .arg("--output")
+        .arg(&report)
+        .arg("--action")
+        .arg("hardlink")
+        .arg("--dry-run")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"groups\"").not())
+        .stdout(predicate::str::contains("Would link"));
+
+    let json: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&report).unwrap()).expect("Invalid JSON output");
+    assert_eq!(json["stats"]["duplicate_files"], 2);
+    assert_eq!(std::fs::read_dir(out_dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn test_output_file_human_has_no_colors() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"duplicate content");
+    create_file(dir.path(), "b.txt", b"duplicate content");
+    let out_dir = TempDir::new().unwrap();
+    let report = out_dir.path().join("report.txt");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--no-progress")
+        .arg("--output")
+        .arg(&report)
+        .env("CLICOLOR_FORCE", "1")
+        .assert()
+        .success()
+        .stdout("");
+
+    let contents = std::fs::read_to_string(&report).unwrap();
+    assert!(contents.contains("Duplicate Report"));
+    assert!(!contents.contains('\x1b'));
+}
+
+#[test]
+fn test_output_file_in_missing_directory_fails() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"duplicate content");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--output")
+        .arg(dir.path().join("missing").join("report.txt"))
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("could not write report"));
+}
+
+#[test]
+fn test_paths0_output() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"content a");
+    create_file(dir.path(), "copy\nof a.txt", b"content a");
+    create_file(dir.path(), "unique.txt", b"unique");
+
+    let root = dir.path().display();
+    dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("paths0")
+        .assert()
+        .success()
+        .stdout(format!("{root}/copy\nof a.txt\0"));
+}
+
+#[test]
+fn test_sort_by_wasted_space() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a1.txt", b"small");
+    create_file(dir.path(), "a2.txt", b"small");
+    create_file(dir.path(), "b1.txt", b"much larger content");
+    create_file(dir.path(), "b2.txt", b"much larger content");
+
+    let root = dir.path().display();
+    dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("fdupes")
+        .arg("--sort")
+        .arg("wasted")
+        .assert()
+        .success()
+        .stdout(format!(
+            "{root}/b1.txt\n{root}/b2.txt\n\n{root}/a1.txt\n{root}/a2.txt\n\n"
+        ));
+}
+
+#[test]
+fn test_max_group_size_truncates_groups() {
+    let dir = TempDir::new().unwrap();
+    for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+        create_file(dir.path(), name, b"template");
+    }
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("json")
+        .arg("--max-group-size")
+        .arg("2")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["stats"]["duplicate_files"], 4);
+    assert_eq!(json["groups"][0]["files"].as_array().unwrap().len(), 2);
+    assert_eq!(json["groups"][0]["omitted_files"], 2);
+}
+
+#[test]
+fn test_top_reports_biggest_groups() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a1.txt", b"small");
+    create_file(dir.path(), "a2.txt", b"small");
+    create_file(dir.path(), "b1.txt", b"much larger content");
+    create_file(dir.path(), "b2.txt", b"much larger content");
+    create_file(dir.path(), "c1.txt", b"medium content");
+    create_file(dir.path(), "c2.txt", b"medium content");
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("json")
+        .arg("--top")
+        .arg("2")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["stats"]["duplicate_files"], 6);
+    let names = common::get_all_filenames(&json);
+    assert_eq!(names, vec!["b1.txt", "b2.txt", "c1.txt", "c2.txt"]);
+}
+
+#[test]
+fn test_format_template_output() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"content");
+    create_file(dir.path(), "copy.txt", b"content");
+    create_file(dir.path(), "unique.txt", b"unique");
+
+    let root = dir.path().display();
+    dedup()
+        .arg(dir.path())
+        .arg("--no-progress")
+        .arg("--format-template")
+        .arg("{group}\\t{size}\\t{is_original}\\t{path}")
+        .assert()
+        .success()
+        .stdout(format!(
+            "1\t7\ttrue\t{root}/a.txt\n1\t7\tfalse\t{root}/copy.txt\n"
+        ));
+}
+
+#[test]
+fn test_relative_paths_output() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"content");
+    create_file(dir.path(), "sub/a.txt", b"content");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--relative-paths")
+        .arg("--format")
+        .arg("fdupes")
+        .assert()
+        .success()
+        .stdout("a.txt\nsub/a.txt\n\n");
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--relative-paths")
+        .arg("--format")
+        .arg("json")
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        json["groups"][0]["files"],
+        serde_json::json!(["a.txt", "sub/a.txt"])
+    );
+}
+
+#[test]
+fn test_json_output_has_schema_version() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"content");
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("json")
+        .output()
+        .unwrap();
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    let output = dedup().arg("--print-schema").output().unwrap();
+    assert!(output.status.success());
+    let schema: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    assert_eq!(report["schema_version"], 1);
+    assert_eq!(
+        schema["properties"]["schema_version"]["const"],
+        report["schema_version"]
+    );
+}
+
+#[test]
+fn test_summary_output() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a1.txt", b"content a");
+    create_file(dir.path(), "a2.txt", b"content a");
+    create_file(dir.path(), "unique.txt", b"unique");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("summary")
+        .assert()
+        .success()
+        .stdout("scanned=3 duplicates=2 groups=1 wasted=9B wasted_bytes=9\n");
+}
+
+#[test]
+fn test_json_output_reports_tree_structure() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"content");
+    create_file(dir.path(), "b.txt", b"other");
+    std::fs::hard_link(dir.path().join("a.txt"), dir.path().join("a-link.txt")).unwrap();
+    std::os::unix::fs::symlink(dir.path().join("b.txt"), dir.path().join("b-link.txt")).unwrap();
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("json")
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    assert_eq!(json["stats"]["total_files"], 3);
+    assert_eq!(json["stats"]["unique_inodes"], 2);
+    assert_eq!(json["stats"]["hardlinked_inodes"], 1);
+    assert_eq!(json["stats"]["symlinks"], 1);
+}
+
+#[test]
+fn test_json_output_reports_performance() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", &[1u8; 10_000]);
+    create_file(dir.path(), "b.txt", &[1u8; 10_000]);
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("json")
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let performance = &json["stats"]["performance"];
+
+    // The partial hash would cover both files completely, they are only read for the full hash
+    assert_eq!(performance["bytes_hashed"], 20_000);
+    for key in [
+        "scan_secs",
+        "size_group_secs",
+        "partial_hash_secs",
+        "full_hash_secs",
+        "hash_secs",
+        "throughput_mb_per_sec",
+    ] {
+        assert!(performance[key].as_f64().unwrap() >= 0.0, "{}", key);
+    }
+}
+
+#[test]
+fn test_group_id_stable_across_runs() {
+    let group_id = |dir: &std::path::Path| {
+        let output = dedup()
+            .arg(dir)
+            .arg("--format")
+            .arg("json")
+            .output()
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        json["groups"][0]["id"].as_str().unwrap().to_string()
+    };
+
+    let first = TempDir::new().unwrap();
+    create_file(first.path(), "a.txt", b"shared content");
+    create_file(first.path(), "b.txt", b"shared content");
+
+    let second = TempDir::new().unwrap();
+    create_file(second.path(), "x/copy1.txt", b"shared content");
+    create_file(second.path(), "y/copy2.txt", b"shared content");
+    create_file(second.path(), "y/copy3.txt", b"shared content");
+
+    assert_eq!(group_id(first.path()), group_id(second.path()));
+}
+
+#[test]
+fn test_by_directory_rollup() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"content");
+    create_file(dir.path(), "downloads/a1.txt", b"content");
+    create_file(dir.path(), "downloads/a2.txt", b"content");
+
+    dedup()
+        .arg(dir.path())
+        .arg("--by-directory")
+        .arg("--no-progress")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Wasted space by directory:"))
+        .stdout(predicate::str::contains(format!(
+            "14 bytes in 2 duplicates: {}",
+            dir.path().join("downloads").display()
+        )));
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--by-directory")
+        .arg("--format")
+        .arg("json")
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["directories"].as_array().unwrap().len(), 1);
+    assert_eq!(json["directories"][0]["wasted_bytes"], 14);
+}
+
+#[test]
+fn test_profile_io_lists_slowest_files() {
+    let dir = TempDir::new().unwrap();
+    let content = vec![7u8; 100_000];
+    create_file(dir.path(), "disk/a.bin", &content);
+    create_file(dir.path(), "disk/b.bin", &content);
+    create_file(dir.path(), "unique.bin", b"unique");
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--profile-io")
+        .arg("--format")
+        .arg("json")
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let files = json["io_profile"]["files"].as_array().unwrap();
+    assert_eq!(files.len(), 2);
+    for file in files {
+        assert!(file["bytes"].as_u64().unwrap() >= 100_000);
+    }
+    assert_eq!(
+        json["io_profile"]["directories"][0]["path"],
+        dir.path().join("disk").to_str().unwrap()
+    );
+    assert_eq!(json["io_profile"]["directories"][0]["files"], 2);
+
+    dedup()
+        .arg(dir.path())
+        .arg("--profile-io")
+        .arg("--no-progress")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Slowest files to hash:"))
+        .stdout(predicate::str::contains("Slowest directories to hash:"));
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--format")
+        .arg("json")
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(json.get("io_profile").is_none());
+}
+
+#[test]
+fn test_estimate_extrapolates_sample() {
+    let dir = TempDir::new().unwrap();
+    for size in 1..=200 {
+        let content = vec![b'x'; size];
+        create_file(dir.path(), &format!("{}/a", size), &content);
+        create_file(dir.path(), &format!("{}/b", size), &content);
+    }
+
+    let output = dedup()
+        .arg("--estimate=20")
+        .arg(dir.path())
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let estimate = &json["estimate"];
+    assert_eq!(estimate["candidate_groups"], 200);
+    let sampled = estimate["sampled_groups"].as_u64().unwrap();
+    assert!(sampled > 0 && sampled < 200);
+    assert_eq!(json["groups"].as_array().unwrap().len() as u64, sampled);
+
+    let files = &estimate["duplicate_files"];
+    let (low, high) = (
+        files["low"].as_u64().unwrap(),
+        files["high"].as_u64().unwrap(),
+    );
+    assert!(low >= sampled * 2 && low <= files["estimate"].as_u64().unwrap());
+    assert!(high <= 400);
+}
+
+#[test]
+fn test_estimate_rejects_modifying_actions() {
+    let dir = TempDir::new().unwrap();
+    dedup()
+        .arg(dir.path())
+        .arg("--estimate")
+        .arg("--action")
+        .arg("hardlink")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("--estimate"));
+}
+
+#[test]
+fn test_hash_algorithm_in_report() {
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "a.txt", b"abc");
+    create_file(dir.path(), "b.txt", b"abc");
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--hash")
+        .arg("sha256")
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["hash_algorithm"], "sha256");
+    assert_eq!(
+        json["groups"][0]["hash"],
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+}
@@ -97,3 +97,74 @@ fn test_size_filter_combined() {
     assert!(filenames.contains(&"medium_a.txt".to_string()));
     assert!(filenames.contains(&"medium_b.txt".to_string()));
 }
+
+#[test]
+fn test_older_than_skips_recent_files() {
+    let dir = TempDir::new().unwrap();
+    let old = std::time::SystemTime::now() - std::time::Duration::from_secs(40 * 24 * 60 * 60);
+    for name in ["old_a.txt", "old_b.txt"] {
+        create_file(dir.path(), name, b"same content");
+        std::fs::File::options()
+            .write(true)
+            .open(dir.path().join(name))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+    }
+    // Still being written, so not considered
+    create_file(dir.path(), "new.txt", b"same content");
+
+    let output = dedup()
+        .arg(dir.path())
+        .arg("--older-than")
+        .arg("30d")
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let mut names = get_all_filenames(&json);
+    names.sort();
+    assert_eq!(names, ["old_a.txt", "old_b.txt"]);
+}
+
+#[test]
+fn test_sparse_files_report_and_min_allocated() {
+    let dir = TempDir::new().unwrap();
+    let size = 1024 * 1024;
+    create_file(dir.path(), "a.img", &vec![0; size]);
+    create_file(dir.path(), "dense.img", &vec![0; size]);
+    // Only holes, nothing is allocated on filesystems supporting sparse files
+    std::fs::File::create(dir.path().join("sparse.img"))
+        .unwrap()
+        .set_len(size as u64)
+        .unwrap();
+
+    let run = |extra: &[&str]| {
+        let output = dedup()
+            .arg(dir.path())
+            .args(extra)
+            .arg("--format")
+            .arg("json")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        serde_json::from_slice::<serde_json::Value>(&output).unwrap()
+    };
+
+    let json = run(&[]);
+    assert_eq!(json["stats"]["wasted_bytes"], 2 * size as u64);
+    let allocated = json["stats"]["allocated_wasted_bytes"].as_u64().unwrap();
+    assert!(allocated >= size as u64 && allocated < 2 * size as u64);
+
+    let json = run(&["--min-allocated", "4096"]);
+    let mut names = get_all_filenames(&json);
+    names.sort();
+    assert_eq!(names, ["a.img", "dense.img"]);
+}